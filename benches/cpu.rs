@@ -0,0 +1,94 @@
+//! Criterion benchmarks for the interpreter, so that changes to it can be checked for slowdowns
+//! with `cargo bench --bench cpu`.
+use std::hint::black_box;
+
+use chip8::builtin::BuiltinRom;
+use chip8::{Emulator, FrameBuffer, Phosphor, Quirks, RenderStyle, Variant};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+/// A loop through one of most kinds of instruction that doesn't draw or wait.
+fn dispatch(c: &mut Criterion) {
+    let mut cpu = Emulator::builder()
+        .program(&[
+            0x6005, 0x7101, 0x8014, 0x8125, 0x8206, 0x830e, 0x3100, 0x4100, 0xa300, 0xf01e, 0xf029,
+            0xf033, 0xf265, 0xc0ff, 0xf015, 0x1200,
+        ])
+        .build()
+        .unwrap()
+        .into_cpu();
+    c.bench_function("dispatch", |b| b.iter(|| cpu.step().unwrap()));
+}
+
+/// DXYN alone, with no display wait, drawing an 8x15 sprite that straddles two bytes of the
+/// display.
+fn draw(c: &mut Criterion) {
+    let mut cpu = Emulator::builder()
+        .program(&[0x6003, 0x6104, 0xa200, 0xd01f, 0x1206])
+        .quirks(Quirks::preset(Variant::Schip))
+        .build()
+        .unwrap()
+        .into_cpu();
+    for _ in 0..3 {
+        cpu.step().unwrap();
+    }
+    c.bench_function("draw", |b| {
+        b.iter(|| {
+            cpu.pc = 0x206;
+            cpu.step().unwrap()
+        })
+    });
+}
+
+/// Whole frames of Pong, which draws and reads the keypad as it plays itself, at a high enough
+/// clock speed for the frame to be busy.
+fn frame(c: &mut Criterion) {
+    let pong = BuiltinRom::find("pong").unwrap();
+    c.bench_function("frame", |b| {
+        b.iter_batched_ref(
+            || {
+                Emulator::builder()
+                    .rom_bytes(pong.rom)
+                    .ips(10_000)
+                    .build()
+                    .unwrap()
+                    .into_cpu()
+            },
+            |cpu| {
+                for _ in 0..10 {
+                    cpu.run_frame().unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+/// Turning a hi-res display into text, each way the terminal UI can, and fading it.
+fn render(c: &mut Criterion) {
+    let mut fb = FrameBuffer::new(128, 64);
+    for y in 0..fb.height() {
+        for x in (y % 3..fb.width()).step_by(3) {
+            fb.set(x, y, 1);
+        }
+    }
+    let mut group = c.benchmark_group("render");
+    for style in [
+        RenderStyle::Block,
+        RenderStyle::HalfBlock,
+        RenderStyle::Braille,
+    ] {
+        group.bench_function(style.to_string(), |b| {
+            b.iter(|| {
+                (0..style.text_size(&fb).1)
+                    .map(|row| style.line(black_box(&fb), row))
+                    .collect::<Vec<_>>()
+            })
+        });
+    }
+    let mut phosphor = Phosphor::new(8);
+    group.bench_function("phosphor", |b| b.iter(|| phosphor.update(black_box(&fb))));
+    group.finish();
+}
+
+criterion_group!(benches, dispatch, draw, frame, render);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+//! Compares the interpreter with compiled blocks (`CPU::set_jit`) on a few ROMs, run flat out.
+//! `cargo bench --bench jit` prints how long each took and the speedup.
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chip8::{Keypad, Memory, Quirks, Variant, CPU};
+
+/// Enough frames to take a noticeable time at this clock speed.
+const FRAMES: u32 = 600;
+const IPS: u32 = 1_000_000;
+
+/// Keeps adding an ever-increasing V0 to V1, counting the carries in V2: all straight-line code.
+const ARITHMETIC: &[u8] = &[
+    0x60, 0x01, 0x81, 0x04, 0x82, 0xf4, 0x83, 0x14, 0x84, 0x36, 0x85, 0x42, 0x70, 0x01, 0x12, 0x02,
+];
+
+fn time(rom: &[u8], jit: bool) -> Duration {
+    let mut memory = Memory::with_size(Variant::Chip8.memory_size());
+    memory.load_rom_bytes(rom).unwrap();
+    let mut cpu = CPU::new(
+        memory,
+        Arc::new(Keypad::new()),
+        Quirks::preset(Variant::Chip8),
+    );
+    cpu.set_ips(IPS);
+    cpu.set_jit(jit);
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        // The test ROMs finish by spinning, so faults aren't expected here.
+        cpu.run_frame().unwrap();
+    }
+    start.elapsed()
+}
+
+fn main() {
+    let corax = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/roms/3-corax+.ch8")).unwrap();
+    for (name, rom) in [("arithmetic", ARITHMETIC), ("corax+", &corax[..])] {
+        let interpreted = time(rom, false);
+        let compiled = time(rom, true);
+        println!(
+            "{:<12} interpreted {:>8.1?}  jit {:>8.1?}  {:.2}x",
+            name,
+            interpreted,
+            compiled,
+            interpreted.as_secs_f64() / compiled.as_secs_f64()
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Runs arbitrary bytes as a ROM, which may fault but must never panic. The first byte picks the
+//! variant, quirks and what happens to stray memory accesses, and the second and third which keys
+//! are held; the rest is the ROM.
+//!
+//! `cargo +nightly fuzz run run_rom`, from the repository root.
+#![no_main]
+
+use chip8::{AccessPolicy, Emulator, Quirks, Variant};
+use libfuzzer_sys::fuzz_target;
+
+/// Enough instructions for a ROM to set up I and draw, loop or call its way into trouble.
+const STEPS: usize = 2000;
+
+fuzz_target!(|data: &[u8]| {
+    let [config, keys_hi, keys_lo, rom @ ..] = data else {
+        return;
+    };
+    let variant = match config & 0b11 {
+        0 => Variant::Chip8,
+        1 => Variant::Schip,
+        2 => Variant::XoChip,
+        _ => Variant::Chip48,
+    };
+    let bit = |n: u8| config & (1 << n) != 0;
+    let quirks = Quirks {
+        shift: bit(2),
+        jump: bit(3),
+        loadstore: bit(4),
+        loadstore_x: variant == Variant::Chip48,
+        display_wait: bit(5),
+        vf_reset: bit(6),
+        clip: bit(7),
+    };
+    let Ok(emulator) = Emulator::builder()
+        .rom_bytes(rom)
+        .variant(variant)
+        .quirks(quirks)
+        .build()
+    else {
+        return;
+    };
+    let mut cpu = emulator.into_cpu();
+    // Run each ROM under every policy: wrapping and clamping reach code that erroring stops short
+    // of.
+    cpu.memory.set_policy(match keys_lo % 3 {
+        0 => AccessPolicy::Error,
+        1 => AccessPolicy::Wrap,
+        _ => AccessPolicy::Clamp,
+    });
+    cpu.keypad()
+        .set_state(u16::from_be_bytes([*keys_hi, *keys_lo]));
+    for _ in 0..STEPS {
+        if cpu.step().is_err() || cpu.has_exited() {
+            break;
+        }
+    }
+    // Timers and the display wait only move on at frame boundaries.
+    for _ in 0..3 {
+        if cpu.run_frame().is_err() {
+            break;
+        }
+    }
+});
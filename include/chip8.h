@@ -0,0 +1,91 @@
+/* Generated from src/ffi.rs by cbindgen. Don't edit. */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The instruction set a ROM was written for.
+typedef enum Chip8Variant {
+  CHIP8_VARIANT_CHIP8,
+  CHIP8_VARIANT_SCHIP,
+  CHIP8_VARIANT_XO_CHIP,
+  CHIP8_VARIANT_CHIP48,
+} Chip8Variant;
+
+// An emulator, opaque to C.
+typedef struct Chip8 Chip8;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// A new emulator with nothing loaded, its random number generator seeded with `seed`. Free it
+// with `chip8_free`.
+struct Chip8 *chip8_new(uint32_t seed);
+
+// Resets the machine and loads the `len` bytes at `rom` to run as `variant`, with that variant's
+// quirks. Fails if the ROM doesn't fit in memory.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`, and `rom` must point to `len` readable bytes.
+int32_t chip8_load_rom(struct Chip8 *chip8,
+                       const uint8_t *rom,
+                       size_t len,
+                       enum Chip8Variant variant);
+
+// Runs one 60Hz frame. Fails once the ROM faults.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+int32_t chip8_step_frame(struct Chip8 *chip8);
+
+// The display: `width * height` bytes, row-major, non-zero for lit pixels, bit 0 for the first
+// XO-CHIP plane and bit 1 for the second. Writes the size to `width` and `height`, which can be
+// null. Only valid until the next call that takes `chip8`.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`, and `width` and `height` must each be null or writable.
+const uint8_t *chip8_framebuffer(const struct Chip8 *chip8, size_t *width, size_t *height);
+
+// Presses or releases hex keypad key `key`.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+void chip8_key_event(const struct Chip8 *chip8, uint8_t key, bool pressed);
+
+// Whether the sound timer is running, for the frontend to start or stop a tone.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+bool chip8_beeping(const struct Chip8 *chip8);
+
+// Why the last call that failed did, or null if the last call succeeded. Only valid until the
+// next call that takes `chip8`.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new`.
+const char *chip8_last_error(const struct Chip8 *chip8);
+
+// Frees an emulator from `chip8_new`. Does nothing with null.
+//
+// # Safety
+//
+// `chip8` must come from `chip8_new` and not have been freed already.
+void chip8_free(struct Chip8 *chip8);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHIP8_H */
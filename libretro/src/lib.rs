@@ -0,0 +1,466 @@
+//! A libretro core, for running ROMs in RetroArch and other libretro frontends. Build with
+//! `cargo build --release -p chip8-libretro` and load `libchip8_libretro.so` (or `.dylib`, or
+//! `.dll`) from `target/release`.
+//!
+//! The frontend calls `retro_run` sixty times a second: each call polls input into the keypad,
+//! runs a frame, and hands back the display and a frame's worth of the tone. The variant and
+//! quirks come from the ROM database when it knows the ROM, and from what the ROM's instructions
+//! look like otherwise.
+#![allow(clippy::missing_safety_doc)]
+
+mod sys;
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_uint, c_void};
+use std::ptr;
+
+use chip8::audio::ToneGenerator;
+use chip8::disasm::Analysis;
+use chip8::{Emulator, Keymap, RomInfo, SaveState, Theme};
+use sys::*;
+
+const FPS: f64 = 60.0;
+const SAMPLE_RATE: f64 = 44100.0;
+/// Stereo frames of audio per video frame.
+const AUDIO_FRAMES: usize = (SAMPLE_RATE / FPS) as usize;
+const VOLUME: f32 = 0.25;
+/// The biggest the display gets, in SUPER-CHIP and XO-CHIP's high resolution mode.
+const MAX_WIDTH: usize = 128;
+const MAX_HEIGHT: usize = 64;
+
+/// Which button on a RetroPad presses which keypad key. The d-pad is on 2, 4, 6 and 8, which most
+/// games move with, and A on 5, which many fire with.
+const JOYPAD: [(c_uint, u8); 16] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x1),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0x3),
+    (RETRO_DEVICE_ID_JOYPAD_L, 0x7),
+    (RETRO_DEVICE_ID_JOYPAD_R, 0x9),
+    (RETRO_DEVICE_ID_JOYPAD_L2, 0xa),
+    (RETRO_DEVICE_ID_JOYPAD_R2, 0xb),
+    (RETRO_DEVICE_ID_JOYPAD_L3, 0xc),
+    (RETRO_DEVICE_ID_JOYPAD_R3, 0xd),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, 0xe),
+    (RETRO_DEVICE_ID_JOYPAD_START, 0xf),
+];
+
+/// The callbacks the frontend has given us, and the game it's running.
+#[derive(Default)]
+struct Core {
+    environment: Option<retro_environment_t>,
+    video_refresh: Option<retro_video_refresh_t>,
+    audio_sample_batch: Option<retro_audio_sample_batch_t>,
+    input_poll: Option<retro_input_poll_t>,
+    input_state: Option<retro_input_state_t>,
+    game: Option<Game>,
+}
+
+struct Game {
+    emulator: Emulator,
+    /// Set once the ROM faults, after which the last frame stays on screen.
+    halted: bool,
+    tone: ToneGenerator,
+    video: Vec<u32>,
+    audio: Vec<i16>,
+}
+
+impl Game {
+    fn new(emulator: Emulator) -> Self {
+        Self {
+            emulator,
+            halted: false,
+            tone: ToneGenerator::new(),
+            video: Vec::with_capacity(MAX_WIDTH * MAX_HEIGHT),
+            audio: Vec::with_capacity(AUDIO_FRAMES * 2),
+        }
+    }
+
+    /// The keypad keys held down on the first RetroPad or the keyboard, as a bitmask.
+    unsafe fn read_keys(input_state: retro_input_state_t) -> u16 {
+        let keymap = Keymap::default();
+        (0..16u8)
+            .filter(|&key| {
+                let (button, _) = JOYPAD.iter().find(|(_, k)| *k == key).unwrap();
+                // libretro's key codes for letters and digits are their lowercase ASCII.
+                let retrok = keymap.char_for(key) as c_uint;
+                input_state(0, RETRO_DEVICE_JOYPAD, 0, *button) != 0
+                    || input_state(0, RETRO_DEVICE_KEYBOARD, 0, retrok) != 0
+            })
+            .fold(0, |keys, key| keys | 1 << key)
+    }
+
+    /// The display as XRGB8888, and its width and height.
+    fn render(&mut self) -> (usize, usize) {
+        let fb = self.emulator.cpu().framebuffer();
+        let theme = Theme::DEFAULT;
+        let xrgb = |c: chip8::Color| (c.r as u32) << 16 | (c.g as u32) << 8 | c.b as u32;
+        let colors = theme.colors().map(xrgb);
+        self.video.clear();
+        self.video
+            .extend(fb.pixels().iter().map(|&pixel| colors[pixel as usize & 3]));
+        (fb.width(), fb.height())
+    }
+
+    /// A frame's worth of the tone, as interleaved stereo.
+    fn synthesize(&mut self) {
+        let cpu = self.emulator.cpu();
+        let (on, pattern) = (cpu.beeping() && !self.halted, cpu.audio());
+        self.audio.clear();
+        for _ in 0..AUDIO_FRAMES {
+            let sample = self
+                .tone
+                .next_sample(on, pattern, SAMPLE_RATE as f32, VOLUME);
+            let sample = (sample * i16::MAX as f32) as i16;
+            self.audio.extend([sample, sample]);
+        }
+    }
+}
+
+thread_local! {
+    // Frontends call into a core from one thread, and the emulator's frontend and beeper hooks
+    // aren't `Send` anyway.
+    static CORE: RefCell<Core> = RefCell::default();
+}
+
+fn with_core<T>(f: impl FnOnce(&mut Core) -> T) -> T {
+    CORE.with(|core| f(&mut core.borrow_mut()))
+}
+
+fn with_game<T>(default: T, f: impl FnOnce(&mut Game) -> T) -> T {
+    with_core(|core| core.game.as_mut().map_or(default, f))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: retro_environment_t) {
+    with_core(|core| core.environment = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: retro_video_refresh_t) {
+    with_core(|core| core.video_refresh = Some(cb));
+}
+
+/// Audio goes to the frontend a frame at a time, through the batch callback.
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: retro_audio_sample_t) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: retro_audio_sample_batch_t) {
+    with_core(|core| core.audio_sample_batch = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: retro_input_poll_t) {
+    with_core(|core| core.input_poll = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: retro_input_state_t) {
+    with_core(|core| core.input_state = Some(cb));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    with_core(|core| core.game = None);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut retro_system_info) {
+    let Some(info) = info.as_mut() else {
+        return;
+    };
+    *info = retro_system_info {
+        library_name: c"chip8-rs".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"ch8|c8|sc8|xo8".as_ptr(),
+        // ROMs are loaded from memory, so they can come out of zip files too.
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut retro_system_av_info) {
+    let Some(info) = info.as_mut() else {
+        return;
+    };
+    let (width, height) = with_game((64, 32), |game| {
+        let cpu = game.emulator.cpu();
+        (cpu.display_width(), cpu.display_height())
+    });
+    *info = retro_system_av_info {
+        geometry: retro_game_geometry {
+            base_width: width as c_uint,
+            base_height: height as c_uint,
+            max_width: MAX_WIDTH as c_uint,
+            max_height: MAX_HEIGHT as c_uint,
+            // Square pixels, at whatever shape the display is: 2:1, or 1:1 for two-page HIRES.
+            aspect_ratio: width as f32 / height as f32,
+        },
+        timing: retro_system_timing {
+            fps: FPS,
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+/// Only the first RetroPad and the keyboard are read.
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_game((), |game| {
+        game.emulator.cpu_mut().reset();
+        game.halted = false;
+    });
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    with_core(|core| {
+        let Some(game) = &mut core.game else {
+            return;
+        };
+        if let Some(poll) = core.input_poll {
+            poll();
+        }
+        if let Some(input_state) = core.input_state {
+            game.emulator
+                .keypad()
+                .set_state(Game::read_keys(input_state));
+        }
+        if !game.halted && game.emulator.run_frame().is_err() {
+            game.halted = true;
+        }
+
+        let (width, height) = game.render();
+        if let Some(video_refresh) = core.video_refresh {
+            video_refresh(
+                game.video.as_ptr() as *const c_void,
+                width as c_uint,
+                height as c_uint,
+                width * 4,
+            );
+        }
+        game.synthesize();
+        if let Some(audio_sample_batch) = core.audio_sample_batch {
+            audio_sample_batch(game.audio.as_ptr(), AUDIO_FRAMES);
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_game(0, |game| game.emulator.cpu().save_state().to_bytes().len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_game(false, |game| {
+        let state = game.emulator.cpu().save_state().to_bytes();
+        if data.is_null() || size < state.len() {
+            return false;
+        }
+        ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let bytes = std::slice::from_raw_parts(data as *const u8, size);
+    with_game(false, |game| {
+        let cpu = game.emulator.cpu_mut();
+        match SaveState::from_bytes(bytes) {
+            Ok(state) if state.variant() == cpu.variant() => {
+                cpu.load_state(&state);
+                game.halted = false;
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(info: *const retro_game_info) -> bool {
+    let Some(info) = info.as_ref() else {
+        return false;
+    };
+    let rom = if info.size == 0 {
+        &[][..]
+    } else if info.data.is_null() {
+        return false;
+    } else {
+        std::slice::from_raw_parts(info.data as *const u8, info.size)
+    };
+
+    let mut builder = Emulator::builder().rom_bytes(rom);
+    builder = match RomInfo::lookup(&chip8::rom_sha1(rom)) {
+        Some(known) => {
+            let builder = builder.variant(known.variant).quirks(known.quirks);
+            match known.ips {
+                Some(ips) => builder.ips(ips),
+                None => builder,
+            }
+        }
+        None => builder.variant(Analysis::new(rom).variant),
+    };
+    let Ok(emulator) = builder.build() else {
+        return false;
+    };
+
+    with_core(|core| {
+        if let Some(environment) = core.environment {
+            let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+            if !environment(
+                RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+                &mut format as *mut _ as *mut c_void,
+            ) {
+                return false;
+            }
+        }
+        core.game = Some(Game::new(emulator));
+        true
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const retro_game_info,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    with_core(|core| core.game = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+/// The machine's RAM, for cheat searches and achievements.
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return ptr::null_mut();
+    }
+    with_game(ptr::null_mut(), |game| {
+        game.emulator.cpu_mut().memory.as_mut_ptr() as *mut c_void
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return 0;
+    }
+    with_game(0, |game| game.emulator.cpu().memory.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static FRAME: RefCell<(Vec<u32>, usize, usize)> = RefCell::default();
+        static AUDIO_FRAMES_SENT: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe extern "C" fn video_refresh(
+        data: *const c_void,
+        width: c_uint,
+        height: c_uint,
+        _: usize,
+    ) {
+        let pixels = std::slice::from_raw_parts(data as *const u32, (width * height) as usize);
+        FRAME.with(|frame| *frame.borrow_mut() = (pixels.to_vec(), width as _, height as _));
+    }
+
+    unsafe extern "C" fn audio_sample_batch(_: *const i16, frames: usize) -> usize {
+        AUDIO_FRAMES_SENT.with(|sent| sent.set(sent.get() + frames));
+        frames
+    }
+
+    unsafe extern "C" fn input_state(_: c_uint, device: c_uint, _: c_uint, id: c_uint) -> i16 {
+        (device == RETRO_DEVICE_JOYPAD && id == RETRO_DEVICE_ID_JOYPAD_A) as i16
+    }
+
+    #[test]
+    fn runs_a_game_for_the_frontend() {
+        // ld v1, 5; wait for key 5 to be down; draw its glyph at the top left; spin
+        let rom = [
+            0x61u8, 0x05, 0xe1, 0x9e, 0x12, 0x02, 0xf1, 0x29, 0xd0, 0x05, 0x12, 0x0a,
+        ];
+        let info = retro_game_info {
+            path: ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: ptr::null(),
+        };
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_state(input_state);
+        unsafe {
+            assert!(retro_load_game(&info));
+            retro_run();
+            retro_run();
+        }
+        assert_eq!(retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM), 4096);
+        assert_eq!(AUDIO_FRAMES_SENT.with(Cell::get), AUDIO_FRAMES * 2);
+
+        FRAME.with(|frame| {
+            let (pixels, width, height) = &*frame.borrow();
+            assert_eq!((*width, *height), (64, 32));
+            let lit = |x: usize, y: usize| pixels[y * width + x] != 0;
+            // The 5 glyph's top row is four pixels wide, and its second only the first.
+            assert!((0..4).all(|x| lit(x, 0)) && !lit(4, 0));
+            assert!(lit(0, 1) && !lit(1, 1));
+        });
+
+        let state_size = retro_serialize_size();
+        let mut state = vec![0u8; state_size];
+        unsafe {
+            assert!(retro_serialize(
+                state.as_mut_ptr() as *mut c_void,
+                state_size
+            ));
+            retro_reset();
+            assert!(retro_unserialize(
+                state.as_ptr() as *const c_void,
+                state_size
+            ));
+        }
+        retro_unload_game();
+        assert_eq!(retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM), 0);
+    }
+}
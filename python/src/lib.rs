@@ -0,0 +1,185 @@
+//! Python bindings, for scripting the emulator from notebooks: stepping through a ROM an
+//! instruction at a time, poking at the keypad and registers, and plotting the display with
+//! numpy. Build and install into the current virtualenv with `maturin develop -m python/Cargo.toml`.
+//!
+//! ```python
+//! import chip8
+//!
+//! emu = chip8.Chip8(open("pong.ch8", "rb").read())
+//! emu.keys[1] = True
+//! emu.frame()
+//! print(emu.registers["pc"])
+//! plt.imshow(emu.framebuffer_as_numpy())
+//! ```
+use std::sync::Arc;
+
+use chip8::{Emulator, Keypad, Rng, Variant};
+use numpy::{PyArray1, PyArray2, PyArrayMethods};
+use pyo3::exceptions::{PyIndexError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+/// An emulator with a ROM loaded.
+#[pyclass(unsendable, module = "chip8")]
+pub struct Chip8 {
+    emulator: Emulator,
+}
+
+#[pymethods]
+impl Chip8 {
+    /// Loads `rom` to run as `variant` (chip8, chip48, schip or xochip), with that variant's
+    /// quirks. The random number generator is seeded from the clock unless there's a `seed`.
+    #[new]
+    #[pyo3(signature = (rom, variant = "chip8", seed = None))]
+    fn new(rom: &[u8], variant: &str, seed: Option<u32>) -> PyResult<Self> {
+        let variant: Variant = variant.parse().map_err(PyValueError::new_err)?;
+        let mut builder = Emulator::builder().rom_bytes(rom).variant(variant);
+        if let Some(seed) = seed {
+            builder = builder.rng(Rng::new(seed));
+        }
+        let emulator = builder
+            .build()
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(Self { emulator })
+    }
+
+    /// Runs one instruction. The timers only count down between frames, so a ROM waiting on
+    /// the delay timer needs `frame` instead.
+    fn step(&mut self) -> PyResult<()> {
+        self.emulator
+            .cpu_mut()
+            .step()
+            .map_err(|err| PyRuntimeError::new_err(err.to_string()))
+    }
+
+    /// Runs one 60Hz frame: a tick of the timers, then a frame's worth of instructions.
+    fn frame(&mut self) -> PyResult<()> {
+        self.emulator
+            .run_frame()
+            .map_err(|err| PyRuntimeError::new_err(err.to_string()))
+    }
+
+    /// The keypad, indexed by key: `emu.keys[5] = True` holds down 5 until it's set back to
+    /// `False`.
+    #[getter]
+    fn keys(&self) -> Keys {
+        Keys {
+            keypad: self.emulator.keypad().clone(),
+        }
+    }
+
+    /// The registers as of now: `v` (a list of all 16), `i`, `pc`, `sp`, `delay`, `sound` and
+    /// `stack`, the return addresses on the stack, oldest first. Changing them changes nothing.
+    #[getter]
+    fn registers<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let snapshot = self.emulator.cpu().snapshot();
+        let registers = PyDict::new(py);
+        // As a list of ints rather than `bytes`, which is how a Vec<u8> would come out.
+        registers.set_item("v", snapshot.v.map(u16::from).to_vec())?;
+        registers.set_item("i", snapshot.i)?;
+        registers.set_item("pc", snapshot.pc)?;
+        registers.set_item("sp", snapshot.sp)?;
+        registers.set_item("delay", snapshot.delay)?;
+        registers.set_item("sound", snapshot.sound)?;
+        registers.set_item("stack", snapshot.call_stack().to_vec())?;
+        Ok(registers)
+    }
+
+    /// A copy of the display as a height by width array of `uint8`, non-zero for lit pixels:
+    /// bit 0 for the first XO-CHIP plane and bit 1 for the second.
+    fn framebuffer_as_numpy<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray2<u8>>> {
+        let fb = self.emulator.cpu().framebuffer();
+        PyArray1::from_slice(py, fb.pixels()).reshape([fb.height(), fb.width()])
+    }
+}
+
+/// The keypad of a `Chip8`, as 16 booleans.
+#[pyclass(module = "chip8")]
+pub struct Keys {
+    keypad: Arc<Keypad>,
+}
+
+impl Keys {
+    fn check(key: usize) -> PyResult<u8> {
+        if key < 16 {
+            Ok(key as u8)
+        } else {
+            Err(PyIndexError::new_err(format!(
+                "keys go from 0 to 15, not {}",
+                key
+            )))
+        }
+    }
+}
+
+#[pymethods]
+impl Keys {
+    fn __len__(&self) -> usize {
+        16
+    }
+
+    fn __getitem__(&self, key: usize) -> PyResult<bool> {
+        Ok(self.keypad.is_pressed(Self::check(key)?))
+    }
+
+    fn __setitem__(&self, key: usize, pressed: bool) -> PyResult<()> {
+        let key = Self::check(key)?;
+        if pressed {
+            self.keypad.press(key);
+        } else {
+            self.keypad.release(key);
+        }
+        Ok(())
+    }
+
+    /// The keys held down, e.g. `Keys([1, 12])`.
+    fn __repr__(&self) -> String {
+        let held: Vec<_> = (0..16)
+            .filter(|&key| self.keypad.is_pressed(key))
+            .map(|key| key.to_string())
+            .collect();
+        format!("Keys([{}])", held.join(", "))
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "chip8")]
+fn chip8_py(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Chip8>()?;
+    module.add_class::<Keys>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_the_emulator() {
+        // ld v0, 0x42; skp v0 (key 2); jp 0x202; ld va, 1; jp 0x208
+        let rom = [0x60, 0x42, 0xe0, 0x9e, 0x12, 0x02, 0x6a, 0x01, 0x12, 0x08];
+        Python::with_gil(|py| {
+            let emu = Py::new(py, Chip8::new(&rom, "chip8", Some(1)).unwrap()).unwrap();
+            pyo3::py_run!(
+                py,
+                emu,
+                r#"
+emu.step()
+regs = emu.registers
+assert regs["v"] == [0x42] + [0] * 15 and regs["pc"] == 0x202, regs
+emu.frame()
+assert emu.registers["v"][0xa] == 0
+emu.keys[2] = True
+assert repr(emu.keys) == "Keys([2])"
+emu.frame()
+assert emu.registers["v"][0xa] == 1
+try:
+    emu.keys[16]
+    assert False
+except IndexError:
+    pass
+"#
+            );
+        });
+    }
+}
@@ -0,0 +1,454 @@
+//! An assembler for the dialect `disasm` prints: Cowgod's mnemonics, plus labels, constants and
+//! data directives.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! speed = 2              ; a constant
+//! start:                 ; a label
+//!     ld i, ship
+//!     ld v0, speed + 1   ; operands can add and subtract numbers, labels and constants
+//!     drw v0, v1, 3
+//!     jp start
+//! ship:
+//!     db 0x18, 0x3c, 0b01111110
+//!     dw 0x1234
+//! ```
+//!
+//! Numbers can be decimal, hex (`0x1f` or `#1f`) or binary (`0b101`). Programs are assembled to
+//! run from 0x200.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::disasm::Instr;
+use crate::Symbols;
+
+/// Where programs are loaded, and so where the first byte of output ends up.
+pub const ORIGIN: u16 = 0x200;
+
+/// A problem with the source, and the (1-based) line it's on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AsmError {}
+
+/// A line of source, minus its label and comment.
+enum Statement<'a> {
+    Constant(&'a str, &'a str),
+    Bytes(Vec<&'a str>),
+    Words(Vec<&'a str>),
+    Instruction(&'a str, Vec<&'a str>),
+}
+
+impl Statement<'_> {
+    /// How many bytes this assembles to.
+    fn size(&self) -> u16 {
+        match self {
+            Statement::Constant(..) => 0,
+            Statement::Bytes(values) => values.len() as u16,
+            Statement::Words(values) => values.len() as u16 * 2,
+            Statement::Instruction(_, operands) if is_long(operands) => 4,
+            #[cfg(feature = "megachip")]
+            Statement::Instruction(mnemonic, _) if mnemonic.eq_ignore_ascii_case("ldhi") => 4,
+            Statement::Instruction(..) => 2,
+        }
+    }
+}
+
+/// Whether these are the operands of XO-CHIP's `ld i, long nnnn`.
+fn is_long(operands: &[&str]) -> bool {
+    match operands {
+        [i, long] => i.eq_ignore_ascii_case("i") && long.to_ascii_lowercase().starts_with("long "),
+        _ => false,
+    }
+}
+
+/// Assembles `source` into a ROM.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    assemble_with_symbols(source).map(|(rom, _)| rom)
+}
+
+/// Assembles `source` into a ROM, along with the addresses of its labels for the debugger.
+pub fn assemble_with_symbols(source: &str) -> Result<(Vec<u8>, Symbols), AsmError> {
+    // First pass: find where everything goes, so labels can be used before they're defined.
+    let mut symbols = HashMap::new();
+    let mut labels = Symbols::new();
+    let mut statements = Vec::new();
+    let mut addr = ORIGIN as u32;
+    for (i, line) in source.lines().enumerate() {
+        let error = |message: String| AsmError {
+            line: i + 1,
+            message,
+        };
+        let mut line = line.split(';').next().unwrap_or_default().trim();
+
+        if let Some((label, rest)) = line.split_once(':') {
+            let label = label.trim();
+            if !is_symbol(label) {
+                return Err(error(format!("'{}' isn't a valid label", label)));
+            }
+            if symbols.insert(label, addr as i64).is_some() {
+                return Err(error(format!("'{}' is defined more than once", label)));
+            }
+            labels.insert(addr as u16, label);
+            line = rest.trim();
+        }
+        if line.is_empty() {
+            continue;
+        }
+
+        let statement = parse_statement(line).map_err(error)?;
+        addr += statement.size() as u32;
+        if addr > 0x10000 {
+            return Err(error("program doesn't fit in memory".to_string()));
+        }
+        statements.push((i + 1, statement));
+    }
+
+    // Constants can refer to labels and to each other, in the order they're defined.
+    for (line, statement) in &statements {
+        if let Statement::Constant(name, value) = statement {
+            let value = evaluate(value, &symbols).map_err(|message| AsmError {
+                line: *line,
+                message,
+            })?;
+            if symbols.insert(name, value).is_some() {
+                return Err(AsmError {
+                    line: *line,
+                    message: format!("'{}' is defined more than once", name),
+                });
+            }
+        }
+    }
+
+    // Second pass: emit everything.
+    let mut rom = Vec::new();
+    for (line, statement) in &statements {
+        emit(statement, &symbols, &mut rom).map_err(|message| AsmError {
+            line: *line,
+            message,
+        })?;
+    }
+    Ok((rom, labels))
+}
+
+fn parse_statement(line: &str) -> Result<Statement<'_>, String> {
+    if let Some((name, value)) = line.split_once('=') {
+        let name = name.trim();
+        if !is_symbol(name) {
+            return Err(format!("'{}' isn't a valid constant name", name));
+        }
+        return Ok(Statement::Constant(name, value.trim()));
+    }
+
+    let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let operands: Vec<&str> = if rest.trim().is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',').map(str::trim).collect()
+    };
+    Ok(match mnemonic.to_ascii_lowercase().as_str() {
+        "db" => Statement::Bytes(operands),
+        "dw" => Statement::Words(operands),
+        _ => Statement::Instruction(mnemonic, operands),
+    })
+}
+
+fn is_symbol(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn emit(
+    statement: &Statement,
+    symbols: &HashMap<&str, i64>,
+    rom: &mut Vec<u8>,
+) -> Result<(), String> {
+    match statement {
+        Statement::Constant(..) => {}
+        Statement::Bytes(values) => {
+            for value in values {
+                rom.push(ranged(evaluate(value, symbols)?, -0x80, 0xff, value)? as u8);
+            }
+        }
+        Statement::Words(values) => {
+            for value in values {
+                let word = ranged(evaluate(value, symbols)?, -0x8000, 0xffff, value)? as u16;
+                rom.extend_from_slice(&word.to_be_bytes());
+            }
+        }
+        Statement::Instruction(mnemonic, operands) => {
+            let instr = parse_instr(mnemonic, operands, symbols)?;
+            rom.extend_from_slice(&instr.encode().to_be_bytes());
+            if let (Instr::LoadILong, [_, long]) = (instr, operands.as_slice()) {
+                let value = long[4..].trim();
+                let addr = ranged(evaluate(value, symbols)?, 0, 0xffff, value)? as u16;
+                rom.extend_from_slice(&addr.to_be_bytes());
+            }
+            #[cfg(feature = "megachip")]
+            if let (Instr::LoadIHigh(_), [_, addr]) = (instr, operands.as_slice()) {
+                let addr = ranged(evaluate(addr, symbols)?, 0, 0xff_ffff, addr)? as u16;
+                rom.extend_from_slice(&addr.to_be_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates a sum like `label + 2 - offset`.
+fn evaluate(expr: &str, symbols: &HashMap<&str, i64>) -> Result<i64, String> {
+    let mut total = 0i64;
+    let mut sign = 1;
+    let mut rest = expr.trim();
+    if rest.is_empty() {
+        return Err("missing value".to_string());
+    }
+    loop {
+        let end = rest
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| c == '+' || c == '-')
+            .map_or(rest.len(), |(i, _)| i);
+        let (term, tail) = rest.split_at(end);
+        let term = term.trim();
+        let (term_sign, term) = match term.strip_prefix('-') {
+            Some(term) => (-1, term.trim()),
+            None => (1, term.strip_prefix('+').unwrap_or(term).trim()),
+        };
+        total += sign * term_sign * value(term, symbols)?;
+
+        rest = tail.trim();
+        if rest.is_empty() {
+            return Ok(total);
+        }
+        sign = if rest.starts_with('-') { -1 } else { 1 };
+        rest = rest[1..].trim();
+        if rest.is_empty() {
+            return Err(format!("'{}' ends with an operator", expr));
+        }
+    }
+}
+
+fn value(term: &str, symbols: &HashMap<&str, i64>) -> Result<i64, String> {
+    let lower = term.to_ascii_lowercase();
+    let parsed = if let Some(hex) = lower.strip_prefix("0x").or(lower.strip_prefix('#')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()
+    } else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+        lower.parse().ok()
+    } else {
+        symbols.get(term).copied()
+    };
+    parsed.ok_or_else(|| {
+        if is_symbol(term) {
+            format!("'{}' isn't defined", term)
+        } else {
+            format!("'{}' isn't a number", term)
+        }
+    })
+}
+
+fn ranged(value: i64, min: i64, max: i64, expr: &str) -> Result<i64, String> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("'{}' is out of range ({})", expr, value))
+    }
+}
+
+fn register(operand: &str) -> Option<u8> {
+    let digits = operand.strip_prefix(['v', 'V'])?;
+    if digits.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digits, 16).ok()
+}
+
+fn parse_instr(
+    mnemonic: &str,
+    operands: &[&str],
+    symbols: &HashMap<&str, i64>,
+) -> Result<Instr, String> {
+    use Instr::*;
+
+    let mnemonic = mnemonic.to_ascii_lowercase();
+    let lower: Vec<String> = operands.iter().map(|o| o.to_ascii_lowercase()).collect();
+    let lower: Vec<&str> = lower.iter().map(String::as_str).collect();
+    let reg = |i: usize| register(operands[i]).ok_or(format!("'{}' isn't a register", operands[i]));
+    let num = |i: usize, max: i64| ranged(evaluate(operands[i], symbols)?, 0, max, operands[i]);
+    let addr = |i: usize| num(i, 0xfff).map(|n| n as u16);
+    let byte = |i: usize| num(i, 0xff).map(|n| n as u8);
+    let nibble = |i: usize| num(i, 0xf).map(|n| n as u8);
+    let is_reg = |i: usize| register(operands[i]).is_some();
+    // `save vx - vy` and `load vx - vy` take a range rather than two operands.
+    let range = || -> Result<(u8, u8), String> {
+        let (x, y) = match operands {
+            [range] => range
+                .split_once('-')
+                .ok_or(format!("'{}' isn't a register range", range))?,
+            [x, y] => (*x, *y),
+            _ => return Err(format!("{} takes a register range like v1 - v3", mnemonic)),
+        };
+        match (register(x.trim()), register(y.trim())) {
+            (Some(x), Some(y)) => Ok((x, y)),
+            _ => Err(format!("'{}' isn't a register range", operands.join(", "))),
+        }
+    };
+
+    Ok(match (mnemonic.as_str(), lower.as_slice()) {
+        ("cls", []) => Cls,
+        ("ret", []) => Ret,
+        ("scd", [_]) => ScrollDown(nibble(0)?),
+        ("scu", [_]) => ScrollUp(nibble(0)?),
+        ("scr", []) => ScrollRight,
+        ("scl", []) => ScrollLeft,
+        ("exit", []) => Exit,
+        ("low", []) => Low,
+        ("high", []) => High,
+        ("hcls", []) => HiresCls,
+        ("jp", ["v0", _]) => JumpV0(addr(1)?),
+        ("jp", [_]) => Jump(addr(0)?),
+        ("call", [_]) => Call(addr(0)?),
+        ("se", [_, _]) if is_reg(1) => SkipEqReg(reg(0)?, reg(1)?),
+        ("se", [_, _]) => SkipEqByte(reg(0)?, byte(1)?),
+        ("sne", [_, _]) if is_reg(1) => SkipNeReg(reg(0)?, reg(1)?),
+        ("sne", [_, _]) => SkipNeByte(reg(0)?, byte(1)?),
+        ("save", _) => range().map(|(x, y)| SaveRange(x, y))?,
+        ("load", _) => range().map(|(x, y)| LoadRange(x, y))?,
+        ("ld", ["i", _]) if is_long(operands) => LoadILong,
+        ("ld", ["i", _]) => LoadI(addr(1)?),
+        ("ld", [_, "dt"]) => GetDelay(reg(0)?),
+        ("ld", [_, "k"]) => WaitKey(reg(0)?),
+        ("ld", ["dt", _]) => SetDelay(reg(1)?),
+        ("ld", ["st", _]) => SetSound(reg(1)?),
+        ("ld", ["f", _]) => Font(reg(1)?),
+        ("ld", ["hf", _]) => BigFont(reg(1)?),
+        ("ld", ["b", _]) => Bcd(reg(1)?),
+        ("ld", ["pitch", _]) => Pitch(reg(1)?),
+        ("ld", ["[i]", _]) => Store(reg(1)?),
+        ("ld", [_, "[i]"]) => Restore(reg(0)?),
+        ("ld", ["r", _]) => SaveFlags(reg(1)?),
+        ("ld", [_, "r"]) => LoadFlags(reg(0)?),
+        ("ld", [_, _]) if is_reg(1) => Move(reg(0)?, reg(1)?),
+        ("ld", [_, _]) => LoadByte(reg(0)?, byte(1)?),
+        ("add", ["i", _]) => AddI(reg(1)?),
+        ("add", [_, _]) if is_reg(1) => Add(reg(0)?, reg(1)?),
+        ("add", [_, _]) => AddByte(reg(0)?, byte(1)?),
+        ("or", [_, _]) => Or(reg(0)?, reg(1)?),
+        ("and", [_, _]) => And(reg(0)?, reg(1)?),
+        ("xor", [_, _]) => Xor(reg(0)?, reg(1)?),
+        ("sub", [_, _]) => Sub(reg(0)?, reg(1)?),
+        ("subn", [_, _]) => SubN(reg(0)?, reg(1)?),
+        // With only one operand, shift Vx itself whatever the shift quirk says.
+        ("shr", [_]) => Shr(reg(0)?, reg(0)?),
+        ("shr", [_, _]) => Shr(reg(0)?, reg(1)?),
+        ("shl", [_]) => Shl(reg(0)?, reg(0)?),
+        ("shl", [_, _]) => Shl(reg(0)?, reg(1)?),
+        ("rnd", [_, _]) => Rand(reg(0)?, byte(1)?),
+        ("drw", [_, _, _]) => Draw(reg(0)?, reg(1)?, nibble(2)?),
+        ("skp", [_]) => SkipKey(reg(0)?),
+        ("sknp", [_]) => SkipNotKey(reg(0)?),
+        ("plane", [_]) => Plane(num(0, 3)? as u8),
+        ("audio", []) => Audio,
+        #[cfg(feature = "megachip")]
+        ("megaoff", []) => MegaOff,
+        #[cfg(feature = "megachip")]
+        ("megaon", []) => MegaOn,
+        #[cfg(feature = "megachip")]
+        ("scru", [_]) => MegaScrollUp(nibble(0)?),
+        #[cfg(feature = "megachip")]
+        ("ldhi", ["i", _]) => LoadIHigh((num(1, 0xff_ffff)? >> 16) as u8),
+        #[cfg(feature = "megachip")]
+        ("ldpal", [_]) => LoadPalette(byte(0)?),
+        #[cfg(feature = "megachip")]
+        ("sprw", [_]) => SpriteWidth(byte(0)?),
+        #[cfg(feature = "megachip")]
+        ("sprh", [_]) => SpriteHeight(byte(0)?),
+        #[cfg(feature = "megachip")]
+        ("alpha", [_]) => Alpha(byte(0)?),
+        #[cfg(feature = "megachip")]
+        ("digisnd", [_]) => PlaySound(num(0, 1)? as u8),
+        #[cfg(feature = "megachip")]
+        ("stopsnd", []) => StopSound,
+        #[cfg(feature = "megachip")]
+        ("bmode", [_]) => BlendMode(num(0, 5)? as u8),
+        #[cfg(feature = "megachip")]
+        ("ccol", [_]) => CollisionColor(byte(0)?),
+        _ => {
+            return Err(format!(
+                "unknown instruction '{} {}'",
+                mnemonic,
+                operands.join(", ")
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Variant;
+
+    #[test]
+    fn assembles_labels_constants_and_data() {
+        let source = "
+            speed = 2          ; a constant
+            start:
+                ld i, ship
+                ld v0, speed + 1
+                drw v0, v1, 3
+                jp start
+            ship: db 0x18, #3c, 0b01111110
+                dw end - start
+            end:
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![
+                0xa2, 0x08, 0x60, 0x03, 0xd0, 0x13, 0x12, 0x00, 0x18, 0x3c, 0x7e, 0x00, 0x0d,
+            ])
+        );
+        let (_, symbols) = assemble_with_symbols(source).unwrap();
+        let labels: Vec<_> = symbols.iter().collect();
+        assert_eq!(labels, [(0x200, "start"), (0x208, "ship"), (0x20d, "end")]);
+    }
+
+    #[test]
+    fn reassembles_disassembled_instructions() {
+        for opcode in 0..=0xffff {
+            let Some(instr) = Instr::decode(opcode, Variant::XoChip) else {
+                continue;
+            };
+            let source = match instr {
+                Instr::LoadILong => "ld i, long 0x1234".to_string(),
+                _ => instr.to_string(),
+            };
+            let rom = assemble(&source).unwrap();
+            assert_eq!(u16::from_be_bytes([rom[0], rom[1]]), opcode, "{}", source);
+        }
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = assemble("cls\njp nowhere").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!(error.message, "'nowhere' isn't defined");
+        assert_eq!(assemble("ld v0, 256").unwrap_err().line, 1);
+        assert!(assemble("frobnicate v0").is_err());
+        assert!(assemble("a: cls\na: cls").is_err());
+    }
+}
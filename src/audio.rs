@@ -0,0 +1,332 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Something that can make the CHIP-8's one and only sound: a tone that plays for as long as the
+/// sound timer is non-zero.
+pub trait Beeper {
+    /// Called once per frame with whether the sound timer is currently running.
+    fn set_beeping(&mut self, on: bool);
+
+    /// Called once per frame, before `set_beeping`, with the XO-CHIP pattern to play instead of
+    /// the plain tone, if there is one. Beepers that can only beep ignore it.
+    fn set_pattern(&mut self, _pattern: Option<AudioPattern>) {}
+
+    /// Called once per frame with the MegaChip sound that's playing, if there is one, which plays
+    /// alongside the tone rather than in place of it.
+    fn set_sound(&mut self, _sound: Option<&DigitizedSound>) {}
+
+    /// Whether the frontend should flash the display to show the tone, for beepers that can't
+    /// play it.
+    fn flashing(&self) -> bool {
+        false
+    }
+}
+
+/// XO-CHIP's sound: a loop of 128 1-bit samples, played at a rate set by the pitch register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioPattern {
+    /// One bit per sample, MSB first.
+    pub samples: [u8; 16],
+    pub pitch: u8,
+}
+
+impl AudioPattern {
+    /// The pitch register's value on reset, which plays 4000 samples a second.
+    pub const DEFAULT_PITCH: u8 = 64;
+    /// Samples in the loop.
+    pub const LEN: usize = 128;
+
+    /// Samples a second, 4000 * 2^((pitch - 64) / 48): every 48 steps of pitch is an octave.
+    pub fn sample_rate(&self) -> f32 {
+        4000.0 * 2f32.powf((self.pitch as f32 - 64.0) / 48.0)
+    }
+
+    /// Whether sample `n` of the loop is high, wrapping around past the end.
+    pub fn sample(&self, n: usize) -> bool {
+        let n = n % Self::LEN;
+        self.samples[n / 8] & (0x80 >> (n % 8)) != 0
+    }
+}
+
+/// MegaChip's digitised sound: 8-bit unsigned samples, played at their own rate whatever the
+/// sound timer says.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigitizedSound {
+    pub samples: Arc<[u8]>,
+    /// Samples a second.
+    pub rate: u16,
+    /// Whether it starts over at the end, rather than stopping.
+    pub looping: bool,
+}
+
+/// Pitch of the beep. The VIP's was fixed by hardware, so any pleasant-ish frequency will do.
+const TONE_HZ: f32 = 440.0;
+
+/// Makes the tone a sample at a time, for frontends that fill audio buffers themselves: a square
+/// wave, or XO-CHIP's pattern if the ROM has loaded one. MegaChip's digitised sound is made
+/// separately, to be mixed in.
+#[derive(Debug, Clone, Default)]
+pub struct ToneGenerator {
+    /// How far through the tone's cycle, or the pattern's loop in samples, playback is.
+    phase: f32,
+    /// The digitised sound playing, and how far through it playback is, in samples.
+    sound: Option<(Arc<[u8]>, f32)>,
+}
+
+impl ToneGenerator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next sample for output at `output_rate` samples a second, from -`volume` to
+    /// `volume`. It's silent unless `on`, as from `CPU::beeping`.
+    pub fn next_sample(
+        &mut self,
+        on: bool,
+        pattern: Option<AudioPattern>,
+        output_rate: f32,
+        volume: f32,
+    ) -> f32 {
+        let high = match pattern {
+            Some(pattern) => {
+                let high = pattern.sample(self.phase as usize);
+                self.phase =
+                    (self.phase + pattern.sample_rate() / output_rate) % AudioPattern::LEN as f32;
+                high
+            }
+            None => {
+                let high = self.phase < 0.5;
+                self.phase = (self.phase + TONE_HZ / output_rate) % 1.0;
+                high
+            }
+        };
+        match (on, high) {
+            (false, _) => 0.0,
+            (true, true) => volume,
+            (true, false) => -volume,
+        }
+    }
+
+    /// The next sample of `sound` for output at `output_rate` samples a second, from -`volume`
+    /// to `volume`. A sound other than the one that was playing starts from the beginning.
+    pub fn next_sound_sample(
+        &mut self,
+        sound: Option<&DigitizedSound>,
+        output_rate: f32,
+        volume: f32,
+    ) -> f32 {
+        let Some(sound) = sound else {
+            self.sound = None;
+            return 0.0;
+        };
+        if !matches!(&self.sound, Some((samples, _)) if Arc::ptr_eq(samples, &sound.samples)) {
+            self.sound = Some((sound.samples.clone(), 0.0));
+        }
+        let (samples, position) = self.sound.as_mut().expect("just started");
+        let len = samples.len() as f32;
+        if *position >= len {
+            if !sound.looping || samples.is_empty() {
+                return 0.0;
+            }
+            *position %= len;
+        }
+        let sample = samples[*position as usize];
+        *position += sound.rate as f32 / output_rate;
+        (sample as f32 - 128.0) / 128.0 * volume
+    }
+}
+
+/// How the tone is made, as chosen with `--audio`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AudioMode {
+    /// A square wave on the default output device, or `Visual` if there isn't one.
+    #[default]
+    Cpal,
+    /// The terminal bell, rung each time the tone starts.
+    Terminal,
+    /// The display flashes for as long as the tone plays.
+    Visual,
+    Off,
+}
+
+impl fmt::Display for AudioMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AudioMode::Cpal => "cpal",
+            AudioMode::Terminal => "terminal",
+            AudioMode::Visual => "visual",
+            AudioMode::Off => "off",
+        })
+    }
+}
+
+impl FromStr for AudioMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cpal" => Ok(AudioMode::Cpal),
+            "terminal" | "bell" => Ok(AudioMode::Terminal),
+            "visual" => Ok(AudioMode::Visual),
+            "off" => Ok(AudioMode::Off),
+            _ => Err(format!(
+                "unknown audio mode '{}' (expected cpal, terminal, visual or off)",
+                s
+            )),
+        }
+    }
+}
+
+/// Discards all sound, for headless use or `--mute`.
+pub struct NullBeeper;
+
+impl Beeper for NullBeeper {
+    fn set_beeping(&mut self, _on: bool) {}
+}
+
+/// Rings the terminal bell on stdout each time the tone starts. Terminals don't hold the bell for
+/// as long as it's asked for, so a long beep sounds the same as a short one.
+#[derive(Default)]
+pub struct TerminalBell {
+    on: bool,
+}
+
+impl Beeper for TerminalBell {
+    fn set_beeping(&mut self, on: bool) {
+        if on && !self.on {
+            let mut stdout = std::io::stdout();
+            let _ = stdout.write_all(b"\x07").and_then(|()| stdout.flush());
+        }
+        self.on = on;
+    }
+}
+
+/// Makes no sound, but has the frontend flash the display while the tone would be playing.
+#[derive(Default)]
+pub struct VisualBeeper {
+    on: bool,
+}
+
+impl Beeper for VisualBeeper {
+    fn set_beeping(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    fn flashing(&self) -> bool {
+        self.on
+    }
+}
+
+#[cfg(feature = "audio")]
+pub use square::SquareWaveBeeper;
+
+#[cfg(feature = "audio")]
+mod square {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    };
+
+    use anyhow::{anyhow, Result};
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{FromSample, SampleFormat, SizedSample, Stream, StreamConfig};
+    use tracing::error;
+
+    use super::{AudioPattern, Beeper, DigitizedSound, ToneGenerator};
+
+    /// What the beeper shares with the audio thread.
+    #[derive(Clone, Default)]
+    struct Shared {
+        on: Arc<AtomicBool>,
+        pattern: Arc<Mutex<Option<AudioPattern>>>,
+        sound: Arc<Mutex<Option<DigitizedSound>>>,
+    }
+
+    /// Plays a square wave on the default output device while beeping, or XO-CHIP's pattern if
+    /// the ROM has loaded one, along with any MegaChip sound.
+    pub struct SquareWaveBeeper {
+        shared: Shared,
+        // Dropping the stream stops playback, so it has to live as long as the beeper.
+        _stream: Stream,
+    }
+
+    impl SquareWaveBeeper {
+        /// `volume` ranges from 0.0 (silent) to 1.0 (full scale).
+        pub fn new(volume: f32) -> Result<Self> {
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or_else(|| anyhow!("no audio output device available"))?;
+            let supported = device.default_output_config()?;
+            let format = supported.sample_format();
+            let config: StreamConfig = supported.into();
+            let shared = Shared::default();
+            let volume = volume.clamp(0.0, 1.0);
+
+            let stream = match format {
+                SampleFormat::F32 => build_stream::<f32>(&device, config, volume, shared.clone())?,
+                SampleFormat::I16 => build_stream::<i16>(&device, config, volume, shared.clone())?,
+                SampleFormat::U16 => build_stream::<u16>(&device, config, volume, shared.clone())?,
+                format => return Err(anyhow!("unsupported sample format {}", format)),
+            };
+            stream.play()?;
+
+            Ok(Self {
+                shared,
+                _stream: stream,
+            })
+        }
+    }
+
+    impl Beeper for SquareWaveBeeper {
+        fn set_beeping(&mut self, on: bool) {
+            self.shared.on.store(on, Ordering::Relaxed);
+        }
+
+        fn set_pattern(&mut self, pattern: Option<AudioPattern>) {
+            *self.shared.pattern.lock().unwrap() = pattern;
+        }
+
+        fn set_sound(&mut self, sound: Option<&DigitizedSound>) {
+            let mut playing = self.shared.sound.lock().unwrap();
+            if playing.as_ref() != sound {
+                *playing = sound.cloned();
+            }
+        }
+    }
+
+    fn build_stream<T>(
+        device: &cpal::Device,
+        config: StreamConfig,
+        volume: f32,
+        Shared { on, pattern, sound }: Shared,
+    ) -> Result<Stream>
+    where
+        T: SizedSample + FromSample<f32>,
+    {
+        let channels = config.channels as usize;
+        let output_rate = config.sample_rate as f32;
+        let mut tone = ToneGenerator::new();
+
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let on = on.load(Ordering::Relaxed);
+                let pattern = *pattern.lock().unwrap();
+                let sound = sound.lock().unwrap().clone();
+                for frame in data.chunks_mut(channels) {
+                    let sample = (tone.next_sample(on, pattern, output_rate, volume)
+                        + tone.next_sound_sample(sound.as_ref(), output_rate, volume))
+                    .clamp(-1.0, 1.0);
+                    for out in frame.iter_mut() {
+                        *out = T::from_sample(sample);
+                    }
+                }
+            },
+            |err| error!(target: "audio", "Audio stream error: {}", err),
+            None,
+        )?;
+        Ok(stream)
+    }
+}
@@ -0,0 +1,165 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Watchpoint;
+
+/// A condition that stops execution when it's met.
+///
+/// Address and opcode breakpoints stop just before the matching instruction executes. Register
+/// breakpoints stop just after the instruction that set the register to the value, so that they
+/// don't keep firing for as long as it holds it. Watchpoints stop just after the instruction that
+/// made the access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// The instruction at this address is about to execute.
+    Address(u16),
+    /// The next instruction's opcode, masked with `mask`, equals `pattern`.
+    Opcode { mask: u16, pattern: u16 },
+    /// Vx has just been set to `value`.
+    Register { x: u8, value: u8 },
+    /// An instruction has just accessed watched memory.
+    Watch(Watchpoint),
+}
+
+impl Breakpoint {
+    /// Breaks on opcodes matching a pattern like `dxyn` or `8xy6`: hex digits have to match, and
+    /// any other character matches anything.
+    pub fn opcode_pattern(pattern: &str) -> Option<Self> {
+        if pattern.chars().count() != 4 {
+            return None;
+        }
+        let (mut mask, mut value) = (0, 0);
+        for c in pattern.chars() {
+            mask <<= 4;
+            value <<= 4;
+            if let Some(digit) = c.to_digit(16) {
+                mask |= 0xf;
+                value |= digit as u16;
+            }
+        }
+        Some(Breakpoint::Opcode {
+            mask,
+            pattern: value,
+        })
+    }
+
+    /// Whether this breakpoint stops execution before running `opcode` at `pc`.
+    pub(crate) fn hits_before(&self, pc: u16, opcode: Option<u16>) -> bool {
+        match *self {
+            Breakpoint::Address(addr) => pc == addr,
+            Breakpoint::Opcode { mask, pattern } => opcode.is_some_and(|op| op & mask == pattern),
+            Breakpoint::Register { .. } | Breakpoint::Watch(_) => false,
+        }
+    }
+
+    /// Whether this breakpoint stops execution after an instruction changed the registers from
+    /// `before` to `after`.
+    pub(crate) fn hits_after(&self, before: &[u8; 16], after: &[u8; 16]) -> bool {
+        match *self {
+            Breakpoint::Register { x, value } => {
+                let x = (x & 0xf) as usize;
+                before[x] != value && after[x] == value
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Breakpoint::Address(addr) => write!(f, "{:03x}", addr),
+            Breakpoint::Opcode { mask, pattern } => {
+                if (0..4).all(|n| matches!((mask >> (n * 4)) & 0xf, 0 | 0xf)) {
+                    for n in (0..4).rev() {
+                        if (mask >> (n * 4)) & 0xf == 0 {
+                            f.write_str("x")?;
+                        } else {
+                            write!(f, "{:x}", (pattern >> (n * 4)) & 0xf)?;
+                        }
+                    }
+                    Ok(())
+                } else {
+                    write!(f, "{:04x}&{:04x}", pattern, mask)
+                }
+            }
+            Breakpoint::Register { x, value } => write!(f, "v{:x}={:02x}", x, value),
+            Breakpoint::Watch(watchpoint) => watchpoint.fmt(f),
+        }
+    }
+}
+
+/// Parses the forms `Display` writes: a hex address (`234` or `0x234`), an opcode pattern with `x`
+/// for wildcards (`dxxx`), a register value (`v3=1f`), or a watchpoint (`w:300-30f`).
+impl FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_ascii_lowercase();
+        let hex = |s: &str| u16::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+
+        if s.contains(':') {
+            return s.parse().map(Breakpoint::Watch);
+        }
+        if let Some((register, value)) = s.split_once('=') {
+            let x = register
+                .strip_prefix('v')
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .filter(|&x| x < 16);
+            let value = hex(value).and_then(|value| u8::try_from(value).ok());
+            return match (x, value) {
+                (Some(x), Some(value)) => Ok(Breakpoint::Register { x, value }),
+                _ => Err(format!("'{}' isn't a register breakpoint like v3=1f", s)),
+            };
+        }
+        if !s.starts_with("0x") && s.chars().any(|c| !c.is_ascii_hexdigit()) {
+            return Breakpoint::opcode_pattern(&s)
+                .ok_or_else(|| format!("'{}' isn't a 4 digit opcode pattern like dxyn", s));
+        }
+        hex(&s).map(Breakpoint::Address).ok_or_else(|| {
+            format!(
+                "'{}' isn't an address, opcode pattern, vX=NN or watchpoint",
+                s
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        assert_eq!("0x234".parse(), Ok(Breakpoint::Address(0x234)));
+        assert_eq!("2a4".parse(), Ok(Breakpoint::Address(0x2a4)));
+        assert_eq!(
+            "Dxyn".parse(),
+            Ok(Breakpoint::Opcode {
+                mask: 0xf000,
+                pattern: 0xd000
+            })
+        );
+        assert_eq!(
+            "vA=10".parse(),
+            Ok(Breakpoint::Register {
+                x: 0xa,
+                value: 0x10
+            })
+        );
+        assert!("v3=100".parse::<Breakpoint>().is_err());
+        assert!("fx".parse::<Breakpoint>().is_err());
+        assert!("zzz".parse::<Breakpoint>().is_err());
+        assert_eq!(
+            "8yy6".parse(),
+            Ok(Breakpoint::Opcode {
+                mask: 0xf00f,
+                pattern: 0x8006
+            })
+        );
+
+        for s in ["234", "8xx6", "vf=01", "r:300-30f"] {
+            assert_eq!(s.parse::<Breakpoint>().unwrap().to_string(), s);
+        }
+    }
+}
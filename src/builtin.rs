@@ -0,0 +1,50 @@
+//! ROMs that come with the emulator, so there's something to run without any ROMs to hand: Pong,
+//! and the test suite in `roms/`.
+
+/// A ROM built into the emulator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinRom {
+    /// What `--builtin` knows it as.
+    pub name: &'static str,
+    pub title: &'static str,
+    pub rom: &'static [u8],
+}
+
+impl BuiltinRom {
+    pub fn find(name: &str) -> Option<&'static BuiltinRom> {
+        BUILTIN_ROMS
+            .iter()
+            .find(|rom| rom.name.eq_ignore_ascii_case(name))
+    }
+}
+
+macro_rules! builtin {
+    ($name:literal, $title:literal, $file:literal) => {
+        BuiltinRom {
+            name: $name,
+            title: $title,
+            rom: include_bytes!(concat!("../roms/", $file)),
+        }
+    };
+}
+
+pub const BUILTIN_ROMS: &[BuiltinRom] = &[
+    builtin!("pong", "Pong for two players", "pong.ch8"),
+    builtin!("ibm-logo", "IBM logo", "2-ibm-logo.ch8"),
+    builtin!("chip8-logo", "CHIP-8 splash screen", "1-chip8-logo.ch8"),
+    builtin!("corax+", "Corax+ opcode test", "3-corax+.ch8"),
+    builtin!("flags", "Flags test", "4-flags.ch8"),
+    builtin!("keypad", "Keypad test", "6-keypad.ch8"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pong_is_up_to_date_with_its_source() {
+        let source = include_str!("../roms/pong.8s");
+        let pong = BuiltinRom::find("Pong").unwrap();
+        assert_eq!(crate::asm::assemble(source).unwrap(), pong.rom);
+    }
+}
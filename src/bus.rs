@@ -0,0 +1,94 @@
+use std::sync::{Arc, Mutex};
+
+use crossbeam_channel::{Receiver, Sender, TryIter};
+
+use crate::Speed;
+
+/// Something for every part of the emulator listening on a `ControlBus` to act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMessage {
+    /// Shut down.
+    Kill,
+    Pause,
+    Resume,
+    /// Start the ROM over, wiping RAM if `hard`.
+    Reset {
+        hard: bool,
+    },
+    SetSpeed(Speed),
+    /// Save the machine to the quick save state file.
+    SaveState,
+    /// Restore the machine from the quick save state file.
+    LoadState,
+}
+
+/// A broadcast channel for `ControlMessage`s. Every subscriber gets its own copy of each message
+/// sent after it subscribed, so any number of threads can react to the same message without
+/// taking it from each other.
+///
+/// Cheap to clone; clones send to the same subscribers.
+#[derive(Clone, Default)]
+pub struct ControlBus {
+    subscribers: Arc<Mutex<Vec<Sender<ControlMessage>>>>,
+}
+
+impl ControlBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts listening for messages.
+    pub fn subscribe(&self) -> Subscription {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        Subscription { rx }
+    }
+
+    /// Sends `message` to every subscriber, forgetting any that have been dropped.
+    pub fn send(&self, message: ControlMessage) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|subscriber| subscriber.send(message).is_ok());
+    }
+}
+
+/// The messages sent on a `ControlBus` since subscribing to it.
+pub struct Subscription {
+    rx: Receiver<ControlMessage>,
+}
+
+impl Subscription {
+    /// The next message, if one has arrived.
+    pub fn try_recv(&self) -> Option<ControlMessage> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Every message that has arrived and not been taken yet.
+    pub fn try_iter(&self) -> TryIter<'_, ControlMessage> {
+        self.rx.try_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_subscriber_gets_every_message() {
+        let bus = ControlBus::new();
+        let (first, second) = (bus.subscribe(), bus.subscribe());
+        bus.clone().send(ControlMessage::Pause);
+        let late = bus.subscribe();
+        bus.send(ControlMessage::Kill);
+        let pause_then_kill = vec![ControlMessage::Pause, ControlMessage::Kill];
+        assert_eq!(first.try_iter().collect::<Vec<_>>(), pause_then_kill);
+        assert_eq!(second.try_iter().collect::<Vec<_>>(), pause_then_kill);
+        assert_eq!(late.try_recv(), Some(ControlMessage::Kill));
+        assert_eq!(late.try_recv(), None);
+
+        drop((first, second));
+        bus.send(ControlMessage::Resume);
+        assert_eq!(bus.subscribers.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,301 @@
+//! Pictures of the display for sharing: PNG screenshots and animated GIFs, drawn in a theme's
+//! colours with every pixel scaled up to a square, and text art for pasting into bug reports.
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::{Color, FrameBuffer, RenderStyle, Theme};
+
+/// How much screenshots and GIFs are scaled up by default, which makes a CHIP-8 display 512x256.
+pub const DEFAULT_CAPTURE_SCALE: u32 = 8;
+
+/// Writes `fb` to `path` as a PNG in `theme`'s colours, or its own palette if it has one, scaled
+/// up `scale` times.
+pub fn write_png<P: AsRef<Path>>(
+    fb: &FrameBuffer,
+    theme: Theme,
+    scale: u32,
+    path: P,
+) -> io::Result<()> {
+    let scale = scale.max(1) as usize;
+    let (width, height) = (fb.width() * scale, fb.height() * scale);
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data = match fb.palette() {
+        Some(colors) => {
+            encoder.set_palette(rgb(colors));
+            scale_with(fb, width, height, |p| p)
+        }
+        None => {
+            encoder.set_palette(palette(theme));
+            scaled(fb, width, height)
+        }
+    };
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(&data).map_err(io::Error::other)?;
+    writer.finish().map_err(io::Error::other)
+}
+
+/// `fb` drawn with `style`'s characters, as the terminal UI draws it, a line of text per row of
+/// them. With a `theme`, it's coloured in with 24-bit ANSI escapes, for terminals to show as it
+/// looked; without, only the characters are there.
+pub fn text_art(fb: &FrameBuffer, style: RenderStyle, theme: Option<Theme>) -> String {
+    let (columns, rows) = style.text_size(fb);
+    let level = |x, y| {
+        if x < fb.width() && y < fb.height() && fb.get(x, y) != 0 {
+            u8::MAX
+        } else {
+            0
+        }
+    };
+    let mut text = String::new();
+    for row in 0..rows {
+        let mut colors = None;
+        for cell in style.cells(columns, row, level) {
+            if let Some(theme) = theme {
+                let shade = |level| theme.bg.mix(theme.fg, level);
+                let (fg, bg) = (shade(cell.fg), shade(cell.bg));
+                if colors != Some((fg, bg)) {
+                    colors = Some((fg, bg));
+                    let _ = write!(
+                        text,
+                        "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                        fg.r, fg.g, fg.b, bg.r, bg.g, bg.b
+                    );
+                }
+            }
+            text.push(cell.glyph);
+        }
+        if theme.is_some() {
+            text.push_str("\x1b[0m");
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// The first of `<stem>-1.<extension>`, `<stem>-2.<extension>` and so on beside `path` that
+/// doesn't exist yet, for screenshots and recordings that don't overwrite each other.
+pub fn numbered_path(path: &Path, extension: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    (1..)
+        .map(|n| path.with_file_name(format!("{}-{}.{}", stem, n, extension)))
+        .find(|path| !path.exists())
+        .expect("there's always a free number")
+}
+
+/// The theme's colours as a palette, indexed by the planes a pixel is lit on.
+pub(crate) fn palette(theme: Theme) -> Vec<u8> {
+    rgb(&theme.colors())
+}
+
+fn rgb(colors: &[Color]) -> Vec<u8> {
+    colors
+        .iter()
+        .flat_map(|color| [color.r, color.g, color.b])
+        .collect()
+}
+
+/// `fb` as one index into `palette`'s colours per pixel, stretched to `width` x `height`, which
+/// should be a whole multiple of its size. A display with its own palette is drawn as lit and
+/// unlit.
+pub(crate) fn scaled(fb: &FrameBuffer, width: usize, height: usize) -> Vec<u8> {
+    match fb.palette() {
+        Some(_) => scale_with(fb, width, height, |p| (p != 0) as u8),
+        None => scale_with(fb, width, height, |p| p & 3),
+    }
+}
+
+/// `fb` stretched to `width` x `height`, with `index` giving each pixel's palette index.
+fn scale_with(fb: &FrameBuffer, width: usize, height: usize, index: impl Fn(u8) -> u8) -> Vec<u8> {
+    let (sx, sy) = (width / fb.width(), height / fb.height());
+    let mut data = Vec::with_capacity(width * height);
+    for row in fb.rows() {
+        let line: Vec<u8> = row
+            .iter()
+            .flat_map(|&p| std::iter::repeat_n(index(p), sx))
+            .collect();
+        for _ in 0..sy {
+            data.extend_from_slice(&line);
+        }
+    }
+    data
+}
+
+/// Records frames of the display to an animated GIF as they're run.
+///
+/// Frames that look the same as the one before are merged into it rather than repeated, so a
+/// still screen costs nothing however long it's held. Each GIF frame is held for as long as the
+/// frames it stands for took to run, at 60 a second; since GIF delays are in hundredths of a
+/// second, and most viewers slow down anything shorter than two of them, recording every frame
+/// of a busy display plays back slower than it ran. Skipping every other frame doesn't.
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    size: (usize, usize),
+    /// How many frames to leave out after each one recorded.
+    skip: u32,
+    /// Frames offered to `add_frame` so far.
+    seen: u64,
+    /// The last frame recorded, not yet written, and how many 60Hz frames it's been showing.
+    pending: Option<(Vec<u8>, u64)>,
+    /// How many 60Hz frames the frames written so far stand for.
+    written: u64,
+    frames: u32,
+}
+
+impl GifRecorder {
+    /// Starts recording to `path` at `width` x `height`, the largest display the ROM might use,
+    /// scaled up `scale` times, recording one frame and then skipping `skip`. Smaller displays
+    /// are stretched to fit.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        (width, height): (usize, usize),
+        theme: Theme,
+        scale: u32,
+        skip: u32,
+    ) -> io::Result<Self> {
+        let scale = scale.max(1) as usize;
+        let size = (width * scale, height * scale);
+        let file = BufWriter::new(File::create(path)?);
+        let too_big = |_| io::Error::other("the GIF would be too big");
+        let mut encoder = gif::Encoder::new(
+            file,
+            size.0.try_into().map_err(too_big)?,
+            size.1.try_into().map_err(too_big)?,
+            &palette(theme),
+        )
+        .map_err(io::Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(io::Error::other)?;
+        Ok(Self {
+            encoder,
+            size,
+            skip,
+            seen: 0,
+            pending: None,
+            written: 0,
+            frames: 0,
+        })
+    }
+
+    /// Records `fb`, if it isn't one of the frames to skip.
+    pub fn add_frame(&mut self, fb: &FrameBuffer) -> io::Result<()> {
+        let every = self.skip as u64 + 1;
+        let skipped = !self.seen.is_multiple_of(every);
+        self.seen += 1;
+        if skipped {
+            return Ok(());
+        }
+        let (width, height) = self.size;
+        // A display bigger than the one the GIF was made for can't be shown in it, so it's left
+        // out rather than failing the whole recording.
+        if !width.is_multiple_of(fb.width()) || !height.is_multiple_of(fb.height()) {
+            return Ok(());
+        }
+        let data = scaled(fb, width, height);
+        match &mut self.pending {
+            Some((pending, shown)) if *pending == data => *shown += every,
+            _ => {
+                self.flush()?;
+                self.pending = Some((data, every));
+            }
+        }
+        Ok(())
+    }
+
+    /// How many frames the GIF has, counting merged ones as one.
+    pub fn frames(&self) -> u32 {
+        self.frames + self.pending.is_some() as u32
+    }
+
+    /// Writes the last frame and finishes the GIF, returning how many frames it has.
+    pub fn finish(mut self) -> io::Result<u32> {
+        self.flush()?;
+        let frames = self.frames;
+        self.encoder.into_inner().map_err(io::Error::other)?;
+        Ok(frames)
+    }
+
+    /// Writes the pending frame, holding it until the time the frames it stands for ran to,
+    /// rounded to a hundredth of a second.
+    fn flush(&mut self) -> io::Result<()> {
+        let Some((data, shown)) = self.pending.take() else {
+            return Ok(());
+        };
+        let centiseconds = |frames: u64| frames * 100 / crate::FRAMES_PER_SECOND as u64;
+        let delay = centiseconds(self.written + shown) - centiseconds(self.written);
+        self.written += shown;
+        let frame = gif::Frame {
+            width: self.size.0 as u16,
+            height: self.size.1 as u16,
+            buffer: data.into(),
+            delay: delay.min(u16::MAX as u64) as u16,
+            ..gif::Frame::default()
+        };
+        self.encoder.write_frame(&frame).map_err(io::Error::other)?;
+        self.frames += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_art_is_coloured_in_runs() {
+        let mut fb = FrameBuffer::new(64, 32);
+        fb.set(0, 0, 1);
+        fb.set(1, 1, 1);
+        let plain = text_art(&fb, RenderStyle::HalfBlock, None);
+        assert_eq!(plain.lines().count(), 16);
+        assert!(plain.starts_with("▀▄  "));
+
+        let theme = Theme::AMBER;
+        let ansi = text_art(&fb, RenderStyle::HalfBlock, Some(theme));
+        let first = ansi.lines().next().unwrap();
+        // Both pixels are drawn in the same colours, then the rest of the line in unlit ones.
+        let (lit, unlit) = ("\x1b[38;2;255;176;0m", "\x1b[38;2;26;15;0m");
+        let background = "\x1b[48;2;26;15;0m";
+        let expected = format!(
+            "{}{}▀▄{}{}{}\x1b[0m",
+            lit,
+            background,
+            unlit,
+            background,
+            " ".repeat(62)
+        );
+        assert_eq!(first, expected);
+    }
+
+    #[test]
+    fn still_frames_are_merged_and_timed() {
+        let path = std::env::temp_dir().join(format!("chip8-capture-{}.gif", std::process::id()));
+        let mut fb = FrameBuffer::new(64, 32);
+        let mut gif = GifRecorder::create(&path, (128, 64), Theme::default(), 1, 1).unwrap();
+        for frame in 0..12 {
+            if frame == 6 {
+                fb.set(0, 0, 1);
+            }
+            gif.add_frame(&fb).unwrap();
+        }
+        assert_eq!(gif.finish().unwrap(), 2);
+
+        let mut decoder = gif::DecodeOptions::new();
+        decoder.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = decoder.read_info(File::open(&path).unwrap()).unwrap();
+        let first = decoder.read_next_frame().unwrap().unwrap().clone();
+        assert_eq!((first.width, first.height, first.delay), (128, 64, 10));
+        assert!(first.buffer.iter().all(|&p| p == 0));
+        let second = decoder.read_next_frame().unwrap().unwrap();
+        assert_eq!(second.delay, 10);
+        // The lit pixel is stretched to 2x2.
+        assert_eq!(&second.buffer[..3], &[1, 1, 0]);
+        assert_eq!(&second.buffer[128..131], &[1, 1, 0]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+//! ROMs in files that say how to run them, as well as plain binaries: Octo source, Octo
+//! cartridges and `.c8b` files.
+//!
+//! An Octo cartridge is a GIF of a cartridge with the program's label on it, as Octo shares
+//! programs. The low two bits of every pixel's colour index carry the data, four pixels to a byte
+//! with the first pixel in the top bits, running on from one frame to the next: a 32-bit
+//! big-endian length and then that many bytes of JSON, with the program's Octo source in
+//! `program` and Octo's settings for it in `options`.
+//!
+//! A `.c8b` file carries compiled bytecode for one or more platforms, and properties for running
+//! it. Numbers are big-endian:
+//!
+//! ```text
+//! 0   "CBF"
+//! 3   version, 0
+//! 4   where the property table starts as a 2-byte offset, or 0 if there isn't one
+//! 6   a 5-byte entry per platform the bytecode's for, up to the first bytecode: the platform, the
+//!     offset of its bytecode and the bytecode's length
+//! ```
+//!
+//! Platforms are 0x00 for CHIP-8, 0x07 and 0x08 for SUPER-CHIP 1.0 and 1.1 and 0x0a for XO-CHIP,
+//! and the first of the file's platforms that's one of these is what runs. Each property is a tag
+//! byte and a 2-byte length, then that many bytes, until a tag of 0xff or the end of the file:
+//!
+//! ```text
+//! 0x00  instructions per second, 4 bytes
+//! 0x01  the author, in UTF-8
+//! 0x02  the title, in UTF-8
+//! 0x04  colours as RGB, 3 bytes each: unlit pixels, then lit ones, then optionally those lit on
+//!       XO-CHIP's second plane and on both planes
+//! 0x05  quirks, a bit each from the lowest up: shift, jump, load/store, display wait, VF reset
+//!       and clipping
+//! ```
+//!
+//! Any other property is skipped.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::{octo, Color, Quirks, RomError, Symbols, Variant};
+
+/// A ROM, and whatever the file it came in says about running it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cartridge {
+    pub rom: Vec<u8>,
+    /// The labels from the source, if the ROM was compiled from Octo source.
+    pub symbols: Symbols,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub variant: Option<Variant>,
+    /// The quirks to run it with, instead of the variant's.
+    pub quirks: Option<Quirks>,
+    pub ips: Option<u32>,
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub fg2: Option<Color>,
+    pub blend: Option<Color>,
+}
+
+impl Cartridge {
+    /// A ROM with nothing to say about how to run it.
+    pub fn plain(rom: Vec<u8>) -> Self {
+        Self {
+            rom,
+            ..Self::default()
+        }
+    }
+
+    /// Reads the ROM at `path`.
+    pub fn load(path: &Path) -> Result<Self, RomError> {
+        Self::read(path, std::fs::read(path)?)
+    }
+
+    /// Makes sense of `bytes`, read from a file at `path`, by its extension: `.8o` for Octo
+    /// source, `.gif` for an Octo cartridge, `.c8b`, or anything else for a plain ROM.
+    pub fn read(path: &Path, bytes: Vec<u8>) -> Result<Self, RomError> {
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("8o") => {
+                let (rom, symbols) = octo::compile(&String::from_utf8_lossy(&bytes))?;
+                Ok(Self {
+                    symbols,
+                    ..Self::plain(rom)
+                })
+            }
+            Some("gif") => Self::from_octo_cart(&bytes),
+            Some("c8b") => Self::from_c8b(&bytes),
+            _ => Ok(Self::plain(bytes)),
+        }
+    }
+
+    /// Reads an Octo cartridge, compiling the program in it.
+    pub fn from_octo_cart(gif: &[u8]) -> Result<Self, RomError> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options
+            .read_info(gif)
+            .map_err(|err| invalid(format!("it isn't a GIF ({})", err)))?;
+        let mut data = Vec::new();
+        while let Some(frame) = decoder
+            .read_next_frame()
+            .map_err(|err| invalid(format!("the GIF is damaged ({})", err)))?
+        {
+            let bytes = frame.buffer.chunks_exact(4);
+            data.extend(bytes.map(|pixels| pixels.iter().fold(0, |byte, p| byte << 2 | p & 3)));
+        }
+        let not_a_cart = || invalid("it's a GIF, but not an Octo cartridge");
+        let len = data
+            .get(..4)
+            .ok_or_else(not_a_cart)
+            .map(|len| u32::from_be_bytes(len.try_into().unwrap()) as usize)?;
+        let json = data.get(4..4 + len).ok_or_else(not_a_cart)?;
+        let cart: Value = serde_json::from_slice(json).map_err(|_| not_a_cart())?;
+        let program = cart["program"].as_str().ok_or_else(not_a_cart)?;
+        let (rom, symbols) = octo::compile(program)?;
+
+        let options = &cart["options"];
+        let flag = |name: &str| options[name].as_bool();
+        let variant = options["maxSize"].as_u64().map(|size| match size {
+            ..=3232 => Variant::Chip8,
+            3233..=3583 => Variant::Schip,
+            _ => Variant::XoChip,
+        });
+        let mut quirks = Quirks::preset(variant.unwrap_or_default());
+        let set = |quirk: &mut bool, value: Option<bool>| *quirk = value.unwrap_or(*quirk);
+        set(&mut quirks.shift, flag("shiftQuirks"));
+        // Octo's quirk is that I *isn't* moved on, where ours is that it is.
+        set(&mut quirks.loadstore, flag("loadStoreQuirks").map(|q| !q));
+        set(&mut quirks.jump, flag("jumpQuirks"));
+        set(&mut quirks.vf_reset, flag("logicQuirks"));
+        set(&mut quirks.clip, flag("clipQuirks"));
+        set(&mut quirks.display_wait, flag("vBlankQuirks"));
+        let color = |name: &str| options[name].as_str().and_then(|c| c.parse().ok());
+        Ok(Self {
+            symbols,
+            variant,
+            quirks: Some(quirks),
+            // Octo counts instructions a frame.
+            ips: options["tickrate"]
+                .as_u64()
+                .map(|rate| (rate * 60).min(u32::MAX as u64) as u32),
+            fg: color("fillColor"),
+            bg: color("backgroundColor"),
+            fg2: color("fillColor2"),
+            blend: color("blendColor"),
+            ..Self::plain(rom)
+        })
+    }
+
+    /// Reads a `.c8b` file, taking the bytecode for the first platform in it that's supported.
+    pub fn from_c8b(bytes: &[u8]) -> Result<Self, RomError> {
+        let short = || invalid("the .c8b file is cut short");
+        let byte = |at: usize| bytes.get(at).copied().ok_or_else(short);
+        let word = |at: usize| -> Result<usize, RomError> {
+            Ok(u16::from_be_bytes([byte(at)?, byte(at + 1)?]) as usize)
+        };
+        if !bytes.starts_with(b"CBF") {
+            return Err(invalid("it isn't a .c8b file"));
+        }
+        if byte(3)? != 0 {
+            return Err(invalid(format!(
+                "it's version {} of the .c8b format, and only version 0 is supported",
+                bytes[3]
+            )));
+        }
+        let properties = word(4)?;
+
+        let mut cart = None;
+        let mut platforms = Vec::new();
+        let (mut at, mut end) = (
+            6,
+            if properties == 0 {
+                bytes.len()
+            } else {
+                properties
+            },
+        );
+        while at < end {
+            let (platform, offset, len) = (byte(at)?, word(at + 1)?, word(at + 3)?);
+            at += 5;
+            end = end.min(offset);
+            platforms.push(format!("{:#04x}", platform));
+            let variant = match platform {
+                0x00 => Variant::Chip8,
+                0x07 | 0x08 => Variant::Schip,
+                0x0a => Variant::XoChip,
+                _ => continue,
+            };
+            if cart.is_none() {
+                let rom = bytes.get(offset..offset + len).ok_or_else(short)?;
+                cart = Some(Self {
+                    variant: Some(variant),
+                    ..Self::plain(rom.to_vec())
+                });
+            }
+        }
+        let Some(mut cart) = cart else {
+            return Err(invalid(format!(
+                "it has bytecode for platforms {}, and none of them are supported",
+                platforms.join(", ")
+            )));
+        };
+
+        let mut at = properties;
+        while properties != 0 && at < bytes.len() && bytes[at] != 0xff {
+            let (tag, len) = (bytes[at], word(at + 1)?);
+            let data = bytes.get(at + 3..at + 3 + len).ok_or_else(short)?;
+            at += 3 + len;
+            let text = || Some(String::from_utf8_lossy(data).into_owned());
+            match (tag, data) {
+                (0x00, &[a, b, c, d]) => cart.ips = Some(u32::from_be_bytes([a, b, c, d])),
+                (0x01, _) => cart.author = text(),
+                (0x02, _) => cart.title = text(),
+                (0x04, &[br, bg, bb, fr, fg, fb, ref rest @ ..]) => {
+                    cart.bg = Some(Color::new(br, bg, bb));
+                    cart.fg = Some(Color::new(fr, fg, fb));
+                    // Then the second plane's colour and both planes' colour, if they're there.
+                    if let &[r2, g2, b2, rb, gb, bb, ..] = rest {
+                        cart.fg2 = Some(Color::new(r2, g2, b2));
+                        cart.blend = Some(Color::new(rb, gb, bb));
+                    }
+                }
+                (0x05, &[bits]) => {
+                    let bit = |n: u8| bits & 1 << n != 0;
+                    cart.quirks = Some(Quirks {
+                        shift: bit(0),
+                        jump: bit(1),
+                        loadstore: bit(2),
+                        loadstore_x: false,
+                        display_wait: bit(3),
+                        vf_reset: bit(4),
+                        clip: bit(5),
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(cart)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> RomError {
+    RomError::Invalid(message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An Octo cartridge with `json` in it, as Octo would make one, over a few frames.
+    fn octo_cart(json: &str) -> Vec<u8> {
+        let (width, height) = (16u16, 8u16);
+        let mut data = (json.len() as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(json.as_bytes());
+        let mut pixels: Vec<u8> = data
+            .iter()
+            .flat_map(|&byte| [6, 4, 2, 0].map(|shift| 4 | (byte >> shift & 3)))
+            .collect();
+        let frame_size = (width * height) as usize;
+        pixels.resize(pixels.len().div_ceil(frame_size) * frame_size, 4);
+
+        let palette: Vec<u8> = (0..16).flat_map(|i| [i * 16, i * 16, i * 16]).collect();
+        let mut gif = Vec::new();
+        let mut encoder = gif::Encoder::new(&mut gif, width, height, &palette).unwrap();
+        for frame in pixels.chunks(frame_size) {
+            let frame = gif::Frame {
+                width,
+                height,
+                buffer: frame.into(),
+                ..gif::Frame::default()
+            };
+            encoder.write_frame(&frame).unwrap();
+        }
+        drop(encoder);
+        gif
+    }
+
+    #[test]
+    fn reads_octo_cartridges() {
+        let json = r##"{
+            "program": ": main\n  v0 := 1\n  loop again\n",
+            "options": {
+                "tickrate": 20, "maxSize": 3583, "loadStoreQuirks": true, "shiftQuirks": true,
+                "jumpQuirks": false, "fillColor": "#FFCC00", "backgroundColor": "#996600",
+                "fillColor2": "#FF6600", "blendColor": "#662200"
+            }
+        }"##;
+        let cart = Cartridge::read(Path::new("game.gif"), octo_cart(json)).unwrap();
+        assert_eq!(cart.rom, [0x60, 0x01, 0x12, 0x02]);
+        assert_eq!(cart.symbols.name(0x200), Some("main"));
+        assert_eq!(cart.variant, Some(Variant::Schip));
+        let quirks = cart.quirks.unwrap();
+        assert!(quirks.shift && !quirks.jump && !quirks.loadstore);
+        assert_eq!(cart.ips, Some(1200));
+        assert_eq!(cart.fg, Some(Color::new(0xff, 0xcc, 0x00)));
+        assert_eq!(cart.bg, Some(Color::new(0x99, 0x66, 0x00)));
+        assert_eq!(cart.fg2, Some(Color::new(0xff, 0x66, 0x00)));
+        assert_eq!(cart.blend, Some(Color::new(0x66, 0x22, 0x00)));
+
+        let mut not_a_cart = octo_cart(json);
+        not_a_cart.truncate(20);
+        assert!(Cartridge::from_octo_cart(&not_a_cart).is_err());
+    }
+
+    #[test]
+    fn reads_c8b_files() {
+        let mut c8b = b"CBF\0".to_vec();
+        c8b.extend_from_slice(&[0x00, 24]); // properties
+        c8b.extend_from_slice(&[0x42, 0x00, 16, 0x00, 2]); // a platform that isn't supported
+        c8b.extend_from_slice(&[0x0a, 0x00, 18, 0x00, 4]); // XO-CHIP
+        c8b.extend_from_slice(&[0x12, 0x00, 0x60, 0x01, 0x12, 0x02, 0, 0]);
+        c8b.extend_from_slice(&[0x00, 0x00, 4, 0x00, 0x00, 0x03, 0xe8]);
+        c8b.extend_from_slice(&[0x02, 0x00, 3, b'B', b'o', b'x']);
+        c8b.extend_from_slice(&[0x05, 0x00, 1, 0b100011]);
+        c8b.push(0xff);
+        let cart = Cartridge::read(Path::new("box.c8b"), c8b.clone()).unwrap();
+        assert_eq!(cart.rom, [0x60, 0x01, 0x12, 0x02]);
+        assert_eq!(cart.variant, Some(Variant::XoChip));
+        assert_eq!(cart.ips, Some(1000));
+        assert_eq!(cart.title.as_deref(), Some("Box"));
+        let quirks = cart.quirks.unwrap();
+        assert!(quirks.shift && quirks.jump && quirks.clip && !quirks.loadstore);
+
+        c8b[11] = 0x01;
+        let err = Cartridge::from_c8b(&c8b).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "it has bytecode for platforms 0x42, 0x01, and none of them are supported"
+        );
+        assert!(Cartridge::from_c8b(&c8b[..8]).is_err());
+    }
+}
@@ -0,0 +1,427 @@
+//! The settings file, `~/.config/chip8-rs/config.toml`. Every setting is optional, and all of them
+//! can be given again in a `[roms]` table for a single ROM, keyed by its file name or the SHA-1
+//! of its contents:
+//!
+//! ```toml
+//! rom_dir = "/home/me/roms"
+//! variant = "schip"
+//! ips = 1000
+//! timing = "ips"
+//! keymap = "qwerty"
+//! theme = "amber"
+//! render_style = "halfblock"
+//! volume = 0.5
+//!
+//! [quirks]
+//! shift = true
+//!
+//! [roms."blinky.ch8"]
+//! variant = "chip8"
+//! quirks = { profile = "chip8", loadstore = false }
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::audio::AudioMode;
+use crate::{
+    AccessPolicy, Cartridge, Color, Keymap, LoadAddress, Quirks, RenderStyle, RomInfo, Theme,
+    Timing, Variant, WriteProtection,
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    /// The file isn't valid TOML, or a setting has the wrong type or an invalid value.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{}", err),
+            ConfigError::Invalid(message) => write!(f, "{}", message.trim_end()),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> Self {
+        ConfigError::Io(err)
+    }
+}
+
+/// Overrides for the variant's quirk profile.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuirkSettings {
+    /// The profile to start from instead of the variant's.
+    #[serde(deserialize_with = "parsed")]
+    pub profile: Option<Variant>,
+    pub shift: Option<bool>,
+    pub jump: Option<bool>,
+    pub loadstore: Option<bool>,
+    pub loadstore_x: Option<bool>,
+    pub display_wait: Option<bool>,
+    pub clip: Option<bool>,
+    pub vf_reset: Option<bool>,
+}
+
+impl QuirkSettings {
+    /// Whichever of `quirks` differ from `variant`'s profile.
+    fn differences(quirks: Quirks, variant: Variant) -> Self {
+        let preset = Quirks::preset(variant);
+        let differs =
+            |quirk: fn(&Quirks) -> bool| Some(quirk(&quirks)).filter(|&q| q != quirk(&preset));
+        QuirkSettings {
+            profile: None,
+            shift: differs(|q| q.shift),
+            jump: differs(|q| q.jump),
+            loadstore: differs(|q| q.loadstore),
+            loadstore_x: differs(|q| q.loadstore_x),
+            display_wait: differs(|q| q.display_wait),
+            clip: differs(|q| q.clip),
+            vf_reset: differs(|q| q.vf_reset),
+        }
+    }
+
+    /// These settings, with any that `over` has taken from it instead.
+    pub fn merge(&self, over: &QuirkSettings) -> QuirkSettings {
+        QuirkSettings {
+            profile: over.profile.or(self.profile),
+            shift: over.shift.or(self.shift),
+            jump: over.jump.or(self.jump),
+            loadstore: over.loadstore.or(self.loadstore),
+            loadstore_x: over.loadstore_x.or(self.loadstore_x),
+            display_wait: over.display_wait.or(self.display_wait),
+            clip: over.clip.or(self.clip),
+            vf_reset: over.vf_reset.or(self.vf_reset),
+        }
+    }
+}
+
+/// Everything the file can set, each of it `None` unless it's set.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    #[serde(deserialize_with = "parsed")]
+    pub variant: Option<Variant>,
+    pub quirks: QuirkSettings,
+    pub ips: Option<u32>,
+    #[serde(deserialize_with = "parsed")]
+    pub timing: Option<Timing>,
+    #[serde(deserialize_with = "parsed")]
+    pub keymap: Option<Keymap>,
+    #[serde(deserialize_with = "parsed")]
+    pub theme: Option<Theme>,
+    #[serde(deserialize_with = "parsed")]
+    pub fg: Option<Color>,
+    #[serde(deserialize_with = "parsed")]
+    pub bg: Option<Color>,
+    #[serde(deserialize_with = "parsed")]
+    pub fg2: Option<Color>,
+    #[serde(deserialize_with = "parsed")]
+    pub blend: Option<Color>,
+    #[serde(deserialize_with = "parsed")]
+    pub memory_policy: Option<AccessPolicy>,
+    #[serde(deserialize_with = "parsed")]
+    pub load_addr: Option<LoadAddress>,
+    #[serde(deserialize_with = "parsed")]
+    pub write_protect: Option<WriteProtection>,
+    #[serde(deserialize_with = "parsed")]
+    pub render_style: Option<RenderStyle>,
+    pub phosphor: Option<u8>,
+    #[serde(deserialize_with = "parsed")]
+    pub audio: Option<AudioMode>,
+    pub mute: Option<bool>,
+    pub volume: Option<f32>,
+    /// Pause while the terminal doesn't have focus.
+    pub pause_on_focus_loss: Option<bool>,
+    /// Set the terminal's window title to the ROM's name.
+    pub window_title: Option<bool>,
+}
+
+impl Settings {
+    /// These settings, with any that `over` has taken from it instead.
+    pub fn merge(&self, over: &Settings) -> Settings {
+        Settings {
+            variant: over.variant.or(self.variant),
+            quirks: self.quirks.merge(&over.quirks),
+            ips: over.ips.or(self.ips),
+            timing: over.timing.or(self.timing),
+            keymap: over.keymap.or(self.keymap),
+            theme: over.theme.or(self.theme),
+            fg: over.fg.or(self.fg),
+            bg: over.bg.or(self.bg),
+            fg2: over.fg2.or(self.fg2),
+            blend: over.blend.or(self.blend),
+            memory_policy: over.memory_policy.or(self.memory_policy),
+            load_addr: over.load_addr.or(self.load_addr),
+            write_protect: over.write_protect.or(self.write_protect),
+            render_style: over.render_style.or(self.render_style),
+            phosphor: over.phosphor.or(self.phosphor),
+            audio: over.audio.or(self.audio),
+            mute: over.mute.or(self.mute),
+            volume: over.volume.or(self.volume),
+            pause_on_focus_loss: over.pause_on_focus_loss.or(self.pause_on_focus_loss),
+            window_title: over.window_title.or(self.window_title),
+        }
+    }
+}
+
+/// What the ROM database recommends, as settings the config file and flags can override. The
+/// quirks are given as whatever differs from the variant's profile, so that switching variants or
+/// profiles in the config file isn't undone by the database.
+impl From<&RomInfo> for Settings {
+    fn from(info: &RomInfo) -> Self {
+        Settings {
+            variant: Some(info.variant),
+            quirks: QuirkSettings::differences(info.quirks, info.variant),
+            ips: info.ips,
+            ..Settings::default()
+        }
+    }
+}
+
+/// What the file a ROM came in says about running it, likewise.
+impl From<&Cartridge> for Settings {
+    fn from(cart: &Cartridge) -> Self {
+        let variant = cart.variant.unwrap_or_default();
+        Settings {
+            variant: cart.variant,
+            quirks: cart
+                .quirks
+                .map(|quirks| QuirkSettings::differences(quirks, variant))
+                .unwrap_or_default(),
+            ips: cart.ips,
+            fg: cart.fg,
+            bg: cart.bg,
+            fg2: cart.fg2,
+            blend: cart.blend,
+            ..Settings::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Config {
+    /// Where the start screen looks for ROMs.
+    pub rom_dir: Option<PathBuf>,
+    pub defaults: Settings,
+    /// Settings for particular ROMs, by file name or SHA-1.
+    pub roms: HashMap<String, Settings>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/chip8-rs/config.toml`, or `~/.config/chip8-rs/config.toml`.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("config.toml"))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        std::fs::read_to_string(path)?.parse()
+    }
+
+    /// The settings for a ROM: the file's defaults, then those for its file name, then those for
+    /// its SHA-1, each overriding the last.
+    pub fn settings_for(&self, file_name: &str, sha1: &str) -> Settings {
+        [file_name, sha1]
+            .iter()
+            .filter_map(|key| self.roms.get(*key))
+            .fold(self.defaults.clone(), |settings, rom| settings.merge(rom))
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |err: toml::de::Error| ConfigError::Invalid(err.to_string());
+        // The defaults are read on their own rather than flattened into `Config`, so that
+        // misspelt settings are caught.
+        let mut table: toml::Table = toml::from_str(s).map_err(invalid)?;
+        let roms = match table.remove("roms") {
+            Some(roms) => roms.try_into().map_err(invalid)?,
+            None => HashMap::new(),
+        };
+        let rom_dir = match table.remove("rom_dir") {
+            Some(dir) => Some(dir.try_into().map_err(invalid)?),
+            None => None,
+        };
+        Ok(Self {
+            rom_dir,
+            defaults: table.try_into().map_err(invalid)?,
+            roms,
+        })
+    }
+}
+
+/// `$XDG_CONFIG_HOME/chip8-rs`, or `~/.config/chip8-rs`.
+fn config_dir() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("chip8-rs"))
+}
+
+/// The ROMs run most recently, newest first, one path to a line in a file next to the config
+/// file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecentRoms {
+    roms: Vec<PathBuf>,
+}
+
+impl RecentRoms {
+    /// How many are remembered.
+    pub const MAX: usize = 10;
+
+    /// `recent.txt` next to `Config::default_path()`.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("recent.txt"))
+    }
+
+    /// The list in `path`, or an empty one if there isn't one yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(Self {
+                roms: text
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .take(Self::MAX)
+                    .collect(),
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut text = String::new();
+        for rom in &self.roms {
+            text.push_str(&rom.to_string_lossy());
+            text.push('\n');
+        }
+        std::fs::write(path, text)
+    }
+
+    pub fn roms(&self) -> &[PathBuf] {
+        &self.roms
+    }
+
+    /// Puts `rom` at the top of the list, forgetting the oldest if it's full.
+    pub fn add(&mut self, rom: PathBuf) {
+        self.roms.retain(|recent| *recent != rom);
+        self.roms.insert(0, rom);
+        self.roms.truncate(Self::MAX);
+    }
+}
+
+/// Reads a setting as a string, and parses it the way the equivalent command line flag would be.
+fn parsed<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) => s.parse().map(Some).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+        variant = "schip"
+        ips = 1000
+        theme = "amber"
+        audio = "visual"
+        pause_on_focus_loss = true
+
+        [quirks]
+        shift = true
+
+        [roms."blinky.ch8"]
+        variant = "chip8"
+        quirks = { profile = "chip8", loadstore = false }
+
+        [roms.0123456789abcdef0123456789abcdef01234567]
+        ips = 500
+        timing = "vip"
+        window_title = false
+    "#;
+
+    #[test]
+    fn rom_settings_override_the_defaults() {
+        let config: Config = CONFIG.parse().unwrap();
+        assert_eq!(config.defaults.theme, Some(Theme::AMBER));
+
+        let other = config.settings_for("other.ch8", "");
+        assert_eq!(other.variant, Some(Variant::Schip));
+        assert_eq!(other.ips, Some(1000));
+        assert_eq!(other.audio, Some(AudioMode::Visual));
+        assert_eq!(other.pause_on_focus_loss, Some(true));
+        assert_eq!(other.window_title, None);
+
+        let blinky = config.settings_for("blinky.ch8", "0123456789abcdef0123456789abcdef01234567");
+        assert_eq!(blinky.variant, Some(Variant::Chip8));
+        assert_eq!(blinky.ips, Some(500));
+        assert_eq!(blinky.timing, Some(Timing::Vip));
+        assert_eq!(blinky.quirks.profile, Some(Variant::Chip8));
+        assert_eq!(blinky.quirks.shift, Some(true));
+        assert_eq!(blinky.quirks.loadstore, Some(false));
+        assert_eq!(blinky.theme, Some(Theme::AMBER));
+        assert_eq!(blinky.window_title, Some(false));
+    }
+
+    #[test]
+    fn reads_the_rom_dir() {
+        let config: Config = "rom_dir = \"roms\"\nips = 10".parse().unwrap();
+        assert_eq!(config.rom_dir, Some(PathBuf::from("roms")));
+        assert_eq!(config.defaults.ips, Some(10));
+        assert!("rom_dir = 3".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn recent_roms_are_newest_first() {
+        let mut recent = RecentRoms::default();
+        for i in 0..12 {
+            recent.add(PathBuf::from(format!("{}.ch8", i)));
+        }
+        recent.add(PathBuf::from("5.ch8"));
+        assert_eq!(recent.roms().len(), RecentRoms::MAX);
+        assert_eq!(recent.roms()[0], PathBuf::from("5.ch8"));
+        assert_eq!(recent.roms()[1], PathBuf::from("11.ch8"));
+        assert_eq!(
+            recent
+                .roms()
+                .iter()
+                .filter(|rom| rom.ends_with("5.ch8"))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let err = "variant = \"nes\"".parse::<Config>().unwrap_err();
+        assert!(err.to_string().contains("unknown variant 'nes'"));
+        assert!("ips = \"fast\"".parse::<Config>().is_err());
+        assert!("audio = \"loud\"".parse::<Config>().is_err());
+        assert!("[quirks]\nwobble = true".parse::<Config>().is_err());
+        assert!("wobble = true".parse::<Config>().is_err());
+    }
+}
@@ -0,0 +1,586 @@
+//! The debugger's command language, for typing at a prompt instead of learning a hotkey for
+//! everything: `break 23a`, `print v3`, `set i 300`, `mem 200 32`, `step 10`, `continue`.
+//!
+//! Addresses and values are hex, with or without `0x`, as everywhere else in the debugger; counts
+//! are decimal. Frontends parse a line into a `ConsoleCommand` and send it to the run loop as
+//! `InputEvent::Console`, which runs it with `execute` and keeps the output for them to show.
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Breakpoint, Command, CpuControl, CPU};
+
+/// How many bytes `mem` shows without a count.
+const DEFAULT_MEM_LEN: u16 = 16;
+/// Bytes per line of `mem`'s output, as in the memory panel.
+const MEM_BYTES_PER_LINE: usize = 8;
+
+/// The commands, for completing and for `help`, with what each one does.
+pub const COMMANDS: [(&str, &str); 12] = [
+    (
+        "break",
+        "break BREAKPOINT: stop at an address, opcode pattern, vX=NN or w:watchpoint",
+    ),
+    ("delete", "delete BREAKPOINT: remove a breakpoint"),
+    ("breakpoints", "breakpoints: list the breakpoints"),
+    (
+        "print",
+        "print [REGISTER|ADDRESS]: show a register, a byte, or all the registers",
+    ),
+    (
+        "set",
+        "set REGISTER|ADDRESS VALUE: change v0-vf, i, pc, sp, dt, st or a byte",
+    ),
+    (
+        "mem",
+        "mem ADDRESS [COUNT]: dump COUNT bytes of memory, 16 by default",
+    ),
+    (
+        "step",
+        "step [COUNT]: pause and run COUNT instructions, 1 by default",
+    ),
+    (
+        "next",
+        "next: pause and run an instruction, or a whole call",
+    ),
+    ("frame", "frame: pause and run a frame"),
+    ("continue", "continue: carry on running"),
+    ("pause", "pause: stop running"),
+    ("help", "help: list the commands"),
+];
+
+/// Something the CPU has that `print` and `set` can read and change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Delay,
+    Sound,
+}
+
+impl Register {
+    /// The names registers go by, for completing.
+    pub const NAMES: [&'static str; 21] = [
+        "v0", "v1", "v2", "v3", "v4", "v5", "v6", "v7", "v8", "v9", "va", "vb", "vc", "vd", "ve",
+        "vf", "i", "pc", "sp", "dt", "st",
+    ];
+
+    fn get(self, cpu: &CPU) -> u16 {
+        match self {
+            Register::V(x) => cpu.registers.v[x as usize] as u16,
+            Register::I => cpu.registers.i,
+            Register::Pc => cpu.pc,
+            Register::Sp => cpu.sp as u16,
+            Register::Delay => cpu.registers.delay as u16,
+            Register::Sound => cpu.registers.sound as u16,
+        }
+    }
+
+    /// Sets the register to `value`, if it fits.
+    fn set(self, cpu: &mut CPU, value: u16) -> Result<(), String> {
+        let byte = || u8::try_from(value).map_err(|_| format!("{} only holds a byte", self));
+        match self {
+            Register::V(x) => cpu.registers.v[x as usize] = byte()?,
+            Register::I => cpu.registers.i = value,
+            Register::Pc => cpu.pc = value,
+            Register::Sp if value as usize > cpu.stack.len() => {
+                return Err(format!("the stack only goes up to {}", cpu.stack.len()))
+            }
+            Register::Sp => cpu.sp = value as u8,
+            Register::Delay => cpu.registers.delay = byte()?,
+            Register::Sound => cpu.registers.sound = byte()?,
+        }
+        Ok(())
+    }
+
+    /// How many hex digits the register is shown with.
+    fn width(self) -> usize {
+        match self {
+            Register::I | Register::Pc => 4,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Register::V(x) => write!(f, "v{:x}", x),
+            Register::I => f.write_str("i"),
+            Register::Pc => f.write_str("pc"),
+            Register::Sp => f.write_str("sp"),
+            Register::Delay => f.write_str("dt"),
+            Register::Sound => f.write_str("st"),
+        }
+    }
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "i" => Ok(Register::I),
+            "pc" => Ok(Register::Pc),
+            "sp" => Ok(Register::Sp),
+            "dt" => Ok(Register::Delay),
+            "st" => Ok(Register::Sound),
+            v => v
+                .strip_prefix('v')
+                .filter(|x| x.len() == 1)
+                .and_then(|x| u8::from_str_radix(x, 16).ok())
+                .map(Register::V)
+                .ok_or_else(|| {
+                    format!(
+                        "unknown register '{}' (expected v0-vf, i, pc, sp, dt or st)",
+                        s
+                    )
+                }),
+        }
+    }
+}
+
+/// What `print` shows and `set` changes: a register, or a byte of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Register(Register),
+    Address(u16),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Register(register) => register.fmt(f),
+            Target::Address(addr) => write!(f, "{:03x}", addr),
+        }
+    }
+}
+
+/// Parses a register name, or failing that a hex address.
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse() {
+            Ok(register) => Ok(Target::Register(register)),
+            Err(err) => u16::from_str_radix(s.trim_start_matches("0x"), 16)
+                .map(Target::Address)
+                .map_err(|_| err),
+        }
+    }
+}
+
+/// A line typed at the debugger's prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleCommand {
+    Break(Breakpoint),
+    Delete(Breakpoint),
+    Breakpoints,
+    /// Shows one thing, or all the registers if it's `None`.
+    Print(Option<Target>),
+    Set(Target, u16),
+    Mem {
+        addr: u16,
+        len: u16,
+    },
+    Step(u16),
+    Next,
+    Frame,
+    Continue,
+    Pause,
+    Help,
+}
+
+impl ConsoleCommand {
+    /// Whether the command changes the machine or how it runs, which would break a movie or a
+    /// netplay session.
+    pub fn changes_machine(&self) -> bool {
+        !matches!(
+            self,
+            ConsoleCommand::Breakpoints
+                | ConsoleCommand::Print(_)
+                | ConsoleCommand::Mem { .. }
+                | ConsoleCommand::Help
+        )
+    }
+
+    /// Runs the command on `cpu`, sending anything to do with running it to `control`, and
+    /// returns what to show for it.
+    pub fn execute(self, cpu: &mut CPU, control: &CpuControl) -> Result<Vec<String>, String> {
+        let done = Ok(Vec::new());
+        match self {
+            ConsoleCommand::Break(breakpoint) => {
+                cpu.add_breakpoint_on(breakpoint);
+                Ok(vec![format!("Breakpoint {}", breakpoint)])
+            }
+            ConsoleCommand::Delete(breakpoint) if !cpu.breakpoints().contains(&breakpoint) => {
+                Err(format!("there's no breakpoint {}", breakpoint))
+            }
+            ConsoleCommand::Delete(breakpoint) => {
+                cpu.remove_breakpoint(breakpoint);
+                done
+            }
+            ConsoleCommand::Breakpoints if cpu.breakpoints().is_empty() => {
+                Ok(vec!["No breakpoints".to_string()])
+            }
+            ConsoleCommand::Breakpoints => {
+                Ok(cpu.breakpoints().iter().map(ToString::to_string).collect())
+            }
+            ConsoleCommand::Print(None) => Ok(registers(cpu)),
+            ConsoleCommand::Print(Some(Target::Register(register))) => Ok(vec![format!(
+                "{} = {:0width$x}",
+                register,
+                register.get(cpu),
+                width = register.width()
+            )]),
+            ConsoleCommand::Print(Some(Target::Address(addr))) => {
+                // Straight from the buffer, so as not to set off watchpoints.
+                match cpu.memory.get(addr as usize) {
+                    Some(byte) => Ok(vec![format!("[{:03x}] = {:02x}", addr, byte)]),
+                    None => Err(format!("{:03x} is past the end of memory", addr)),
+                }
+            }
+            ConsoleCommand::Set(Target::Register(register), value) => {
+                register.set(cpu, value)?;
+                done
+            }
+            ConsoleCommand::Set(Target::Address(addr), value) => {
+                let value =
+                    u8::try_from(value).map_err(|_| "memory only holds a byte".to_string())?;
+                // Straight into the buffer, as a debugger can write anywhere, protected or not.
+                match cpu.memory.get_mut(addr as usize) {
+                    Some(byte) => *byte = value,
+                    None => return Err(format!("{:03x} is past the end of memory", addr)),
+                }
+                done
+            }
+            ConsoleCommand::Mem { addr, len } => {
+                let start = addr as usize;
+                if start >= cpu.memory.len() {
+                    return Err(format!("{:03x} is past the end of memory", addr));
+                }
+                let end = (start + len as usize).min(cpu.memory.len());
+                Ok(cpu.memory[start..end]
+                    .chunks(MEM_BYTES_PER_LINE)
+                    .enumerate()
+                    .map(|(line, bytes)| {
+                        let hex: Vec<_> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                        format!(
+                            "{:03x}  {}",
+                            start + line * MEM_BYTES_PER_LINE,
+                            hex.join(" ")
+                        )
+                    })
+                    .collect())
+            }
+            ConsoleCommand::Step(count) => {
+                for _ in 0..count {
+                    control.send(Command::Step);
+                }
+                done
+            }
+            ConsoleCommand::Next => {
+                control.send(Command::StepOver);
+                done
+            }
+            ConsoleCommand::Frame => {
+                control.send(Command::AdvanceFrame);
+                done
+            }
+            ConsoleCommand::Continue => {
+                control.send(Command::Resume);
+                done
+            }
+            ConsoleCommand::Pause => {
+                control.send(Command::Pause);
+                done
+            }
+            ConsoleCommand::Help => Ok(COMMANDS.iter().map(|(_, help)| help.to_string()).collect()),
+        }
+    }
+}
+
+/// All the registers, as the registers panel shows them.
+fn registers(cpu: &CPU) -> Vec<String> {
+    let v = &cpu.registers.v;
+    let mut lines = vec![format!(
+        "pc {:04x}  i {:04x}  sp {:x}  dt {:02x}  st {:02x}",
+        cpu.pc, cpu.registers.i, cpu.sp, cpu.registers.delay, cpu.registers.sound
+    )];
+    for (half, values) in v.chunks(8).enumerate() {
+        let values: Vec<_> = values
+            .iter()
+            .enumerate()
+            .map(|(x, value)| format!("v{:x} {:02x}", half * 8 + x, value))
+            .collect();
+        lines.push(values.join("  "));
+    }
+    lines
+}
+
+impl fmt::Display for ConsoleCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConsoleCommand::Break(breakpoint) => write!(f, "break {}", breakpoint),
+            ConsoleCommand::Delete(breakpoint) => write!(f, "delete {}", breakpoint),
+            ConsoleCommand::Breakpoints => f.write_str("breakpoints"),
+            ConsoleCommand::Print(None) => f.write_str("print"),
+            ConsoleCommand::Print(Some(target)) => write!(f, "print {}", target),
+            ConsoleCommand::Set(target, value) => write!(f, "set {} {:x}", target, value),
+            ConsoleCommand::Mem { addr, len } => write!(f, "mem {:03x} {}", addr, len),
+            ConsoleCommand::Step(count) => write!(f, "step {}", count),
+            ConsoleCommand::Next => f.write_str("next"),
+            ConsoleCommand::Frame => f.write_str("frame"),
+            ConsoleCommand::Continue => f.write_str("continue"),
+            ConsoleCommand::Pause => f.write_str("pause"),
+            ConsoleCommand::Help => f.write_str("help"),
+        }
+    }
+}
+
+/// Parses a line like `break 0x23a` or `mem 200 32`. Commands can be shortened to any prefix that
+/// isn't ambiguous, like `b`, `c` or `s`.
+impl FromStr for ConsoleCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let Some(word) = words.next() else {
+            return Err("type a command, or help for the list".to_string());
+        };
+        let name = command_named(word)?;
+        let args: Vec<&str> = words.collect();
+        let usage = || {
+            let (_, help) = COMMANDS.iter().find(|(n, _)| *n == name).unwrap();
+            let usage = help.split(':').next().unwrap_or(name);
+            format!("usage: {}", usage)
+        };
+        let hex = |s: &str| {
+            u16::from_str_radix(s.trim_start_matches("0x"), 16)
+                .map_err(|_| format!("'{}' isn't a hex number", s))
+        };
+        let count = |s: &str| {
+            s.parse::<u16>()
+                .map_err(|_| format!("'{}' isn't a count", s))
+        };
+        let command = match (name, args.as_slice()) {
+            ("break", [breakpoint]) => ConsoleCommand::Break(breakpoint.parse()?),
+            ("delete", [breakpoint]) => ConsoleCommand::Delete(breakpoint.parse()?),
+            ("breakpoints", []) => ConsoleCommand::Breakpoints,
+            ("print", []) => ConsoleCommand::Print(None),
+            ("print", [target]) => ConsoleCommand::Print(Some(target.parse()?)),
+            ("set", [target, value]) => ConsoleCommand::Set(target.parse()?, hex(value)?),
+            ("mem", [addr]) => ConsoleCommand::Mem {
+                addr: hex(addr)?,
+                len: DEFAULT_MEM_LEN,
+            },
+            ("mem", [addr, len]) => ConsoleCommand::Mem {
+                addr: hex(addr)?,
+                len: count(len)?,
+            },
+            ("step", []) => ConsoleCommand::Step(1),
+            ("step", [n]) => ConsoleCommand::Step(count(n)?),
+            ("next", []) => ConsoleCommand::Next,
+            ("frame", []) => ConsoleCommand::Frame,
+            ("continue", []) => ConsoleCommand::Continue,
+            ("pause", []) => ConsoleCommand::Pause,
+            ("help", []) => ConsoleCommand::Help,
+            _ => return Err(usage()),
+        };
+        Ok(command)
+    }
+}
+
+/// The command `word` is short for.
+fn command_named(word: &str) -> Result<&'static str, String> {
+    let word = word.to_ascii_lowercase();
+    // `b`, `s` and `p` are the usual debugger shorthands, whatever else they might be short for.
+    let preferred = match word.as_str() {
+        "b" => Some("break"),
+        "s" => Some("step"),
+        "p" => Some("print"),
+        word => COMMANDS
+            .iter()
+            .map(|(name, _)| *name)
+            .find(|name| *name == word),
+    };
+    let matching: Vec<_> = COMMANDS
+        .iter()
+        .map(|(name, _)| *name)
+        .filter(|name| name.starts_with(word.as_str()))
+        .collect();
+    match (preferred, matching.as_slice()) {
+        (Some(name), _) => Ok(name),
+        (None, [name]) => Ok(name),
+        (None, []) => Err(format!("unknown command '{}' (try help)", word)),
+        (None, names) => Err(format!("'{}' could be {}", word, names.join(" or "))),
+    }
+}
+
+/// Ways `line` could go on: the commands it could be the start of, or for `print` and `set`, the
+/// registers. Each is the whole line completed.
+pub fn complete(line: &str) -> Vec<String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let starting_word = line.is_empty() || line.ends_with(char::is_whitespace);
+    match (words.as_slice(), starting_word) {
+        ([], _) | ([_], false) => {
+            let word = words
+                .first()
+                .copied()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            COMMANDS
+                .iter()
+                .filter(|(name, _)| name.starts_with(word.as_str()))
+                .map(|(name, _)| format!("{} ", name))
+                .collect()
+        }
+        ([command], true) | ([command, _], false) => {
+            if !matches!(command_named(command), Ok("print" | "set")) {
+                return Vec::new();
+            }
+            let word = if starting_word { "" } else { words[1] }.to_ascii_lowercase();
+            Register::NAMES
+                .iter()
+                .filter(|name| name.starts_with(word.as_str()))
+                .map(|name| format!("{} {} ", command, name))
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Debugger, Keypad, Memory, Quirks};
+    use std::sync::Arc;
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            "break 0x23A".parse(),
+            Ok(ConsoleCommand::Break(Breakpoint::Address(0x23a)))
+        );
+        assert_eq!(
+            "p v3".parse(),
+            Ok(ConsoleCommand::Print(Some(Target::Register(Register::V(
+                3
+            )))))
+        );
+        assert_eq!(
+            "print 300".parse(),
+            Ok(ConsoleCommand::Print(Some(Target::Address(0x300))))
+        );
+        assert_eq!(
+            "set i 0x300".parse(),
+            Ok(ConsoleCommand::Set(Target::Register(Register::I), 0x300))
+        );
+        assert_eq!(
+            "mem 0x200 32".parse(),
+            Ok(ConsoleCommand::Mem {
+                addr: 0x200,
+                len: 32
+            })
+        );
+        assert_eq!("s 10".parse(), Ok(ConsoleCommand::Step(10)));
+        assert_eq!("c".parse(), Ok(ConsoleCommand::Continue));
+        assert_eq!(
+            "set q 1".parse::<ConsoleCommand>(),
+            Err("unknown register 'q' (expected v0-vf, i, pc, sp, dt or st)".to_string())
+        );
+        assert_eq!(
+            "mem".parse::<ConsoleCommand>(),
+            Err("usage: mem ADDRESS [COUNT]".to_string())
+        );
+        assert_eq!(
+            "bre".parse::<ConsoleCommand>(),
+            Err("'bre' could be break or breakpoints".to_string())
+        );
+        for (name, _) in COMMANDS {
+            let line = match name {
+                "break" | "delete" => format!("{} 2a4", name),
+                "set" => format!("{} v1 2", name),
+                "mem" => format!("{} 200", name),
+                _ => name.to_string(),
+            };
+            let command: ConsoleCommand = line.parse().unwrap();
+            assert_eq!(command.to_string().parse(), Ok(command));
+        }
+    }
+
+    #[test]
+    fn completion() {
+        assert_eq!(complete("br"), ["break ", "breakpoints "]);
+        assert_eq!(complete("co"), ["continue "]);
+        assert_eq!(complete("set p"), ["set pc "]);
+        assert_eq!(complete("print ").len(), Register::NAMES.len());
+        assert!(complete("mem 2").is_empty());
+    }
+
+    #[test]
+    fn executing() {
+        let mut memory = Memory::new();
+        memory[0x200..0x204].copy_from_slice(&[0x70, 0x01, 0x12, 0x00]);
+        let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::default());
+        let control = CpuControl::new();
+        let mut run = |line: &str| line.parse::<ConsoleCommand>()?.execute(&mut cpu, &control);
+
+        assert_eq!(run("set v3 1f"), Ok(vec![]));
+        assert_eq!(run("print v3"), Ok(vec!["v3 = 1f".to_string()]));
+        assert_eq!(run("set v3 100"), Err("v3 only holds a byte".to_string()));
+        assert_eq!(run("break 202"), Ok(vec!["Breakpoint 202".to_string()]));
+        assert_eq!(run("breakpoints"), Ok(vec!["202".to_string()]));
+        assert_eq!(run("delete 202"), Ok(vec![]));
+        assert_eq!(
+            run("delete 202"),
+            Err("there's no breakpoint 202".to_string())
+        );
+        assert_eq!(
+            run("mem 200 10"),
+            Ok(vec![
+                "200  70 01 12 00 00 00 00 00".to_string(),
+                "208  00 00".to_string()
+            ])
+        );
+        assert_eq!(run("print 201"), Ok(vec!["[201] = 01".to_string()]));
+        assert_eq!(run("set 201 2"), Ok(vec![]));
+        assert_eq!(run("print 201"), Ok(vec!["[201] = 02".to_string()]));
+        assert_eq!(
+            run("set 201 1ff"),
+            Err("memory only holds a byte".to_string())
+        );
+        assert_eq!(
+            run("set 1000 0"),
+            Err("1000 is past the end of memory".to_string())
+        );
+    }
+
+    #[test]
+    fn edits_take_effect_on_resume() {
+        // Count up in V0, then jump to counting up in V1 forever.
+        let mut memory = Memory::new();
+        memory[0x200..0x208].copy_from_slice(&[0x70, 0x01, 0x12, 0x04, 0x71, 0x01, 0x12, 0x04]);
+        let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::default());
+        cpu.set_jit(true);
+        let mut debugger = Debugger::new(CpuControl::new());
+        let control = debugger.control();
+        debugger.run_frame(&mut cpu).unwrap();
+
+        let run = |cpu: &mut CPU, line: &str| {
+            let command: ConsoleCommand = line.parse().unwrap();
+            command.execute(cpu, &control).unwrap();
+        };
+        run(&mut cpu, "pause");
+        debugger.run_frame(&mut cpu).unwrap();
+        // Count in fives from 0x10 instead, from the start of the loop.
+        run(&mut cpu, "set pc 204");
+        run(&mut cpu, "set v1 10");
+        run(&mut cpu, "set 205 5");
+        run(&mut cpu, "step 4");
+        debugger.run_frame(&mut cpu).unwrap();
+        assert_eq!(cpu.registers.v[1], 0x1a);
+        assert_eq!(cpu.pc, 0x204);
+    }
+}
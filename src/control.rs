@@ -0,0 +1,259 @@
+use crossbeam_channel::{Receiver, Sender};
+
+use crate::error::Result;
+use crate::{Breakpoint, Rewind, CPU};
+
+/// Something a frontend wants the execution loop to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Pause,
+    Resume,
+    TogglePause,
+    /// Pause, then execute exactly one instruction.
+    Step,
+    /// Like `Step`, but runs a whole subroutine call (2NNN) to completion before pausing again.
+    StepOver,
+    /// Pause, then run exactly one frame, timers and all.
+    AdvanceFrame,
+    /// Pause, then go back to the previous frame boundary, if there's history to go back to.
+    Rewind,
+}
+
+/// A channel for sending `Command`s to whatever is driving the CPU. Cheap to clone, so it can be
+/// handed out to input handlers freely.
+#[derive(Clone)]
+pub struct CpuControl {
+    tx: Sender<Command>,
+    rx: Receiver<Command>,
+}
+
+impl CpuControl {
+    pub fn new() -> Self {
+        let (tx, rx) = crossbeam_channel::unbounded();
+        Self { tx, rx }
+    }
+
+    pub fn send(&self, command: Command) {
+        self.tx.send(command).unwrap();
+    }
+
+    fn try_recv(&self) -> Option<Command> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Default for CpuControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drives a CPU frame by frame, free-running or paused according to the commands arriving on its
+/// `CpuControl`.
+pub struct Debugger {
+    control: CpuControl,
+    paused: bool,
+    /// While stepping over a call, the return address and stack depth to stop at.
+    step_over: Option<(u16, u8)>,
+    /// The breakpoint that caused the current pause, if any.
+    stopped_at: Option<Breakpoint>,
+    /// Recent frames, recorded as they finish.
+    rewind: Rewind,
+}
+
+impl Debugger {
+    pub fn new(control: CpuControl) -> Self {
+        Self {
+            control,
+            paused: false,
+            step_over: None,
+            stopped_at: None,
+            rewind: Rewind::default(),
+        }
+    }
+
+    /// How many frames of history `Command::Rewind` can go back through. Defaults to
+    /// `DEFAULT_REWIND_FRAMES`; 0 turns recording off.
+    pub fn set_rewind_depth(&mut self, depth: usize) {
+        self.rewind.set_depth(depth);
+    }
+
+    /// Forgets the rewind history, e.g. after loading a save state.
+    pub fn clear_rewind(&mut self) {
+        self.rewind.clear();
+    }
+
+    /// A handle for sending this debugger commands.
+    pub fn control(&self) -> CpuControl {
+        self.control.clone()
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused && self.step_over.is_none()
+    }
+
+    /// The breakpoint execution is paused at, if that's why it's paused.
+    pub fn stopped_at(&self) -> Option<Breakpoint> {
+        self.stopped_at
+    }
+
+    /// Handles any pending commands, then runs `cpu` for one 60Hz frame unless paused. Single
+    /// steps don't tick the timers; only whole frames do.
+    pub fn run_frame(&mut self, cpu: &mut CPU) -> Result<()> {
+        let mut advance = false;
+        while let Some(command) = self.control.try_recv() {
+            match command {
+                Command::Pause => self.pause(),
+                Command::Resume => self.resume(),
+                Command::TogglePause if self.paused() => self.resume(),
+                Command::TogglePause => self.pause(),
+                Command::Step => {
+                    self.pause();
+                    cpu.step()?;
+                }
+                Command::StepOver => {
+                    self.pause();
+                    match cpu.opcode_at(cpu.pc) {
+                        Some(0x2000..=0x2fff) => {
+                            self.step_over = Some((cpu.pc.wrapping_add(2), cpu.sp));
+                        }
+                        _ => cpu.step()?,
+                    }
+                }
+                Command::AdvanceFrame => {
+                    self.pause();
+                    advance = true;
+                }
+                Command::Rewind => {
+                    self.pause();
+                    self.rewind.step_back(cpu);
+                }
+            }
+        }
+
+        if let Some((ret, sp)) = self.step_over {
+            if cpu.run_frame_until(|cpu| cpu.pc == ret && cpu.sp == sp)? {
+                self.step_over = None;
+            }
+        } else if !self.paused || advance {
+            cpu.run_frame()?;
+        } else {
+            return Ok(());
+        }
+        self.rewind.record(cpu);
+        if let Some(breakpoint) = cpu.breakpoint_hit() {
+            self.pause();
+            self.stopped_at = Some(breakpoint);
+        }
+        Ok(())
+    }
+
+    fn pause(&mut self) {
+        self.paused = true;
+        self.step_over = None;
+        self.stopped_at = None;
+    }
+
+    fn resume(&mut self) {
+        self.paused = false;
+        self.stopped_at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with;
+
+    #[test]
+    fn pause_and_step() {
+        // Count up in V0 forever.
+        let mut cpu = cpu_with(&[0x7001, 0x1200]);
+        let mut debugger = Debugger::new(CpuControl::new());
+        let control = debugger.control();
+
+        control.send(Command::Pause);
+        debugger.run_frame(&mut cpu).unwrap();
+        assert!(debugger.paused());
+        assert_eq!(cpu.registers.v[0], 0);
+
+        control.send(Command::Step);
+        control.send(Command::Step);
+        control.send(Command::Step);
+        debugger.run_frame(&mut cpu).unwrap();
+        assert_eq!(cpu.registers.v[0], 2);
+        assert_eq!(cpu.pc, 0x202);
+
+        control.send(Command::TogglePause);
+        debugger.run_frame(&mut cpu).unwrap();
+        assert!(!debugger.paused());
+        assert!(cpu.registers.v[0] > 2);
+    }
+
+    #[test]
+    fn frame_advance_runs_one_frame() {
+        // Count up in V0 forever.
+        let mut cpu = cpu_with(&[0x7001, 0x1200]);
+        cpu.set_ips(120);
+        let mut debugger = Debugger::new(CpuControl::new());
+        debugger.control().send(Command::AdvanceFrame);
+        debugger.run_frame(&mut cpu).unwrap();
+        assert!(debugger.paused());
+        assert_eq!(cpu.registers.v[0], 1);
+        debugger.run_frame(&mut cpu).unwrap();
+        assert_eq!(cpu.registers.v[0], 1);
+    }
+
+    #[test]
+    fn step_over_runs_the_whole_call() {
+        // 0x200: call 0x206; 0x202: spin. 0x206: add to V0 20 times, then return.
+        let mut cpu = cpu_with(&[0x2206, 0x1202, 0x0000, 0x7001, 0x3014, 0x1206, 0x00ee]);
+        let mut debugger = Debugger::new(CpuControl::new());
+        debugger.control().send(Command::StepOver);
+        // The call takes several frames at the default clock speed.
+        for _ in 0..10 {
+            debugger.run_frame(&mut cpu).unwrap();
+        }
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.sp, 0);
+        assert_eq!(cpu.registers.v[0], 20);
+        assert!(debugger.paused());
+        assert_eq!(debugger.stopped_at(), None);
+
+        // Anything that isn't a call is just a single step.
+        debugger.control().send(Command::StepOver);
+        debugger.run_frame(&mut cpu).unwrap();
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.snapshot().last_instruction, Some((0x202, 0x1202)));
+    }
+
+    #[test]
+    fn rewind_goes_back_a_frame() {
+        let mut cpu = cpu_with(&[0x7001, 0x1200]);
+        let mut debugger = Debugger::new(CpuControl::new());
+        debugger.run_frame(&mut cpu).unwrap();
+        let first = cpu.save_state();
+        debugger.run_frame(&mut cpu).unwrap();
+        assert_ne!(cpu.save_state(), first);
+
+        debugger.control().send(Command::Rewind);
+        debugger.run_frame(&mut cpu).unwrap();
+        assert!(debugger.paused());
+        assert_eq!(cpu.save_state(), first);
+    }
+
+    #[test]
+    fn breakpoints_pause() {
+        let mut cpu = cpu_with(&[0x7001, 0x1200]);
+        cpu.add_breakpoint(0x202);
+        let mut debugger = Debugger::new(CpuControl::new());
+        debugger.run_frame(&mut cpu).unwrap();
+        assert!(debugger.paused());
+        assert_eq!(debugger.stopped_at(), Some(Breakpoint::Address(0x202)));
+
+        debugger.control().send(Command::Resume);
+        debugger.run_frame(&mut cpu).unwrap();
+        assert_eq!(cpu.registers.v[0], 2);
+        assert_eq!(debugger.stopped_at(), Some(Breakpoint::Address(0x202)));
+    }
+}
@@ -0,0 +1,808 @@
+//! A CHIP-8 machine that needs neither `std` nor an allocator, for running ROMs on
+//! microcontrollers driving real displays like LED matrices. Build the crate with
+//! `default-features = false` to get only this module and what it depends on.
+//!
+//! It runs the original CHIP-8 instruction set with the same `Quirks` as `CPU`, but none of what
+//! `CPU` has for debugging and the extended variants. Everything is in fixed-size arrays owned by
+//! the `Machine`, about 4.5K in all, and whatever drives it reads the display and presses keys
+//! on it directly.
+//!
+//! Fetching, decoding and running the instructions is here for `CPU` to run too, through
+//! `Interpreter`, so that every interpreter agrees on them; its compiled blocks run the
+//! instructions that only touch the registers through `Registers::execute`.
+use ::core::fmt;
+use ::core::ops::{Deref, DerefMut};
+
+use crate::disasm::Instr;
+use crate::error::{Chip8Error, Result};
+use crate::{Quirks, Rng, Variant};
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+/// RAM size of the original interpreters.
+pub const MEMORY_SIZE: usize = 0x1000;
+/// Where ROMs are loaded, right after the interpreter area.
+pub const PROGRAM_START: usize = 0x200;
+/// Where the 4x5 hex digit sprites used by Fx29 live.
+pub const FONT_ADDR: usize = 0x000;
+/// Where the SUPER-CHIP 8x10 hex digit sprites used by Fx30 live, right after the small font.
+/// Only `CPU`'s memory has them.
+pub const BIG_FONT_ADDR: usize = 0x050;
+
+/// The 4x5 sprites for the hex digits, 5 bytes each.
+pub const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// The ROM is `size` bytes, but only `max` fit between 0x200 and the end of RAM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomTooBig {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl fmt::Display for RomTooBig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the ROM is {} bytes, but only {} fit in memory",
+            self.size, self.max
+        )
+    }
+}
+
+/// 4K of RAM with the font loaded.
+#[derive(Clone)]
+pub struct Memory {
+    buf: [u8; MEMORY_SIZE],
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        let mut buf = [0; MEMORY_SIZE];
+        buf[FONT_ADDR..FONT_ADDR + FONT.len()].copy_from_slice(&FONT);
+        Self { buf }
+    }
+
+    /// Copies `rom` in at 0x200.
+    pub fn load_rom(&mut self, rom: &[u8]) -> ::core::result::Result<(), RomTooBig> {
+        let max = MEMORY_SIZE - PROGRAM_START;
+        if rom.len() > max {
+            return Err(RomTooBig {
+                size: rom.len(),
+                max,
+            });
+        }
+        self.buf[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(rom);
+        Ok(())
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for Memory {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for Memory {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+/// The 16-key hex keypad as a bitmask, bit `n` set while key `n` is held. Only the low nibble
+/// of a key is significant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Keypad {
+    state: u16,
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(&mut self, key: u8) {
+        self.state |= 1 << (key & 0xf);
+    }
+
+    pub fn release(&mut self, key: u8) {
+        self.state &= !(1 << (key & 0xf));
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.state & 1 << (key & 0xf) != 0
+    }
+
+    pub fn state(&self) -> u16 {
+        self.state
+    }
+
+    /// Sets every key at once, e.g. from a scan of a hardware keypad matrix.
+    pub fn set_state(&mut self, state: u16) {
+        self.state = state;
+    }
+}
+
+/// The 64x32 display, one bit per pixel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    rows: [u64; HEIGHT],
+    changed: bool,
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        Self {
+            rows: [0; HEIGHT],
+            changed: true,
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.rows[y] & Self::mask(x) != 0
+    }
+
+    /// Each row as a `u64` with the leftmost pixel in the top bit, the order most LED matrix
+    /// drivers shift them out in.
+    pub fn rows(&self) -> &[u64; HEIGHT] {
+        &self.rows
+    }
+
+    /// Whether anything has been drawn or cleared since the last call, so a display only needs
+    /// refreshing when it has.
+    pub fn take_changed(&mut self) -> bool {
+        ::core::mem::take(&mut self.changed)
+    }
+
+    fn clear(&mut self) {
+        self.rows = [0; HEIGHT];
+        self.changed = true;
+    }
+
+    /// Flips the pixel at (`x`, `y`), returning whether it was lit.
+    fn xor_pixel(&mut self, x: usize, y: usize) -> bool {
+        let was_lit = self.pixel(x, y);
+        self.rows[y] ^= Self::mask(x);
+        self.changed = true;
+        was_lit
+    }
+
+    fn mask(x: usize) -> u64 {
+        1 << (WIDTH - 1 - x)
+    }
+}
+
+impl Default for FrameBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// https://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.2
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Registers {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub delay: u8,
+    pub sound: u8,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Self {
+            v: [0; 16],
+            i: 0,
+            delay: 0,
+            sound: 0,
+        }
+    }
+
+    /// Runs `instr` if it only reads and writes the registers, and returns whether it did. I
+    /// wraps around at 64K.
+    pub fn execute(&mut self, instr: Instr, quirks: Quirks) -> bool {
+        let v = &mut self.v;
+        match instr {
+            // 6xkk - ld vx, byte
+            Instr::LoadByte(x, kk) => v[x as usize] = kk,
+            // 7xkk - add vx, byte
+            Instr::AddByte(x, kk) => v[x as usize] = v[x as usize].wrapping_add(kk),
+            // 8xy0 - ld vx, vy
+            Instr::Move(x, y) => v[x as usize] = v[y as usize],
+            // 8xy1 - or vx, vy, 8xy2 - and vx, vy, 8xy3 - xor vx, vy
+            Instr::Or(x, y) | Instr::And(x, y) | Instr::Xor(x, y) => {
+                let (vx, vy) = (v[x as usize], v[y as usize]);
+                v[x as usize] = match instr {
+                    Instr::Or(..) => vx | vy,
+                    Instr::And(..) => vx & vy,
+                    _ => vx ^ vy,
+                };
+                if quirks.vf_reset {
+                    v[0xf] = 0;
+                }
+            }
+            // 8xy4 - add vx, vy
+            Instr::Add(x, y) => {
+                let (res, overflow) = v[x as usize].overflowing_add(v[y as usize]);
+                v[x as usize] = res;
+                v[0xf] = overflow as u8;
+            }
+            // 8xy5 - sub vx, vy
+            Instr::Sub(x, y) => {
+                let (res, overflow) = v[x as usize].overflowing_sub(v[y as usize]);
+                v[x as usize] = res;
+                // not borrow
+                v[0xf] = !overflow as u8;
+            }
+            // 8xy7 - subn vx, vy
+            Instr::SubN(x, y) => {
+                let (res, overflow) = v[y as usize].overflowing_sub(v[x as usize]);
+                v[x as usize] = res;
+                v[0xf] = !overflow as u8;
+            }
+            // 8xy6 - shr vx {, vy}, 8xye - shl vx {, vy}
+            // without the shift quirk, vy is shifted into vx rather than vx in place.
+            Instr::Shr(x, y) | Instr::Shl(x, y) => {
+                if !quirks.shift {
+                    v[x as usize] = v[y as usize];
+                }
+                let vx = v[x as usize];
+                let (res, flag) = match instr {
+                    Instr::Shr(..) => (vx >> 1, vx & 0x1),
+                    _ => (vx << 1, vx >> 7),
+                };
+                v[x as usize] = res;
+                v[0xf] = flag;
+            }
+            // annn - ld i, addr
+            Instr::LoadI(nnn) => self.i = nnn,
+            // fx1e - add i, vx
+            Instr::AddI(x) => self.i = self.i.wrapping_add(v[x as usize] as u16),
+            // fx29 - ld f, vx
+            // set i = location of sprite for digit vx.
+            Instr::Font(x) => self.i = (FONT_ADDR + (v[x as usize] & 0xf) as usize * 5) as u16,
+            // fx30 - ld hf, vx (schip)
+            // set i = location of the 8x10 big font sprite for digit vx.
+            Instr::BigFont(x) => {
+                self.i = (BIG_FONT_ADDR + (v[x as usize] & 0xf) as usize * 10) as u16
+            }
+            // fx07 - ld vx, dt
+            Instr::GetDelay(x) => v[x as usize] = self.delay,
+            // fx15 - ld dt, vx
+            Instr::SetDelay(x) => self.delay = v[x as usize],
+            // fx18 - ld st, vx
+            Instr::SetSound(x) => self.sound = v[x as usize],
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Default for Registers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// FX0A's wait for a key, with `held_key` the key it's seen pressed so far. Like the COSMAC VIP,
+/// the key is only taken once it's been released again, so a ROM looping on FX0A doesn't read
+/// the same press twice: returns it then, and `None` until then, while FX0A should run again.
+pub fn wait_for_key(held_key: &mut Option<u8>, is_pressed: impl Fn(u8) -> bool) -> Option<u8> {
+    match *held_key {
+        Some(key) if !is_pressed(key) => held_key.take(),
+        Some(_) => None,
+        None => {
+            *held_key = (0..16).find(|&key| is_pressed(key));
+            None
+        }
+    }
+}
+
+/// What running an instruction needs from the interpreter it runs on. `Machine` and `CPU` both
+/// run every instruction through `step`, so they can't disagree on one: `CPU` only adds the
+/// instructions of the extended variants, and what it keeps track of for debugging.
+pub(crate) trait Interpreter {
+    fn registers(&self) -> &Registers;
+    fn registers_mut(&mut self) -> &mut Registers;
+    fn pc_mut(&mut self) -> &mut u16;
+    /// The call stack and how much of it is in use.
+    fn stack(&mut self) -> (&mut [u16; 16], &mut u8);
+    fn quirks(&self) -> Quirks;
+    fn rng(&mut self) -> &mut Rng;
+    /// The keys held, as a bitmask like `Keypad::state`.
+    fn keys(&self) -> u16;
+    /// The key FX0A saw pressed and is waiting to be released.
+    fn held_key(&mut self) -> &mut Option<u8>;
+    /// Whether a sprite has been drawn since the last 60Hz tick, for the display-wait quirk.
+    fn drawn_this_frame(&mut self) -> &mut bool;
+    /// Holds everything until the next 60Hz tick, for a DXYN stalled by the display-wait quirk.
+    fn wait_for_vblank(&mut self);
+
+    /// The opcode at `pc`, or an error if it's out of reach.
+    fn fetch(&mut self, pc: u16) -> Result<u16>;
+    /// The instruction `opcode` at `pc` decodes to, if it's one this interpreter runs.
+    fn decode(&mut self, pc: u16, opcode: u16) -> Option<Instr>;
+    /// Runs `instr` if the interpreter has its own way of running it, returning whether it did.
+    /// Everything else is run the same everywhere.
+    fn execute_extended(&mut self, _pc: u16, _instr: Instr) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Copies the bytes from `addr` on into `buf`, or errors for the instruction at `pc` if they
+    /// can't all be read.
+    fn read(&mut self, pc: u16, addr: usize, buf: &mut [u8]) -> Result<()>;
+    /// Copies `bytes` to memory from `addr` on, or errors for the instruction at `pc` if they
+    /// can't all be written.
+    fn write(&mut self, pc: u16, addr: usize, bytes: &[u8]) -> Result<()>;
+
+    /// The address in I.
+    fn i_addr(&self) -> usize {
+        self.registers().i as usize
+    }
+
+    /// Points I at `addr`.
+    fn set_i(&mut self, addr: usize) {
+        self.registers_mut().i = addr as u16;
+    }
+
+    /// Skips the next instruction.
+    fn skip(&mut self) {
+        let pc = self.pc_mut();
+        *pc = pc.wrapping_add(2);
+    }
+
+    fn clear(&mut self);
+    fn display_size(&self) -> (usize, usize);
+    /// Flips the pixel at (`x`, `y`) on `plane`, returning whether it was lit.
+    fn xor_pixel(&mut self, x: usize, y: usize, plane: u8) -> bool;
+
+    /// The planes that drawing affects, as a bitmask.
+    fn planes(&self) -> u8 {
+        1
+    }
+
+    /// The width and height of the sprite DXYN draws, for an `n` of its height.
+    fn sprite_size(&self, n: u8) -> (usize, usize) {
+        (8, n as usize)
+    }
+
+    /// Whether DXYN sets VF to how many rows collided, rather than to whether any did.
+    fn counts_collided_rows(&self) -> bool {
+        false
+    }
+
+    /// Draws the sprite at I at (VX, VY), for DXYN.
+    fn draw(&mut self, pc: u16, x: u8, y: u8, n: u8) -> Result<()> {
+        draw_sprite(self, pc, x, y, n)
+    }
+}
+
+/// Fetches, decodes and executes the instruction at the PC of `machine`.
+pub(crate) fn step(machine: &mut (impl Interpreter + ?Sized)) -> Result<()> {
+    let pc = *machine.pc_mut();
+    let opcode = machine.fetch(pc)?;
+    *machine.pc_mut() = pc.wrapping_add(2);
+    let unknown = || Chip8Error::UnknownOpcode { pc, opcode };
+    let instr = machine.decode(pc, opcode).ok_or_else(unknown)?;
+    if machine.execute_extended(pc, instr)? {
+        return Ok(());
+    }
+    let quirks = machine.quirks();
+    if machine.registers_mut().execute(instr, quirks) {
+        // annn, fx1e, fx29 and fx30 point i somewhere under 64k, with nothing left in
+        // megachip's top byte.
+        if let Instr::LoadI(_) | Instr::AddI(_) | Instr::Font(_) | Instr::BigFont(_) = instr {
+            let i = machine.registers().i;
+            machine.set_i(i as usize);
+        }
+        return Ok(());
+    }
+    let v = machine.registers().v;
+    let keys = machine.keys();
+    let pressed = |key: u8| keys & 1 << (key & 0xf) != 0;
+    match instr {
+        // 00e0 - cls
+        Instr::Cls => machine.clear(),
+        // 00ee - ret
+        Instr::Ret => {
+            let (stack, sp) = machine.stack();
+            if *sp == 0 {
+                return Err(Chip8Error::StackUnderflow { pc });
+            }
+            *sp -= 1;
+            let addr = stack[*sp as usize];
+            *machine.pc_mut() = addr;
+        }
+        // 1nnn - jp addr
+        Instr::Jump(nnn) => *machine.pc_mut() = nnn,
+        // 2nnn - call addr
+        Instr::Call(nnn) => {
+            let ret = *machine.pc_mut();
+            let (stack, sp) = machine.stack();
+            if *sp as usize == stack.len() {
+                return Err(Chip8Error::StackOverflow {
+                    pc,
+                    depth: stack.len(),
+                });
+            }
+            stack[*sp as usize] = ret;
+            *sp += 1;
+            *machine.pc_mut() = nnn;
+        }
+        // 3xkk - se vx, byte, 4xkk - sne vx, byte
+        Instr::SkipEqByte(x, kk) => skip_if(machine, v[x as usize] == kk),
+        Instr::SkipNeByte(x, kk) => skip_if(machine, v[x as usize] != kk),
+        // 5xy0 - se vx, vy, 9xy0 - sne vx, vy
+        Instr::SkipEqReg(x, y) => skip_if(machine, v[x as usize] == v[y as usize]),
+        Instr::SkipNeReg(x, y) => skip_if(machine, v[x as usize] != v[y as usize]),
+        // bnnn - jp v0, addr
+        // with the jump quirk, this is bxnn instead: jump to xnn + vx.
+        Instr::JumpV0(nnn) => {
+            let offset = v[if quirks.jump { nnn >> 8 } else { 0 } as usize];
+            *machine.pc_mut() = nnn + offset as u16;
+        }
+        // cxkk - rnd vx, byte
+        Instr::Rand(x, kk) => {
+            let random = machine.rng().next_u8();
+            machine.registers_mut().v[x as usize] = random & kk;
+        }
+        // dxyn - drw vx, vy, n
+        // with the display-wait quirk, only one sprite is drawn per frame: any further dxyn
+        // stalls on the pc until the next 60hz tick.
+        Instr::Draw(..) if quirks.display_wait && *machine.drawn_this_frame() => {
+            *machine.pc_mut() = pc;
+            machine.wait_for_vblank();
+        }
+        Instr::Draw(x, y, n) => {
+            *machine.drawn_this_frame() = true;
+            machine.draw(pc, x, y, n)?;
+        }
+        // ex9e - skp vx, exa1 - sknp vx
+        Instr::SkipKey(x) => skip_if(machine, pressed(v[x as usize])),
+        Instr::SkipNotKey(x) => skip_if(machine, !pressed(v[x as usize])),
+        // fx0a - ld vx, k
+        // until a key's been pressed and released, the pc is left on this instruction so it
+        // runs again; the timers keep counting meanwhile.
+        Instr::WaitKey(x) => match wait_for_key(machine.held_key(), pressed) {
+            Some(key) => machine.registers_mut().v[x as usize] = key,
+            None => *machine.pc_mut() = pc,
+        },
+        // fx33 - ld b, vx
+        Instr::Bcd(x) => {
+            let vx = v[x as usize];
+            let i = machine.i_addr();
+            machine.write(pc, i, &[vx / 100, vx / 10 % 10, vx % 10])?;
+        }
+        // fx55 - ld [i], vx
+        Instr::Store(x) => {
+            let i = machine.i_addr();
+            machine.write(pc, i, &v[..=x as usize])?;
+            machine.set_i(i + quirks.loadstore_step(x) as usize);
+        }
+        // fx65 - ld vx, [i]
+        Instr::Restore(x) => {
+            let i = machine.i_addr();
+            let mut values = [0; 16];
+            machine.read(pc, i, &mut values[..=x as usize])?;
+            machine.registers_mut().v[..=x as usize].copy_from_slice(&values[..=x as usize]);
+            machine.set_i(i + quirks.loadstore_step(x) as usize);
+        }
+        _ => return Err(unknown()),
+    }
+    Ok(())
+}
+
+fn skip_if(machine: &mut (impl Interpreter + ?Sized), condition: bool) {
+    if condition {
+        machine.skip();
+    }
+}
+
+/// Draws the sprite at I at (VX, VY) on each of the planes selected, wrapping it around the
+/// edges unless the clip quirk is on, and sets VF for whether it collided with anything. With
+/// both planes selected, the second plane's sprite follows the first's.
+pub(crate) fn draw_sprite(
+    machine: &mut (impl Interpreter + ?Sized),
+    pc: u16,
+    x: u8,
+    y: u8,
+    n: u8,
+) -> Result<()> {
+    let (width, height) = machine.display_size();
+    let v = machine.registers().v;
+    let vx = v[x as usize] as usize % width;
+    let vy = v[y as usize] as usize % height;
+    let (cols, rows) = machine.sprite_size(n);
+    let planes = machine.planes();
+    let sprite_len = rows * cols / 8;
+    // Two planes of a SUPER-CHIP 16x16 sprite at most.
+    let mut sprites = [0; 64];
+    let sprites = &mut sprites[..sprite_len * planes.count_ones() as usize];
+    let i = machine.i_addr();
+    machine.read(pc, i, sprites)?;
+
+    let clip = machine.quirks().clip;
+    let mut collided_rows = 0;
+    let mut sprite = &sprites[..];
+    for plane in [1, 2] {
+        if planes & plane == 0 {
+            continue;
+        }
+        for row in 0..rows {
+            let mut collided = false;
+            for col in 0..cols {
+                let byte = sprite[row * cols / 8 + col / 8];
+                let (px, py) = (vx + col, vy + row);
+                if byte >> (7 - col % 8) & 1 == 0 || clip && (px >= width || py >= height) {
+                    continue;
+                }
+                collided |= machine.xor_pixel(px % width, py % height, plane);
+            }
+            collided_rows += collided as u8;
+        }
+        sprite = &sprite[sprite_len..];
+    }
+    machine.registers_mut().v[0xf] = if machine.counts_collided_rows() {
+        collided_rows
+    } else {
+        (collided_rows > 0) as u8
+    };
+    Ok(())
+}
+
+/// A CHIP-8 interpreter and everything attached to it.
+#[derive(Clone)]
+pub struct Machine {
+    pub memory: Memory,
+    pub display: FrameBuffer,
+    pub keypad: Keypad,
+    registers: Registers,
+    pc: u16,
+    stack: [u16; 16],
+    sp: u8,
+    quirks: Quirks,
+    rng: Rng,
+    /// The key FX0A saw pressed and is waiting to be released.
+    held_key: Option<u8>,
+    drawn_this_frame: bool,
+    waiting_for_vblank: bool,
+}
+
+impl Machine {
+    /// A machine with nothing loaded. There's no clock to seed `rng` from, so pick a seed from
+    /// whatever the hardware has, like an unconnected ADC pin.
+    pub fn new(quirks: Quirks, rng: Rng) -> Self {
+        Self {
+            memory: Memory::new(),
+            display: FrameBuffer::new(),
+            keypad: Keypad::new(),
+            registers: Registers::new(),
+            pc: PROGRAM_START as u16,
+            stack: [0; 16],
+            sp: 0,
+            quirks,
+            rng,
+            held_key: None,
+            drawn_this_frame: false,
+            waiting_for_vblank: false,
+        }
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> ::core::result::Result<(), RomTooBig> {
+        self.memory.load_rom(rom)
+    }
+
+    pub fn v(&self) -> &[u8; 16] {
+        &self.registers.v
+    }
+
+    pub fn i(&self) -> u16 {
+        self.registers.i
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn delay(&self) -> u8 {
+        self.registers.delay
+    }
+
+    pub fn sound(&self) -> u8 {
+        self.registers.sound
+    }
+
+    /// Whether the buzzer should currently be sounding.
+    pub fn beeping(&self) -> bool {
+        self.registers.sound > 0
+    }
+
+    /// Runs one 60Hz frame: ticks the timers, then executes up to `instructions` instructions,
+    /// `DEFAULT_IPS / 60` for the usual speed. Stops early at the first error, or when the
+    /// display wait quirk holds a sprite until the next frame.
+    pub fn run_frame(&mut self, instructions: u32) -> Result<()> {
+        self.tick_timers();
+        for _ in 0..instructions {
+            self.step()?;
+            if self.waiting_for_vblank {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Decrements the delay and sound timers. Should be called at 60Hz, and doubles as the
+    /// vertical blank that DXYN waits for under the display-wait quirk.
+    pub fn tick_timers(&mut self) {
+        self.drawn_this_frame = false;
+        self.waiting_for_vblank = false;
+        self.registers.delay = self.registers.delay.saturating_sub(1);
+        self.registers.sound = self.registers.sound.saturating_sub(1);
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    pub fn step(&mut self) -> Result<()> {
+        step(self)
+    }
+}
+
+impl Interpreter for Machine {
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    fn pc_mut(&mut self) -> &mut u16 {
+        &mut self.pc
+    }
+
+    fn stack(&mut self) -> (&mut [u16; 16], &mut u8) {
+        (&mut self.stack, &mut self.sp)
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    fn keys(&self) -> u16 {
+        self.keypad.state()
+    }
+
+    fn held_key(&mut self) -> &mut Option<u8> {
+        &mut self.held_key
+    }
+
+    fn drawn_this_frame(&mut self) -> &mut bool {
+        &mut self.drawn_this_frame
+    }
+
+    fn wait_for_vblank(&mut self) {
+        self.waiting_for_vblank = true;
+    }
+
+    fn fetch(&mut self, pc: u16) -> Result<u16> {
+        match self.memory.get(pc as usize..pc as usize + 2) {
+            Some(&[high, low]) => Ok(u16::from_be_bytes([high, low])),
+            _ => Err(Chip8Error::MemoryOutOfBounds {
+                pc,
+                addr: pc as usize,
+            }),
+        }
+    }
+
+    fn decode(&mut self, _pc: u16, opcode: u16) -> Option<Instr> {
+        Instr::decode(opcode, Variant::Chip8)
+    }
+
+    fn read(&mut self, pc: u16, addr: usize, buf: &mut [u8]) -> Result<()> {
+        let bytes = self.memory.get(addr..addr + buf.len());
+        buf.copy_from_slice(bytes.ok_or(Chip8Error::MemoryOutOfBounds { pc, addr })?);
+        Ok(())
+    }
+
+    fn write(&mut self, pc: u16, addr: usize, bytes: &[u8]) -> Result<()> {
+        let dest = self.memory.get_mut(addr..addr + bytes.len());
+        dest.ok_or(Chip8Error::MemoryOutOfBounds { pc, addr })?
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.display.clear();
+    }
+
+    fn display_size(&self) -> (usize, usize) {
+        (WIDTH, HEIGHT)
+    }
+
+    fn xor_pixel(&mut self, x: usize, y: usize, _plane: u8) -> bool {
+        self.display.xor_pixel(x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_a_rom_without_std() {
+        // ld v0, 0x0a; ld i, 0x300; ld b, v0; ld f, v0; drw v1, v1, 5; drw v1, v1, 5; jp 0x20c
+        let rom = [
+            0x60, 0x0a, 0xa3, 0x00, 0xf0, 0x33, 0xf0, 0x29, 0xd1, 0x15, 0xd1, 0x15, 0x12, 0x0c,
+        ];
+        let mut machine = Machine::new(Quirks::preset(Variant::Chip8), Rng::new(1));
+        machine.load_rom(&rom).unwrap();
+
+        machine.run_frame(20).unwrap();
+        // The display wait quirk holds the second sprite until the next frame.
+        assert_eq!(machine.pc(), 0x20a);
+        assert_eq!(machine.display.rows()[0] >> 60, 0xf);
+        assert_eq!(machine.display.rows()[1] >> 60, 0x9);
+        assert_eq!(&machine.memory[0x300..0x303], &[0, 1, 0]);
+        assert!(machine.display.take_changed());
+        assert!(!machine.display.take_changed());
+
+        machine.run_frame(20).unwrap();
+        // Drawing the same sprite again erases it, and collides.
+        assert_eq!(machine.v()[0xf], 1);
+        assert_eq!(machine.display.rows()[0], 0);
+        assert_eq!(machine.pc(), 0x20c);
+    }
+
+    #[test]
+    fn waits_for_a_key_to_be_released() {
+        // ld v3, k
+        let mut machine = Machine::new(Quirks::default(), Rng::new(1));
+        machine.load_rom(&[0xf3, 0x0a]).unwrap();
+        machine.step().unwrap();
+        machine.keypad.press(0xb);
+        machine.step().unwrap();
+        machine.step().unwrap();
+        assert_eq!(machine.pc(), 0x200);
+        machine.keypad.release(0xb);
+        machine.step().unwrap();
+        assert_eq!((machine.pc(), machine.v()[3]), (0x202, 0xb));
+    }
+
+    #[test]
+    fn rejects_a_rom_too_big_for_memory() {
+        let mut machine = Machine::new(Quirks::default(), Rng::new(1));
+        assert_eq!(
+            machine.load_rom(&[0; 4000]),
+            Err(RomTooBig {
+                size: 4000,
+                max: 0xe00
+            })
+        );
+    }
+}
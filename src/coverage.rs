@@ -0,0 +1,209 @@
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+
+const EXECUTED: u8 = 1;
+const READ: u8 = 2;
+
+/// How a byte of memory has been used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Usage {
+    Unused,
+    /// Executed as part of an instruction.
+    Code,
+    /// Read through I, by a sprite draw or a register load.
+    Data,
+    /// Both, as in a ROM that reads its own code.
+    Both,
+}
+
+impl Usage {
+    fn from_flags(flags: u8) -> Self {
+        match flags & (EXECUTED | READ) {
+            0 => Usage::Unused,
+            EXECUTED => Usage::Code,
+            READ => Usage::Data,
+            _ => Usage::Both,
+        }
+    }
+
+    fn flags(self) -> u8 {
+        match self {
+            Usage::Unused => 0,
+            Usage::Code => EXECUTED,
+            Usage::Data => READ,
+            Usage::Both => EXECUTED | READ,
+        }
+    }
+
+    /// Whether the byte was executed.
+    pub fn is_code(self) -> bool {
+        self.flags() & EXECUTED != 0
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Usage::Unused => "unused",
+            Usage::Code => "code",
+            Usage::Data => "data",
+            Usage::Both => "code+data",
+        })
+    }
+}
+
+impl FromStr for Usage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unused" => Ok(Usage::Unused),
+            "code" => Ok(Usage::Code),
+            "data" => Ok(Usage::Data),
+            "code+data" => Ok(Usage::Both),
+            _ => Err(format!("'{}' isn't unused, code, data or code+data", s)),
+        }
+    }
+}
+
+/// Which bytes of memory have ever been executed, and which read as data. Over a ROM, that tells
+/// its code from its data better than following jumps can, and shows up the parts that never got
+/// used at all.
+///
+/// It's saved as a map with a line per run of bytes used the same way, from the first address to
+/// the last in hex, like `200-2a3 code`, which `Disassembly::with_coverage` can take.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// Flags for each byte, grown as far as the highest address used.
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn mark(&mut self, addr: usize, flag: u8) {
+        if addr >= self.flags.len() {
+            self.flags.resize(addr + 1, 0);
+        }
+        self.flags[addr] |= flag;
+    }
+
+    /// Notes the `len` bytes from `addr` as executed.
+    pub fn execute(&mut self, addr: usize, len: usize) {
+        for addr in addr..addr + len {
+            self.mark(addr, EXECUTED);
+        }
+    }
+
+    /// Notes the byte at `addr` as read as data.
+    pub fn read(&mut self, addr: usize) {
+        self.mark(addr, READ);
+    }
+
+    pub fn usage(&self, addr: usize) -> Usage {
+        Usage::from_flags(self.flags.get(addr).copied().unwrap_or(0))
+    }
+
+    /// Forgets everything seen so far.
+    pub fn clear(&mut self) {
+        self.flags.clear();
+    }
+
+    /// The runs of bytes used the same way across `range`, in order.
+    pub fn runs(&self, range: Range<usize>) -> Vec<(Range<usize>, Usage)> {
+        let mut runs: Vec<(Range<usize>, Usage)> = Vec::new();
+        for addr in range {
+            let usage = self.usage(addr);
+            match runs.last_mut() {
+                Some((run, last)) if *last == usage => run.end = addr + 1,
+                _ => runs.push((addr..addr + 1, usage)),
+            }
+        }
+        runs
+    }
+
+    /// The map of the bytes in `range`.
+    pub fn map(&self, range: Range<usize>) -> String {
+        let mut map =
+            String::from("; chip8 coverage map: first-last address, then how it was used\n");
+        for (run, usage) in self.runs(range) {
+            map.push_str(&format!(
+                "{:03x}-{:03x} {}\n",
+                run.start,
+                run.end - 1,
+                usage
+            ));
+        }
+        map
+    }
+
+    /// Reads a map written by `map`. Lines starting with `;` are comments.
+    pub fn parse_map(map: &str) -> Result<Self, String> {
+        let mut coverage = Self::new();
+        for (n, line) in map.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let (run, usage) = parse_run(line).map_err(|err| format!("line {}: {}", n + 1, err))?;
+            for addr in run {
+                coverage.mark(addr, usage.flags());
+            }
+        }
+        Ok(coverage)
+    }
+
+    /// Writes the map of the bytes in `range` to `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P, range: Range<usize>) -> io::Result<()> {
+        std::fs::write(path, self.map(range))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse_map(&std::fs::read_to_string(path)?)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+/// Parses a line of a map, like `200-2a3 code`.
+fn parse_run(line: &str) -> Result<(Range<usize>, Usage), String> {
+    let (run, usage) = line
+        .split_once(' ')
+        .ok_or("expected a range and how it was used")?;
+    let (first, last) = run.split_once('-').ok_or("expected first-last")?;
+    let addr = |s| usize::from_str_radix(s, 16).map_err(|_| format!("bad address '{}'", s));
+    Ok((addr(first)?..addr(last)? + 1, usage.trim().parse()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_runs_of_code_and_data() {
+        let mut coverage = Coverage::new();
+        coverage.execute(0x200, 4);
+        coverage.read(0x204);
+        coverage.read(0x205);
+        coverage.execute(0x205, 2);
+        assert_eq!(coverage.usage(0x205), Usage::Both);
+        assert_eq!(coverage.usage(0x300), Usage::Unused);
+
+        let map = coverage.map(0x200..0x20a);
+        assert!(map.ends_with(
+            "200-203 code\n204-204 data\n205-205 code+data\n206-206 code\n207-209 unused\n"
+        ));
+        assert_eq!(
+            Coverage::parse_map(&map).unwrap().runs(0x200..0x20a),
+            coverage.runs(0x200..0x20a)
+        );
+        assert_eq!(
+            Coverage::parse_map("200-20x code").unwrap_err(),
+            "line 1: bad address '20x'"
+        );
+    }
+}
@@ -0,0 +1,2167 @@
+use std::sync::Arc;
+
+use tracing::{debug_span, trace_span};
+
+use crate::audio::{AudioPattern, DigitizedSound};
+use crate::breakpoint::Breakpoint;
+use crate::core::Interpreter;
+pub use crate::core::Registers;
+use crate::coverage::Coverage;
+use crate::decode::DecodeCache;
+use crate::disasm::Instr;
+use crate::error::{Chip8Error, Result};
+use crate::jit::BlockCache;
+#[cfg(feature = "megachip")]
+use crate::megachip::{self, MegaChip, PlayingSound, MEGA_DISPLAY_HEIGHT, MEGA_DISPLAY_WIDTH};
+use crate::memimage::MemoryImage;
+use crate::memory::{OutOfBounds, RomError};
+use crate::profile::Profiler;
+use crate::rng::Rng;
+use crate::savestate::SaveState;
+use crate::timing::{Timing, VIP_FRAME_BUDGET};
+use crate::trace::Tracer;
+use crate::{FrameBuffer, Keypad, Memory, Quirks, Symbols, Variant};
+
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+/// SUPER-CHIP's hi-res mode doubles the resolution in both directions.
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+/// The two-page HIRES CHIP-8 interpreter doubled the height only.
+pub const TWO_PAGE_DISPLAY_HEIGHT: usize = 64;
+/// Where HIRES CHIP-8 ROMs start once the interpreter has switched to 64x64. The ROM's first
+/// instruction jumps to 0x260, where the patch to the VIP's interpreter that it carries begins.
+const TWO_PAGE_START: u16 = 0x2c0;
+
+/// The size the display is running at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Resolution {
+    /// 64x32, what everything starts in.
+    #[default]
+    Low,
+    /// 64x64, for ROMs written for the two-page HIRES CHIP-8 interpreter.
+    TwoPage,
+    /// SUPER-CHIP's 128x64.
+    High,
+    /// MegaChip's 256x192, with a palette index for each pixel.
+    #[cfg(feature = "megachip")]
+    Mega,
+}
+
+impl Resolution {
+    /// The display's width and height at this resolution.
+    pub fn size(self) -> (usize, usize) {
+        match self {
+            Resolution::Low => (DISPLAY_WIDTH, DISPLAY_HEIGHT),
+            Resolution::TwoPage => (DISPLAY_WIDTH, TWO_PAGE_DISPLAY_HEIGHT),
+            Resolution::High => (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT),
+            #[cfg(feature = "megachip")]
+            Resolution::Mega => (MEGA_DISPLAY_WIDTH, MEGA_DISPLAY_HEIGHT),
+        }
+    }
+}
+
+/// Whether `rom` was written for the HIRES CHIP-8 interpreter, as they all start with a jump
+/// to 0x260. They run 64x64 when run as plain CHIP-8.
+pub fn is_two_page(rom: &[u8]) -> bool {
+    rom.starts_with(&[0x12, 0x60])
+}
+
+/// Timers and the display both run at 60Hz, so the CPU is driven in 60Hz frames.
+pub const FRAMES_PER_SECOND: u32 = 60;
+/// Roughly what the COSMAC VIP managed for typical programs; most games are tuned around it.
+pub const DEFAULT_IPS: u32 = 700;
+
+/// A read-only copy of the CPU's programmer-visible state, for debuggers and other frontends
+/// that shouldn't be poking at the CPU directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub stack: [u16; 16],
+    pub delay: u8,
+    pub sound: u8,
+    /// The address and opcode of the most recently executed instruction, if any.
+    pub last_instruction: Option<(u16, u16)>,
+}
+
+impl Snapshot {
+    /// The return addresses currently on the stack, oldest first.
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[..(self.sp as usize).min(self.stack.len())]
+    }
+}
+
+/// The CHIP-8 interpreter core. Owns all machine state except the keypad, which is shared with
+/// whatever frontend is feeding it input.
+///
+/// Everything is taken from http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.1
+pub struct CPU {
+    pub memory: Memory,
+    pub registers: Registers,
+    pub pc: u16,
+    pub sp: u8,
+    pub stack: [u16; 16],
+    /// Each pixel is a bitmask of the display planes it's lit on; only XO-CHIP uses plane 2.
+    display: FrameBuffer,
+    resolution: Resolution,
+    /// The planes that drawing, clearing and scrolling affect, selected by XO-CHIP's Fn01.
+    planes: u8,
+    keypad: Arc<Keypad>,
+    /// The key Fx0A saw pressed and is now waiting to be released.
+    held_key: Option<u8>,
+    quirks: Quirks,
+    variant: Variant,
+    /// The HP-48 "RPL user flags" that SUPER-CHIP's Fx75/Fx85 save registers to. SUPER-CHIP only
+    /// has 8 of them; XO-CHIP allows all 16 registers.
+    rpl: [u8; 16],
+    /// XO-CHIP's 128 1-bit audio samples, loaded by Fx02.
+    audio_pattern: [u8; 16],
+    /// XO-CHIP's pitch register, set by Fx3A, which sets how fast the pattern plays.
+    pitch: u8,
+    #[cfg(feature = "megachip")]
+    mega: MegaChip,
+    rng: Rng,
+    /// Set once a sprite has been drawn since the last 60Hz tick, for the display-wait quirk.
+    drawn_this_frame: bool,
+    /// Set while a DXYN is stalled waiting for the next 60Hz tick.
+    waiting_for_vblank: bool,
+    /// Set once the ROM has executed SUPER-CHIP's 00FD exit.
+    exited: bool,
+    /// The address and opcode of the last instruction fetched.
+    last_instruction: Option<(u16, u16)>,
+    breakpoints: Vec<Breakpoint>,
+    /// The breakpoint that stopped the last frame. Cleared by the next instruction, which is
+    /// allowed to run even if it's on a breakpoint so that execution can resume.
+    breakpoint_hit: Option<Breakpoint>,
+    /// Instructions per second.
+    ips: u32,
+    timing: Timing,
+    /// Leftover instruction budget (in 1/60ths of an instruction) carried over between frames, so
+    /// clock speeds that aren't a multiple of 60 still average out correctly. Under VIP timing,
+    /// it's the machine cycles spent so far this frame instead, which can run past the frame's
+    /// budget and into the next one.
+    cycle_remainder: u32,
+    /// How many instructions were left to run in a frame that stopped early, at a breakpoint or
+    /// at the end of a step over, for the next call to `run_frame_until` to finish without
+    /// ticking the timers again.
+    frame_left: Option<u32>,
+    /// How many instructions have been executed, for measuring the real clock speed.
+    instructions: u64,
+    decoded: DecodeCache,
+    /// Whether to run straight-line code as compiled blocks rather than an instruction at a time.
+    jit: bool,
+    blocks: BlockCache,
+    /// The last instructions executed.
+    tracer: Tracer,
+    profiler: Profiler,
+    coverage: Coverage,
+    /// Names for addresses, for showing the call stack and code.
+    symbols: Symbols,
+}
+
+impl CPU {
+    /// Creates a CPU ready to run whatever ROM has been loaded into `memory`.
+    pub fn new(memory: Memory, keypad: Arc<Keypad>, quirks: Quirks) -> Self {
+        Self {
+            pc: memory.load_addr() as u16,
+            memory,
+            registers: Registers::new(),
+            sp: 0,
+            stack: [0; 16],
+            display: FrameBuffer::new(DISPLAY_WIDTH, DISPLAY_HEIGHT),
+            resolution: Resolution::Low,
+            planes: 1,
+            keypad,
+            held_key: None,
+            quirks,
+            variant: Variant::default(),
+            rpl: [0; 16],
+            audio_pattern: [0; 16],
+            pitch: AudioPattern::DEFAULT_PITCH,
+            #[cfg(feature = "megachip")]
+            mega: MegaChip::default(),
+            rng: Rng::default(),
+            drawn_this_frame: false,
+            waiting_for_vblank: false,
+            exited: false,
+            last_instruction: None,
+            breakpoints: Vec::new(),
+            breakpoint_hit: None,
+            ips: DEFAULT_IPS,
+            timing: Timing::default(),
+            cycle_remainder: 0,
+            frame_left: None,
+            instructions: 0,
+            decoded: DecodeCache::default(),
+            jit: false,
+            blocks: BlockCache::default(),
+            tracer: Tracer::default(),
+            profiler: Profiler::default(),
+            coverage: Coverage::default(),
+            symbols: Symbols::new(),
+        }
+    }
+
+    /// Reseeds the random number generator behind Cxkk, for reproducible runs.
+    pub fn seed_rng(&mut self, seed: u32) {
+        self.rng = Rng::new(seed);
+    }
+
+    /// Replaces the random number generator behind Cxkk.
+    pub fn set_rng(&mut self, rng: Rng) {
+        self.rng = rng;
+    }
+
+    pub fn ips(&self) -> u32 {
+        self.ips
+    }
+
+    /// Sets the clock speed in instructions per second, independently of the 60Hz timers.
+    pub fn set_ips(&mut self, ips: u32) {
+        self.ips = ips;
+        self.cycle_remainder = 0;
+        self.frame_left = None;
+    }
+
+    /// Runs straight runs of register-only instructions as blocks compiled to closures, for speed
+    /// in turbo. Blocks are put aside whenever breakpoints, watchpoints or the profiler need to
+    /// see instructions one at a time, and under VIP timing.
+    pub fn set_jit(&mut self, on: bool) {
+        self.jit = on;
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    /// Switches between a flat clock speed and the COSMAC VIP's cycle costs, under which the clock
+    /// speed is ignored.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+        self.cycle_remainder = 0;
+        self.frame_left = None;
+    }
+
+    /// How many instructions have been executed since the CPU was created. Save states, rewinding
+    /// and resets don't change it.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// The last instructions executed, oldest first. Like the instruction count, it carries on
+    /// through save states, rewinding and resets, so it shows what really ran.
+    pub fn trace(&self) -> &Tracer {
+        &self.tracer
+    }
+
+    /// Changes how many instructions the trace keeps. 0 turns tracing off.
+    pub fn set_trace_len(&mut self, len: usize) {
+        self.tracer.set_capacity(len);
+    }
+
+    /// Replaces the trace, for showing a copy of a machine running elsewhere.
+    pub(crate) fn set_trace(&mut self, tracer: Tracer) {
+        self.tracer = tracer;
+    }
+
+    /// What's been executed and how long it took, if profiling's on. Like the trace, it carries on
+    /// through save states, rewinding and resets.
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    /// Starts or stops profiling. Off, instructions aren't timed at all.
+    pub fn set_profiling(&mut self, on: bool) {
+        self.profiler.set_enabled(on);
+    }
+
+    /// Replaces the profile, for showing a copy of a machine running elsewhere.
+    pub(crate) fn set_profiler(&mut self, profiler: Profiler) {
+        self.profiler = profiler;
+    }
+
+    /// Which bytes of memory have been executed and which read as data. It carries on through save
+    /// states, rewinding and resets, and starts over when a different ROM is loaded with `reload`.
+    pub fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
+
+    /// Replaces the coverage, for showing a copy of a machine running elsewhere.
+    pub(crate) fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = coverage;
+    }
+
+    /// Names for addresses in the ROM, if it has any.
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    /// Names addresses in the ROM, for `backtrace` and debuggers to show in place of addresses.
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    /// Notes the `len` bytes from `addr` as read as data.
+    fn cover_data(&mut self, addr: usize, len: usize) {
+        for addr in addr..addr + len {
+            if let Ok(addr) = self.memory.resolve(addr) {
+                self.coverage.read(addr);
+            }
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    /// Swaps in `memory`, e.g. sized for another variant, with the watchpoints carried over to it.
+    pub fn set_memory(&mut self, mut memory: Memory) {
+        for &watchpoint in self.memory.watchpoints() {
+            memory.add_watchpoint(watchpoint);
+        }
+        self.memory = memory;
+    }
+
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+    }
+
+    /// The display, row-major, `display_width()` pixels per row. Each pixel is a bitmask of the
+    /// planes it's lit on, so anything non-zero is lit.
+    pub fn display(&self) -> &[u8] {
+        self.display.pixels()
+    }
+
+    /// The display, for frontends to draw.
+    pub fn framebuffer(&self) -> &FrameBuffer {
+        &self.display
+    }
+
+    /// Replaces the display, changes and all, for showing a copy of a machine running elsewhere.
+    pub(crate) fn set_framebuffer(&mut self, display: FrameBuffer) {
+        self.display = display;
+    }
+
+    /// Forgets what has changed on the display, once a frontend has drawn it.
+    pub fn clear_dirty(&mut self) {
+        self.display.clear_dirty();
+    }
+
+    pub fn display_width(&self) -> usize {
+        self.display.width()
+    }
+
+    pub fn display_height(&self) -> usize {
+        self.display.height()
+    }
+
+    /// Whether SUPER-CHIP's 128x64 mode is on.
+    pub fn hires(&self) -> bool {
+        self.resolution == Resolution::High
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// The biggest the display gets for the ROM loaded, as width and height.
+    pub fn max_display_size(&self) -> (usize, usize) {
+        if self.variant == Variant::Chip8 && is_two_page(self.memory.rom()) {
+            Resolution::TwoPage.size()
+        } else {
+            self.variant.max_display_size()
+        }
+    }
+
+    /// XO-CHIP's current audio pattern buffer, one bit per sample, MSB first.
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.audio_pattern
+    }
+
+    /// What XO-CHIP plays while the sound timer runs instead of the plain tone: the pattern
+    /// buffer at the current pitch. `None` until an XO-CHIP ROM has loaded a pattern, so ROMs
+    /// that never do still beep.
+    pub fn audio(&self) -> Option<AudioPattern> {
+        (self.variant.is_xochip() && self.audio_pattern != [0; 16]).then_some(AudioPattern {
+            samples: self.audio_pattern,
+            pitch: self.pitch,
+        })
+    }
+
+    /// The MegaChip sound that's playing, if there is one.
+    pub fn digitized_sound(&self) -> Option<&DigitizedSound> {
+        #[cfg(feature = "megachip")]
+        return self.mega.sound.as_ref().map(|playing| &playing.sound);
+        #[cfg(not(feature = "megachip"))]
+        None
+    }
+
+    /// Switching resolution clears the screen, as it does in Octo and modern SUPER-CHIP emulators.
+    fn set_resolution(&mut self, resolution: Resolution) {
+        self.resolution = resolution;
+        let (width, height) = resolution.size();
+        self.display.resize(width, height);
+        #[cfg(feature = "megachip")]
+        if resolution == Resolution::Mega {
+            self.mega.back.clear();
+            let palette = self.mega.palette.as_slice().into();
+            self.display.set_palette(Some(palette));
+        }
+    }
+
+    /// Whether MegaChip's 0011 has switched to its colour display.
+    #[cfg(feature = "megachip")]
+    fn mega_mode(&self) -> bool {
+        self.resolution == Resolution::Mega
+    }
+
+    /// Scrolls the selected planes by (`dx`, `dy`) pixels, shifting in blank pixels. MegaChip
+    /// scrolls what's being drawn rather than what's showing.
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        #[cfg(feature = "megachip")]
+        if self.mega_mode() {
+            self.mega.back.scroll(dx, dy, u8::MAX);
+            return;
+        }
+        self.display.scroll(dx, dy, self.planes);
+    }
+
+    /// Whether the ROM has asked to exit (SUPER-CHIP's 00FD).
+    pub fn has_exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn keypad(&self) -> &Arc<Keypad> {
+        &self.keypad
+    }
+
+    /// Stops `run_frame` just before the instruction at `addr` executes.
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.add_breakpoint_on(Breakpoint::Address(addr));
+    }
+
+    /// Stops `run_frame` whenever `breakpoint`'s condition is met.
+    pub fn add_breakpoint_on(&mut self, breakpoint: Breakpoint) {
+        if let Breakpoint::Watch(watchpoint) = breakpoint {
+            self.memory.add_watchpoint(watchpoint);
+        }
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if let Breakpoint::Watch(watchpoint) = breakpoint {
+            self.memory.remove_watchpoint(watchpoint);
+        }
+        self.breakpoints.retain(|&b| b != breakpoint);
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    /// The return addresses currently on the stack, oldest first.
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[..(self.sp as usize).min(self.stack.len())]
+    }
+
+    /// The subroutine calls on the stack, innermost first: where each was called from, and the
+    /// subroutine it called, if the instruction there is still a call.
+    pub fn calls(&self) -> Vec<(u16, Option<u16>)> {
+        self.call_stack()
+            .iter()
+            .rev()
+            .map(|ret| {
+                let site = ret.wrapping_sub(2);
+                let target = self
+                    .opcode_at(site)
+                    .filter(|opcode| opcode & 0xf000 == 0x2000)
+                    .map(|opcode| opcode & 0x0fff);
+                (site, target)
+            })
+            .collect()
+    }
+
+    /// Where each subroutine on the stack was called from, innermost first, like `2a2 <- 20e`,
+    /// or `update+6 <- main+c` with symbols. Empty outside any subroutine.
+    pub fn backtrace(&self) -> String {
+        let sites: Vec<_> = self
+            .call_stack()
+            .iter()
+            .rev()
+            .map(|ret| self.symbols.describe(ret.wrapping_sub(2)))
+            .collect();
+        sites.join(" <- ")
+    }
+
+    /// The breakpoint that stopped the last `run_frame`, until the next instruction executes.
+    pub fn breakpoint_hit(&self) -> Option<Breakpoint> {
+        self.breakpoint_hit
+    }
+
+    /// Copies out the registers, stack and timers.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            v: self.registers.v,
+            i: self.registers.i,
+            pc: self.pc,
+            sp: self.sp,
+            stack: self.stack,
+            delay: self.registers.delay,
+            sound: self.registers.sound,
+            last_instruction: self.last_instruction,
+        }
+    }
+
+    /// Captures the whole machine, including the keypad, so that `load_state` can put it back
+    /// exactly.
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            variant: self.variant,
+            memory: self.memory.to_vec(),
+            v: self.registers.v,
+            i: self.registers.i,
+            pc: self.pc,
+            sp: self.sp,
+            stack: self.stack,
+            delay: self.registers.delay,
+            sound: self.registers.sound,
+            display: self.display().to_vec(),
+            resolution: self.resolution,
+            planes: self.planes,
+            keys: self.keypad.state(),
+            held_key: self.held_key,
+            rpl: self.rpl,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            rng: self.rng.state(),
+            drawn_this_frame: self.drawn_this_frame,
+            waiting_for_vblank: self.waiting_for_vblank,
+            exited: self.exited,
+            cycle_remainder: self.cycle_remainder,
+            #[cfg(feature = "megachip")]
+            mega: self.variant.is_megachip().then(|| {
+                // What's changed on the back buffer isn't part of the machine.
+                let mut mega = self.mega.clone();
+                mega.back.clear_dirty();
+                (mega, self.display.palette().map(<[_]>::to_vec))
+            }),
+        }
+    }
+
+    /// Restores a state taken by `save_state`, switching to its variant. Quirks, clock speed and
+    /// breakpoints are left as they are.
+    pub fn load_state(&mut self, state: &SaveState) {
+        self.variant = state.variant;
+        self.memory.restore(&state.memory);
+        self.registers.v = state.v;
+        self.registers.i = state.i;
+        self.pc = state.pc;
+        self.sp = state.sp;
+        self.stack = state.stack;
+        self.registers.delay = state.delay;
+        self.registers.sound = state.sound;
+        self.set_resolution(state.resolution);
+        self.display.copy_from(&state.display);
+        #[cfg(feature = "megachip")]
+        {
+            let (mega, shown) = state.mega.clone().unwrap_or_default();
+            self.mega = mega;
+            self.display.set_palette(shown.map(Into::into));
+        }
+        self.planes = state.planes;
+        self.keypad.set_state(state.keys);
+        self.held_key = state.held_key;
+        self.rpl = state.rpl;
+        self.audio_pattern = state.audio_pattern;
+        self.pitch = state.pitch;
+        self.rng = Rng::new(state.rng);
+        self.drawn_this_frame = state.drawn_this_frame;
+        self.waiting_for_vblank = state.waiting_for_vblank;
+        self.exited = state.exited;
+        self.cycle_remainder = state.cycle_remainder;
+        self.frame_left = None;
+        self.last_instruction = None;
+        self.breakpoint_hit = None;
+    }
+
+    /// Copies out all of RAM, with the registers, stack and timers.
+    pub fn memory_image(&self) -> MemoryImage {
+        MemoryImage {
+            memory: self.memory.to_vec(),
+            registers: Some(self.snapshot()),
+        }
+    }
+
+    /// Writes an image taken by `memory_image`, or loaded from a file, into RAM from address 0,
+    /// and puts back the registers, stack and timers if it has them. Nothing changes if it's
+    /// bigger than RAM.
+    pub fn load_memory_image(
+        &mut self,
+        image: &MemoryImage,
+    ) -> std::result::Result<(), OutOfBounds> {
+        self.memory.load_image(&image.memory)?;
+        if let Some(registers) = &image.registers {
+            self.registers.v = registers.v;
+            self.registers.i = registers.i;
+            self.pc = registers.pc;
+            self.sp = registers.sp;
+            self.stack = registers.stack;
+            self.registers.delay = registers.delay;
+            self.registers.sound = registers.sound;
+        }
+        self.last_instruction = None;
+        self.breakpoint_hit = None;
+        Ok(())
+    }
+
+    /// Starts the machine over with `rom` in place of whatever was loaded, as `reset` would. Nothing
+    /// changes if `rom` doesn't fit.
+    pub fn reload(&mut self, rom: &[u8]) -> std::result::Result<(), RomError> {
+        self.memory.reload_rom(rom)?;
+        self.coverage.clear();
+        self.soft_reset();
+        Ok(())
+    }
+
+    /// Starts the ROM over as if the machine had just been switched on: RAM is wiped and the ROM
+    /// loaded into it again, and the registers, stack, timers and display are cleared. Quirks,
+    /// variant, clock speed, breakpoints and watchpoints are kept, and so are the RPL flags, which
+    /// the HP-48 kept between programs.
+    pub fn reset(&mut self) {
+        self.memory.reset();
+        self.soft_reset();
+    }
+
+    /// Like `reset`, but RAM is left as it is, as with the COSMAC VIP's reset switch. Anything
+    /// the ROM has written to itself stays written.
+    pub fn soft_reset(&mut self) {
+        self.registers = Registers::new();
+        self.pc = self.memory.load_addr() as u16;
+        self.sp = 0;
+        self.stack = [0; 16];
+        self.set_resolution(Resolution::Low);
+        self.display.clear();
+        self.planes = 1;
+        self.keypad.release_all();
+        self.held_key = None;
+        self.audio_pattern = [0; 16];
+        self.pitch = AudioPattern::DEFAULT_PITCH;
+        #[cfg(feature = "megachip")]
+        {
+            self.mega = MegaChip::default();
+        }
+        self.drawn_this_frame = false;
+        self.waiting_for_vblank = false;
+        self.exited = false;
+        self.last_instruction = None;
+        self.breakpoint_hit = None;
+        self.cycle_remainder = 0;
+        self.frame_left = None;
+    }
+
+    /// Runs one 60Hz frame: ticks the timers, then executes a frame's worth of instructions,
+    /// stopping early at the first error or breakpoint. If the last frame stopped early, runs the
+    /// rest of it instead, without ticking the timers.
+    pub fn run_frame(&mut self) -> Result<()> {
+        self.run_frame_until(|_| false).map(|_| ())
+    }
+
+    /// Like `run_frame`, but also stops as soon as `stop` returns true for the CPU state after an
+    /// instruction. Returns whether it stopped early, either for that or for a breakpoint.
+    pub fn run_frame_until(&mut self, stop: impl FnMut(&CPU) -> bool) -> Result<bool> {
+        let _frame = debug_span!(target: "frame", "frame").entered();
+        let instructions = match self.frame_left.take() {
+            Some(left) => left,
+            None => self.start_frame(),
+        };
+        let (stopped, ran) = self.run_instructions(instructions, stop)?;
+        if stopped {
+            self.frame_left = Some(instructions - ran);
+        }
+        Ok(stopped)
+    }
+
+    /// Ticks the timers for a new frame, and returns how many instructions it has to run.
+    fn start_frame(&mut self) -> u32 {
+        // Ticking first means a timer set during the frame keeps its value until the next one.
+        self.tick_timers();
+
+        match self.timing {
+            Timing::Ips => {
+                let budget = self.cycle_remainder + self.ips;
+                self.cycle_remainder = budget % FRAMES_PER_SECOND;
+                budget / FRAMES_PER_SECOND
+            }
+            // Whatever the last frame overran by comes out of this one.
+            Timing::Vip => {
+                self.cycle_remainder = self.cycle_remainder.saturating_sub(VIP_FRAME_BUDGET);
+                u32::MAX
+            }
+        }
+    }
+
+    /// Runs up to `instructions` instructions of the frame, as `run_frame_until` does. Returns
+    /// whether it stopped early, and how many it ran.
+    fn run_instructions(
+        &mut self,
+        instructions: u32,
+        mut stop: impl FnMut(&CPU) -> bool,
+    ) -> Result<(bool, u32)> {
+        let mut ran = 0;
+        while ran < instructions {
+            if self.timing == Timing::Vip && self.cycle_remainder >= VIP_FRAME_BUDGET {
+                break;
+            }
+            let block = self.run_block(instructions - ran);
+            if block > 0 {
+                ran += block;
+                if stop(self) {
+                    return Ok((true, ran));
+                }
+                continue;
+            }
+            if self.breakpoint_hit.is_none() {
+                let (pc, opcode) = (self.pc, self.opcode_at(self.pc));
+                if let Some(&hit) = self.breakpoints.iter().find(|b| b.hits_before(pc, opcode)) {
+                    self.breakpoint_hit = Some(hit);
+                    return Ok((true, ran));
+                }
+            }
+            let before = self.registers.v;
+            self.step()?;
+            ran += 1;
+            let after = &self.registers.v;
+            let hit = self
+                .breakpoints
+                .iter()
+                .find(|b| b.hits_after(&before, after))
+                .copied()
+                .or_else(|| {
+                    // Watchpoints added straight to memory count too.
+                    let hit = self.memory.watch_hit()?;
+                    Some(Breakpoint::Watch(hit.watchpoint))
+                });
+            if hit.is_some() {
+                self.breakpoint_hit = hit;
+                return Ok((true, ran));
+            }
+            if stop(self) {
+                return Ok((true, ran));
+            }
+            // Nothing else can happen until the next frame, so don't burn the rest of the budget.
+            if self.waiting_for_vblank {
+                break;
+            }
+        }
+        Ok((false, ran))
+    }
+
+    /// Runs the compiled block at the PC, if there is one, it has no more than `limit`
+    /// instructions, and nothing needs to see them one at a time. Returns how many ran.
+    fn run_block(&mut self, limit: u32) -> u32 {
+        let observed = !self.breakpoints.is_empty()
+            || !self.memory.watchpoints().is_empty()
+            || self.profiler.is_enabled();
+        // Blocks only know about a 16-bit I.
+        if !self.jit || self.timing != Timing::Ips || observed || self.variant.is_megachip() {
+            return 0;
+        }
+        let Some(block) = self
+            .blocks
+            .get(self.pc, &self.memory, self.quirks, self.variant)
+        else {
+            return 0;
+        };
+        if block.len() > limit as usize {
+            return 0;
+        }
+        let start = self.pc as usize;
+        for (opcode, op) in block.ops() {
+            let pc = self.pc;
+            self.tracer.record(pc, opcode);
+            self.last_instruction = Some((pc, opcode));
+            op(&mut self.registers);
+            self.pc = pc.wrapping_add(2);
+        }
+        self.coverage.execute(start, block.len() * 2);
+        self.instructions += block.len() as u64;
+        self.breakpoint_hit = None;
+        block.len() as u32
+    }
+
+    /// Whether the buzzer should currently be sounding.
+    pub fn beeping(&self) -> bool {
+        self.registers.sound > 0
+    }
+
+    /// Decrements the delay and sound timers. Should be called at 60Hz, and doubles as the
+    /// vertical blank that DXYN waits for under the display-wait quirk.
+    pub fn tick_timers(&mut self) {
+        self.drawn_this_frame = false;
+        self.waiting_for_vblank = false;
+        self.registers.delay = self.registers.delay.saturating_sub(1);
+        self.registers.sound = self.registers.sound.saturating_sub(1);
+    }
+
+    /// Errors unless the `len` bytes starting at `addr` can all be accessed.
+    fn check_bounds(&self, pc: u16, addr: usize, len: usize) -> Result<()> {
+        self.memory
+            .check_range(addr, len)
+            .map_err(|err| out_of_bounds(pc, err))
+    }
+
+    /// The opcode stored at `addr`, or `None` if it runs off the end of RAM and the access policy
+    /// doesn't allow that.
+    pub fn opcode_at(&self, addr: u16) -> Option<u16> {
+        let byte = |addr: usize| Some(self.memory[self.memory.resolve(addr).ok()?]);
+        Some(u16::from_be_bytes([
+            byte(addr as usize)?,
+            byte(addr as usize + 1)?,
+        ]))
+    }
+
+    /// Fetches, decodes and executes a single instruction.
+    pub fn step(&mut self) -> Result<()> {
+        if !self.profiler.is_enabled() {
+            return self.execute();
+        }
+        let pc = self.pc;
+        let start = std::time::Instant::now();
+        let result = self.execute();
+        let elapsed = start.elapsed();
+        // Only count instructions that were fetched: one off the end of memory never ran.
+        if let Some((_, opcode)) = self.last_instruction.filter(|&(at, _)| at == pc) {
+            self.profiler.record(pc, opcode, self.pc, elapsed);
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<()> {
+        let pc = self.pc;
+        let _step = trace_span!(
+            target: "cpu",
+            "step",
+            pc = format_args!("{:04x}", pc),
+            opcode = format_args!("{:04x}", self.opcode_at(pc).unwrap_or_default())
+        )
+        .entered();
+        crate::core::step(self)
+    }
+}
+
+impl Interpreter for CPU {
+    fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    fn pc_mut(&mut self) -> &mut u16 {
+        &mut self.pc
+    }
+
+    fn stack(&mut self) -> (&mut [u16; 16], &mut u8) {
+        (&mut self.stack, &mut self.sp)
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    fn rng(&mut self) -> &mut Rng {
+        &mut self.rng
+    }
+
+    fn keys(&self) -> u16 {
+        self.keypad.state()
+    }
+
+    fn held_key(&mut self) -> &mut Option<u8> {
+        &mut self.held_key
+    }
+
+    fn drawn_this_frame(&mut self) -> &mut bool {
+        &mut self.drawn_this_frame
+    }
+
+    fn wait_for_vblank(&mut self) {
+        self.waiting_for_vblank = true;
+    }
+
+    fn fetch(&mut self, pc: u16) -> Result<u16> {
+        let opcode = self.opcode_at(pc).ok_or(Chip8Error::MemoryOutOfBounds {
+            pc,
+            addr: pc as usize,
+        })?;
+        self.tracer.record(pc, opcode);
+        self.coverage.execute(pc as usize, 2);
+        self.last_instruction = Some((pc, opcode));
+        self.instructions += 1;
+        self.breakpoint_hit = None;
+        self.memory.clear_watch_hit();
+        Ok(opcode)
+    }
+
+    fn decode(&mut self, pc: u16, opcode: u16) -> Option<Instr> {
+        self.decoded.decode(pc, opcode, self.variant)
+    }
+
+    fn execute_extended(&mut self, pc: u16, instr: Instr) -> Result<bool> {
+        let fault = |err| out_of_bounds(pc, err);
+        // every instruction comes through here first, so vip timing charges for it here.
+        if self.timing == Timing::Vip {
+            self.cycle_remainder += Timing::vip_cycles(instr, &self.registers.v);
+        }
+        match instr {
+            // 00e0 - cls
+            // in megachip mode, show what's been drawn and start drawing the next frame.
+            #[cfg(feature = "megachip")]
+            Instr::Cls if self.mega_mode() => {
+                self.mega.present(&mut self.display);
+            }
+            // 0230 - hcls (hires chip-8)
+            // clear the screen. the two-page interpreter's own routine for it.
+            Instr::HiresCls => {
+                self.clear();
+            }
+            // 00cn - scd n (schip)
+            // scroll the display down by n pixels.
+            Instr::ScrollDown(n) => {
+                self.scroll(0, n as isize);
+            }
+            // 00dn - scu n (xo-chip)
+            // scroll the display up by n pixels.
+            Instr::ScrollUp(n) => {
+                self.scroll(0, -(n as isize));
+            }
+            // 00fb - scr (schip)
+            // scroll the display right by 4 pixels.
+            Instr::ScrollRight => {
+                self.scroll(4, 0);
+            }
+            // 00fc - scl (schip)
+            // scroll the display left by 4 pixels.
+            Instr::ScrollLeft => {
+                self.scroll(-4, 0);
+            }
+            // 00fd - exit (schip)
+            // exit the interpreter. there's nothing to return to here, so just park the pc on it.
+            Instr::Exit => {
+                self.pc = pc;
+                self.exited = true;
+            }
+            // 00fe - low (schip)
+            // disable hi-res mode.
+            Instr::Low => {
+                self.set_resolution(Resolution::Low);
+            }
+            // 00ff - high (schip)
+            // enable 128x64 hi-res mode.
+            Instr::High => {
+                self.set_resolution(Resolution::High);
+            }
+            // 0010 - megaoff (megachip)
+            // leave megachip mode for the 64x32 display.
+            #[cfg(feature = "megachip")]
+            Instr::MegaOff => {
+                self.set_resolution(Resolution::Low);
+            }
+            // 0011 - megaon (megachip)
+            // switch to the 256x192 colour display.
+            #[cfg(feature = "megachip")]
+            Instr::MegaOn => {
+                self.set_resolution(Resolution::Mega);
+            }
+            // 00bn - scru n (megachip)
+            // scroll the display up by n pixels.
+            #[cfg(feature = "megachip")]
+            Instr::MegaScrollUp(n) => {
+                self.scroll(0, -(n as isize));
+            }
+            // 01nn nnnn - ldhi i, nnnnnn (megachip)
+            // load i with a 24-bit address: nn, then the word after the opcode.
+            #[cfg(feature = "megachip")]
+            Instr::LoadIHigh(nn) => {
+                let next = self.pc as usize;
+                self.check_bounds(pc, next, 2)?;
+                let low = u16::from_be_bytes([
+                    self.memory.read(next).map_err(fault)?,
+                    self.memory.read(next + 1).map_err(fault)?,
+                ]);
+                self.set_i((nn as usize) << 16 | low as usize);
+                self.coverage.execute(next, 2);
+                self.pc = self.pc.wrapping_add(2);
+            }
+            // 02nn - ldpal nn (megachip)
+            // load nn colours into the palette from i, 4 bytes of argb each, from index 1 on.
+            #[cfg(feature = "megachip")]
+            Instr::LoadPalette(nn) => {
+                let (i, len) = (self.i_addr(), nn as usize * 4);
+                self.check_bounds(pc, i, len)?;
+                let argb = (i..i + len)
+                    .map(|addr| self.memory.read(addr))
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(fault)?;
+                self.cover_data(i, len);
+                self.mega.load_palette(&argb);
+            }
+            // 03nn - sprw nn, 04nn - sprh nn (megachip)
+            // set the width or height of the sprites dxyn draws. 0 is 256.
+            #[cfg(feature = "megachip")]
+            Instr::SpriteWidth(nn) => {
+                self.mega.sprite_width = if nn == 0 { 256 } else { nn as usize };
+            }
+            #[cfg(feature = "megachip")]
+            Instr::SpriteHeight(nn) => {
+                self.mega.sprite_height = if nn == 0 { 256 } else { nn as usize };
+            }
+            // 05nn - alpha nn, 080n - bmode n (megachip)
+            // set the sprite opacity and how sprites blend with what's under them.
+            #[cfg(feature = "megachip")]
+            Instr::Alpha(nn) => {
+                self.mega.alpha = nn;
+            }
+            #[cfg(feature = "megachip")]
+            Instr::BlendMode(n) => {
+                self.mega.blend = n;
+            }
+            // 060n - digisnd n (megachip)
+            // play the digitised sound at i, looping it if n is 0 and once if it's 1.
+            #[cfg(feature = "megachip")]
+            Instr::PlaySound(n) => {
+                let header = self.i_addr();
+                let sound = megachip::read_sound(&self.memory, header, n == 0).map_err(fault)?;
+                self.mega.sound = Some(PlayingSound { header, sound });
+            }
+            // 0700 - stopsnd (megachip)
+            // stop the digitised sound.
+            #[cfg(feature = "megachip")]
+            Instr::StopSound => {
+                self.mega.sound = None;
+            }
+            // 09nn - ccol nn (megachip)
+            // set the palette index that dxyn reports collisions with.
+            #[cfg(feature = "megachip")]
+            Instr::CollisionColor(nn) => {
+                self.mega.collision = nn;
+            }
+            // 1260 - jump to 0x260 (hires chip-8)
+            // a HIRES CHIP-8 ROM's first instruction jumps into the patch it carries for the
+            // VIP's interpreter, which switches to 64x64 and runs the program after it.
+            Instr::Jump(0x260) if pc == 0x200 && self.variant == Variant::Chip8 => {
+                self.set_resolution(Resolution::TwoPage);
+                self.pc = TWO_PAGE_START;
+            }
+            // 5xy2 - save vx - vy (xo-chip)
+            // store registers vx through vy in memory starting at i, in either direction. i is unchanged.
+            Instr::SaveRange(x, y) => {
+                let (x, y) = (x as usize, y as usize);
+                let i = self.i_addr();
+                self.check_bounds(pc, i, x.abs_diff(y) + 1)?;
+                for offset in 0..=x.abs_diff(y) {
+                    let value = self.registers.v[register_between(x, y, offset)];
+                    self.memory.write(i + offset, value).map_err(fault)?;
+                }
+            }
+            // 5xy3 - load vx - vy (xo-chip)
+            // read registers vx through vy from memory starting at i, in either direction. i is unchanged.
+            Instr::LoadRange(x, y) => {
+                let (x, y) = (x as usize, y as usize);
+                let i = self.i_addr();
+                self.check_bounds(pc, i, x.abs_diff(y) + 1)?;
+                for offset in 0..=x.abs_diff(y) {
+                    self.registers.v[register_between(x, y, offset)] =
+                        self.memory.read(i + offset).map_err(fault)?;
+                }
+                self.cover_data(i, x.abs_diff(y) + 1);
+            }
+            // f000 nnnn - ld i, long nnnn (xo-chip)
+            // load i with the 16-bit address in the following word.
+            Instr::LoadILong => {
+                let next = self.pc as usize;
+                self.check_bounds(pc, next, 2)?;
+                let nnnn = u16::from_be_bytes([
+                    self.memory.read(next).map_err(fault)?,
+                    self.memory.read(next + 1).map_err(fault)?,
+                ]);
+                self.set_i(nnnn as usize);
+                self.coverage.execute(next, 2);
+                self.pc = self.pc.wrapping_add(2);
+            }
+            // fn01 - plane n (xo-chip)
+            // select which of the two display planes subsequent drawing affects.
+            Instr::Plane(n) => {
+                self.planes = n;
+            }
+            // f002 - audio (xo-chip)
+            // load the 16-byte audio pattern buffer from memory at i.
+            Instr::Audio => {
+                let i = self.i_addr();
+                self.check_bounds(pc, i, 16)?;
+                for (offset, sample) in self.audio_pattern.iter_mut().enumerate() {
+                    *sample = self.memory.read(i + offset).map_err(fault)?;
+                }
+                self.cover_data(i, 16);
+            }
+            // megachip's i carries on past 64k into a byte of its own.
+            #[cfg(feature = "megachip")]
+            Instr::AddI(x) if self.variant.is_megachip() => {
+                self.set_i(self.i_addr() + self.registers.v[x as usize] as usize);
+            }
+            // fx3a - ld pitch, vx (xo-chip)
+            // set the pitch register, which the audio pattern plays at 4000*2^((vx-64)/48) hz.
+            Instr::Pitch(x) => {
+                self.pitch = self.registers.v[x as usize];
+            }
+            // fx75 - ld r, vx (schip)
+            // store v0 through vx in the rpl user flags (x <= 7, or any x on xo-chip).
+            Instr::SaveFlags(x) => {
+                self.rpl[..=x as usize].copy_from_slice(&self.registers.v[..=x as usize]);
+            }
+            // fx85 - ld vx, r (schip)
+            // read v0 through vx from the rpl user flags (x <= 7, or any x on xo-chip).
+            Instr::LoadFlags(x) => {
+                self.registers.v[..=x as usize].copy_from_slice(&self.rpl[..=x as usize]);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    fn read(&mut self, pc: u16, addr: usize, buf: &mut [u8]) -> Result<()> {
+        self.check_bounds(pc, addr, buf.len())?;
+        for (offset, byte) in buf.iter_mut().enumerate() {
+            *byte = self
+                .memory
+                .read(addr + offset)
+                .map_err(|err| out_of_bounds(pc, err))?;
+        }
+        self.cover_data(addr, buf.len());
+        Ok(())
+    }
+
+    fn write(&mut self, pc: u16, addr: usize, bytes: &[u8]) -> Result<()> {
+        self.check_bounds(pc, addr, bytes.len())?;
+        for (offset, &byte) in bytes.iter().enumerate() {
+            self.memory
+                .write(addr + offset, byte)
+                .map_err(|err| out_of_bounds(pc, err))?;
+        }
+        Ok(())
+    }
+
+    /// The address in I, which MegaChip's 01NN NNNN can put past 64K.
+    fn i_addr(&self) -> usize {
+        #[cfg(feature = "megachip")]
+        return (self.mega.i_high as usize) << 16 | self.registers.i as usize;
+        #[cfg(not(feature = "megachip"))]
+        (self.registers.i as usize)
+    }
+
+    /// Points I at `addr`, wrapping it around to fit: at 64K, or at 16M for MegaChip.
+    fn set_i(&mut self, addr: usize) {
+        self.registers.i = addr as u16;
+        #[cfg(feature = "megachip")]
+        {
+            self.mega.i_high = if self.variant.is_megachip() {
+                (addr >> 16) as u8
+            } else {
+                0
+            };
+        }
+    }
+
+    /// Skips the next instruction. XO-CHIP's F000 and MegaChip's 01NN are twice as long as
+    /// everything else, so they need skipping over in one go.
+    fn skip(&mut self) {
+        let next = self.pc as usize;
+        let long = match self.memory.get(next..next + 2) {
+            Some([0xf0, 0x00]) => self.variant.is_xochip(),
+            Some([0x01, _]) => self.variant.is_megachip(),
+            _ => false,
+        };
+        self.pc = self.pc.wrapping_add(if long { 4 } else { 2 });
+    }
+
+    /// Clears the selected planes.
+    fn clear(&mut self) {
+        self.display.clear_planes(self.planes);
+    }
+
+    fn display_size(&self) -> (usize, usize) {
+        (self.display_width(), self.display_height())
+    }
+
+    fn xor_pixel(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        self.display.xor_pixel(x, y, plane)
+    }
+
+    fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    /// SUPER-CHIP's DXY0 draws a 16x16 sprite, two bytes per row.
+    fn sprite_size(&self, n: u8) -> (usize, usize) {
+        if n == 0 && self.variant.is_schip() {
+            (16, 16)
+        } else {
+            (8, n as usize)
+        }
+    }
+
+    /// In SUPER-CHIP hi-res, VF is set to the number of sprite rows that collided.
+    fn counts_collided_rows(&self) -> bool {
+        self.hires() && self.variant == Variant::Schip
+    }
+
+    fn draw(&mut self, pc: u16, x: u8, y: u8, n: u8) -> Result<()> {
+        // in megachip mode, dxyn draws a sprite of sprw x sprh palette indices instead, and
+        // vf is set if it drew over the collision colour.
+        #[cfg(feature = "megachip")]
+        if self.mega_mode() {
+            let mut sprite = vec![0; self.mega.sprite_len()];
+            self.read(pc, self.i_addr(), &mut sprite)?;
+            let (vx, vy) = (self.registers.v[x as usize], self.registers.v[y as usize]);
+            self.registers.v[0xf] = self.mega.draw(vx as usize, vy as usize, &sprite) as u8;
+            return Ok(());
+        }
+        // the vip's interpreter waits for the display interrupt before drawing, and nothing
+        // else runs until the next frame.
+        if self.timing == Timing::Vip {
+            self.cycle_remainder = self.cycle_remainder.max(VIP_FRAME_BUDGET);
+        }
+        let _draw = trace_span!(
+            target: "display",
+            "draw",
+            x = self.registers.v[x as usize] as usize % self.display_width(),
+            y = self.registers.v[y as usize] as usize % self.display_height(),
+            n
+        )
+        .entered();
+        crate::core::draw_sprite(self, pc, x, y, n)
+    }
+}
+
+/// The fault for the instruction at `pc` reaching past the end of RAM.
+fn out_of_bounds(pc: u16, err: OutOfBounds) -> Chip8Error {
+    Chip8Error::MemoryOutOfBounds { pc, addr: err.addr }
+}
+
+/// The `offset`th register when walking from `x` towards `y`.
+fn register_between(x: usize, y: usize, offset: usize) -> usize {
+    if x <= y {
+        x + offset
+    } else {
+        x - offset
+    }
+}
+
+/// Builds a CPU with the default quirks and `program` loaded as its ROM at 0x200, for tests.
+#[cfg(test)]
+pub(crate) fn cpu_with(program: &[u16]) -> CPU {
+    variant_cpu_with(Variant::default(), program)
+}
+
+/// `cpu_with`, but running as `variant` with as much memory as `variant` has.
+#[cfg(test)]
+pub(crate) fn variant_cpu_with(variant: Variant, program: &[u16]) -> CPU {
+    let mut memory = Memory::with_size(variant.memory_size());
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+    memory.load_rom_bytes(&rom).unwrap();
+    let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::default());
+    cpu.set_variant(variant);
+    cpu
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::BIG_FONT_ADDR;
+    use crate::{Access, AccessPolicy, LoadAddress, Watchpoint};
+
+    fn run(cpu: &mut CPU, steps: usize) {
+        for _ in 0..steps {
+            cpu.step().unwrap();
+        }
+    }
+
+    fn pixel(cpu: &CPU, x: usize, y: usize) -> bool {
+        cpu.display()[y * DISPLAY_WIDTH + x] != 0
+    }
+
+    #[test]
+    fn snapshot_reflects_state() {
+        let mut cpu = cpu_with(&[0x6a12, 0x2206, 0x0000, 0xa345]);
+        assert_eq!(cpu.snapshot().last_instruction, None);
+        run(&mut cpu, 3);
+        let snapshot = cpu.snapshot();
+        assert_eq!(snapshot.v[0xa], 0x12);
+        assert_eq!(snapshot.i, 0x345);
+        assert_eq!(snapshot.pc, 0x208);
+        assert_eq!(snapshot.call_stack(), &[0x204]);
+        assert_eq!(snapshot.last_instruction, Some((0x206, 0xa345)));
+    }
+
+    #[test]
+    fn breakpoints_stop_the_frame() {
+        // Count up in V0 forever.
+        let mut cpu = cpu_with(&[0x7001, 0x1200]);
+        cpu.add_breakpoint(0x202);
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.breakpoint_hit(), Some(Breakpoint::Address(0x202)));
+        assert_eq!((cpu.pc, cpu.registers.v[0]), (0x202, 1));
+        // Resuming runs the instruction it stopped on before breaking again.
+        cpu.run_frame().unwrap();
+        assert_eq!((cpu.pc, cpu.registers.v[0]), (0x202, 2));
+
+        cpu.remove_breakpoint(Breakpoint::Address(0x202));
+        cpu.add_breakpoint_on(Breakpoint::Register { x: 0, value: 5 });
+        cpu.run_frame().unwrap();
+        assert_eq!(
+            cpu.breakpoint_hit(),
+            Some(Breakpoint::Register { x: 0, value: 5 })
+        );
+        assert_eq!((cpu.pc, cpu.registers.v[0]), (0x202, 5));
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.breakpoint_hit(), None);
+
+        let mut cpu = cpu_with(&[0x6005, 0xd001, 0x1204]);
+        cpu.add_breakpoint_on(Breakpoint::opcode_pattern("dxyn").unwrap());
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.pc, 0x202);
+    }
+
+    #[test]
+    fn frames_stopped_early_finish_without_ticking_the_timers_again() {
+        // Set the delay timer, then count up in V1 forever, at 10 instructions a frame.
+        let mut cpu = cpu_with(&[0x60ff, 0xf015, 0x7101, 0x1204]);
+        cpu.set_ips(600);
+        assert!(cpu.run_frame_until(|cpu| cpu.registers.v[1] == 3).unwrap());
+        assert_eq!(cpu.instructions(), 7);
+        // The rest of the frame: three more instructions, and no tick.
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.instructions(), 10);
+        assert_eq!((cpu.registers.delay, cpu.registers.v[1]), (0xff, 4));
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.instructions(), 20);
+        assert_eq!(cpu.registers.delay, 0xfe);
+    }
+
+    #[test]
+    fn watchpoints_stop_after_the_access() {
+        // Draw the "0" glyph, then store V0-V1 at 0x300, then spin.
+        let mut cpu = cpu_with(&[0xd005, 0xa300, 0xf155, 0x1206]);
+        cpu.memory
+            .add_watchpoint(Watchpoint::new(0x300, 0x30f, Access::Write));
+        cpu.add_breakpoint_on(Breakpoint::Watch(Watchpoint::new(0x4, 0x4, Access::Read)));
+
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.pc, 0x202);
+        let hit = cpu.memory.watch_hit().unwrap();
+        assert_eq!((hit.addr, hit.write, hit.value), (0x4, false, 0xf0));
+
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.pc, 0x206);
+        assert_eq!(
+            cpu.breakpoint_hit(),
+            Some(Breakpoint::Watch(Watchpoint::new(
+                0x300,
+                0x30f,
+                Access::Write
+            )))
+        );
+        let hit = cpu.memory.watch_hit().unwrap();
+        assert_eq!((hit.addr, hit.write), (0x300, true));
+    }
+
+    #[test]
+    fn self_modifying_code_runs_as_rewritten() {
+        // Run 7101 twice, then overwrite it with 7110 and run that.
+        let mut cpu = cpu_with(&[0x7101, 0x1200]);
+        run(&mut cpu, 4);
+        assert_eq!(cpu.registers.v[1], 2);
+        cpu.memory.write(0x201, 0x10).unwrap();
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[1], 0x12);
+    }
+
+    #[test]
+    fn jit_runs_the_same_as_the_interpreter() {
+        // Keep adding an ever-increasing V0 to V1, counting the carries in V2.
+        let program = [0x6001, 0x8104, 0x82f4, 0x7001, 0x1202];
+        let mut interpreted = cpu_with(&program);
+        let mut compiled = cpu_with(&program);
+        compiled.set_jit(true);
+        for _ in 0..30 {
+            interpreted.run_frame().unwrap();
+            compiled.run_frame().unwrap();
+            assert_eq!(compiled.snapshot(), interpreted.snapshot());
+            assert_eq!(compiled.instructions(), interpreted.instructions());
+        }
+        assert_eq!(
+            compiled.trace().entries().collect::<Vec<_>>(),
+            interpreted.trace().entries().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn cls_clears_display() {
+        let mut cpu = cpu_with(&[0x00e0]);
+        cpu.display.set(0, 0, 1);
+        cpu.display.set(63, 31, 1);
+        run(&mut cpu, 1);
+        assert!(cpu.display().iter().all(|&p| p == 0));
+    }
+
+    #[test]
+    fn call_and_return() {
+        let mut cpu = cpu_with(&[0x2206, 0x0000, 0x0000, 0x00ee]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x206);
+        assert_eq!(cpu.sp, 1);
+        assert_eq!(cpu.stack[0], 0x202);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.sp, 0);
+    }
+
+    #[test]
+    fn stack_overflow_and_underflow_are_errors() {
+        let mut cpu = cpu_with(&[0x2202, 0x2200]);
+        run(&mut cpu, 16);
+        assert_eq!(
+            cpu.step(),
+            Err(Chip8Error::StackOverflow {
+                pc: 0x200,
+                depth: 16
+            })
+        );
+        assert_eq!(
+            cpu.backtrace(),
+            format!("{}202 <- 200", "202 <- 200 <- ".repeat(7))
+        );
+
+        let mut cpu = cpu_with(&[0x00ee]);
+        assert_eq!(cpu.step(), Err(Chip8Error::StackUnderflow { pc: 0x200 }));
+    }
+
+    #[test]
+    fn calls_are_named_by_symbols() {
+        // main calls update, which calls draw, which spins.
+        let mut cpu = cpu_with(&[0x2204, 0x1200, 0x2208, 0x00ee, 0x1208]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.calls(), [(0x204, Some(0x208)), (0x200, Some(0x204))]);
+        assert_eq!(cpu.backtrace(), "204 <- 200");
+
+        let mut symbols = Symbols::new();
+        symbols.insert(0x200, "main");
+        symbols.insert(0x204, "update");
+        cpu.set_symbols(symbols);
+        assert_eq!(cpu.backtrace(), "update <- main");
+    }
+
+    #[test]
+    fn jump() {
+        let mut cpu = cpu_with(&[0x1345]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x345);
+    }
+
+    #[test]
+    fn jump_plus_v0() {
+        let mut cpu = cpu_with(&[0x6010, 0xb300]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x310);
+    }
+
+    #[test]
+    fn jump_quirk_uses_vx() {
+        let mut cpu = cpu_with(&[0x6010, 0x6320, 0xb300]);
+        cpu.quirks.jump = true;
+        run(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x320);
+    }
+
+    #[test]
+    fn skip_if_equal_immediate() {
+        let mut cpu = cpu_with(&[0x6142, 0x3142]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x206);
+
+        let mut cpu = cpu_with(&[0x6142, 0x3143]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn skip_if_not_equal_immediate() {
+        let mut cpu = cpu_with(&[0x6142, 0x4143]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x206);
+
+        let mut cpu = cpu_with(&[0x6142, 0x4142]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x204);
+    }
+
+    #[test]
+    fn skip_if_registers_equal_compares_values_not_indices() {
+        let mut cpu = cpu_with(&[0x6107, 0x6207, 0x5120]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x208);
+
+        let mut cpu = cpu_with(&[0x6107, 0x6208, 0x5120]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn skip_if_registers_not_equal() {
+        let mut cpu = cpu_with(&[0x6107, 0x6208, 0x9120]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x208);
+
+        let mut cpu = cpu_with(&[0x6107, 0x6207, 0x9120]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn register_skips_require_zero_low_nibble() {
+        let mut cpu = cpu_with(&[0x5121]);
+        assert_eq!(
+            cpu.step(),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0x5121
+            })
+        );
+        let mut cpu = cpu_with(&[0x9121]);
+        assert!(cpu.step().is_err());
+    }
+
+    #[test]
+    fn load_and_add_immediate() {
+        let mut cpu = cpu_with(&[0x6aff, 0x7a02]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[0xa], 0xff);
+        run(&mut cpu, 1);
+        // 7xkk wraps and never touches VF
+        assert_eq!(cpu.registers.v[0xa], 0x01);
+        assert_eq!(cpu.registers.v[0xf], 0);
+    }
+
+    #[test]
+    fn register_logic() {
+        let mut cpu = cpu_with(&[0x61f0, 0x623c, 0x8120]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x3c);
+
+        let mut cpu = cpu_with(&[0x61f0, 0x623c, 0x8121]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0xfc);
+
+        let mut cpu = cpu_with(&[0x61f0, 0x623c, 0x8122]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x30);
+
+        let mut cpu = cpu_with(&[0x61f0, 0x623c, 0x8123]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0xcc);
+    }
+
+    #[test]
+    fn vf_reset_quirk() {
+        for op in [0x8121, 0x8122, 0x8123] {
+            let mut cpu = cpu_with(&[0x6f05, 0x61ff, op]);
+            cpu.quirks.vf_reset = true;
+            run(&mut cpu, 3);
+            assert_eq!(cpu.registers.v[0xf], 0);
+
+            let mut cpu = cpu_with(&[0x6f05, 0x61ff, op]);
+            run(&mut cpu, 3);
+            assert_eq!(cpu.registers.v[0xf], 5);
+        }
+    }
+
+    #[test]
+    fn add_sets_carry() {
+        let mut cpu = cpu_with(&[0x61f0, 0x6220, 0x8124]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x10);
+        assert_eq!(cpu.registers.v[0xf], 1);
+
+        let mut cpu = cpu_with(&[0x6110, 0x6220, 0x8124]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x30);
+        assert_eq!(cpu.registers.v[0xf], 0);
+    }
+
+    #[test]
+    fn sub_sets_not_borrow() {
+        let mut cpu = cpu_with(&[0x6130, 0x6210, 0x8125]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x20);
+        assert_eq!(cpu.registers.v[0xf], 1);
+
+        let mut cpu = cpu_with(&[0x6110, 0x6230, 0x8125]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0xe0);
+        assert_eq!(cpu.registers.v[0xf], 0);
+    }
+
+    #[test]
+    fn subn_sets_not_borrow() {
+        let mut cpu = cpu_with(&[0x6110, 0x6230, 0x8127]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x20);
+        assert_eq!(cpu.registers.v[0xf], 1);
+
+        let mut cpu = cpu_with(&[0x6130, 0x6210, 0x8127]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0xe0);
+        assert_eq!(cpu.registers.v[0xf], 0);
+    }
+
+    #[test]
+    fn flag_wins_when_vf_is_the_destination() {
+        let mut cpu = cpu_with(&[0x6fff, 0x6102, 0x8f14]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[0xf], 1);
+    }
+
+    #[test]
+    fn shifts_copy_vy_by_default() {
+        let mut cpu = cpu_with(&[0x6100, 0x6205, 0x8126]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x02);
+        assert_eq!(cpu.registers.v[0xf], 1);
+
+        let mut cpu = cpu_with(&[0x6100, 0x6281, 0x812e]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x02);
+        assert_eq!(cpu.registers.v[0xf], 1);
+    }
+
+    #[test]
+    fn shifts_in_place_with_shiftquirk() {
+        let mut cpu = cpu_with(&[0x6104, 0x62ff, 0x8126]);
+        cpu.quirks.shift = true;
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x02);
+        assert_eq!(cpu.registers.v[0xf], 0);
+
+        let mut cpu = cpu_with(&[0x6140, 0x62ff, 0x812e]);
+        cpu.quirks.shift = true;
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[1], 0x80);
+        assert_eq!(cpu.registers.v[0xf], 0);
+    }
+
+    #[test]
+    fn load_i() {
+        let mut cpu = cpu_with(&[0xa123]);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.i, 0x123);
+    }
+
+    #[test]
+    fn random_is_masked() {
+        let mut cpu = cpu_with(&[0xc100, 0xc20f]);
+        cpu.seed_rng(1);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[1], 0);
+        assert_eq!(cpu.registers.v[2] & 0xf0, 0);
+    }
+
+    #[test]
+    fn draw_xors_and_reports_collision() {
+        // draw the "0" font sprite at (1, 2) twice
+        let mut cpu = cpu_with(&[0x6101, 0x6202, 0xa000, 0xd125, 0xd125]);
+        run(&mut cpu, 4);
+        assert!(pixel(&cpu, 1, 2));
+        assert!(pixel(&cpu, 4, 2));
+        assert!(!pixel(&cpu, 2, 3));
+        assert_eq!(cpu.registers.v[0xf], 0);
+        run(&mut cpu, 1);
+        assert!(cpu.display().iter().all(|&p| p == 0));
+        assert_eq!(cpu.registers.v[0xf], 1);
+    }
+
+    #[test]
+    fn display_wait_quirk_draws_once_per_frame() {
+        let mut cpu = cpu_with(&[0xa000, 0xd005, 0xd005, 0x7101, 0x1208]);
+        cpu.quirks.display_wait = true;
+        cpu.set_ips(600);
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.pc, 0x204);
+        assert!(pixel(&cpu, 0, 0));
+        cpu.run_frame().unwrap();
+        assert!(!pixel(&cpu, 0, 0));
+        assert_eq!(cpu.registers.v[1], 1);
+    }
+
+    #[test]
+    fn draw_wraps_around_edges() {
+        let mut cpu = cpu_with(&[0x613e, 0x621f, 0xa000, 0xd122]);
+        run(&mut cpu, 4);
+        assert!(pixel(&cpu, 62, 31));
+        assert!(pixel(&cpu, 1, 31));
+        assert!(pixel(&cpu, 62, 0));
+    }
+
+    #[test]
+    fn clip_quirk_clips_at_edges() {
+        let mut cpu = cpu_with(&[0x617e, 0x625f, 0xa000, 0xd122]);
+        cpu.quirks.clip = true;
+        run(&mut cpu, 4);
+        // the start position still wraps to (62, 31), but only the top-left corner fits
+        assert!(pixel(&cpu, 62, 31));
+        assert_eq!(cpu.display().iter().filter(|&&p| p != 0).count(), 2);
+    }
+
+    #[test]
+    fn key_skips() {
+        let mut cpu = cpu_with(&[0x6105, 0xe19e, 0x0000, 0xe1a1]);
+        cpu.keypad().press(5);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x206);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x208);
+
+        let mut cpu = cpu_with(&[0x6105, 0xe1a1]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0x206);
+    }
+
+    #[test]
+    fn wait_for_key_stores_key_on_release() {
+        let mut cpu = cpu_with(&[0xf30a]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x200);
+
+        cpu.keypad().press(0xb);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.pc, 0x200);
+
+        cpu.keypad().release(0xb);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x202);
+        assert_eq!(cpu.registers.v[3], 0xb);
+    }
+
+    #[test]
+    fn timers() {
+        let mut cpu = cpu_with(&[0x6102, 0xf115, 0xf118, 0xf207]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.delay, 2);
+        assert_eq!(cpu.registers.sound, 2);
+        cpu.tick_timers();
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[2], 1);
+        cpu.tick_timers();
+        cpu.tick_timers();
+        assert_eq!(cpu.registers.delay, 0);
+        assert_eq!(cpu.registers.sound, 0);
+    }
+
+    #[test]
+    fn add_to_i() {
+        let mut cpu = cpu_with(&[0xa100, 0x6120, 0xf11e]);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.i, 0x120);
+    }
+
+    #[test]
+    fn font_sprite_location() {
+        let mut cpu = cpu_with(&[0x610a, 0xf129]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.i, 50);
+    }
+
+    #[test]
+    fn bcd() {
+        let mut cpu = cpu_with(&[0x61fe, 0xa300, 0xf133]);
+        run(&mut cpu, 3);
+        assert_eq!(&cpu.memory[0x300..0x303], &[2, 5, 4]);
+    }
+
+    #[test]
+    fn store_and_load_registers() {
+        let mut cpu = cpu_with(&[0x6011, 0x6122, 0x6233, 0xa300, 0xf155, 0xa301, 0xf165]);
+        run(&mut cpu, 5);
+        assert_eq!(&cpu.memory[0x300..0x303], &[0x11, 0x22, 0]);
+        assert_eq!(cpu.registers.i, 0x300);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[0], 0x22);
+        assert_eq!(cpu.registers.v[1], 0);
+        assert_eq!(cpu.registers.v[2], 0x33);
+    }
+
+    #[test]
+    fn loadstore_quirk_increments_i() {
+        let mut cpu = cpu_with(&[0xa300, 0xf255, 0xf165]);
+        cpu.quirks.loadstore = true;
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.i, 0x303);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.i, 0x305);
+
+        let mut cpu = cpu_with(&[0xa300, 0xf255, 0xf165]);
+        cpu.quirks = Quirks::preset(Variant::Chip48);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.i, 0x303);
+    }
+
+    #[test]
+    fn out_of_bounds_access_is_an_error() {
+        let mut cpu = cpu_with(&[0xaffe, 0xf255]);
+        run(&mut cpu, 1);
+        assert_eq!(
+            cpu.step(),
+            Err(Chip8Error::MemoryOutOfBounds {
+                pc: 0x202,
+                addr: 0x1000
+            })
+        );
+    }
+
+    #[test]
+    fn wrapping_policy_wraps_stray_accesses() {
+        let mut cpu = cpu_with(&[0xaffe, 0xf255]);
+        cpu.memory.set_policy(AccessPolicy::Wrap);
+        cpu.registers.v[..3].copy_from_slice(&[1, 2, 3]);
+        run(&mut cpu, 2);
+        assert_eq!(&cpu.memory[0xffe..], &[1, 2]);
+        assert_eq!(cpu.memory[0], 3);
+    }
+
+    #[test]
+    fn unknown_opcode_is_an_error() {
+        let mut cpu = cpu_with(&[0xe1ff]);
+        assert_eq!(
+            cpu.step(),
+            Err(Chip8Error::UnknownOpcode {
+                pc: 0x200,
+                opcode: 0xe1ff
+            })
+        );
+    }
+
+    #[test]
+    fn run_frame_honours_ips() {
+        let mut cpu = cpu_with(&[0x1200]);
+        cpu.set_ips(90);
+        cpu.registers.delay = 10;
+        cpu.run_frame().unwrap();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.registers.delay, 8);
+        // 90 IPS is 1.5 instructions per frame, so two frames run three instructions in total
+        let mut cpu = cpu_with(&[0x7101, 0x7101, 0x7101, 0x7101]);
+        cpu.set_ips(90);
+        cpu.run_frame().unwrap();
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.registers.v[1], 3);
+    }
+
+    #[test]
+    fn vip_timing_charges_each_instruction_its_cycles() {
+        // Count up in V0 forever: 33 cycles a time round the loop.
+        let mut cpu = cpu_with(&[0x7001, 0x1200]);
+        cpu.set_ips(1);
+        cpu.set_timing(Timing::Vip);
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.registers.v[0] as u32, VIP_FRAME_BUDGET.div_ceil(33));
+        assert_eq!(cpu.pc, 0x200);
+
+        // Nothing runs after a sprite until the next frame.
+        let mut cpu = cpu_with(&[0xd015, 0x7101, 0x1200]);
+        cpu.set_timing(Timing::Vip);
+        cpu.run_frame().unwrap();
+        assert_eq!((cpu.pc, cpu.registers.v[1]), (0x202, 0));
+        cpu.run_frame().unwrap();
+        assert!(cpu.registers.v[1] > 0);
+    }
+
+    #[test]
+    fn schip_opcodes_are_unknown_in_chip8() {
+        let mut cpu = cpu_with(&[0x00ff]);
+        assert!(cpu.step().is_err());
+    }
+
+    #[test]
+    fn hires_toggle_changes_resolution_and_clears() {
+        let mut cpu = variant_cpu_with(Variant::Schip, &[0x00ff, 0x00fe]);
+        cpu.display.set(0, 0, 1);
+        cpu.display.set(63, 31, 1);
+        run(&mut cpu, 1);
+        assert!(cpu.hires());
+        assert_eq!(
+            cpu.display().len(),
+            HIRES_DISPLAY_WIDTH * HIRES_DISPLAY_HEIGHT
+        );
+        assert!(cpu.display().iter().all(|&p| p == 0));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.display_width(), DISPLAY_WIDTH);
+    }
+
+    #[test]
+    fn hires_chip8_roms_run_at_64x64() {
+        let mut rom = vec![0; 0xc0];
+        rom[..2].copy_from_slice(&[0x12, 0x60]);
+        // v1 = 40, then draw the "0" at (0, 40), then clear.
+        rom.extend([0x61, 0x28, 0xa0, 0x00, 0xd0, 0x15, 0x02, 0x30]);
+        let mut memory = Memory::new();
+        memory.load_rom_bytes(&rom).unwrap();
+        let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::default());
+        assert_eq!(cpu.max_display_size(), (64, 64));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.resolution(), Resolution::TwoPage);
+        assert_eq!(cpu.pc, 0x2c0);
+        assert_eq!((cpu.display_width(), cpu.display_height()), (64, 64));
+        run(&mut cpu, 3);
+        assert!(pixel(&cpu, 0, 40));
+        run(&mut cpu, 1);
+        assert!(cpu.display().iter().all(|&p| p == 0));
+        cpu.reset();
+        assert_eq!(cpu.resolution(), Resolution::Low);
+    }
+
+    #[cfg(feature = "megachip")]
+    #[test]
+    fn megachip_draws_colour_sprites_out_of_sight_until_cls() {
+        let mut cpu = variant_cpu_with(
+            Variant::MegaChip,
+            &[
+                0x0011, // megaon
+                0xa300, // ld i, 0x300
+                0x0201, // ldpal 1
+                0x0101, 0x0000, // ldhi i, 0x010000
+                0x0302, // sprw 2
+                0x0401, // sprh 1
+                0x6003, // ld v0, 3
+                0xd000, // drw v0, v0, 0
+                0x00e0, // cls
+                0x0901, // ccol 1
+                0xd000, // drw v0, v0, 0
+                0xd000, // drw v0, v0, 0
+            ],
+        );
+        cpu.memory[0x300..0x304].copy_from_slice(&[0xff, 0x12, 0x34, 0x56]);
+        // The second pixel is transparent.
+        cpu.memory[0x10000..0x10002].copy_from_slice(&[1, 0]);
+
+        run(&mut cpu, 1);
+        assert_eq!((cpu.display_width(), cpu.display_height()), (256, 192));
+        run(&mut cpu, 7);
+        assert_eq!(cpu.i_addr(), 0x10000);
+        assert!(cpu.display().iter().all(|&p| p == 0));
+        run(&mut cpu, 1);
+        assert_eq!(cpu.framebuffer().get(3, 3), 1);
+        assert_eq!(cpu.framebuffer().get(4, 3), 0);
+        assert_eq!(
+            cpu.framebuffer().palette().map(|palette| palette[1]),
+            Some(crate::Color::new(0x12, 0x34, 0x56))
+        );
+        assert!(cpu.mega.back.pixels().iter().all(|&p| p == 0));
+
+        // Drawing over the collision colour sets VF.
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.v[0xf], 0);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[0xf], 1);
+    }
+
+    #[cfg(feature = "megachip")]
+    #[test]
+    fn megachip_plays_digitised_sound() {
+        // ld i, 0x300, then digisnd 1, then skip over an ldhi and stop.
+        let mut cpu = variant_cpu_with(
+            Variant::MegaChip,
+            &[0xa300, 0x0601, 0x3000, 0x0101, 0x2345, 0x0700],
+        );
+        cpu.memory[0x300..0x309].copy_from_slice(&[0x1f, 0x40, 0, 0, 3, 0, 0x80, 0xff, 0x00]);
+        run(&mut cpu, 2);
+        let sound = cpu.digitized_sound().unwrap();
+        assert_eq!((sound.rate, sound.looping), (8000, false));
+        assert_eq!(&sound.samples[..], &[0x80, 0xff, 0x00]);
+        let state = cpu.save_state();
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x20a);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.digitized_sound(), None);
+        cpu.load_state(&SaveState::from_bytes(&state.to_bytes()).unwrap());
+        assert_eq!(cpu.save_state(), state);
+        assert!(cpu.digitized_sound().is_some());
+    }
+
+    #[test]
+    fn scrolling() {
+        let mut cpu = variant_cpu_with(Variant::Schip, &[0x00c3, 0x00fb, 0x00fc]);
+        cpu.display.set(0, 0, 1);
+        run(&mut cpu, 1);
+        assert!(pixel(&cpu, 0, 3));
+        assert!(!pixel(&cpu, 0, 0));
+        run(&mut cpu, 1);
+        assert!(pixel(&cpu, 4, 3));
+        run(&mut cpu, 1);
+        assert!(pixel(&cpu, 0, 3));
+        assert_eq!(cpu.display().iter().filter(|&&p| p != 0).count(), 1);
+    }
+
+    #[test]
+    fn big_sprite_counts_collided_rows_in_hires() {
+        let mut cpu = variant_cpu_with(Variant::Schip, &[0x00ff, 0xa300, 0xd000, 0xd000]);
+        cpu.memory[0x300..0x320].fill(0xff);
+        run(&mut cpu, 3);
+        let width = cpu.display_width();
+        let lit = cpu.display().iter().filter(|&&p| p != 0).count();
+        assert_eq!(lit, 256);
+        assert_eq!(cpu.display()[15 * width + 15], 1);
+        assert_eq!(cpu.registers.v[0xf], 0);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[0xf], 16);
+    }
+
+    #[test]
+    fn big_font_and_rpl_flags() {
+        let mut cpu = variant_cpu_with(
+            Variant::Schip,
+            &[0x6109, 0xf130, 0x6207, 0xf275, 0x6200, 0xf285],
+        );
+        run(&mut cpu, 2);
+        assert_eq!(cpu.registers.i as usize, BIG_FONT_ADDR + 90);
+        run(&mut cpu, 3);
+        assert_eq!(cpu.registers.v[2], 0);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.v[1], 9);
+        assert_eq!(cpu.registers.v[2], 7);
+    }
+
+    #[test]
+    fn wait_for_key_at_the_top_of_memory() {
+        let mut cpu = variant_cpu_with(Variant::XoChip, &[]);
+        cpu.memory[0xfffe..].copy_from_slice(&[0xf3, 0x0a]);
+        cpu.pc = 0xfffe;
+        run(&mut cpu, 2);
+        assert_eq!(cpu.pc, 0xfffe);
+    }
+
+    #[test]
+    fn long_i_and_skipping_over_it() {
+        let mut cpu = variant_cpu_with(
+            Variant::XoChip,
+            &[0xf000, 0xabcd, 0x3000, 0xf000, 0x1234, 0x0000],
+        );
+        run(&mut cpu, 1);
+        assert_eq!(cpu.registers.i, 0xabcd);
+        assert_eq!(cpu.pc, 0x204);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.pc, 0x20a);
+    }
+
+    #[test]
+    fn save_and_load_register_ranges() {
+        let mut cpu = variant_cpu_with(
+            Variant::XoChip,
+            &[0x6201, 0x6302, 0x6403, 0xa300, 0x5242, 0x5423],
+        );
+        run(&mut cpu, 5);
+        assert_eq!(&cpu.memory[0x300..0x303], &[1, 2, 3]);
+        assert_eq!(cpu.registers.i, 0x300);
+        // loading in reverse order swaps v2 and v4
+        run(&mut cpu, 1);
+        assert_eq!(&cpu.registers.v[2..5], &[3, 2, 1]);
+    }
+
+    #[test]
+    fn drawing_on_both_planes_reads_two_sprites() {
+        let mut cpu = variant_cpu_with(Variant::XoChip, &[0xf301, 0xa300, 0xd001]);
+        cpu.memory[0x300] = 0x80;
+        cpu.memory[0x301] = 0x40;
+        run(&mut cpu, 3);
+        assert_eq!(cpu.display()[0], 1);
+        assert_eq!(cpu.display()[1], 2);
+    }
+
+    #[test]
+    fn clear_and_scroll_only_touch_selected_planes() {
+        let mut cpu = variant_cpu_with(Variant::XoChip, &[0xf201, 0x00d1, 0x00e0]);
+        cpu.display.set(0, 1, 3);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.display()[0], 2);
+        assert_eq!(cpu.display()[DISPLAY_WIDTH], 1);
+        run(&mut cpu, 1);
+        assert_eq!(cpu.display()[0], 0);
+        assert_eq!(cpu.display()[DISPLAY_WIDTH], 1);
+    }
+
+    #[test]
+    fn resets_start_the_rom_over() {
+        // v0 = 7, stored to 0x300, then spin.
+        let mut cpu = cpu_with(&[0x6007, 0xa300, 0xf055, 0x1206]);
+        run(&mut cpu, 4);
+        cpu.soft_reset();
+        assert_eq!((cpu.pc, cpu.registers.v[0]), (0x200, 0));
+        assert_eq!(cpu.memory[0x300], 7);
+        run(&mut cpu, 4);
+        cpu.reset();
+        assert_eq!(
+            (cpu.pc, cpu.registers.v[0], cpu.memory[0x300]),
+            (0x200, 0, 0)
+        );
+        assert_eq!(
+            &cpu.memory[0x200..0x208],
+            &[0x60, 0x07, 0xa3, 0x00, 0xf0, 0x55, 0x12, 0x06]
+        );
+    }
+
+    #[test]
+    fn eti660_roms_start_at_0x600() {
+        let mut memory = Memory::new();
+        memory.set_load_addr(LoadAddress::ETI660);
+        memory.load_rom_bytes(&[0x60, 0x07, 0x16, 0x02]).unwrap();
+        let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::default());
+        assert_eq!(cpu.pc, 0x600);
+        run(&mut cpu, 2);
+        assert_eq!((cpu.pc, cpu.registers.v[0]), (0x602, 7));
+        cpu.reset();
+        assert_eq!(cpu.pc, 0x600);
+    }
+
+    #[test]
+    fn audio_pattern_and_extended_memory() {
+        let mut cpu = variant_cpu_with(Variant::XoChip, &[0xf000, 0xfff0, 0xf002]);
+        cpu.memory[0xfff0..0x10000].copy_from_slice(&[0xaa; 16]);
+        run(&mut cpu, 2);
+        assert_eq!(cpu.audio_pattern(), &[0xaa; 16]);
+    }
+
+    #[test]
+    fn pitch_sets_how_fast_the_pattern_plays() {
+        // ld v1, 112; ld pitch, v1
+        let mut cpu = variant_cpu_with(Variant::XoChip, &[0x6170, 0xf13a]);
+        assert_eq!(cpu.audio(), None);
+        run(&mut cpu, 2);
+        cpu.audio_pattern = [0xf0; 16];
+        let audio = cpu.audio().unwrap();
+        assert_eq!(audio.pitch, 112);
+        // 48 above the default is an octave up.
+        assert_eq!(audio.sample_rate(), 8000.0);
+    }
+}
@@ -0,0 +1,125 @@
+//! Crash reports: the state of the machine when a ROM faulted or the emulator panicked, written
+//! to a text file that can be attached to a bug report.
+//!
+//! A report has what went wrong, the PC and the opcode there, the registers and call stack, the
+//! last `REPORT_TRACE_LEN` instructions executed and a hex dump of all of memory.
+
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::disasm::Instr;
+use crate::CPU;
+
+/// How many of the last instructions executed a report includes.
+pub const REPORT_TRACE_LEN: usize = 100;
+/// Bytes per row of the memory dump.
+const DUMP_BYTES_PER_ROW: usize = 16;
+
+/// The report for `cpu`, which stopped because of `reason`.
+pub fn report(cpu: &CPU, reason: &str) -> String {
+    let mut out = String::new();
+    // Writing to a String can't fail.
+    let _ = write_report(&mut out, cpu, reason);
+    out
+}
+
+/// Writes the report for `cpu` to a file named `chip8-crash-<unix time>.txt` in `dir`, and
+/// returns its path.
+pub fn save(cpu: &CPU, reason: &str, dir: &Path) -> io::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let path = dir.join(format!("chip8-crash-{}.txt", timestamp));
+    std::fs::write(&path, report(cpu, reason))?;
+    Ok(path)
+}
+
+fn write_report(out: &mut String, cpu: &CPU, reason: &str) -> std::fmt::Result {
+    let snapshot = cpu.snapshot();
+    writeln!(out, "chip8-rs crash report")?;
+    writeln!(out)?;
+    writeln!(out, "Reason: {}", reason)?;
+    writeln!(out, "ROM SHA-1: {}", crate::rom_sha1(cpu.memory.rom()))?;
+    writeln!(out, "Variant: {}", cpu.variant())?;
+    writeln!(out, "Quirks: {:?}", cpu.quirks())?;
+    writeln!(out)?;
+
+    writeln!(out, "PC: {:04x}", snapshot.pc)?;
+    match snapshot.last_instruction {
+        Some((pc, opcode)) => {
+            let instr = Instr::decode(opcode, cpu.variant())
+                .map_or_else(|| "unknown".to_string(), |instr| instr.to_string());
+            writeln!(
+                out,
+                "Last instruction: {:04x}: {:04x} ({})",
+                pc, opcode, instr
+            )?;
+        }
+        None => writeln!(out, "Last instruction: none")?,
+    }
+    writeln!(
+        out,
+        "I: {:04x}  DT: {:02x}  ST: {:02x}  SP: {}",
+        snapshot.i, snapshot.delay, snapshot.sound, snapshot.sp
+    )?;
+    for (row, regs) in snapshot.v.chunks(4).enumerate() {
+        let regs: Vec<_> = regs
+            .iter()
+            .enumerate()
+            .map(|(i, v)| format!("V{:X}: {:02x}", row * 4 + i, v))
+            .collect();
+        writeln!(out, "{}", regs.join("  "))?;
+    }
+    let stack: Vec<_> = snapshot
+        .call_stack()
+        .iter()
+        .map(|ret| format!("{:04x}", ret))
+        .collect();
+    if stack.is_empty() {
+        writeln!(out, "Stack: empty")?;
+    } else {
+        writeln!(out, "Stack: {}", stack.join(" "))?;
+        writeln!(out, "Called from: {}", cpu.backtrace())?;
+    }
+    writeln!(out)?;
+
+    let trace = cpu.trace();
+    let shown = trace.len().min(REPORT_TRACE_LEN);
+    writeln!(out, "Last {} instructions, oldest first:", shown)?;
+    for entry in trace.entries().skip(trace.len() - shown) {
+        writeln!(out, "{}", entry.describe(cpu.variant()))?;
+    }
+    writeln!(out)?;
+
+    writeln!(out, "Memory:")?;
+    for (row, bytes) in cpu.memory.chunks(DUMP_BYTES_PER_ROW).enumerate() {
+        let hex: Vec<_> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+        writeln!(out, "{:04x}: {}", row * DUMP_BYTES_PER_ROW, hex.join(" "))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Keypad, Memory, Quirks};
+    use std::sync::Arc;
+
+    #[test]
+    fn reports_what_led_up_to_a_fault() {
+        // ld v3, 0x42; call 0x206; ret; 206: ret, back to a ret with nothing to return to
+        let mut memory = Memory::new();
+        memory
+            .load_rom_bytes(&[0x63, 0x42, 0x22, 0x06, 0x00, 0xee, 0x00, 0xee])
+            .unwrap();
+        let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::default());
+        let err = cpu.run_frame().unwrap_err();
+        let report = report(&cpu, &err.to_string());
+        assert!(report.contains(&format!("Reason: {}", err)));
+        assert!(report.contains("V3: 42"));
+        assert!(report.contains("0202: 2206  call 0x206"));
+        assert!(report.contains("0200: 63 42 22 06"));
+    }
+}
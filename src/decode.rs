@@ -0,0 +1,77 @@
+use crate::disasm::Instr;
+use crate::Variant;
+
+/// Instructions already decoded, by the address they were fetched from, so that loops don't
+/// decode the same opcodes over and over.
+///
+/// Each entry remembers the opcode it was decoded from and is only used while memory still holds
+/// it, so code the ROM rewrites, or a debugger pokes, is decoded afresh rather than run stale.
+pub struct DecodeCache {
+    entries: Vec<Option<(u16, Instr)>>,
+    /// What the entries were decoded as.
+    variant: Variant,
+}
+
+impl DecodeCache {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            variant: Variant::default(),
+        }
+    }
+
+    /// Decodes `opcode`, fetched from `addr`, as `Instr::decode` would.
+    pub fn decode(&mut self, addr: u16, opcode: u16, variant: Variant) -> Option<Instr> {
+        if variant != self.variant {
+            self.clear();
+            self.variant = variant;
+        }
+        let addr = addr as usize;
+        if addr >= self.entries.len() {
+            self.entries.resize(addr + 1, None);
+        }
+        match self.entries[addr] {
+            Some((cached, instr)) if cached == opcode => Some(instr),
+            _ => {
+                let instr = Instr::decode(opcode, variant)?;
+                self.entries[addr] = Some((opcode, instr));
+                Some(instr)
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewritten_code_is_decoded_again() {
+        let mut cache = DecodeCache::new();
+        let decode =
+            |cache: &mut DecodeCache, opcode, variant| cache.decode(0x200, opcode, variant);
+        assert_eq!(
+            decode(&mut cache, 0x6105, Variant::Chip8),
+            Some(Instr::LoadByte(1, 5))
+        );
+        assert_eq!(
+            decode(&mut cache, 0x7105, Variant::Chip8),
+            Some(Instr::AddByte(1, 5))
+        );
+        assert_eq!(decode(&mut cache, 0x00ff, Variant::Chip8), None);
+        assert_eq!(
+            decode(&mut cache, 0x00ff, Variant::Schip),
+            Some(Instr::High)
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! Differential testing: runs a ROM on `CPU` and on the `core` module's `Machine` side by side,
+//! with the same keys and random numbers, and reports the first place they disagree. They run
+//! the same instruction code, but each has its own memory, display, keypad and frame timing,
+//! and `CPU` has everything it adds for debugging and the extended variants in the way.
+//!
+//! Only CHIP-8 ROMs can be compared, since that's all `Machine` runs. Frames are compared as a
+//! whole, and once one differs it's run again an instruction at a time to find which
+//! instruction was the first to go wrong:
+//!
+//! ```no_run
+//! # use chip8::{differential::Lockstep, Quirks, Variant};
+//! # let rom = [0u8; 2];
+//! let mut lockstep = Lockstep::new(&rom, Quirks::preset(Variant::Chip8), 7).unwrap();
+//! if let Err(divergence) = lockstep.run(std::iter::repeat_n(0, 600)) {
+//!     panic!("{}", divergence);
+//! }
+//! ```
+use std::fmt;
+use std::sync::Arc;
+
+use crate::core::{self, Machine};
+use crate::disasm::Instr;
+use crate::error::Result;
+use crate::memory::RomError;
+use crate::{snapshot, Chip8Error, Keypad, Memory, Quirks, Rng, Variant, CPU};
+
+/// The first difference between the two interpreters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The frame it happened in, counting from 0.
+    pub frame: u32,
+    /// The address and opcode of the instruction after which they differed, if it could be
+    /// pinned down to one.
+    pub instruction: Option<(u16, u16)>,
+    /// What differs, like `V3 is 04, but 08 in the reference`.
+    pub differences: Vec<String>,
+    /// The two displays side by side, reference on the left, if they differ.
+    pub display: Option<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "diverged from the reference in frame {}", self.frame)?;
+        if let Some((pc, opcode)) = self.instruction {
+            let instr = Instr::decode(opcode, Variant::Chip8)
+                .map_or_else(|| "???".to_string(), |instr| instr.to_string());
+            write!(f, ", after {:03x}: {:04x}  {}", pc, opcode, instr)?;
+        }
+        writeln!(f)?;
+        for difference in &self.differences {
+            writeln!(f, "  {}", difference)?;
+        }
+        if let Some(display) = &self.display {
+            write!(f, "{}", display)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// A ROM running on both interpreters at once.
+pub struct Lockstep {
+    cpu: CPU,
+    reference: Machine,
+    /// How many instructions each frame runs.
+    per_frame: u32,
+    frame: u32,
+    /// The fault both interpreters stopped at, once they have.
+    faulted: Option<Chip8Error>,
+}
+
+impl Lockstep {
+    /// Loads `rom` into both, with `quirks` and their random number generators seeded with
+    /// `seed`, running `DEFAULT_IPS`.
+    pub fn new(rom: &[u8], quirks: Quirks, seed: u32) -> std::result::Result<Self, RomError> {
+        let mut memory = Memory::new();
+        memory.load_rom_bytes(rom)?;
+        let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), quirks);
+        cpu.set_rng(Rng::new(seed));
+        let mut reference = Machine::new(quirks, Rng::new(seed));
+        reference.load_rom(rom).map_err(|err| RomError::TooBig {
+            size: err.size,
+            max: err.max,
+        })?;
+        let mut lockstep = Self {
+            cpu,
+            reference,
+            per_frame: 0,
+            frame: 0,
+            faulted: None,
+        };
+        lockstep.set_ips(crate::DEFAULT_IPS);
+        Ok(lockstep)
+    }
+
+    /// Runs `ips` instructions a second, rounded down to a whole number a frame.
+    pub fn set_ips(&mut self, ips: u32) {
+        self.per_frame = ips / crate::FRAMES_PER_SECOND;
+        self.cpu.set_ips(self.per_frame * crate::FRAMES_PER_SECOND);
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn reference(&self) -> &Machine {
+        &self.reference
+    }
+
+    /// Runs a frame for each keypad state in `keys`, as a bitmask like a `Movie`'s, until they
+    /// diverge or both fault the same way. Returns how many frames ran.
+    pub fn run(
+        &mut self,
+        keys: impl IntoIterator<Item = u16>,
+    ) -> std::result::Result<u32, Divergence> {
+        let start = self.frame;
+        for keys in keys {
+            if !self.run_frame(keys)? {
+                break;
+            }
+        }
+        Ok(self.frame - start)
+    }
+
+    /// Runs a frame on both with `keys` held. Returns false, without running anything, once both
+    /// have faulted.
+    pub fn run_frame(&mut self, keys: u16) -> std::result::Result<bool, Divergence> {
+        if self.faulted.is_some() {
+            return Ok(false);
+        }
+        let before = (self.cpu.save_state(), self.reference.clone());
+        self.cpu.keypad().set_state(keys);
+        self.reference.keypad.set_state(keys);
+        let results = (
+            self.cpu.run_frame(),
+            self.reference.run_frame(self.per_frame),
+        );
+        if let Err(mut divergence) = self.compare(&results) {
+            // Go back over the frame an instruction at a time to find where it went wrong.
+            self.cpu.load_state(&before.0);
+            self.reference = before.1;
+            if let Some(instruction) = self.step_to_divergence() {
+                divergence = instruction;
+            }
+            return Err(divergence);
+        }
+        self.frame += 1;
+        if let (Err(err), Err(_)) = results {
+            self.faulted = Some(err);
+        }
+        Ok(self.faulted.is_none())
+    }
+
+    /// The frame just run, stepped again from the start with the state restored to before it, up
+    /// to the first instruction after which they differ.
+    fn step_to_divergence(&mut self) -> Option<Divergence> {
+        self.cpu.tick_timers();
+        self.reference.tick_timers();
+        for _ in 0..self.per_frame {
+            let (pc, opcode) = (self.reference.pc(), self.cpu.opcode_at(self.cpu.pc)?);
+            let results = (self.cpu.step(), self.reference.step());
+            if let Err(mut divergence) = self.compare(&results) {
+                divergence.instruction = Some((pc, opcode));
+                return Some(divergence);
+            }
+            if results.0.is_err() {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Checks that both are in the same state after running with `results`.
+    fn compare(&self, results: &(Result<()>, Result<()>)) -> std::result::Result<(), Divergence> {
+        let (cpu, reference) = (&self.cpu, &self.reference);
+        let mut differences = Vec::new();
+        let mut differ = |what: &str, ours: String, theirs: String| {
+            if ours != theirs {
+                differences.push(format!(
+                    "{} is {}, but {} in the reference",
+                    what, ours, theirs
+                ));
+            }
+        };
+        let outcome = |result: &Result<()>| match result {
+            Ok(()) => "running".to_string(),
+            Err(err) => format!("halted ({})", err),
+        };
+        differ("the machine", outcome(&results.0), outcome(&results.1));
+        differ(
+            "PC",
+            format!("{:03x}", cpu.pc),
+            format!("{:03x}", reference.pc()),
+        );
+        differ(
+            "I",
+            format!("{:03x}", cpu.registers.i),
+            format!("{:03x}", reference.i()),
+        );
+        for (x, (ours, theirs)) in cpu.registers.v.iter().zip(reference.v()).enumerate() {
+            differ(
+                &format!("V{:X}", x),
+                format!("{:02x}", ours),
+                format!("{:02x}", theirs),
+            );
+        }
+        differ(
+            "DT",
+            format!("{:02x}", cpu.registers.delay),
+            format!("{:02x}", reference.delay()),
+        );
+        differ(
+            "ST",
+            format!("{:02x}", cpu.registers.sound),
+            format!("{:02x}", reference.sound()),
+        );
+
+        let theirs = reference_text(reference);
+        let ours = cpu.framebuffer().to_text();
+        let display = (ours != theirs).then(|| snapshot::diff(&theirs, &ours));
+        if display.is_some() {
+            differences.push("the display differs".to_string());
+        }
+        if differences.is_empty() {
+            return Ok(());
+        }
+        Err(Divergence {
+            frame: self.frame,
+            instruction: None,
+            differences,
+            display,
+        })
+    }
+}
+
+/// The reference's display in the `FrameBuffer::to_text` format.
+fn reference_text(machine: &Machine) -> String {
+    let mut text = String::with_capacity((core::WIDTH + 1) * core::HEIGHT);
+    for y in 0..core::HEIGHT {
+        text.extend((0..core::WIDTH).map(|x| {
+            if machine.display.pixel(x, y) {
+                '#'
+            } else {
+                '.'
+            }
+        }));
+        text.push('\n');
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testroms::TEST_ROMS;
+
+    #[test]
+    fn the_test_roms_agree() {
+        for test in TEST_ROMS {
+            let mut lockstep = Lockstep::new(test.rom, Quirks::preset(Variant::Chip8), 1).unwrap();
+            let frames = std::iter::repeat_n(0, test.frames as usize);
+            if let Err(divergence) = lockstep.run(frames) {
+                panic!("{}: {}", test.name, divergence);
+            }
+        }
+    }
+
+    #[test]
+    fn pinpoints_the_first_instruction_to_differ() {
+        // Add 1 to V0 three times, then spin. Ours adds 2 the second time.
+        let rom = [0x70, 0x01, 0x70, 0x01, 0x70, 0x01, 0x12, 0x06];
+        let mut lockstep = Lockstep::new(&rom, Quirks::default(), 1).unwrap();
+        lockstep.cpu.memory[0x203] = 0x02;
+        let divergence = lockstep.run_frame(0).unwrap_err();
+        assert_eq!(divergence.frame, 0);
+        assert_eq!(divergence.instruction, Some((0x202, 0x7002)));
+        assert_eq!(
+            divergence.differences,
+            ["V0 is 03, but 02 in the reference"]
+        );
+        assert!(divergence
+            .to_string()
+            .contains("after 202: 7002  add v0, 0x02"));
+    }
+}
@@ -0,0 +1,919 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::{BTreeMap, BTreeSet};
+
+#[cfg(feature = "std")]
+use crate::coverage::{Coverage, Usage};
+#[cfg(feature = "std")]
+use crate::memory::MEMORY_SIZE;
+use crate::Variant;
+
+/// A decoded instruction. This is what the CPU executes, so anything that decodes here runs and
+/// anything that doesn't is an unknown opcode.
+///
+/// `x` and `y` are register numbers, `n` a nibble, `kk` a byte and `nnn` a 12-bit address,
+/// as in http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#3.0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instr {
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 00CN (SUPER-CHIP)
+    ScrollDown(u8),
+    /// 00DN (XO-CHIP)
+    ScrollUp(u8),
+    /// 00FB (SUPER-CHIP)
+    ScrollRight,
+    /// 00FC (SUPER-CHIP)
+    ScrollLeft,
+    /// 00FD (SUPER-CHIP)
+    Exit,
+    /// 00FE (SUPER-CHIP)
+    Low,
+    /// 00FF (SUPER-CHIP)
+    High,
+    /// 0230 (HIRES CHIP-8), which clears the 64x64 display.
+    HiresCls,
+    /// 0010 (MegaChip)
+    #[cfg(feature = "megachip")]
+    MegaOff,
+    /// 0011 (MegaChip)
+    #[cfg(feature = "megachip")]
+    MegaOn,
+    /// 00BN (MegaChip)
+    #[cfg(feature = "megachip")]
+    MegaScrollUp(u8),
+    /// 01NN NNNN (MegaChip). The low 16 bits of the address are the word after the opcode.
+    #[cfg(feature = "megachip")]
+    LoadIHigh(u8),
+    /// 02NN (MegaChip)
+    #[cfg(feature = "megachip")]
+    LoadPalette(u8),
+    /// 03NN (MegaChip)
+    #[cfg(feature = "megachip")]
+    SpriteWidth(u8),
+    /// 04NN (MegaChip)
+    #[cfg(feature = "megachip")]
+    SpriteHeight(u8),
+    /// 05NN (MegaChip)
+    #[cfg(feature = "megachip")]
+    Alpha(u8),
+    /// 060N (MegaChip)
+    #[cfg(feature = "megachip")]
+    PlaySound(u8),
+    /// 0700 (MegaChip)
+    #[cfg(feature = "megachip")]
+    StopSound,
+    /// 080N (MegaChip)
+    #[cfg(feature = "megachip")]
+    BlendMode(u8),
+    /// 09NN (MegaChip)
+    #[cfg(feature = "megachip")]
+    CollisionColor(u8),
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XKK
+    SkipEqByte(u8, u8),
+    /// 4XKK
+    SkipNeByte(u8, u8),
+    /// 5XY0
+    SkipEqReg(u8, u8),
+    /// 5XY2 (XO-CHIP)
+    SaveRange(u8, u8),
+    /// 5XY3 (XO-CHIP)
+    LoadRange(u8, u8),
+    /// 6XKK
+    LoadByte(u8, u8),
+    /// 7XKK
+    AddByte(u8, u8),
+    /// 8XY0
+    Move(u8, u8),
+    /// 8XY1
+    Or(u8, u8),
+    /// 8XY2
+    And(u8, u8),
+    /// 8XY3
+    Xor(u8, u8),
+    /// 8XY4
+    Add(u8, u8),
+    /// 8XY5
+    Sub(u8, u8),
+    /// 8XY6
+    Shr(u8, u8),
+    /// 8XY7
+    SubN(u8, u8),
+    /// 8XYE
+    Shl(u8, u8),
+    /// 9XY0
+    SkipNeReg(u8, u8),
+    /// ANNN
+    LoadI(u16),
+    /// BNNN, or BXNN with the jump quirk.
+    JumpV0(u16),
+    /// CXKK
+    Rand(u8, u8),
+    /// DXYN
+    Draw(u8, u8, u8),
+    /// EX9E
+    SkipKey(u8),
+    /// EXA1
+    SkipNotKey(u8),
+    /// F000 NNNN (XO-CHIP). The address is the word after the opcode.
+    LoadILong,
+    /// FN01 (XO-CHIP)
+    Plane(u8),
+    /// F002 (XO-CHIP)
+    Audio,
+    /// FX3A (XO-CHIP)
+    Pitch(u8),
+    /// FX07
+    GetDelay(u8),
+    /// FX0A
+    WaitKey(u8),
+    /// FX15
+    SetDelay(u8),
+    /// FX18
+    SetSound(u8),
+    /// FX1E
+    AddI(u8),
+    /// FX29
+    Font(u8),
+    /// FX30 (SUPER-CHIP)
+    BigFont(u8),
+    /// FX33
+    Bcd(u8),
+    /// FX55
+    Store(u8),
+    /// FX65
+    Restore(u8),
+    /// FX75 (SUPER-CHIP)
+    SaveFlags(u8),
+    /// FX85 (SUPER-CHIP)
+    LoadFlags(u8),
+}
+
+impl Instr {
+    /// Decodes `opcode`, or returns `None` if it isn't an instruction in `variant`.
+    pub fn decode(opcode: u16, variant: Variant) -> Option<Self> {
+        use Instr::*;
+
+        let x = ((opcode & 0x0f00) >> 8) as u8;
+        let y = ((opcode & 0x00f0) >> 4) as u8;
+        let n = (opcode & 0x000f) as u8;
+        let kk = (opcode & 0x00ff) as u8;
+        let nnn = opcode & 0x0fff;
+        let (schip, xochip) = (variant.is_schip(), variant.is_xochip());
+        #[cfg(feature = "megachip")]
+        let mega = variant.is_megachip();
+
+        Some(match opcode {
+            #[cfg(feature = "megachip")]
+            0x0000..=0x09ff if mega => match opcode {
+                0x0010 => MegaOff,
+                0x0011 => MegaOn,
+                0x00b0..=0x00bf => MegaScrollUp(n),
+                0x0100..=0x01ff => LoadIHigh(kk),
+                0x0200..=0x02ff => LoadPalette(kk),
+                0x0300..=0x03ff => SpriteWidth(kk),
+                0x0400..=0x04ff => SpriteHeight(kk),
+                0x0500..=0x05ff => Alpha(kk),
+                0x0600..=0x060f => PlaySound(n),
+                0x0700 => StopSound,
+                0x0800..=0x080f => BlendMode(n),
+                0x0900..=0x09ff => CollisionColor(kk),
+                0x00e0 => Cls,
+                0x00ee => Ret,
+                0x00c0..=0x00cf => ScrollDown(n),
+                0x00fb => ScrollRight,
+                0x00fc => ScrollLeft,
+                0x00fd => Exit,
+                0x00fe => Low,
+                0x00ff => High,
+                _ => return None,
+            },
+            0x00e0 => Cls,
+            0x00ee => Ret,
+            0x00c0..=0x00cf if schip => ScrollDown(n),
+            0x00d0..=0x00df if xochip => ScrollUp(n),
+            0x00fb if schip => ScrollRight,
+            0x00fc if schip => ScrollLeft,
+            0x00fd if schip => Exit,
+            0x00fe if schip => Low,
+            0x00ff if schip => High,
+            0x0230 => HiresCls,
+            0x1000..=0x1fff => Jump(nnn),
+            0x2000..=0x2fff => Call(nnn),
+            0x3000..=0x3fff => SkipEqByte(x, kk),
+            0x4000..=0x4fff => SkipNeByte(x, kk),
+            0x5000..=0x5fff => match n {
+                0x0 => SkipEqReg(x, y),
+                0x2 if xochip => SaveRange(x, y),
+                0x3 if xochip => LoadRange(x, y),
+                _ => return None,
+            },
+            0x6000..=0x6fff => LoadByte(x, kk),
+            0x7000..=0x7fff => AddByte(x, kk),
+            0x8000..=0x8fff => match n {
+                0x0 => Move(x, y),
+                0x1 => Or(x, y),
+                0x2 => And(x, y),
+                0x3 => Xor(x, y),
+                0x4 => Add(x, y),
+                0x5 => Sub(x, y),
+                0x6 => Shr(x, y),
+                0x7 => SubN(x, y),
+                0xe => Shl(x, y),
+                _ => return None,
+            },
+            0x9000..=0x9fff if n == 0 => SkipNeReg(x, y),
+            0xa000..=0xafff => LoadI(nnn),
+            0xb000..=0xbfff => JumpV0(nnn),
+            0xc000..=0xcfff => Rand(x, kk),
+            0xd000..=0xdfff => Draw(x, y, n),
+            0xe000..=0xefff => match kk {
+                0x9e => SkipKey(x),
+                0xa1 => SkipNotKey(x),
+                _ => return None,
+            },
+            0xf000 if xochip => LoadILong,
+            0xf000..=0xffff => match kk {
+                0x01 if xochip && x < 4 => Plane(x),
+                0x02 if xochip && x == 0 => Audio,
+                0x07 => GetDelay(x),
+                0x0a => WaitKey(x),
+                0x15 => SetDelay(x),
+                0x18 => SetSound(x),
+                0x1e => AddI(x),
+                0x29 => Font(x),
+                0x30 if schip => BigFont(x),
+                0x33 => Bcd(x),
+                0x3a if xochip => Pitch(x),
+                0x55 => Store(x),
+                0x65 => Restore(x),
+                // SUPER-CHIP only has 8 flag registers; XO-CHIP has 16.
+                0x75 if schip && (x < 8 || xochip) => SaveFlags(x),
+                0x85 if schip && (x < 8 || xochip) => LoadFlags(x),
+                _ => return None,
+            },
+            _ => return None,
+        })
+    }
+
+    /// The opcode for this instruction, the inverse of `decode`.
+    pub fn encode(&self) -> u16 {
+        use Instr::*;
+
+        let xy = |base: u16, x: u8, y: u8| base | (x as u16 & 0xf) << 8 | (y as u16 & 0xf) << 4;
+        let xkk = |base: u16, x: u8, kk: u8| base | (x as u16 & 0xf) << 8 | kk as u16;
+        match *self {
+            Cls => 0x00e0,
+            Ret => 0x00ee,
+            ScrollDown(n) => 0x00c0 | (n as u16 & 0xf),
+            ScrollUp(n) => 0x00d0 | (n as u16 & 0xf),
+            ScrollRight => 0x00fb,
+            ScrollLeft => 0x00fc,
+            Exit => 0x00fd,
+            Low => 0x00fe,
+            High => 0x00ff,
+            HiresCls => 0x0230,
+            #[cfg(feature = "megachip")]
+            MegaOff => 0x0010,
+            #[cfg(feature = "megachip")]
+            MegaOn => 0x0011,
+            #[cfg(feature = "megachip")]
+            MegaScrollUp(n) => 0x00b0 | (n as u16 & 0xf),
+            #[cfg(feature = "megachip")]
+            LoadIHigh(nn) => 0x0100 | nn as u16,
+            #[cfg(feature = "megachip")]
+            LoadPalette(nn) => 0x0200 | nn as u16,
+            #[cfg(feature = "megachip")]
+            SpriteWidth(nn) => 0x0300 | nn as u16,
+            #[cfg(feature = "megachip")]
+            SpriteHeight(nn) => 0x0400 | nn as u16,
+            #[cfg(feature = "megachip")]
+            Alpha(nn) => 0x0500 | nn as u16,
+            #[cfg(feature = "megachip")]
+            PlaySound(n) => 0x0600 | (n as u16 & 0xf),
+            #[cfg(feature = "megachip")]
+            StopSound => 0x0700,
+            #[cfg(feature = "megachip")]
+            BlendMode(n) => 0x0800 | (n as u16 & 0xf),
+            #[cfg(feature = "megachip")]
+            CollisionColor(nn) => 0x0900 | nn as u16,
+            Jump(nnn) => 0x1000 | (nnn & 0x0fff),
+            Call(nnn) => 0x2000 | (nnn & 0x0fff),
+            SkipEqByte(x, kk) => xkk(0x3000, x, kk),
+            SkipNeByte(x, kk) => xkk(0x4000, x, kk),
+            SkipEqReg(x, y) => xy(0x5000, x, y),
+            SaveRange(x, y) => xy(0x5002, x, y),
+            LoadRange(x, y) => xy(0x5003, x, y),
+            LoadByte(x, kk) => xkk(0x6000, x, kk),
+            AddByte(x, kk) => xkk(0x7000, x, kk),
+            Move(x, y) => xy(0x8000, x, y),
+            Or(x, y) => xy(0x8001, x, y),
+            And(x, y) => xy(0x8002, x, y),
+            Xor(x, y) => xy(0x8003, x, y),
+            Add(x, y) => xy(0x8004, x, y),
+            Sub(x, y) => xy(0x8005, x, y),
+            Shr(x, y) => xy(0x8006, x, y),
+            SubN(x, y) => xy(0x8007, x, y),
+            Shl(x, y) => xy(0x800e, x, y),
+            SkipNeReg(x, y) => xy(0x9000, x, y),
+            LoadI(nnn) => 0xa000 | (nnn & 0x0fff),
+            JumpV0(nnn) => 0xb000 | (nnn & 0x0fff),
+            Rand(x, kk) => xkk(0xc000, x, kk),
+            Draw(x, y, n) => xy(0xd000, x, y) | (n as u16 & 0xf),
+            SkipKey(x) => xkk(0xe000, x, 0x9e),
+            SkipNotKey(x) => xkk(0xe000, x, 0xa1),
+            LoadILong => 0xf000,
+            Plane(n) => xkk(0xf000, n, 0x01),
+            Audio => 0xf002,
+            GetDelay(x) => xkk(0xf000, x, 0x07),
+            WaitKey(x) => xkk(0xf000, x, 0x0a),
+            SetDelay(x) => xkk(0xf000, x, 0x15),
+            SetSound(x) => xkk(0xf000, x, 0x18),
+            AddI(x) => xkk(0xf000, x, 0x1e),
+            Font(x) => xkk(0xf000, x, 0x29),
+            BigFont(x) => xkk(0xf000, x, 0x30),
+            Bcd(x) => xkk(0xf000, x, 0x33),
+            Pitch(x) => xkk(0xf000, x, 0x3a),
+            Store(x) => xkk(0xf000, x, 0x55),
+            Restore(x) => xkk(0xf000, x, 0x65),
+            SaveFlags(x) => xkk(0xf000, x, 0x75),
+            LoadFlags(x) => xkk(0xf000, x, 0x85),
+        }
+    }
+
+    /// The instruction's size in bytes, including any operand words.
+    pub fn size(&self) -> u16 {
+        match self {
+            Instr::LoadILong => 4,
+            #[cfg(feature = "megachip")]
+            Instr::LoadIHigh(_) => 4,
+            _ => 2,
+        }
+    }
+
+    /// The instruction's opcode pattern, like `8XY4`.
+    pub fn pattern(&self) -> &'static str {
+        use Instr::*;
+
+        match self {
+            Cls => "00E0",
+            Ret => "00EE",
+            ScrollDown(..) => "00CN",
+            ScrollUp(..) => "00DN",
+            ScrollRight => "00FB",
+            ScrollLeft => "00FC",
+            Exit => "00FD",
+            Low => "00FE",
+            High => "00FF",
+            HiresCls => "0230",
+            #[cfg(feature = "megachip")]
+            MegaOff => "0010",
+            #[cfg(feature = "megachip")]
+            MegaOn => "0011",
+            #[cfg(feature = "megachip")]
+            MegaScrollUp(..) => "00BN",
+            #[cfg(feature = "megachip")]
+            LoadIHigh(..) => "01NN",
+            #[cfg(feature = "megachip")]
+            LoadPalette(..) => "02NN",
+            #[cfg(feature = "megachip")]
+            SpriteWidth(..) => "03NN",
+            #[cfg(feature = "megachip")]
+            SpriteHeight(..) => "04NN",
+            #[cfg(feature = "megachip")]
+            Alpha(..) => "05NN",
+            #[cfg(feature = "megachip")]
+            PlaySound(..) => "060N",
+            #[cfg(feature = "megachip")]
+            StopSound => "0700",
+            #[cfg(feature = "megachip")]
+            BlendMode(..) => "080N",
+            #[cfg(feature = "megachip")]
+            CollisionColor(..) => "09NN",
+            Jump(..) => "1NNN",
+            Call(..) => "2NNN",
+            SkipEqByte(..) => "3XKK",
+            SkipNeByte(..) => "4XKK",
+            SkipEqReg(..) => "5XY0",
+            SaveRange(..) => "5XY2",
+            LoadRange(..) => "5XY3",
+            LoadByte(..) => "6XKK",
+            AddByte(..) => "7XKK",
+            Move(..) => "8XY0",
+            Or(..) => "8XY1",
+            And(..) => "8XY2",
+            Xor(..) => "8XY3",
+            Add(..) => "8XY4",
+            Sub(..) => "8XY5",
+            Shr(..) => "8XY6",
+            SubN(..) => "8XY7",
+            Shl(..) => "8XYE",
+            SkipNeReg(..) => "9XY0",
+            LoadI(..) => "ANNN",
+            JumpV0(..) => "BNNN",
+            Rand(..) => "CXKK",
+            Draw(..) => "DXYN",
+            SkipKey(..) => "EX9E",
+            SkipNotKey(..) => "EXA1",
+            LoadILong => "F000",
+            Plane(..) => "FN01",
+            Audio => "F002",
+            GetDelay(..) => "FX07",
+            WaitKey(..) => "FX0A",
+            SetDelay(..) => "FX15",
+            SetSound(..) => "FX18",
+            AddI(..) => "FX1E",
+            Font(..) => "FX29",
+            BigFont(..) => "FX30",
+            Bcd(..) => "FX33",
+            Pitch(..) => "FX3A",
+            Store(..) => "FX55",
+            Restore(..) => "FX65",
+            SaveFlags(..) => "FX75",
+            LoadFlags(..) => "FX85",
+        }
+    }
+
+    /// The earliest variant that has this instruction.
+    pub fn variant(&self) -> Variant {
+        [
+            Variant::Chip8,
+            Variant::Schip,
+            Variant::XoChip,
+            #[cfg(feature = "megachip")]
+            Variant::MegaChip,
+        ]
+        .into_iter()
+        .find(|&variant| Instr::decode(self.encode(), variant) == Some(*self))
+        .unwrap_or(Variant::XoChip)
+    }
+
+    /// Whether this instruction conditionally skips the next one.
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instr::SkipEqByte(..)
+                | Instr::SkipNeByte(..)
+                | Instr::SkipEqReg(..)
+                | Instr::SkipNeReg(..)
+                | Instr::SkipKey(_)
+                | Instr::SkipNotKey(_)
+        )
+    }
+}
+
+/// Writes the instruction in Cowgod's syntax. `LoadILong`'s operand isn't part of the
+/// instruction, so it's left for the caller to append, and so are the low four digits of
+/// `LoadIHigh`'s.
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instr::*;
+
+        match *self {
+            Cls => write!(f, "cls"),
+            Ret => write!(f, "ret"),
+            ScrollDown(n) => write!(f, "scd {}", n),
+            ScrollUp(n) => write!(f, "scu {}", n),
+            ScrollRight => write!(f, "scr"),
+            ScrollLeft => write!(f, "scl"),
+            Exit => write!(f, "exit"),
+            Low => write!(f, "low"),
+            High => write!(f, "high"),
+            HiresCls => write!(f, "hcls"),
+            #[cfg(feature = "megachip")]
+            MegaOff => write!(f, "megaoff"),
+            #[cfg(feature = "megachip")]
+            MegaOn => write!(f, "megaon"),
+            #[cfg(feature = "megachip")]
+            MegaScrollUp(n) => write!(f, "scru {}", n),
+            #[cfg(feature = "megachip")]
+            LoadIHigh(nn) => write!(f, "ldhi i, {:#04x}", nn),
+            #[cfg(feature = "megachip")]
+            LoadPalette(nn) => write!(f, "ldpal {}", nn),
+            #[cfg(feature = "megachip")]
+            SpriteWidth(nn) => write!(f, "sprw {}", nn),
+            #[cfg(feature = "megachip")]
+            SpriteHeight(nn) => write!(f, "sprh {}", nn),
+            #[cfg(feature = "megachip")]
+            Alpha(nn) => write!(f, "alpha {:#04x}", nn),
+            #[cfg(feature = "megachip")]
+            PlaySound(n) => write!(f, "digisnd {}", n),
+            #[cfg(feature = "megachip")]
+            StopSound => write!(f, "stopsnd"),
+            #[cfg(feature = "megachip")]
+            BlendMode(n) => write!(f, "bmode {}", n),
+            #[cfg(feature = "megachip")]
+            CollisionColor(nn) => write!(f, "ccol {:#04x}", nn),
+            Jump(nnn) => write!(f, "jp {:#05x}", nnn),
+            Call(nnn) => write!(f, "call {:#05x}", nnn),
+            SkipEqByte(x, kk) => write!(f, "se v{:x}, {:#04x}", x, kk),
+            SkipNeByte(x, kk) => write!(f, "sne v{:x}, {:#04x}", x, kk),
+            SkipEqReg(x, y) => write!(f, "se v{:x}, v{:x}", x, y),
+            SaveRange(x, y) => write!(f, "save v{:x} - v{:x}", x, y),
+            LoadRange(x, y) => write!(f, "load v{:x} - v{:x}", x, y),
+            LoadByte(x, kk) => write!(f, "ld v{:x}, {:#04x}", x, kk),
+            AddByte(x, kk) => write!(f, "add v{:x}, {:#04x}", x, kk),
+            Move(x, y) => write!(f, "ld v{:x}, v{:x}", x, y),
+            Or(x, y) => write!(f, "or v{:x}, v{:x}", x, y),
+            And(x, y) => write!(f, "and v{:x}, v{:x}", x, y),
+            Xor(x, y) => write!(f, "xor v{:x}, v{:x}", x, y),
+            Add(x, y) => write!(f, "add v{:x}, v{:x}", x, y),
+            Sub(x, y) => write!(f, "sub v{:x}, v{:x}", x, y),
+            Shr(x, y) => write!(f, "shr v{:x}, v{:x}", x, y),
+            SubN(x, y) => write!(f, "subn v{:x}, v{:x}", x, y),
+            Shl(x, y) => write!(f, "shl v{:x}, v{:x}", x, y),
+            SkipNeReg(x, y) => write!(f, "sne v{:x}, v{:x}", x, y),
+            LoadI(nnn) => write!(f, "ld i, {:#05x}", nnn),
+            JumpV0(nnn) => write!(f, "jp v0, {:#05x}", nnn),
+            Rand(x, kk) => write!(f, "rnd v{:x}, {:#04x}", x, kk),
+            Draw(x, y, n) => write!(f, "drw v{:x}, v{:x}, {}", x, y, n),
+            SkipKey(x) => write!(f, "skp v{:x}", x),
+            SkipNotKey(x) => write!(f, "sknp v{:x}", x),
+            LoadILong => write!(f, "ld i, long"),
+            Plane(n) => write!(f, "plane {}", n),
+            Audio => write!(f, "audio"),
+            GetDelay(x) => write!(f, "ld v{:x}, dt", x),
+            WaitKey(x) => write!(f, "ld v{:x}, k", x),
+            SetDelay(x) => write!(f, "ld dt, v{:x}", x),
+            SetSound(x) => write!(f, "ld st, v{:x}", x),
+            AddI(x) => write!(f, "add i, v{:x}", x),
+            Font(x) => write!(f, "ld f, v{:x}", x),
+            BigFont(x) => write!(f, "ld hf, v{:x}", x),
+            Bcd(x) => write!(f, "ld b, v{:x}", x),
+            Pitch(x) => write!(f, "ld pitch, v{:x}", x),
+            Store(x) => write!(f, "ld [i], v{:x}", x),
+            Restore(x) => write!(f, "ld v{:x}, [i]", x),
+            SaveFlags(x) => write!(f, "ld r, v{:x}", x),
+            LoadFlags(x) => write!(f, "ld v{:x}, r", x),
+        }
+    }
+}
+
+/// One line of a `Disassembly`: either an instruction or a run of bytes that no reachable code
+/// executes.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Code {
+        addr: u16,
+        instr: Instr,
+        /// The opcode and any operand words.
+        words: Vec<u16>,
+    },
+    Data {
+        addr: u16,
+        bytes: Vec<u8>,
+    },
+}
+
+/// An annotated listing of a ROM. Code is told apart from data by following every path the
+/// program can take from its entry point; anything never reached is listed as data. That misses
+/// code only reached through computed jumps (BNNN), which gets listed as data too, unless a
+/// coverage map from running the ROM says otherwise.
+#[cfg(feature = "std")]
+pub struct Disassembly {
+    pub lines: Vec<Line>,
+    /// For each jump or call target, where it's jumped to or called from.
+    pub branches: BTreeMap<u16, BTreeSet<u16>>,
+    /// For each address loaded into I, which instructions load it.
+    pub data_refs: BTreeMap<u16, BTreeSet<u16>>,
+}
+
+/// How many data bytes get listed per line.
+#[cfg(feature = "std")]
+const DATA_BYTES_PER_LINE: usize = 8;
+
+#[cfg(feature = "std")]
+impl Disassembly {
+    /// Disassembles `rom`, which gets loaded at `origin` and starts executing there.
+    pub fn new(rom: &[u8], origin: u16, variant: Variant) -> Self {
+        Self::with_coverage(rom, origin, variant, &Coverage::new())
+    }
+
+    /// Like `new`, but also follows paths from everywhere `coverage` saw executed, and never
+    /// decodes what it only saw read as data.
+    pub fn with_coverage(rom: &[u8], origin: u16, variant: Variant, coverage: &Coverage) -> Self {
+        let end = origin as usize + rom.len();
+        let word_at = |addr: usize| {
+            let i = addr.checked_sub(origin as usize)?;
+            Some(u16::from_be_bytes([*rom.get(i)?, *rom.get(i + 1)?]))
+        };
+
+        let mut code = BTreeMap::new();
+        let mut branches: BTreeMap<u16, BTreeSet<u16>> = BTreeMap::new();
+        let mut data_refs: BTreeMap<u16, BTreeSet<u16>> = BTreeMap::new();
+        let mut pending = vec![origin];
+        // Popped last, so that following jumps gets first say over where instructions start.
+        pending.splice(
+            0..0,
+            coverage
+                .runs(origin as usize..end)
+                .into_iter()
+                .filter(|(_, usage)| usage.is_code())
+                .map(|(run, _)| run.start as u16),
+        );
+        while let Some(addr) = pending.pop() {
+            if code.contains_key(&addr)
+                || [addr, addr.wrapping_add(1)]
+                    .iter()
+                    .any(|&addr| coverage.usage(addr as usize) == Usage::Data)
+            {
+                continue;
+            }
+            let Some(instr) = word_at(addr as usize).and_then(|op| Instr::decode(op, variant))
+            else {
+                continue;
+            };
+            if instr.size() == 4 && word_at(addr as usize + 2).is_none() {
+                continue;
+            }
+            code.insert(addr, instr);
+
+            let next = addr.wrapping_add(instr.size());
+            match instr {
+                Instr::Jump(target) | Instr::Call(target) => {
+                    branches.entry(target).or_default().insert(addr);
+                    pending.push(target);
+                    if matches!(instr, Instr::Call(_)) {
+                        pending.push(next);
+                    }
+                }
+                Instr::Ret | Instr::Exit | Instr::JumpV0(_) => {}
+                _ if instr.is_skip() => {
+                    let skipped = word_at(next as usize)
+                        .and_then(|op| Instr::decode(op, variant))
+                        .map_or(2, |instr| instr.size());
+                    pending.push(next);
+                    pending.push(next.wrapping_add(skipped));
+                }
+                Instr::LoadI(target) => {
+                    data_refs.entry(target).or_default().insert(addr);
+                    pending.push(next);
+                }
+                _ => pending.push(next),
+            }
+        }
+
+        let mut lines = Vec::new();
+        let mut addr = origin as usize;
+        while addr < end {
+            if let Some(&instr) = code.get(&(addr as u16)) {
+                let words = (0..instr.size() as usize / 2)
+                    .filter_map(|i| word_at(addr + i * 2))
+                    .collect();
+                lines.push(Line::Code {
+                    addr: addr as u16,
+                    instr,
+                    words,
+                });
+                addr += instr.size() as usize;
+                continue;
+            }
+
+            // Break data runs wherever something refers to, so that it starts its own line.
+            let start = addr;
+            addr += 1;
+            while addr < end
+                && addr - start < DATA_BYTES_PER_LINE
+                && !code.contains_key(&(addr as u16))
+                && !data_refs.contains_key(&(addr as u16))
+                && !branches.contains_key(&(addr as u16))
+            {
+                addr += 1;
+            }
+            let range = start - origin as usize..addr - origin as usize;
+            lines.push(Line::Data {
+                addr: start as u16,
+                bytes: rom[range].to_vec(),
+            });
+        }
+
+        Self {
+            lines,
+            branches,
+            data_refs,
+        }
+    }
+}
+
+/// What can be worked out about a ROM without running it, from its reachable code.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Analysis {
+    /// The earliest variant that has every instruction the ROM uses, and enough memory for it.
+    pub variant: Variant,
+    /// How many times each instruction pattern appears, by `Instr::pattern`.
+    pub histogram: BTreeMap<&'static str, usize>,
+    pub code_bytes: usize,
+    pub data_bytes: usize,
+}
+
+#[cfg(feature = "std")]
+impl Analysis {
+    /// Analyses `rom` as XO-CHIP, which has every instruction the other variants do.
+    pub fn new(rom: &[u8]) -> Self {
+        let disassembly = Disassembly::new(rom, 0x200, Variant::XoChip);
+        let mut analysis = Self {
+            variant: if rom.len() > MEMORY_SIZE - 0x200 {
+                Variant::XoChip
+            } else {
+                Variant::Chip8
+            },
+            histogram: BTreeMap::new(),
+            code_bytes: 0,
+            data_bytes: 0,
+        };
+        for line in &disassembly.lines {
+            match line {
+                Line::Code { instr, .. } => {
+                    analysis.variant = analysis.variant.max(instr.variant());
+                    *analysis.histogram.entry(instr.pattern()).or_default() += 1;
+                    analysis.code_bytes += instr.size() as usize;
+                }
+                Line::Data { bytes, .. } => analysis.data_bytes += bytes.len(),
+            }
+        }
+        analysis
+    }
+}
+
+#[cfg(feature = "std")]
+fn join_addrs(addrs: &BTreeSet<u16>) -> String {
+    addrs
+        .iter()
+        .map(|addr| format!("{:03x}", addr))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// One line per instruction or data run, as `address  bytes  mnemonic`, with a comment above
+/// anything jumped to, called or loaded into I saying where from.
+#[cfg(feature = "std")]
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            let addr = match line {
+                Line::Code { addr, .. } | Line::Data { addr, .. } => *addr,
+            };
+            if let Some(from) = self.branches.get(&addr) {
+                writeln!(f, "; {:03x}: branched to from {}", addr, join_addrs(from))?;
+            }
+            if let Some(from) = self.data_refs.get(&addr) {
+                writeln!(f, "; {:03x}: loaded into i by {}", addr, join_addrs(from))?;
+            }
+
+            match line {
+                Line::Code { addr, instr, words } => {
+                    let hex: Vec<_> = words.iter().map(|w| format!("{:04x}", w)).collect();
+                    write!(f, "{:03x}  {:<24}  {}", addr, hex.join(" "), instr)?;
+                    if let (Instr::LoadILong, Some(nnnn)) = (instr, words.get(1)) {
+                        write!(f, " {:#06x}", nnnn)?;
+                    }
+                    #[cfg(feature = "megachip")]
+                    if let (Instr::LoadIHigh(_), Some(nnnn)) = (instr, words.get(1)) {
+                        write!(f, "{:04x}", nnnn)?;
+                    }
+                    writeln!(f)?;
+                }
+                Line::Data { addr, bytes } => {
+                    let hex: Vec<_> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+                    let db: Vec<_> = bytes.iter().map(|b| format!("{:#04x}", b)).collect();
+                    writeln!(
+                        f,
+                        "{:03x}  {:<24}  db {}",
+                        addr,
+                        hex.join(" "),
+                        db.join(", ")
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decoding_depends_on_variant() {
+        assert_eq!(
+            Instr::decode(0xd125, Variant::Chip8),
+            Some(Instr::Draw(1, 2, 5))
+        );
+        assert_eq!(Instr::decode(0x00ff, Variant::Chip8), None);
+        assert_eq!(Instr::decode(0x00ff, Variant::Schip), Some(Instr::High));
+        assert_eq!(Instr::decode(0xf875, Variant::Schip), None);
+        assert_eq!(
+            Instr::decode(0xf875, Variant::XoChip),
+            Some(Instr::SaveFlags(8))
+        );
+        assert_eq!(Instr::decode(0x8008, Variant::XoChip), None);
+        assert_eq!(
+            Instr::decode(0x5122, Variant::XoChip).unwrap().to_string(),
+            "save v1 - v2"
+        );
+        assert_eq!(
+            Instr::decode(0xa22a, Variant::Chip8).unwrap().to_string(),
+            "ld i, 0x22a"
+        );
+    }
+
+    #[test]
+    fn encode_inverts_decode() {
+        for opcode in 0..=0xffff {
+            if let Some(instr) = Instr::decode(opcode, Variant::XoChip) {
+                assert_eq!(instr.encode(), opcode, "{}", instr);
+            }
+        }
+    }
+
+    #[test]
+    fn unreachable_bytes_are_data() {
+        // 200: ld i, 208; 202: se v0, 0; 204: call 20a; 206: jp 206; 208: sprite; 20a: ret
+        let rom = [
+            0xa2, 0x08, 0x30, 0x00, 0x22, 0x0a, 0x12, 0x06, 0xff, 0x81, 0x00, 0xee,
+        ];
+        let disasm = Disassembly::new(&rom, 0x200, Variant::Chip8);
+        assert_eq!(disasm.lines.len(), 6);
+        assert_eq!(
+            disasm.lines[4],
+            Line::Data {
+                addr: 0x208,
+                bytes: vec![0xff, 0x81]
+            }
+        );
+        assert!(matches!(
+            disasm.lines[5],
+            Line::Code {
+                addr: 0x20a,
+                instr: Instr::Ret,
+                ..
+            }
+        ));
+        assert_eq!(disasm.branches[&0x206], BTreeSet::from([0x206]));
+        assert_eq!(disasm.data_refs[&0x208], BTreeSet::from([0x200]));
+
+        let listing = disasm.to_string();
+        assert!(listing.contains("; 20a: branched to from 204\n20a  00ee"));
+        assert!(listing.contains("208  ff 81"));
+    }
+
+    #[test]
+    fn coverage_finds_computed_jumps_and_data() {
+        // 200: ld v0, 2; 202: jp v0, 206; 204: sprite; 206: jp 206
+        let rom = [0x60, 0x02, 0xb2, 0x04, 0x00, 0xee, 0x12, 0x06];
+        let flow = Disassembly::new(&rom, 0x200, Variant::Chip8);
+        assert!(matches!(flow.lines[2], Line::Data { addr: 0x204, .. }));
+
+        let mut coverage = Coverage::new();
+        coverage.execute(0x200, 4);
+        coverage.execute(0x206, 2);
+        coverage.read(0x204);
+        coverage.read(0x205);
+        let disasm = Disassembly::with_coverage(&rom, 0x200, Variant::Chip8, &coverage);
+        assert!(matches!(disasm.lines[2], Line::Data { addr: 0x204, .. }));
+        assert!(matches!(
+            disasm.lines[3],
+            Line::Code {
+                addr: 0x206,
+                instr: Instr::Jump(0x206),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn analysis_finds_the_variant_needed() {
+        let rom = |ops: &[u16]| {
+            ops.iter()
+                .flat_map(|op| op.to_be_bytes())
+                .collect::<Vec<_>>()
+        };
+        let chip8 = Analysis::new(&rom(&[0x00e0, 0x6001, 0xd005, 0x1206]));
+        assert_eq!(chip8.variant, Variant::Chip8);
+        assert_eq!(chip8.histogram.get("DXYN"), Some(&1));
+        assert_eq!(chip8.code_bytes, 8);
+
+        assert_eq!(
+            Analysis::new(&rom(&[0x00ff, 0x1202])).variant,
+            Variant::Schip
+        );
+        assert_eq!(
+            Analysis::new(&rom(&[0xf875, 0x1202])).variant,
+            Variant::XoChip
+        );
+        // Unreachable data doesn't count.
+        assert_eq!(
+            Analysis::new(&rom(&[0x1200, 0x00ff])).variant,
+            Variant::Chip8
+        );
+    }
+}
@@ -0,0 +1,272 @@
+use crate::audio::{AudioPattern, Beeper, DigitizedSound, NullBeeper};
+use crate::error::Result;
+use crate::{
+    FrameBuffer, Frontend, InputEvent, Keypad, LoadAddress, Memory, Quirks, Rng, RomError, RunLoop,
+    RunStatus, Variant, CPU,
+};
+use std::sync::Arc;
+
+/// A machine with a ROM loaded and whatever it's plugged into: the way to embed the emulator in
+/// something else. Set one up with `Emulator::builder`.
+///
+/// Either drive it a frame at a time with `run_frame`, or give it a frontend and `run` it.
+pub struct Emulator {
+    cpu: CPU,
+    beeper: Box<dyn Beeper>,
+    frontend: Option<Box<dyn Frontend>>,
+}
+
+impl Emulator {
+    pub fn builder() -> EmulatorBuilder {
+        EmulatorBuilder::default()
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    /// The keypad the ROM reads, for pressing and releasing keys.
+    pub fn keypad(&self) -> &Arc<Keypad> {
+        self.cpu.keypad()
+    }
+
+    pub fn into_cpu(self) -> CPU {
+        self.cpu
+    }
+
+    /// Runs one 60Hz frame, then plays the tone on the beeper and shows the display on the
+    /// frontend, if there is one. Input is up to the caller.
+    pub fn run_frame(&mut self) -> Result<()> {
+        self.cpu.run_frame()?;
+        let cpu = &self.cpu;
+        self.beeper.set_pattern(cpu.audio());
+        self.beeper.set_sound(cpu.digitized_sound());
+        self.beeper.set_beeping(cpu.beeping());
+        if let Some(frontend) = &mut self.frontend {
+            frontend.play_pattern(cpu.audio());
+            frontend.play_sound(cpu.digitized_sound());
+            frontend.beep(cpu.beeping());
+            frontend.present(cpu.framebuffer());
+        }
+        self.cpu.clear_dirty();
+        Ok(())
+    }
+
+    /// Runs in real time on the frontend, as `RunLoop::run` does, until it sends
+    /// `InputEvent::Quit`. Without a frontend, there's nothing to show it on or quit it from, so
+    /// it returns straight away.
+    pub fn run(&mut self) {
+        let Some(frontend) = &mut self.frontend else {
+            return;
+        };
+        // The loop has the machine while it runs, leaving a blank one in its place.
+        let blank = CPU::new(Memory::new(), self.cpu.keypad().clone(), self.cpu.quirks());
+        let mut run_loop = RunLoop::new(std::mem::replace(&mut self.cpu, blank));
+        run_loop.run(&mut Attached {
+            frontend: frontend.as_mut(),
+            beeper: self.beeper.as_mut(),
+        });
+        self.cpu = run_loop.into_cpu();
+    }
+}
+
+/// Sets up an `Emulator`. Everything but the ROM has a default: the quirks of the variant,
+/// which is CHIP-8 unless told otherwise, a random number generator seeded from the clock, no
+/// sound and no frontend.
+#[derive(Default)]
+pub struct EmulatorBuilder {
+    rom: Vec<u8>,
+    load_addr: LoadAddress,
+    variant: Variant,
+    quirks: Option<Quirks>,
+    rng: Option<Rng>,
+    ips: Option<u32>,
+    beeper: Option<Box<dyn Beeper>>,
+    frontend: Option<Box<dyn Frontend>>,
+}
+
+impl EmulatorBuilder {
+    /// The ROM to load, at 0x200 unless `load_addr` says otherwise.
+    pub fn rom_bytes(mut self, rom: impl Into<Vec<u8>>) -> Self {
+        self.rom = rom.into();
+        self
+    }
+
+    /// The ROM as a list of opcodes, for small hand-written programs.
+    pub fn program(self, opcodes: &[u16]) -> Self {
+        self.rom_bytes(
+            opcodes
+                .iter()
+                .flat_map(|op| op.to_be_bytes())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Where the ROM is loaded and starts running, e.g. `LoadAddress::ETI660` for ETI-660 ROMs.
+    pub fn load_addr(mut self, load_addr: LoadAddress) -> Self {
+        self.load_addr = load_addr;
+        self
+    }
+
+    /// What the ROM was written for, which decides how much memory there is and the quirks, if
+    /// they aren't set.
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = Some(quirks);
+        self
+    }
+
+    /// The generator behind Cxkk, e.g. `Rng::new(seed)` for reproducible runs.
+    pub fn rng(mut self, rng: Rng) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+
+    /// Instructions per second, `DEFAULT_IPS` if not set.
+    pub fn ips(mut self, ips: u32) -> Self {
+        self.ips = Some(ips);
+        self
+    }
+
+    pub fn beeper(mut self, beeper: impl Beeper + 'static) -> Self {
+        self.beeper = Some(Box::new(beeper));
+        self
+    }
+
+    pub fn frontend(mut self, frontend: impl Frontend + 'static) -> Self {
+        self.frontend = Some(Box::new(frontend));
+        self
+    }
+
+    /// Loads the ROM, which fails only if it doesn't fit in memory.
+    pub fn build(self) -> std::result::Result<Emulator, RomError> {
+        let mut memory = Memory::with_size(self.variant.memory_size());
+        memory.set_load_addr(self.load_addr);
+        memory.load_rom_bytes(&self.rom)?;
+        let quirks = self.quirks.unwrap_or(Quirks::preset(self.variant));
+        let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), quirks);
+        cpu.set_variant(self.variant);
+        if let Some(rng) = self.rng {
+            cpu.set_rng(rng);
+        }
+        if let Some(ips) = self.ips {
+            cpu.set_ips(ips);
+        }
+        Ok(Emulator {
+            cpu,
+            beeper: self.beeper.unwrap_or_else(|| Box::new(NullBeeper)),
+            frontend: self.frontend,
+        })
+    }
+}
+
+/// A frontend with the emulator's beeper playing alongside it.
+struct Attached<'a> {
+    frontend: &'a mut dyn Frontend,
+    beeper: &'a mut dyn Beeper,
+}
+
+impl Frontend for Attached<'_> {
+    fn present(&mut self, fb: &FrameBuffer) {
+        self.frontend.present(fb);
+    }
+
+    fn poll_input(&mut self) -> Vec<InputEvent> {
+        self.frontend.poll_input()
+    }
+
+    fn beep(&mut self, on: bool) {
+        self.beeper.set_beeping(on);
+        self.frontend.beep(on);
+    }
+
+    fn play_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.beeper.set_pattern(pattern);
+        self.frontend.play_pattern(pattern);
+    }
+
+    fn play_sound(&mut self, sound: Option<&DigitizedSound>) {
+        self.beeper.set_sound(sound);
+        self.frontend.play_sound(sound);
+    }
+
+    fn present_machine(&mut self, cpu: &CPU, status: &RunStatus) {
+        self.frontend.present_machine(cpu, status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Counts the frames it's shown and the beeps, and quits after three frames.
+    struct Counter(Arc<Mutex<(u32, u32)>>);
+
+    impl Frontend for Counter {
+        fn present(&mut self, _: &FrameBuffer) {
+            self.0.lock().unwrap().0 += 1;
+        }
+
+        fn poll_input(&mut self) -> Vec<InputEvent> {
+            if self.0.lock().unwrap().0 >= 3 {
+                vec![InputEvent::Quit]
+            } else {
+                Vec::new()
+            }
+        }
+
+        fn beep(&mut self, on: bool) {
+            self.0.lock().unwrap().1 += on as u32;
+        }
+    }
+
+    impl Beeper for Counter {
+        fn set_beeping(&mut self, on: bool) {
+            self.0.lock().unwrap().1 += on as u32;
+        }
+    }
+
+    #[test]
+    fn builds_a_machine_from_its_parts() {
+        // ld v0, 0x3c; ld st, v0; rnd v1, 0xff; jp 0x206
+        let rom = [0x60, 0x3c, 0xf0, 0x18, 0xc1, 0xff, 0x12, 0x06];
+        let frontend = Arc::new(Mutex::new((0, 0)));
+        let beeper = Arc::new(Mutex::new((0, 0)));
+        let mut emulator = Emulator::builder()
+            .rom_bytes(rom)
+            .variant(Variant::Schip)
+            .rng(Rng::new(7))
+            .ips(600)
+            .beeper(Counter(beeper.clone()))
+            .frontend(Counter(frontend.clone()))
+            .build()
+            .unwrap();
+        assert_eq!(emulator.cpu().quirks(), Quirks::preset(Variant::Schip));
+        assert_eq!(emulator.cpu().ips(), 600);
+
+        emulator.run_frame().unwrap();
+        let mut rng = Rng::new(7);
+        assert_eq!(emulator.cpu().snapshot().v[1], rng.next_u8());
+        assert_eq!(*frontend.lock().unwrap(), (1, 1));
+        assert_eq!(*beeper.lock().unwrap(), (0, 1));
+
+        emulator.run();
+        assert_eq!(*frontend.lock().unwrap(), (3, 3));
+        assert_eq!(*beeper.lock().unwrap(), (0, 3));
+    }
+
+    #[test]
+    fn rejects_a_rom_too_big_for_memory() {
+        let result = Emulator::builder().rom_bytes(vec![0; 4096]).build();
+        assert!(matches!(result, Err(RomError::TooBig { .. })));
+    }
+}
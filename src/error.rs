@@ -0,0 +1,41 @@
+use core::fmt;
+
+/// A fatal error raised while executing a ROM. The CPU is left pointing just past the faulting
+/// instruction, so callers can report the error and stop, rather than tearing down the process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    /// The opcode at `pc` doesn't decode to any instruction.
+    UnknownOpcode { pc: u16, opcode: u16 },
+    /// 2nnn was executed with all `depth` stack slots already in use.
+    StackOverflow { pc: u16, depth: usize },
+    /// 00EE was executed with an empty stack.
+    StackUnderflow { pc: u16 },
+    /// The instruction at `pc` tried to access memory past the end of RAM.
+    MemoryOutOfBounds { pc: u16, addr: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::UnknownOpcode { pc, opcode } => {
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, pc)
+            }
+            Chip8Error::StackOverflow { pc, depth } => {
+                write!(f, "stack overflow at {:03X} ({} calls deep)", pc, depth)
+            }
+            Chip8Error::StackUnderflow { pc } => write!(f, "stack underflow at {:03X}", pc),
+            Chip8Error::MemoryOutOfBounds { pc, addr } => {
+                write!(
+                    f,
+                    "out of bounds memory access to {:04X} at {:03X}",
+                    addr, pc
+                )
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Chip8Error {}
+
+pub type Result<T> = core::result::Result<T, Chip8Error>;
@@ -0,0 +1,235 @@
+//! A C ABI for driving the emulator from frontends written in other languages, like C and C++
+//! emulator shells. Building with the `ffi` feature generates `include/chip8.h` to go with it.
+//!
+//! The frontend owns a `Chip8` from `chip8_new` until it hands it back to `chip8_free`, and
+//! drives it much like the browser bindings do: a frame at a time, reading the display out and
+//! passing key events in. Functions that can fail return 0 on success and -1 on failure, with
+//! `chip8_last_error` saying what went wrong.
+use std::ffi::{c_char, CString};
+use std::ptr;
+
+use crate::{Emulator, Rng, Variant};
+
+/// The instruction set a ROM was written for.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip8Variant {
+    Chip8,
+    Schip,
+    XoChip,
+    Chip48,
+}
+
+impl From<Chip8Variant> for Variant {
+    fn from(variant: Chip8Variant) -> Self {
+        match variant {
+            Chip8Variant::Chip8 => Variant::Chip8,
+            Chip8Variant::Schip => Variant::Schip,
+            Chip8Variant::XoChip => Variant::XoChip,
+            Chip8Variant::Chip48 => Variant::Chip48,
+        }
+    }
+}
+
+/// An emulator, opaque to C.
+pub struct Chip8 {
+    emulator: Emulator,
+    seed: u32,
+    last_error: Option<CString>,
+}
+
+impl Chip8 {
+    /// Turns `result` into a return code, keeping the error for `chip8_last_error`.
+    fn status<E: std::fmt::Display>(&mut self, result: Result<(), E>) -> i32 {
+        match result {
+            Ok(()) => {
+                self.last_error = None;
+                0
+            }
+            Err(err) => {
+                // Error messages never have NULs in them.
+                self.last_error = CString::new(err.to_string()).ok();
+                -1
+            }
+        }
+    }
+}
+
+/// A new emulator with nothing loaded, its random number generator seeded with `seed`. Free it
+/// with `chip8_free`.
+#[no_mangle]
+pub extern "C" fn chip8_new(seed: u32) -> *mut Chip8 {
+    let emulator = Emulator::builder()
+        .rng(Rng::new(seed))
+        .build()
+        .expect("no ROM always fits");
+    Box::into_raw(Box::new(Chip8 {
+        emulator,
+        seed,
+        last_error: None,
+    }))
+}
+
+/// Resets the machine and loads the `len` bytes at `rom` to run as `variant`, with that variant's
+/// quirks. Fails if the ROM doesn't fit in memory.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`, and `rom` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(
+    chip8: *mut Chip8,
+    rom: *const u8,
+    len: usize,
+    variant: Chip8Variant,
+) -> i32 {
+    let Some(chip8) = chip8.as_mut() else {
+        return -1;
+    };
+    let rom = if len == 0 {
+        &[][..]
+    } else if rom.is_null() {
+        return chip8.status(Err("the ROM is a null pointer"));
+    } else {
+        std::slice::from_raw_parts(rom, len)
+    };
+    let result = Emulator::builder()
+        .rom_bytes(rom)
+        .variant(variant.into())
+        .rng(Rng::new(chip8.seed))
+        .build()
+        .map(|emulator| chip8.emulator = emulator);
+    chip8.status(result)
+}
+
+/// Runs one 60Hz frame. Fails once the ROM faults.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step_frame(chip8: *mut Chip8) -> i32 {
+    let Some(chip8) = chip8.as_mut() else {
+        return -1;
+    };
+    let result = chip8.emulator.run_frame();
+    chip8.status(result)
+}
+
+/// The display: `width * height` bytes, row-major, non-zero for lit pixels, bit 0 for the first
+/// XO-CHIP plane and bit 1 for the second. Writes the size to `width` and `height`, which can be
+/// null. Only valid until the next call that takes `chip8`.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`, and `width` and `height` must each be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(
+    chip8: *const Chip8,
+    width: *mut usize,
+    height: *mut usize,
+) -> *const u8 {
+    let Some(chip8) = chip8.as_ref() else {
+        return ptr::null();
+    };
+    let fb = chip8.emulator.cpu().framebuffer();
+    if let Some(width) = width.as_mut() {
+        *width = fb.width();
+    }
+    if let Some(height) = height.as_mut() {
+        *height = fb.height();
+    }
+    fb.pixels().as_ptr()
+}
+
+/// Presses or releases hex keypad key `key`.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_key_event(chip8: *const Chip8, key: u8, pressed: bool) {
+    let Some(chip8) = chip8.as_ref() else {
+        return;
+    };
+    if pressed {
+        chip8.emulator.keypad().press(key);
+    } else {
+        chip8.emulator.keypad().release(key);
+    }
+}
+
+/// Whether the sound timer is running, for the frontend to start or stop a tone.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_beeping(chip8: *const Chip8) -> bool {
+    chip8
+        .as_ref()
+        .is_some_and(|chip8| chip8.emulator.cpu().beeping())
+}
+
+/// Why the last call that failed did, or null if the last call succeeded. Only valid until the
+/// next call that takes `chip8`.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_last_error(chip8: *const Chip8) -> *const c_char {
+    chip8
+        .as_ref()
+        .and_then(|chip8| chip8.last_error.as_ref())
+        .map_or(ptr::null(), |err| err.as_ptr())
+}
+
+/// Frees an emulator from `chip8_new`. Does nothing with null.
+///
+/// # Safety
+///
+/// `chip8` must come from `chip8_new` and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn drives_the_emulator_through_the_c_abi() {
+        // Draw the 0 glyph at the top left, then return with nothing to return to.
+        let rom = [0x60, 0x00, 0xf0, 0x29, 0xd0, 0x05, 0x00, 0xee];
+        unsafe {
+            let chip8 = chip8_new(1);
+            assert_eq!(
+                chip8_load_rom(chip8, rom.as_ptr(), rom.len(), Chip8Variant::Chip8),
+                0
+            );
+            assert_eq!(chip8_step_frame(chip8), -1);
+            let err = CStr::from_ptr(chip8_last_error(chip8));
+            assert_eq!(err.to_str().unwrap(), "stack underflow at 206");
+
+            let (mut width, mut height) = (0, 0);
+            let display = chip8_framebuffer(chip8, &mut width, &mut height);
+            assert_eq!((width, height), (64, 32));
+            let display = std::slice::from_raw_parts(display, width * height);
+            assert_eq!(display[..5], [1, 1, 1, 1, 0]);
+
+            chip8_key_event(chip8, 0xa, true);
+            assert!((*chip8).emulator.keypad().is_pressed(0xa));
+            let too_big = [0; 4096];
+            assert_eq!(
+                chip8_load_rom(chip8, too_big.as_ptr(), too_big.len(), Chip8Variant::Chip8),
+                -1
+            );
+            chip8_free(chip8);
+        }
+    }
+}
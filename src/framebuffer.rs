@@ -0,0 +1,359 @@
+use std::sync::Arc;
+
+use crate::Color;
+
+/// The part of a `FrameBuffer` that has changed since its dirty region was last cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DirtyRegion {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRegion {
+    /// The rows the region covers.
+    pub fn rows(&self) -> std::ops::Range<usize> {
+        self.y..self.y + self.height
+    }
+
+    /// Grows the region to take in (`x`, `y`).
+    fn include(&mut self, x: usize, y: usize) {
+        let (right, bottom) = (
+            (self.x + self.width).max(x + 1),
+            (self.y + self.height).max(y + 1),
+        );
+        self.x = self.x.min(x);
+        self.y = self.y.min(y);
+        self.width = right - self.x;
+        self.height = bottom - self.y;
+    }
+}
+
+/// The display: a grid of pixels at the current resolution, with a record of which of them have
+/// changed so frontends can skip redrawing what hasn't.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    /// Row-major. Each pixel is a bitmask of the display planes it's lit on, or an index into
+    /// `palette` if there is one.
+    pixels: Vec<u8>,
+    dirty: Option<DirtyRegion>,
+    /// The colours of MegaChip's display, whose pixels pick one each.
+    palette: Option<Arc<[Color]>>,
+}
+
+impl FrameBuffer {
+    /// A blank display, all of it dirty.
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width * height],
+            dirty: Self::everything(width, height),
+            palette: None,
+        }
+    }
+
+    fn everything(width: usize, height: usize) -> Option<DirtyRegion> {
+        Some(DirtyRegion {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        })
+        .filter(|_| width * height > 0)
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Every pixel, row-major, `width()` per row. Anything non-zero is lit.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// The colour each pixel value stands for, for displays in colour rather than lit or unlit.
+    pub fn palette(&self) -> Option<&[Color]> {
+        self.palette.as_deref()
+    }
+
+    /// Colours the display in with `palette`, or takes it back to lit and unlit with `None`.
+    pub fn set_palette(&mut self, palette: Option<Arc<[Color]>>) {
+        if self.palette != palette {
+            self.palette = palette;
+            self.dirty = Self::everything(self.width, self.height);
+        }
+    }
+
+    /// The planes the pixel at (`x`, `y`) is lit on, or 0 if it's off or off the screen.
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x]
+        } else {
+            0
+        }
+    }
+
+    /// Sets the planes the pixel at (`x`, `y`) is lit on. Does nothing off the screen.
+    pub fn set(&mut self, x: usize, y: usize, planes: u8) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let pixel = &mut self.pixels[y * self.width + x];
+        if *pixel != planes {
+            *pixel = planes;
+            self.mark(x, y);
+        }
+    }
+
+    /// Flips the pixel at (`x`, `y`) on `planes`, the way sprites are drawn, and whether that
+    /// turned anything off. Does nothing off the screen.
+    pub fn xor_pixel(&mut self, x: usize, y: usize, planes: u8) -> bool {
+        if x >= self.width || y >= self.height || planes == 0 {
+            return false;
+        }
+        let pixel = &mut self.pixels[y * self.width + x];
+        let collided = *pixel & planes != 0;
+        *pixel ^= planes;
+        self.mark(x, y);
+        collided
+    }
+
+    /// Turns every pixel off.
+    pub fn clear(&mut self) {
+        self.clear_planes(u8::MAX);
+    }
+
+    /// Turns every pixel off on `planes`, leaving the others alone.
+    pub fn clear_planes(&mut self, planes: u8) {
+        let mut changed = false;
+        for pixel in self.pixels.iter_mut() {
+            changed |= *pixel & planes != 0;
+            *pixel &= !planes;
+        }
+        if changed {
+            self.dirty = Self::everything(self.width, self.height);
+        }
+    }
+
+    /// Shifts `planes` by (`dx`, `dy`) pixels, shifting in blank pixels.
+    pub fn scroll(&mut self, dx: isize, dy: isize, planes: u8) {
+        let (width, height) = (self.width, self.height);
+        let before = self.pixels.clone();
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = (x as isize - dx, y as isize - dy);
+                let moved =
+                    if (0..width as isize).contains(&sx) && (0..height as isize).contains(&sy) {
+                        before[sy as usize * width + sx as usize]
+                    } else {
+                        0
+                    };
+                let idx = y * width + x;
+                self.pixels[idx] = (before[idx] & !planes) | (moved & planes);
+            }
+        }
+        if self.pixels != before {
+            self.dirty = Self::everything(width, height);
+        }
+    }
+
+    /// Switches to a `width` by `height` display, blanking it and dropping any palette.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.palette = None;
+        self.pixels.clear();
+        self.pixels.resize(width * height, 0);
+        self.dirty = Self::everything(width, height);
+    }
+
+    /// Replaces every pixel, for restoring a saved display.
+    pub(crate) fn copy_from(&mut self, pixels: &[u8]) {
+        let len = self.pixels.len();
+        self.pixels.copy_from_slice(&pixels[..len]);
+        self.dirty = Self::everything(self.width, self.height);
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.width)
+    }
+
+    /// The display as text, one line per row, with `#` for lit pixels and `.` for unlit ones.
+    /// This is the format of snapshot files.
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.width + 1) * self.height);
+        for row in self.rows() {
+            text.extend(row.iter().map(|&p| if p != 0 { '#' } else { '.' }));
+            text.push('\n');
+        }
+        text
+    }
+
+    /// What has changed since `clear_dirty()` was last called, or `None` if nothing has.
+    pub fn dirty(&self) -> Option<DirtyRegion> {
+        self.dirty
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.is_some()
+    }
+
+    /// Marks the whole display as drawn.
+    pub fn clear_dirty(&mut self) {
+        self.dirty = None;
+    }
+
+    fn mark(&mut self, x: usize, y: usize) {
+        match &mut self.dirty {
+            Some(region) => region.include(x, y),
+            None => {
+                self.dirty = Some(DirtyRegion {
+                    x,
+                    y,
+                    width: 1,
+                    height: 1,
+                })
+            }
+        }
+    }
+}
+
+/// Fades pixels out over a few frames instead of turning them off at once, which hides the flicker
+/// of sprites being erased and redrawn every frame. Frontends feed it each frame they draw and
+/// use its brightness levels in place of the display's on/off pixels.
+#[derive(Debug, Clone)]
+pub struct Phosphor {
+    /// How much brightness an unlit pixel loses each frame.
+    decay: u8,
+    width: usize,
+    height: usize,
+    /// Row-major, from 0 for off to 255 for fully lit.
+    levels: Vec<u8>,
+}
+
+impl Phosphor {
+    /// Fades pixels out over `frames` frames.
+    pub fn new(frames: u8) -> Self {
+        Self {
+            decay: (u8::MAX / frames.max(1)).max(1),
+            width: 0,
+            height: 0,
+            levels: Vec::new(),
+        }
+    }
+
+    /// Lights up whatever's lit in `fb` and fades everything else a step. Returns the rows that
+    /// changed brightness, or `None` if none did.
+    pub fn update(&mut self, fb: &FrameBuffer) -> Option<std::ops::Range<usize>> {
+        if (fb.width(), fb.height()) != (self.width, self.height) {
+            self.width = fb.width();
+            self.height = fb.height();
+            self.levels = vec![0; self.width * self.height];
+        }
+        let (mut first, mut last) = (None, 0);
+        for (i, (level, &pixel)) in self.levels.iter_mut().zip(fb.pixels()).enumerate() {
+            let faded = if pixel != 0 {
+                u8::MAX
+            } else {
+                level.saturating_sub(self.decay)
+            };
+            if faded != *level {
+                *level = faded;
+                let y = i / self.width;
+                first.get_or_insert(y);
+                last = y;
+            }
+        }
+        first.map(|first| first..last + 1)
+    }
+
+    /// How bright the pixel at (`x`, `y`) is, or 0 if it's off the screen.
+    pub fn level(&self, x: usize, y: usize) -> u8 {
+        if x < self.width && y < self.height {
+            self.levels[y * self.width + x]
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xor_reports_collisions_and_tracks_dirt() {
+        let mut fb = FrameBuffer::new(64, 32);
+        assert!(fb.is_dirty());
+        fb.clear_dirty();
+
+        assert!(!fb.xor_pixel(3, 4, 1));
+        assert!(!fb.xor_pixel(10, 2, 1));
+        assert_eq!(fb.get(3, 4), 1);
+        assert_eq!(
+            fb.dirty(),
+            Some(DirtyRegion {
+                x: 3,
+                y: 2,
+                width: 8,
+                height: 3
+            })
+        );
+        assert!(fb.xor_pixel(3, 4, 1));
+        assert_eq!(fb.get(3, 4), 0);
+        fb.xor_pixel(10, 2, 1);
+
+        // Nothing's lit, so neither of these change anything.
+        fb.clear_dirty();
+        fb.set(0, 0, 0);
+        fb.clear();
+        assert!(!fb.is_dirty());
+        assert!(!fb.xor_pixel(64, 0, 1));
+        assert!(!fb.is_dirty());
+    }
+
+    #[test]
+    fn resizing_blanks_the_display() {
+        let mut fb = FrameBuffer::new(64, 32);
+        fb.set(1, 1, 3);
+        fb.clear_dirty();
+        fb.resize(128, 64);
+        assert_eq!((fb.width(), fb.height()), (128, 64));
+        assert_eq!(fb.rows().count(), 64);
+        assert!(fb.pixels().iter().all(|&p| p == 0));
+        assert_eq!(fb.dirty().map(|region| region.rows()), Some(0..64));
+    }
+
+    #[test]
+    fn scrolls_only_the_selected_planes() {
+        let mut fb = FrameBuffer::new(8, 4);
+        fb.set(0, 0, 3);
+        fb.scroll(1, 1, 2);
+        assert_eq!(fb.get(0, 0), 1);
+        assert_eq!(fb.get(1, 1), 2);
+    }
+
+    #[test]
+    fn phosphor_fades_pixels_out() {
+        let mut fb = FrameBuffer::new(8, 4);
+        let mut phosphor = Phosphor::new(3);
+        fb.set(1, 2, 1);
+        assert_eq!(phosphor.update(&fb), Some(2..3));
+        assert_eq!(phosphor.level(1, 2), 255);
+        fb.set(1, 2, 0);
+        phosphor.update(&fb);
+        assert_eq!(phosphor.level(1, 2), 170);
+        phosphor.update(&fb);
+        phosphor.update(&fb);
+        assert_eq!(phosphor.level(1, 2), 0);
+        assert_eq!(phosphor.update(&fb), None);
+    }
+}
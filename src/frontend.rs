@@ -0,0 +1,1196 @@
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use tracing::{debug_span, error, info};
+
+use crate::audio::{AudioPattern, DigitizedSound};
+use crate::capture::{self, GifRecorder, DEFAULT_CAPTURE_SCALE};
+use crate::console::ConsoleCommand;
+use crate::crash;
+use crate::pacing::{FramePacer, FRAME_DURATION};
+#[cfg(feature = "lua")]
+use crate::script::Script;
+#[cfg(feature = "video")]
+use crate::video::{VideoFormat, VideoRecorder};
+#[allow(deprecated)]
+use crate::GameShell;
+use crate::{
+    Breakpoint, Cartridge, Chip8Error, Command, CpuControl, Debugger, FrameBuffer, Movie, Quirks,
+    Symbols, Theme, Timing, Variant, CPU,
+};
+use crate::{
+    ControlBus, ControlMessage, Keypad, Memory, Netplay, RomError, SaveState, Subscription,
+};
+use crate::{Coverage, Profiler, Tracer, WatchHit};
+
+/// How long a notice stays in `RunStatus` after it's posted.
+const NOTICE_DURATION: Duration = Duration::from_secs(2);
+/// The speeds `InputEvent::Faster` and `InputEvent::Slower` go through, as percentages.
+const SPEEDS: [u16; 7] = [10, 25, 50, 100, 200, 500, 1000];
+/// How many lines of the console's output `RunStatus` keeps.
+const CONSOLE_LINES: usize = 100;
+
+/// Something the user did, translated by a frontend into what it means for the emulator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    /// A hex keypad key went down.
+    KeyDown(u8),
+    /// A hex keypad key came back up.
+    KeyUp(u8),
+    /// Pause, resume, step or rewind.
+    Control(Command),
+    /// A line typed at the debugger's prompt. What it prints goes in `RunStatus::console`.
+    Console(ConsoleCommand),
+    /// Save the machine to the quick save state file.
+    SaveState,
+    /// Restore the machine from the quick save state file.
+    LoadState,
+    /// Start the machine over with the ROM read afresh from disk.
+    Reload,
+    /// Switch to the ROM in this file, e.g. one dropped on the window, and start it. It runs with
+    /// the settings `RunLoop::set_rom_setup` works out for it, or the same as the last if that
+    /// isn't set.
+    Open(PathBuf),
+    /// Write the instruction trace to a file next to the quick save state file.
+    DumpTrace,
+    /// Write RAM and the registers to a numbered memory image next to the quick save state file.
+    DumpMemory,
+    /// Save the display as a PNG next to the quick save state file.
+    Screenshot,
+    /// Save the display as text next to the quick save state file, as a grid of `#` and `.` in
+    /// the `FrameBuffer::to_text` format.
+    TextScreenshot,
+    /// Start recording video next to the quick save state file, or stop and save it.
+    #[cfg(feature = "video")]
+    ToggleVideo,
+    /// Start the ROM over, wiping RAM and loading it again if `hard`, or leaving RAM as it is.
+    Reset {
+        hard: bool,
+    },
+    /// Speed emulation up a step, up to 10 times real time.
+    Faster,
+    /// Slow emulation down a step, down to a tenth of real time.
+    Slower,
+    /// Run as fast as possible while turbo is held down, or go back to the set speed once it's
+    /// let go.
+    Turbo(bool),
+    Quit,
+}
+
+/// How fast emulated time runs. Everything speeds up or slows down together, timers included, so
+/// games play the same, just faster or slower.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    /// A percentage of real time.
+    Scaled(u16),
+    /// As fast as the machine will go.
+    Turbo,
+}
+
+impl Speed {
+    pub const NORMAL: Speed = Speed::Scaled(100);
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::NORMAL
+    }
+}
+
+/// `2x`, `0.5x` and so on, or `turbo`.
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Speed::Scaled(percent) => write!(f, "{}x", *percent as f32 / 100.0),
+            Speed::Turbo => f.write_str("turbo"),
+        }
+    }
+}
+
+/// What the run loop is doing, beyond what's on the display.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RunStatus {
+    pub paused: bool,
+    /// The breakpoint execution is paused at, if that's why it's paused.
+    pub stopped_at: Option<Breakpoint>,
+    /// The access that set off the watchpoint in `stopped_at`, if it's a watchpoint.
+    pub watch_hit: Option<WatchHit>,
+    /// The error the ROM faulted with. Nothing runs until it's rewound or a state is loaded.
+    pub halted: Option<Chip8Error>,
+    /// How many frames have been recorded, while recording a movie.
+    pub recording: Option<usize>,
+    /// The frame being played back and the movie's length, while playing one back.
+    pub playback: Option<(usize, usize)>,
+    /// A short-lived message about something that just happened, like a state being saved.
+    pub notice: Option<String>,
+    pub speed: Speed,
+    /// Emulated frames per second, as measured over the last second of running.
+    pub fps: u32,
+    /// Instructions per second, likewise.
+    pub ips: u32,
+    /// The delay and sound timers.
+    pub delay: u8,
+    pub sound: u8,
+    /// The variant whose preset quirks are in effect, or `None` if they've been mixed by hand.
+    pub quirks: Option<Variant>,
+    /// The debugger console's output so far, most recent last: each command, then what it
+    /// printed.
+    pub console: Vec<String>,
+}
+
+/// What the emulation thread sends the frontend each frame in `RunLoop::run_threaded`.
+struct Frame {
+    state: SaveState,
+    /// The display again, with what's changed since the frontend last saw it.
+    display: FrameBuffer,
+    status: RunStatus,
+    beeping: bool,
+    trace: Tracer,
+    profiler: Profiler,
+    coverage: Coverage,
+}
+
+/// A way of showing the emulator to the user and taking their input: a terminal, a window, a
+/// browser canvas. `RunLoop` does everything else.
+pub trait Frontend {
+    /// Draws the display.
+    fn present(&mut self, fb: &FrameBuffer);
+
+    /// Everything the user has done since the last call.
+    fn poll_input(&mut self) -> Vec<InputEvent>;
+
+    /// Starts or stops the tone. Called every frame, whether or not it has changed.
+    fn beep(&mut self, on: bool);
+
+    /// Sets what XO-CHIP ROMs play in place of the plain tone, as from `CPU::audio`. Called every
+    /// frame before `beep`. Frontends that can only beep needn't do anything with it.
+    fn play_pattern(&mut self, _pattern: Option<AudioPattern>) {}
+
+    /// Sets the MegaChip sound to play alongside the tone, as from `CPU::digitized_sound`. Called
+    /// every frame before `beep`. Frontends that can't play samples needn't do anything with it.
+    fn play_sound(&mut self, _sound: Option<&DigitizedSound>) {}
+
+    /// What `RunLoop` calls to draw each frame, with the whole machine and the loop's status for
+    /// frontends that show debugging views or a status line. By default it just presents the
+    /// display. The display's dirty region covers everything that's changed since the last call.
+    fn present_machine(&mut self, cpu: &CPU, status: &RunStatus) {
+        let _ = status;
+        self.present(cpu.framebuffer());
+    }
+}
+
+/// How to run a ROM: what it's run as, and the memory to load it into, sized for the variant and
+/// with its load address, access policy and write protection set.
+pub struct RomSetup {
+    pub variant: Variant,
+    pub quirks: Quirks,
+    pub ips: u32,
+    pub timing: Timing,
+    pub memory: Memory,
+    /// A file of labels to load, in place of any the ROM's file brings.
+    pub symbols: Option<PathBuf>,
+}
+
+/// Works out how to run the ROM in a file, given what was read from it.
+type RomSetupFn = Box<dyn FnMut(&Path, &Cartridge) -> RomSetup + Send>;
+
+/// The emulation loop shared by every interactive frontend: runs the CPU at 60 frames a second
+/// through a `Debugger`, applies the frontend's input, and takes care of save states and movies.
+pub struct RunLoop {
+    cpu: CPU,
+    debugger: Debugger,
+    control: CpuControl,
+    halted: Option<Chip8Error>,
+    recording: Option<Movie>,
+    /// The movie being played back and the next frame of it to play.
+    playback: Option<(Movie, usize)>,
+    netplay: Option<Netplay>,
+    state_path: Option<PathBuf>,
+    /// Where crash reports are written, if they are.
+    crash_dir: Option<PathBuf>,
+    /// The colours and scale screenshots are drawn in.
+    capture: (Theme, u32),
+    gif: Option<GifRecorder>,
+    #[cfg(feature = "video")]
+    video: Option<VideoRecorder>,
+    /// What `InputEvent::ToggleVideo` records as.
+    #[cfg(feature = "video")]
+    video_format: VideoFormat,
+    #[allow(deprecated)]
+    game: Option<GameShell>,
+    rom_setup: Option<RomSetupFn>,
+    /// A message for the status, and when it was posted.
+    notice: Option<(String, Instant)>,
+    /// The last `CONSOLE_LINES` lines of the console's output.
+    console: Vec<String>,
+    /// Input from elsewhere than the frontend.
+    inputs: (Sender<InputEvent>, Receiver<InputEvent>),
+    bus: Option<Subscription>,
+    speed: Speed,
+    /// Whether turbo is being held down, which overrides `speed`.
+    turbo: bool,
+    pacer: FramePacer,
+    #[cfg(feature = "lua")]
+    script: Option<Script>,
+}
+
+impl RunLoop {
+    pub fn new(cpu: CPU) -> Self {
+        let debugger = Debugger::new(CpuControl::new());
+        Self {
+            cpu,
+            control: debugger.control(),
+            debugger,
+            halted: None,
+            recording: None,
+            playback: None,
+            netplay: None,
+            state_path: None,
+            crash_dir: None,
+            capture: (Theme::default(), DEFAULT_CAPTURE_SCALE),
+            gif: None,
+            #[cfg(feature = "video")]
+            video: None,
+            #[cfg(feature = "video")]
+            video_format: VideoFormat::default(),
+            game: None,
+            rom_setup: None,
+            notice: None,
+            console: Vec::new(),
+            inputs: crossbeam_channel::unbounded(),
+            bus: None,
+            speed: Speed::NORMAL,
+            turbo: false,
+            pacer: FramePacer::new(Instant::now()),
+            #[cfg(feature = "lua")]
+            script: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn into_cpu(self) -> CPU {
+        self.cpu
+    }
+
+    pub fn debugger_mut(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    /// Where `InputEvent::SaveState` and `InputEvent::LoadState` save to and load from, and where
+    /// `InputEvent::DumpTrace`, `InputEvent::DumpMemory` and the screenshots write next to, with a
+    /// `.trace` extension and as numbered memory images, PNGs and text files. They're ignored until it's set.
+    pub fn set_state_path(&mut self, path: PathBuf) {
+        self.state_path = Some(path);
+    }
+
+    /// Draws `InputEvent::Screenshot`'s screenshots in `theme`'s colours, scaled up `scale`
+    /// times.
+    pub fn set_capture(&mut self, theme: Theme, scale: u32) {
+        self.capture = (theme, scale);
+    }
+
+    /// Writes a crash report to `dir` whenever the ROM faults or emulation panics. See `crash`.
+    pub fn save_crash_reports(&mut self, dir: PathBuf) {
+        self.crash_dir = Some(dir);
+    }
+
+    /// Switches to the ROM at `path`, set up by `rom_setup` if there is one, and returns how big it
+    /// is. The machine is left as it was if it can't be loaded.
+    #[allow(deprecated)]
+    fn open(&mut self, path: &Path) -> Result<usize, RomError> {
+        let mut cart = Cartridge::load(path)?;
+        match &mut self.rom_setup {
+            Some(rom_setup) => {
+                let mut setup = rom_setup(path, &cart);
+                setup.memory.load_rom_bytes(&cart.rom)?;
+                if let Some(symbols) = &setup.symbols {
+                    cart.symbols = Symbols::load(symbols).map_err(|err| {
+                        RomError::Invalid(format!("couldn't load {}: {:#}", symbols.display(), err))
+                    })?;
+                }
+                self.cpu.set_memory(setup.memory);
+                self.cpu.set_variant(setup.variant);
+                self.cpu.set_quirks(setup.quirks);
+                self.cpu.set_ips(setup.ips);
+                self.cpu.set_timing(setup.timing);
+                self.cpu.reload(&cart.rom)?;
+            }
+            None => self.cpu.reload(&cart.rom)?,
+        }
+        // The last ROM's labels would only mislead.
+        self.cpu.set_symbols(cart.symbols);
+        self.halted = None;
+        self.debugger.clear_rewind();
+        let game = GameShell::new(path.to_path_buf(), self.cpu.quirks());
+        self.state_path = Some(game.state_path());
+        self.game = Some(game);
+        Ok(cart.rom.len())
+    }
+
+    /// Writes a crash report, if they're wanted, and returns where it went.
+    fn report_crash(&self, reason: &str) -> Option<PathBuf> {
+        let dir = self.crash_dir.as_ref()?;
+        match crash::save(&self.cpu, reason, dir) {
+            Ok(path) => {
+                error!("Wrote a crash report to {}", path.display());
+                Some(path)
+            }
+            Err(err) => {
+                error!(
+                    "Couldn't write a crash report to {}: {}",
+                    dir.display(),
+                    err
+                );
+                None
+            }
+        }
+    }
+
+    /// Calls `script`'s callbacks after every frame that runs, and whenever execution stops at a
+    /// breakpoint. If the script errors, it's stopped, and the ROM carries on without it.
+    #[cfg(feature = "lua")]
+    pub fn set_script(&mut self, script: Script) {
+        self.script = Some(script);
+    }
+
+    /// Calls the script's callbacks if anything's run since `before`, the instruction count
+    /// and breakpoint stopped at before the frame, or if it's newly stopped at a breakpoint.
+    #[cfg(feature = "lua")]
+    fn run_script(&mut self, before: (u64, Option<Breakpoint>)) {
+        let stopped = self.debugger.stopped_at().is_some() && before.1.is_none();
+        if self.cpu.instructions() == before.0 && !stopped {
+            return;
+        }
+        let Some(script) = &mut self.script else {
+            return;
+        };
+        match script.after_frame(&mut self.cpu) {
+            Ok(true) => self.control.send(Command::Resume),
+            Ok(false) => {}
+            Err(err) => {
+                error!("Script stopped: {}", err);
+                self.script = None;
+                self.post(format!("Script stopped: {}", err));
+            }
+        }
+    }
+
+    /// The game being run, whose ROM `InputEvent::Reload` reads afresh. It's ignored until this
+    /// is set.
+    #[allow(deprecated)]
+    pub fn set_game(&mut self, game: GameShell) {
+        self.game = Some(game);
+    }
+
+    /// Works out how to run each ROM `InputEvent::Open` switches to, as the ROM first given was,
+    /// rather than with the last one's settings.
+    pub fn set_rom_setup(
+        &mut self,
+        setup: impl FnMut(&Path, &Cartridge) -> RomSetup + Send + 'static,
+    ) {
+        self.rom_setup = Some(Box::new(setup));
+    }
+
+    /// A handle for sending the loop input from somewhere other than the frontend, like a thread
+    /// watching the ROM for changes. It's handled along with the frontend's.
+    pub fn input_sender(&self) -> Sender<InputEvent> {
+        self.inputs.0.clone()
+    }
+
+    /// Has the loop act on messages sent on `bus` as well as the frontend's input. `Kill` makes it
+    /// quit.
+    pub fn listen(&mut self, bus: &ControlBus) {
+        self.bus = Some(bus.subscribe());
+    }
+
+    /// Starts recording a movie, which should be done before the first frame runs.
+    pub fn record(&mut self) {
+        self.recording = Some(Movie::start_recording(&mut self.cpu));
+    }
+
+    /// The movie being recorded, if any.
+    pub fn recording(&self) -> Option<&Movie> {
+        self.recording.as_ref()
+    }
+
+    /// Records every frame run from now on to `gif`, until `take_gif`.
+    pub fn record_gif(&mut self, gif: GifRecorder) {
+        self.gif = Some(gif);
+    }
+
+    /// Stops recording the GIF, if one's being recorded, and hands it back to be finished.
+    pub fn take_gif(&mut self) -> Option<GifRecorder> {
+        self.gif.take()
+    }
+
+    /// Records every frame run from now on, with the sound, to `video`, until `take_video` or
+    /// `InputEvent::ToggleVideo`.
+    #[cfg(feature = "video")]
+    pub fn record_video(&mut self, video: VideoRecorder) {
+        self.video = Some(video);
+    }
+
+    /// Stops recording video, if it's being recorded, and hands it back to be finished.
+    #[cfg(feature = "video")]
+    pub fn take_video(&mut self) -> Option<VideoRecorder> {
+        self.video.take()
+    }
+
+    /// What `InputEvent::ToggleVideo` records as.
+    #[cfg(feature = "video")]
+    pub fn set_video_format(&mut self, format: VideoFormat) {
+        self.video_format = format;
+    }
+
+    /// Starts recording video to a new file next to the quick save state file, or finishes the
+    /// one being recorded.
+    #[cfg(feature = "video")]
+    fn toggle_video(&mut self) {
+        if let Some(video) = self.video.take() {
+            let path = video.path().to_path_buf();
+            let notice = match video.finish() {
+                Ok(frames) => format!("Recorded {} frames to {}", frames, path.display()),
+                Err(err) => format!("Couldn't save {}: {}", path.display(), err),
+            };
+            self.post(notice);
+            return;
+        }
+        let Some(state_path) = &self.state_path else {
+            return;
+        };
+        let path = capture::numbered_path(state_path, self.video_format.extension());
+        let (theme, scale) = self.capture;
+        let size = self.cpu.max_display_size();
+        let notice = match VideoRecorder::create(&path, size, theme, scale) {
+            Ok(video) => {
+                self.video = Some(video);
+                format!("Recording to {}", path.display())
+            }
+            Err(err) => format!("Couldn't record to {}: {}", path.display(), err),
+        };
+        self.post(notice);
+    }
+
+    /// Plays `movie` back from the first frame, which should be done before the first frame
+    /// runs. Execution carries on as normal once it's over.
+    pub fn play(&mut self, movie: Movie) {
+        movie.start_playback(&mut self.cpu);
+        self.playback = Some((movie, 0));
+    }
+
+    /// Plays in step with another player over `netplay`, which should be done before the first
+    /// frame runs. If the connection drops, the ROM carries on with just this side's keys.
+    pub fn play_netplay(&mut self, netplay: Netplay) {
+        let notice = if netplay.is_host() {
+            format!(
+                "Playing with {}, who has keys {}",
+                netplay.peer(),
+                netplay.guest_keys()
+            )
+        } else {
+            format!(
+                "Playing with {} on keys {}",
+                netplay.peer(),
+                netplay.guest_keys()
+            )
+        };
+        self.post(notice);
+        self.netplay = Some(netplay);
+    }
+
+    /// Whether a movie is being recorded or played back. The execution controls and loading
+    /// states would break it, so they're ignored for the duration.
+    fn in_movie(&self) -> bool {
+        self.recording.is_some()
+            || self
+                .playback
+                .as_ref()
+                .is_some_and(|(movie, frame)| *frame < movie.len())
+    }
+
+    fn post(&mut self, notice: String) {
+        info!("{}", notice);
+        self.notice = Some((notice, Instant::now()));
+    }
+
+    pub fn status(&self) -> RunStatus {
+        let stopped_at = self.debugger.stopped_at();
+        RunStatus {
+            paused: self.debugger.paused(),
+            stopped_at,
+            watch_hit: stopped_at.and(self.cpu.memory.watch_hit()),
+            halted: self.halted.clone(),
+            recording: self.recording.as_ref().map(Movie::len),
+            playback: self
+                .playback
+                .as_ref()
+                .filter(|_| self.in_movie())
+                .map(|(movie, frame)| (*frame, movie.len())),
+            notice: self
+                .notice
+                .as_ref()
+                .filter(|(_, posted)| posted.elapsed() < NOTICE_DURATION)
+                .map(|(notice, _)| notice.clone()),
+            speed: self.speed(),
+            fps: self.pacer.rates().0,
+            ips: self.pacer.rates().1,
+            delay: self.cpu.registers.delay,
+            sound: self.cpu.registers.sound,
+            quirks: self.cpu.quirks().profile(),
+            console: self.console.clone(),
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        if self.turbo {
+            Speed::Turbo
+        } else {
+            self.speed
+        }
+    }
+
+    pub fn handle(&mut self, event: InputEvent) {
+        let _input = debug_span!(target: "input", "input", ?event).entered();
+        match event {
+            InputEvent::KeyDown(key) => self.cpu.keypad().press(key),
+            InputEvent::KeyUp(key) => self.cpu.keypad().release(key),
+            InputEvent::Control(_)
+            | InputEvent::LoadState
+            | InputEvent::Reload
+            | InputEvent::Open(_)
+            | InputEvent::Reset { .. }
+                if self.in_movie() || self.netplay.is_some() => {}
+            InputEvent::Control(command) => {
+                // Rewinding is the way back from a fault.
+                if command == Command::Rewind {
+                    self.halted = None;
+                }
+                self.control.send(command);
+            }
+            InputEvent::Console(command) => {
+                let mut output = vec![format!("> {}", command)];
+                if command.changes_machine() && (self.in_movie() || self.netplay.is_some()) {
+                    output.push("Not while a movie or netplay is running".to_string());
+                } else {
+                    match command.execute(&mut self.cpu, &self.control) {
+                        Ok(lines) => output.extend(lines),
+                        Err(err) => output.push(format!("Error: {}", err)),
+                    }
+                }
+                self.console.extend(output);
+                let excess = self.console.len().saturating_sub(CONSOLE_LINES);
+                self.console.drain(..excess);
+            }
+            InputEvent::SaveState => {
+                let Some(path) = self.state_path.clone() else {
+                    return;
+                };
+                let notice = match std::fs::write(&path, self.cpu.save_state().to_bytes()) {
+                    Ok(()) => format!("Saved state to {}", path.display()),
+                    Err(err) => format!("Couldn't use {}: {}", path.display(), err),
+                };
+                self.post(notice);
+            }
+            InputEvent::LoadState => {
+                let Some(path) = self.state_path.clone() else {
+                    return;
+                };
+                let loaded = std::fs::read(&path)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|bytes| Ok(SaveState::from_bytes(&bytes)?));
+                let notice = match loaded {
+                    Ok(state) => {
+                        self.cpu.load_state(&state);
+                        self.halted = None;
+                        self.debugger.clear_rewind();
+                        format!("Loaded state from {}", path.display())
+                    }
+                    Err(err) => format!("Couldn't use {}: {}", path.display(), err),
+                };
+                self.post(notice);
+            }
+            InputEvent::DumpTrace => {
+                let Some(path) = self
+                    .state_path
+                    .as_ref()
+                    .map(|path| path.with_extension("trace"))
+                else {
+                    return;
+                };
+                let trace = self.cpu.trace();
+                let notice = match trace.save(&path, self.cpu.variant()) {
+                    Ok(()) => format!(
+                        "Saved the last {} instructions to {}",
+                        trace.len(),
+                        path.display()
+                    ),
+                    Err(err) => format!("Couldn't use {}: {}", path.display(), err),
+                };
+                self.post(notice);
+            }
+            InputEvent::DumpMemory => {
+                let Some(state_path) = &self.state_path else {
+                    return;
+                };
+                let path = capture::numbered_path(state_path, "mem");
+                let notice = match self.cpu.memory_image().save(&path) {
+                    Ok(()) => format!("Saved memory and registers to {}", path.display()),
+                    Err(err) => format!("Couldn't use {}: {}", path.display(), err),
+                };
+                self.post(notice);
+            }
+            InputEvent::Screenshot => {
+                let Some(state_path) = &self.state_path else {
+                    return;
+                };
+                let path = capture::numbered_path(state_path, "png");
+                let (theme, scale) = self.capture;
+                let notice = match capture::write_png(self.cpu.framebuffer(), theme, scale, &path) {
+                    Ok(()) => format!("Saved a screenshot to {}", path.display()),
+                    Err(err) => format!("Couldn't use {}: {}", path.display(), err),
+                };
+                self.post(notice);
+            }
+            InputEvent::TextScreenshot => {
+                let Some(state_path) = &self.state_path else {
+                    return;
+                };
+                let path = capture::numbered_path(state_path, "txt");
+                let notice = match std::fs::write(&path, self.cpu.framebuffer().to_text()) {
+                    Ok(()) => format!("Saved the display as text to {}", path.display()),
+                    Err(err) => format!("Couldn't use {}: {}", path.display(), err),
+                };
+                self.post(notice);
+            }
+            #[cfg(feature = "video")]
+            InputEvent::ToggleVideo => self.toggle_video(),
+            InputEvent::Reload => {
+                let Some(game) = &self.game else {
+                    self.post("There's no ROM file to reload".to_string());
+                    return;
+                };
+                let notice = match game.reload(&mut self.cpu) {
+                    Ok(size) => {
+                        self.halted = None;
+                        self.debugger.clear_rewind();
+                        format!("Reloaded {} ({} bytes)", game.rom_path().display(), size)
+                    }
+                    Err(err) => format!("Couldn't reload {}: {}", game.rom_path().display(), err),
+                };
+                self.post(notice);
+            }
+            InputEvent::Open(path) => {
+                let notice = match self.open(&path) {
+                    Ok(size) => format!("Loaded {} ({} bytes)", path.display(), size),
+                    Err(err) => format!("Couldn't load {}: {}", path.display(), err),
+                };
+                self.post(notice);
+            }
+            InputEvent::Reset { hard } => {
+                if hard {
+                    self.cpu.reset();
+                } else {
+                    self.cpu.soft_reset();
+                }
+                self.halted = None;
+                self.debugger.clear_rewind();
+                self.post(if hard { "Reset" } else { "Soft reset" }.to_string());
+            }
+            InputEvent::Faster => {
+                if let Speed::Scaled(percent) = self.speed {
+                    let faster = SPEEDS.iter().find(|&&speed| speed > percent);
+                    self.speed = Speed::Scaled(*faster.unwrap_or(&percent));
+                }
+            }
+            InputEvent::Slower => {
+                let percent = match self.speed {
+                    Speed::Scaled(percent) => percent,
+                    Speed::Turbo => u16::MAX,
+                };
+                let slower = SPEEDS.iter().rev().find(|&&speed| speed < percent);
+                self.speed = Speed::Scaled(*slower.unwrap_or(&percent));
+            }
+            InputEvent::Turbo(on) => self.turbo = on,
+            InputEvent::Quit => {}
+        }
+    }
+
+    /// Runs one 60Hz frame: the next frame of the movie, if one's being recorded or played back,
+    /// or otherwise whatever the debugger has been told to do. Once the ROM faults, nothing runs,
+    /// but the last frame stays on the display.
+    pub fn run_frame(&mut self) {
+        if self.halted.is_some() {
+            return;
+        }
+        let mut finished = false;
+        #[cfg(feature = "lua")]
+        let before = (self.cpu.instructions(), self.debugger.stopped_at());
+        if let Some(netplay) = &mut self.netplay {
+            if let Err(err) = netplay.sync_keys(&self.cpu) {
+                self.netplay = None;
+                self.post(format!("Netplay ended: {}", err));
+            }
+        }
+        let result = match (&mut self.recording, &mut self.playback) {
+            (Some(movie), _) => movie.record_frame(&mut self.cpu),
+            (_, Some((movie, frame))) if *frame < movie.len() => {
+                let result = movie.play_frame(*frame, &mut self.cpu).map(|_| ());
+                *frame += 1;
+                finished = *frame == movie.len();
+                result
+            }
+            _ if self.netplay.is_some() => self.cpu.run_frame(),
+            _ => self.debugger.run_frame(&mut self.cpu),
+        };
+        if finished {
+            self.post("Playback finished".to_string());
+        }
+        if let Some(gif) = &mut self.gif {
+            if let Err(err) = gif.add_frame(self.cpu.framebuffer()) {
+                self.gif = None;
+                self.post(format!("Stopped recording the GIF: {}", err));
+            }
+        }
+        #[cfg(feature = "video")]
+        if let Some(video) = &mut self.video {
+            let (fb, beeping, pattern) =
+                (self.cpu.framebuffer(), self.cpu.beeping(), self.cpu.audio());
+            if let Err(err) = video.add_frame(fb, beeping, pattern) {
+                self.video = None;
+                self.post(format!("Stopped recording video: {}", err));
+            }
+        }
+        #[cfg(feature = "lua")]
+        if result.is_ok() {
+            self.run_script(before);
+        }
+        if let Err(err) = result {
+            error!("Emulation halted: {}", err);
+            if !self.cpu.call_stack().is_empty() {
+                error!("Call stack: {}", self.cpu.backtrace());
+            }
+            if let Some(path) = self.report_crash(&err.to_string()) {
+                self.post(format!(
+                    "Halted: {} (crash report in {})",
+                    err,
+                    path.display()
+                ));
+            }
+            self.halted = Some(err);
+        }
+    }
+
+    /// Runs until the frontend sends `InputEvent::Quit`, keeping to 60 frames a second of
+    /// emulated time, scaled by the speed. In turbo, it runs as many frames as it can fit in each
+    /// real one instead.
+    pub fn run(&mut self, frontend: &mut impl Frontend) {
+        self.pacer = FramePacer::new(Instant::now());
+        while self.update(frontend.poll_input()) {
+            frontend.play_pattern(self.cpu.audio());
+            frontend.play_sound(self.cpu.digitized_sound());
+            frontend.beep(self.cpu.beeping());
+            frontend.present_machine(&self.cpu, &self.status());
+            self.cpu.clear_dirty();
+
+            self.pacer.wait();
+        }
+    }
+
+    /// Like `run`, but with emulation on a thread of its own and the frontend kept on this one,
+    /// so that a frontend that's slow to draw can't hold emulation up. Each frame, the emulation
+    /// thread sends the frontend a copy of the machine to present, and the frontend's input goes
+    /// back the other way. Once either side quits, the loop is handed back.
+    pub fn run_threaded(mut self, frontend: &mut impl Frontend) -> RunLoop {
+        let inputs = self.input_sender();
+        let (send_frame, frames) = crossbeam_channel::bounded::<Frame>(1);
+        let mut view = CPU::new(
+            Memory::with_size(self.cpu.memory.len()),
+            Arc::new(Keypad::new()),
+            self.cpu.quirks(),
+        );
+        view.set_symbols(self.cpu.symbols().clone());
+        let emulation = std::thread::spawn(move || {
+            self.pacer = FramePacer::new(Instant::now());
+            while self.update(Vec::new()) {
+                let frame = Frame {
+                    state: self.cpu.save_state(),
+                    display: self.cpu.framebuffer().clone(),
+                    status: self.status(),
+                    beeping: self.cpu.beeping(),
+                    trace: self.cpu.trace().clone(),
+                    profiler: self.cpu.profiler().clone(),
+                    coverage: self.cpu.coverage().clone(),
+                };
+                // If the frontend hasn't taken the last frame yet, the display's changes carry
+                // over to the next one.
+                if send_frame.try_send(frame).is_ok() {
+                    self.cpu.clear_dirty();
+                }
+                self.pacer.wait();
+            }
+            self
+        });
+        loop {
+            let events = frontend.poll_input();
+            if events.contains(&InputEvent::Quit) {
+                let _ = inputs.send(InputEvent::Quit);
+                break;
+            }
+            for event in events {
+                let _ = inputs.send(event);
+            }
+            match frames.recv_timeout(FRAME_DURATION) {
+                Ok(frame) => {
+                    view.load_state(&frame.state);
+                    view.set_framebuffer(frame.display);
+                    view.set_trace(frame.trace);
+                    view.set_profiler(frame.profiler);
+                    view.set_coverage(frame.coverage);
+                    frontend.play_pattern(view.audio());
+                    frontend.play_sound(view.digitized_sound());
+                    frontend.beep(frame.beeping);
+                    frontend.present_machine(&view, &frame.status);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                // Something else told the emulation thread to quit.
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+        emulation
+            .join()
+            .unwrap_or_else(|panic| panic::resume_unwind(panic))
+    }
+
+    /// Handles `events` and any input sent from elsewhere, then runs however many frames are due.
+    /// Returns false once it's time to quit.
+    fn update(&mut self, mut events: Vec<InputEvent>) -> bool {
+        events.extend(self.inputs.1.try_iter());
+        let messages: Vec<_> = self.bus.iter().flat_map(Subscription::try_iter).collect();
+        for message in messages {
+            let event = match message {
+                ControlMessage::Kill => return false,
+                ControlMessage::Pause => InputEvent::Control(Command::Pause),
+                ControlMessage::Resume => InputEvent::Control(Command::Resume),
+                ControlMessage::Reset { hard } => InputEvent::Reset { hard },
+                ControlMessage::SaveState => InputEvent::SaveState,
+                ControlMessage::LoadState => InputEvent::LoadState,
+                ControlMessage::SetSpeed(speed) => {
+                    self.speed = speed;
+                    continue;
+                }
+            };
+            events.push(event);
+        }
+        for event in events {
+            if event == InputEvent::Quit {
+                return false;
+            }
+            self.handle(event);
+        }
+        let percent = match self.speed() {
+            Speed::Scaled(percent) => percent as u32,
+            Speed::Turbo => 0,
+        };
+        self.reporting_panics(|run_loop| {
+            for _ in 0..run_loop.pacer.frames_due(Instant::now(), percent) {
+                run_loop.run_paced_frame();
+            }
+            if run_loop.speed() == Speed::Turbo {
+                while Instant::now() < run_loop.pacer.deadline()
+                    && !run_loop.debugger.paused()
+                    && run_loop.halted.is_none()
+                {
+                    run_loop.run_paced_frame();
+                }
+            }
+        });
+        true
+    }
+
+    /// Runs `f`, and if it panics, writes a crash report before carrying on panicking.
+    fn reporting_panics(&mut self, f: impl FnOnce(&mut Self)) {
+        let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| f(self))) else {
+            return;
+        };
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        if let Some(path) = self.report_crash(&format!("panicked: {}", message)) {
+            eprintln!("Wrote a crash report to {}", path.display());
+        }
+        panic::resume_unwind(panic);
+    }
+
+    /// Runs a frame, counting it towards the measured rates if it isn't paused or halted.
+    fn run_paced_frame(&mut self) {
+        let counts = !self.debugger.paused() && self.halted.is_none();
+        self.run_frame();
+        if counts {
+            self.pacer
+                .ran_frame(Instant::now(), self.cpu.instructions());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with;
+    use crate::{Access, Watchpoint};
+
+    /// Presses key 5, then quits once something lit has been presented.
+    struct Script {
+        polls: usize,
+        lit: bool,
+        beeped: bool,
+    }
+
+    impl Frontend for Script {
+        fn present(&mut self, fb: &FrameBuffer) {
+            self.lit |= fb.pixels().iter().any(|&p| p != 0);
+        }
+
+        fn poll_input(&mut self) -> Vec<InputEvent> {
+            self.polls += 1;
+            match self.polls {
+                1 => vec![InputEvent::KeyDown(5)],
+                _ if self.lit => vec![InputEvent::Quit],
+                _ => Vec::new(),
+            }
+        }
+
+        fn beep(&mut self, on: bool) {
+            self.beeped |= on;
+        }
+    }
+
+    #[test]
+    fn runs_a_frontend_until_it_quits() {
+        // Wait for key 5, then draw the 0 glyph and beep.
+        let program = [
+            0x6505, 0xe59e, 0x1202, 0xf029, 0xd005, 0x6108, 0xf118, 0x120c,
+        ];
+        let mut run_loop = RunLoop::new(cpu_with(&program));
+        let mut script = Script {
+            polls: 0,
+            lit: false,
+            beeped: false,
+        };
+        run_loop.run(&mut script);
+        assert!(script.lit);
+        assert!(script.beeped);
+        assert!(run_loop.cpu().keypad().is_pressed(5));
+    }
+
+    #[test]
+    fn runs_a_frontend_on_another_thread() {
+        let program = [
+            0x6505, 0xe59e, 0x1202, 0xf029, 0xd005, 0x6108, 0xf118, 0x120c,
+        ];
+        let mut script = Script {
+            polls: 0,
+            lit: false,
+            beeped: false,
+        };
+        let run_loop = RunLoop::new(cpu_with(&program)).run_threaded(&mut script);
+        assert!(script.lit);
+        assert!(script.beeped);
+        assert!(run_loop.cpu().keypad().is_pressed(5));
+    }
+
+    /// Quits after a few frames, noting which ones had anything new to draw.
+    struct Watcher {
+        dirty: Vec<bool>,
+    }
+
+    impl Frontend for Watcher {
+        fn present(&mut self, fb: &FrameBuffer) {
+            self.dirty.push(fb.is_dirty());
+        }
+
+        fn poll_input(&mut self) -> Vec<InputEvent> {
+            if self.dirty.len() < 5 {
+                Vec::new()
+            } else {
+                vec![InputEvent::Quit]
+            }
+        }
+
+        fn beep(&mut self, _: bool) {}
+    }
+
+    #[test]
+    fn only_changes_are_dirty() {
+        // Draw the 0 glyph, then spin.
+        let mut run_loop = RunLoop::new(cpu_with(&[0xf029, 0xd005, 0x1204]));
+        let mut watcher = Watcher { dirty: Vec::new() };
+        run_loop.run(&mut watcher);
+        assert!(watcher.dirty[0]);
+        assert!(!watcher.dirty[4]);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn reloads_the_rom_from_disk() {
+        let path = std::env::temp_dir().join(format!("chip8-reload-{}.ch8", std::process::id()));
+        // v0 += 1, forever.
+        std::fs::write(&path, [0x70, 0x01, 0x12, 0x00]).unwrap();
+        let mut run_loop = RunLoop::new(cpu_with(&[]));
+        let game = GameShell::new(path.clone(), Quirks::default());
+        game.reload(run_loop.cpu_mut()).unwrap();
+        run_loop.set_game(game);
+        run_loop.run_frame();
+        assert!(run_loop.cpu().registers.v[0] > 0);
+
+        // Reassembled to v1 += 1 instead.
+        std::fs::write(&path, [0x71, 0x01, 0x12, 0x00]).unwrap();
+        run_loop.handle(InputEvent::Reload);
+        run_loop.run_frame();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(run_loop.cpu().registers.v[0], 0);
+        assert!(run_loop.cpu().registers.v[1] > 0);
+    }
+
+    #[test]
+    fn opens_another_rom() {
+        let path = std::env::temp_dir().join(format!("chip8-open-{}.8o", std::process::id()));
+        std::fs::write(&path, ": main\n  loop v2 += 1 again\n").unwrap();
+        // v0 += 1, forever.
+        let mut run_loop = RunLoop::new(cpu_with(&[0x7001, 0x1200]));
+        run_loop.run_frame();
+        run_loop.handle(InputEvent::Open(path.clone()));
+        run_loop.run_frame();
+        run_loop.handle(InputEvent::Open(path.with_extension("missing")));
+        std::fs::remove_file(&path).unwrap();
+        let cpu = run_loop.cpu();
+        assert_eq!(cpu.registers.v[0], 0);
+        assert!(cpu.registers.v[2] > 0);
+        assert_eq!(cpu.symbols().name(0x200), Some("main"));
+        assert!(run_loop
+            .status()
+            .notice
+            .unwrap()
+            .starts_with("Couldn't load"));
+    }
+
+    #[test]
+    fn opens_a_rom_with_its_own_settings() {
+        let path = std::env::temp_dir().join(format!("chip8-setup-{}.ch8", std::process::id()));
+        let sym = path.with_extension("sym");
+        // lores, which only SUPER-CHIP has; ld i, 0x300; ld [i], v0; jp 0x206
+        let rom = [0x00, 0xfe, 0xa3, 0x00, 0xf0, 0x55, 0x12, 0x06];
+        std::fs::write(&path, rom).unwrap();
+        std::fs::write(&sym, "200 start\n").unwrap();
+        let mut run_loop = RunLoop::new(cpu_with(&[0x1200]));
+        let watchpoint = Watchpoint::new(0x300, 0x300, Access::Write);
+        run_loop
+            .cpu_mut()
+            .add_breakpoint_on(Breakpoint::Watch(watchpoint));
+        let symbols = sym.clone();
+        run_loop.set_rom_setup(move |rom, cart| {
+            assert_eq!(cart.rom, [0x00, 0xfe, 0xa3, 0x00, 0xf0, 0x55, 0x12, 0x06]);
+            assert_eq!(rom.extension().unwrap(), "ch8");
+            RomSetup {
+                variant: Variant::Schip,
+                quirks: Quirks::preset(Variant::Schip),
+                ips: 1000,
+                timing: Timing::Ips,
+                memory: Memory::new(),
+                symbols: Some(symbols.clone()),
+            }
+        });
+        run_loop.handle(InputEvent::Open(path.clone()));
+        run_loop.run_frame();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&sym).unwrap();
+        let status = run_loop.status();
+        let cpu = run_loop.cpu();
+        assert_eq!(status.halted, None);
+        // The watchpoint carries over to the new memory.
+        assert_eq!(status.stopped_at, Some(Breakpoint::Watch(watchpoint)));
+        assert_eq!(cpu.variant(), Variant::Schip);
+        assert_eq!(cpu.quirks(), Quirks::preset(Variant::Schip));
+        assert_eq!(cpu.ips(), 1000);
+        assert_eq!(cpu.symbols().name(0x200), Some("start"));
+    }
+
+    #[test]
+    fn resetting_recovers_from_a_fault() {
+        // Return with nothing on the stack.
+        let mut run_loop = RunLoop::new(cpu_with(&[0x00ee]));
+        run_loop.run_frame();
+        assert!(run_loop.status().halted.is_some());
+        run_loop.handle(InputEvent::Reset { hard: false });
+        assert_eq!(run_loop.status().halted, None);
+        assert_eq!(run_loop.cpu().pc, 0x200);
+    }
+
+    #[test]
+    fn reports_the_timers_and_quirk_profile() {
+        // DT = 30, then spin.
+        let mut run_loop = RunLoop::new(cpu_with(&[0x601e, 0xf015, 0x1204]));
+        run_loop.run_frame();
+        let status = run_loop.status();
+        assert_eq!((status.delay, status.sound), (30, 0));
+        assert_eq!(status.quirks, None);
+        run_loop
+            .cpu_mut()
+            .set_quirks(Quirks::preset(Variant::Schip));
+        assert_eq!(run_loop.status().quirks, Some(Variant::Schip));
+    }
+
+    #[test]
+    fn speed_goes_from_a_tenth_to_ten_times() {
+        let mut run_loop = RunLoop::new(cpu_with(&[0x1200]));
+        run_loop.handle(InputEvent::Faster);
+        assert_eq!(run_loop.status().speed, Speed::Scaled(200));
+        for _ in 0..10 {
+            run_loop.handle(InputEvent::Slower);
+        }
+        assert_eq!(run_loop.speed().to_string(), "0.1x");
+        run_loop.handle(InputEvent::Turbo(true));
+        assert_eq!(run_loop.speed(), Speed::Turbo);
+        run_loop.handle(InputEvent::Turbo(false));
+        assert_eq!(run_loop.speed(), Speed::Scaled(10));
+    }
+
+    #[test]
+    fn listens_on_a_control_bus() {
+        let bus = ControlBus::new();
+        let mut run_loop = RunLoop::new(cpu_with(&[0x1200]));
+        run_loop.listen(&bus);
+        bus.send(ControlMessage::Pause);
+        bus.send(ControlMessage::SetSpeed(Speed::Scaled(50)));
+        assert!(run_loop.update(Vec::new()));
+        run_loop.run_frame();
+        assert!(run_loop.status().paused);
+        assert_eq!(run_loop.speed(), Speed::Scaled(50));
+        bus.send(ControlMessage::Kill);
+        assert!(!run_loop.update(Vec::new()));
+    }
+
+    #[test]
+    fn movies_lock_out_the_controls() {
+        let mut run_loop = RunLoop::new(cpu_with(&[0x7001, 0x1200]));
+        run_loop.record();
+        run_loop.handle(InputEvent::Control(Command::Pause));
+        run_loop.run_frame();
+        run_loop.run_frame();
+        let status = run_loop.status();
+        assert!(!status.paused);
+        assert_eq!(status.recording, Some(2));
+    }
+}
@@ -0,0 +1,475 @@
+//! A GDB remote debugging server, for stepping through a ROM from gdb, or anything else that
+//! speaks its remote protocol, instead of the TUI's debugger.
+//!
+//! The debugger sees V0 to VF, I, PC, SP and the two timers as registers, and RAM as memory.
+//! Software breakpoints are address breakpoints on the CPU, and any set with `--break` stop
+//! execution too. While running, the ROM gets 60Hz frames in real time, and
+//! `monitor press <key>`, `monitor release <key>` and `monitor screen` work the keypad and show
+//! the display.
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use gdbstub::arch::{Arch, Registers as GdbRegisters};
+use gdbstub::common::Signal;
+use gdbstub::conn::ConnectionExt;
+use gdbstub::stub::run_blocking::{self, BlockingEventLoop};
+use gdbstub::stub::{DisconnectReason, GdbStub, SingleThreadStopReason};
+use gdbstub::target::ext::base::singlethread::{
+    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps, SingleThreadSingleStep,
+    SingleThreadSingleStepOps,
+};
+use gdbstub::target::ext::base::BaseOps;
+use gdbstub::target::ext::breakpoints::{
+    Breakpoints, BreakpointsOps, SwBreakpoint, SwBreakpointOps,
+};
+use gdbstub::target::ext::monitor_cmd::{outputln, ConsoleOutput, MonitorCmd, MonitorCmdOps};
+use gdbstub::target::{Target, TargetError, TargetResult};
+use tracing::{info, warn};
+
+use crate::pacing::FramePacer;
+use crate::{Breakpoint, Chip8Error, CPU};
+
+/// The registers in the order the target description lists them.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8-rs.cpu">
+    <reg name="v0" bitsize="8" type="uint8" regnum="0"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>"#;
+
+/// The CHIP-8 as gdb sees it: 16-bit addresses, and no kinds of software breakpoint to pick from.
+pub enum Chip8Arch {}
+
+impl Arch for Chip8Arch {
+    type Usize = u16;
+    type Registers = GdbRegs;
+    type BreakpointKind = usize;
+    type RegId = ();
+
+    fn target_description_xml() -> Option<&'static str> {
+        Some(TARGET_XML)
+    }
+}
+
+/// The registers gdb reads and writes all at once, sent as V0 to VF, I and PC (little-endian),
+/// SP, DT and ST.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GdbRegs {
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub delay: u8,
+    pub sound: u8,
+}
+
+impl GdbRegs {
+    const LEN: usize = 16 + 2 + 2 + 3;
+}
+
+impl GdbRegisters for GdbRegs {
+    type ProgramCounter = u16;
+
+    fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        let bytes = self
+            .v
+            .iter()
+            .copied()
+            .chain(self.i.to_le_bytes())
+            .chain(self.pc.to_le_bytes())
+            .chain([self.sp, self.delay, self.sound]);
+        for byte in bytes {
+            write_byte(Some(byte));
+        }
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        if bytes.len() != Self::LEN {
+            return Err(());
+        }
+        self.v.copy_from_slice(&bytes[..16]);
+        self.i = u16::from_le_bytes([bytes[16], bytes[17]]);
+        self.pc = u16::from_le_bytes([bytes[18], bytes[19]]);
+        [self.sp, self.delay, self.sound] = [bytes[20], bytes[21], bytes[22]];
+        Ok(())
+    }
+}
+
+/// What the debugger last asked the CPU to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Stopped,
+    Step,
+    Continue,
+}
+
+/// A CPU being driven by a debugger.
+pub struct GdbServer {
+    cpu: CPU,
+    mode: Mode,
+    pacer: FramePacer,
+}
+
+impl GdbServer {
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            cpu,
+            mode: Mode::Stopped,
+            pacer: FramePacer::new(std::time::Instant::now()),
+        }
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    /// Waits for a debugger to connect to `addr`.
+    pub fn accept(addr: impl ToSocketAddrs) -> io::Result<TcpStream> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, peer) = listener.accept()?;
+        info!(target: "gdb", "Debugger connected from {}", peer);
+        Ok(stream)
+    }
+
+    /// Debugs the CPU over `stream`, which starts out stopped, until the debugger detaches or kills
+    /// it, or the ROM exits.
+    pub fn serve(&mut self, stream: TcpStream) -> io::Result<()> {
+        let reason = GdbStub::new(stream)
+            .run_blocking::<EventLoop>(self)
+            .map_err(|err| io::Error::other(err.to_string()))?;
+        match reason {
+            DisconnectReason::Disconnect => info!(target: "gdb", "Debugger detached"),
+            DisconnectReason::Kill => info!(target: "gdb", "Debugger killed the ROM"),
+            DisconnectReason::TargetExited(_) => info!(target: "gdb", "The ROM exited"),
+            DisconnectReason::TargetTerminated(signal) => {
+                info!(target: "gdb", "The ROM stopped with {}", signal)
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the CPU as the debugger asked until it stops, or until the debugger has something to
+    /// say. Returns how it stopped, or `None` if the debugger interrupted it.
+    fn run(
+        &mut self,
+        mut interrupted: impl FnMut() -> bool,
+    ) -> Option<SingleThreadStopReason<u16>> {
+        loop {
+            if interrupted() {
+                return None;
+            }
+            let result = match self.mode {
+                Mode::Stopped => return None,
+                Mode::Step => self.cpu.step().map(|()| true),
+                Mode::Continue => {
+                    self.pacer.wait();
+                    self.cpu.run_frame_until(|cpu| cpu.has_exited())
+                }
+            };
+            let stopped = match result {
+                Ok(stopped) => stopped,
+                Err(err) => {
+                    warn!(target: "gdb", "Stopped on a fault: {}", err);
+                    self.mode = Mode::Stopped;
+                    return Some(SingleThreadStopReason::Signal(fault_signal(&err)));
+                }
+            };
+            if self.cpu.has_exited() {
+                self.mode = Mode::Stopped;
+                return Some(SingleThreadStopReason::Exited(0));
+            }
+            if stopped {
+                let reason = match self.cpu.breakpoint_hit() {
+                    Some(Breakpoint::Address(_)) => SingleThreadStopReason::SwBreak(()),
+                    Some(_) => SingleThreadStopReason::Signal(Signal::SIGTRAP),
+                    None => SingleThreadStopReason::DoneStep,
+                };
+                self.mode = Mode::Stopped;
+                return Some(reason);
+            }
+        }
+    }
+}
+
+/// The signal a Unix process would have got for the same kind of fault.
+fn fault_signal(err: &Chip8Error) -> Signal {
+    match err {
+        Chip8Error::UnknownOpcode { .. } => Signal::SIGILL,
+        Chip8Error::StackOverflow { .. }
+        | Chip8Error::StackUnderflow { .. }
+        | Chip8Error::MemoryOutOfBounds { .. } => Signal::SIGSEGV,
+    }
+}
+
+enum EventLoop {}
+
+impl BlockingEventLoop for EventLoop {
+    type Target = GdbServer;
+    type Connection = TcpStream;
+    type StopReason = SingleThreadStopReason<u16>;
+
+    #[allow(clippy::type_complexity)]
+    fn wait_for_stop_reason(
+        server: &mut GdbServer,
+        conn: &mut TcpStream,
+    ) -> Result<
+        run_blocking::Event<Self::StopReason>,
+        run_blocking::WaitForStopReasonError<&'static str, io::Error>,
+    > {
+        // A broken connection counts as something to read, so that reading reports it.
+        let reason = server.run(|| conn.peek().map(|byte| byte.is_some()).unwrap_or(true));
+        match reason {
+            Some(reason) => Ok(run_blocking::Event::TargetStopped(reason)),
+            None => conn
+                .read()
+                .map(run_blocking::Event::IncomingData)
+                .map_err(run_blocking::WaitForStopReasonError::Connection),
+        }
+    }
+
+    fn on_interrupt(server: &mut GdbServer) -> Result<Option<Self::StopReason>, &'static str> {
+        server.mode = Mode::Stopped;
+        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+    }
+}
+
+impl Target for GdbServer {
+    type Arch = Chip8Arch;
+    type Error = &'static str;
+
+    fn base_ops(&mut self) -> BaseOps<'_, Chip8Arch, &'static str> {
+        BaseOps::SingleThread(self)
+    }
+
+    fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+
+    fn support_monitor_cmd(&mut self) -> Option<MonitorCmdOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadBase for GdbServer {
+    fn read_registers(&mut self, regs: &mut GdbRegs) -> TargetResult<(), Self> {
+        let cpu = &self.cpu;
+        *regs = GdbRegs {
+            v: cpu.registers.v,
+            i: cpu.registers.i,
+            pc: cpu.pc,
+            sp: cpu.sp,
+            delay: cpu.registers.delay,
+            sound: cpu.registers.sound,
+        };
+        Ok(())
+    }
+
+    fn write_registers(&mut self, regs: &GdbRegs) -> TargetResult<(), Self> {
+        let cpu = &mut self.cpu;
+        cpu.registers.v = regs.v;
+        cpu.registers.i = regs.i;
+        cpu.pc = regs.pc;
+        // The stack only has 16 slots.
+        cpu.sp = regs.sp.min(16);
+        cpu.registers.delay = regs.delay;
+        cpu.registers.sound = regs.sound;
+        Ok(())
+    }
+
+    fn read_addrs(&mut self, start: u16, data: &mut [u8]) -> TargetResult<usize, Self> {
+        let memory = &self.cpu.memory[..];
+        let start = (start as usize).min(memory.len());
+        let len = data.len().min(memory.len() - start);
+        data[..len].copy_from_slice(&memory[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_addrs(&mut self, start: u16, data: &[u8]) -> TargetResult<(), Self> {
+        let start = start as usize;
+        let Some(memory) = self.cpu.memory.get_mut(start..start + data.len()) else {
+            return Err(TargetError::NonFatal);
+        };
+        memory.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadResume for GdbServer {
+    fn resume(&mut self, signal: Option<Signal>) -> Result<(), &'static str> {
+        if signal.is_some() {
+            return Err("there are no signals to continue with");
+        }
+        self.mode = Mode::Continue;
+        // Don't run the frames missed while stopped all at once.
+        self.pacer = FramePacer::new(std::time::Instant::now());
+        Ok(())
+    }
+
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadSingleStep for GdbServer {
+    fn step(&mut self, signal: Option<Signal>) -> Result<(), &'static str> {
+        if signal.is_some() {
+            return Err("there are no signals to step with");
+        }
+        self.mode = Mode::Step;
+        Ok(())
+    }
+}
+
+impl Breakpoints for GdbServer {
+    fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SwBreakpoint for GdbServer {
+    fn add_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        self.cpu.add_breakpoint(addr);
+        Ok(true)
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        let breakpoint = Breakpoint::Address(addr);
+        if !self.cpu.breakpoints().contains(&breakpoint) {
+            return Ok(false);
+        }
+        self.cpu.remove_breakpoint(breakpoint);
+        Ok(true)
+    }
+}
+
+impl MonitorCmd for GdbServer {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), &'static str> {
+        let cmd = String::from_utf8_lossy(cmd);
+        let mut words = cmd.split_whitespace();
+        let key = |word: Option<&str>| {
+            word.and_then(|word| u8::from_str_radix(word, 16).ok())
+                .filter(|&key| key < 16)
+        };
+        match (words.next(), words.next()) {
+            (Some("press"), word) => match key(word) {
+                Some(key) => self.cpu.keypad().press(key),
+                None => outputln!(out, "Which key? 0 to f"),
+            },
+            (Some("release"), word) => match key(word) {
+                Some(key) => self.cpu.keypad().release(key),
+                None => outputln!(out, "Which key? 0 to f"),
+            },
+            (Some("screen"), None) => outputln!(out, "{}", self.cpu.framebuffer().to_text()),
+            _ => outputln!(out, "Commands: press <key>, release <key>, screen"),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GdbServer;
+    use crate::{Keypad, Memory, Quirks, CPU};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+
+    /// Sends a packet and returns the reply, acknowledging it and expanding any run-length
+    /// encoding.
+    fn request(stream: &mut TcpStream, packet: &str) -> String {
+        let sum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(stream, "${}#{:02x}", packet, sum).unwrap();
+        let mut reply = Vec::new();
+        let mut byte = [0];
+        // Skip the acknowledgement, then read up to the checksum.
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'+' if reply.is_empty() => {}
+                b'$' => reply.clear(),
+                b'#' => break,
+                // `x*n` is x repeated n - 29 more times.
+                b'*' => {
+                    stream.read_exact(&mut byte).unwrap();
+                    let last = *reply.last().unwrap();
+                    reply.extend(std::iter::repeat_n(last, byte[0] as usize - 29));
+                }
+                other => reply.push(other),
+            }
+        }
+        stream.read_exact(&mut [0; 2]).unwrap();
+        stream.write_all(b"+").unwrap();
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[test]
+    fn debugs_a_rom_over_the_remote_protocol() {
+        // ld v0, 0x42; add v0, 1; jp 0x202
+        let mut memory = Memory::new();
+        memory[0x200..0x206].copy_from_slice(&[0x60, 0x42, 0x70, 0x01, 0x12, 0x02]);
+        let cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::default());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let mut server = GdbServer::new(cpu);
+            server.serve(listener.accept().unwrap().0).unwrap();
+            server
+        });
+        let mut gdb = TcpStream::connect(addr).unwrap();
+
+        assert_eq!(request(&mut gdb, "?"), "T05thread:01;");
+        // V0 to VF, then I, PC (00 02 little-endian), SP and the timers.
+        assert_eq!(
+            request(&mut gdb, "g"),
+            format!("{}00000002000000", "00".repeat(16))
+        );
+        assert_eq!(request(&mut gdb, "m200,4"), "60427001");
+        assert_eq!(request(&mut gdb, "s"), "S05");
+        assert_eq!(&request(&mut gdb, "g")[..2], "42");
+        assert_eq!(request(&mut gdb, "Z0,204,2"), "OK");
+        assert_eq!(request(&mut gdb, "c"), "T05thread:01;swbreak:;");
+        let regs = request(&mut gdb, "g");
+        assert_eq!((&regs[..2], &regs[36..40]), ("43", "0402"));
+        assert_eq!(request(&mut gdb, "M300,2:abcd"), "OK");
+        assert_eq!(request(&mut gdb, "z0,204,2"), "OK");
+        request(&mut gdb, "D");
+
+        let server = server.join().unwrap();
+        assert_eq!(server.cpu().memory[0x300..0x302], [0xab, 0xcd]);
+        assert!(server.cpu().breakpoints().is_empty());
+    }
+}
@@ -0,0 +1,259 @@
+//! A windowed frontend, drawing the display with `pixels` in a `winit` window. Unlike a terminal,
+//! a window gets real key-down and key-up events, and presenting each frame waits for vsync.
+use std::sync::Arc;
+use std::time::Duration;
+
+use chip8::audio::{AudioPattern, Beeper, DigitizedSound};
+use chip8::{
+    Color, Command, FrameBuffer, Frontend, InputEvent, Keymap, Phosphor, RunStatus, Speed, Theme,
+    CPU,
+};
+use pixels::{Pixels, SurfaceTexture};
+use tracing::{debug_span, error};
+use winit::dpi::LogicalSize;
+use winit::event::{ElementState, Event, KeyEvent, WindowEvent};
+use winit::event_loop::EventLoop;
+use winit::keyboard::{Key, ModifiersState, NamedKey};
+use winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
+use winit::window::{Window, WindowBuilder};
+
+/// Window pixels per lo-res display pixel.
+const SCALE: u32 = 10;
+
+pub struct Gui {
+    event_loop: EventLoop<()>,
+    window: Arc<Window>,
+    pixels: Pixels<'static>,
+    /// The resolution `pixels` is currently sized for.
+    size: (usize, usize),
+    title: String,
+    keymap: Keymap,
+    /// The modifier keys held down, which winit reports separately from key presses.
+    modifiers: ModifiersState,
+    theme: Theme,
+    phosphor: Option<Phosphor>,
+    beeper: Box<dyn Beeper>,
+}
+
+impl Gui {
+    pub fn new(
+        rom_title: String,
+        keymap: Keymap,
+        theme: Theme,
+        phosphor: Option<Phosphor>,
+        beeper: Box<dyn Beeper>,
+    ) -> anyhow::Result<Self> {
+        let event_loop = EventLoop::new()?;
+        let size = LogicalSize::new(
+            chip8::DISPLAY_WIDTH as u32 * SCALE,
+            chip8::DISPLAY_HEIGHT as u32 * SCALE,
+        );
+        let title = format!("[Chip8-RS] {}", rom_title);
+        let window = Arc::new(
+            WindowBuilder::new()
+                .with_title(&title)
+                .with_inner_size(size)
+                .with_min_inner_size(size)
+                .build(&event_loop)?,
+        );
+        let pixels = {
+            let inner = window.inner_size();
+            let surface = SurfaceTexture::new(inner.width, inner.height, Arc::clone(&window));
+            Pixels::new(
+                chip8::DISPLAY_WIDTH as u32,
+                chip8::DISPLAY_HEIGHT as u32,
+                surface,
+            )?
+        };
+        Ok(Self {
+            event_loop,
+            window,
+            pixels,
+            size: (chip8::DISPLAY_WIDTH, chip8::DISPLAY_HEIGHT),
+            title,
+            keymap,
+            modifiers: ModifiersState::empty(),
+            theme,
+            phosphor,
+            beeper,
+        })
+    }
+}
+
+impl Frontend for Gui {
+    fn present(&mut self, fb: &FrameBuffer) {
+        let _present = debug_span!(target: "display", "present").entered();
+        if (fb.width(), fb.height()) != self.size {
+            self.size = (fb.width(), fb.height());
+            if let Err(err) = self
+                .pixels
+                .resize_buffer(fb.width() as u32, fb.height() as u32)
+            {
+                error!("Couldn't resize the display: {}", err);
+            }
+        }
+        // Show the tone by inverting the display, if it can't be heard: every pixel takes the
+        // colour it would have with its first plane flipped.
+        let theme = if self.beeper.flashing() {
+            Theme {
+                fg: self.theme.bg,
+                bg: self.theme.fg,
+                fg2: self.theme.blend,
+                blend: self.theme.fg2,
+            }
+        } else {
+            self.theme
+        };
+        let (fg, bg) = (theme.fg, theme.bg);
+        let colors = theme.colors().map(Color::rgba);
+        let frame = self.pixels.frame_mut();
+        if let Some(palette) = fb.palette() {
+            for (rgba, &pixel) in frame.chunks_exact_mut(4).zip(fb.pixels()) {
+                rgba.copy_from_slice(&palette[pixel as usize].rgba());
+            }
+        } else if let Some(phosphor) = &mut self.phosphor {
+            phosphor.update(fb);
+            for (i, rgba) in frame.chunks_exact_mut(4).enumerate() {
+                let level = phosphor.level(i % fb.width(), i / fb.width());
+                rgba.copy_from_slice(&bg.mix(fg, level).rgba());
+            }
+        } else {
+            for (rgba, &pixel) in frame.chunks_exact_mut(4).zip(fb.pixels()) {
+                rgba.copy_from_slice(&colors[pixel as usize & 3]);
+            }
+        }
+        if let Err(err) = self.pixels.render() {
+            error!("Couldn't draw the display: {}", err);
+        }
+    }
+
+    /// Shows anything other than running normally in the title bar.
+    fn present_machine(&mut self, cpu: &CPU, status: &RunStatus) {
+        let title = match status {
+            RunStatus {
+                halted: Some(err), ..
+            } => format!("{} - Halted: {}", self.title, err),
+            RunStatus {
+                recording: Some(frames),
+                ..
+            } => format!("{} - Recording frame {}", self.title, frames),
+            RunStatus {
+                stopped_at: Some(breakpoint),
+                ..
+            } => format!("{} - Breakpoint {} hit", self.title, breakpoint),
+            RunStatus { paused: true, .. } => format!("{} - Paused", self.title),
+            RunStatus { speed, .. } if *speed != Speed::NORMAL => {
+                format!("{} - Speed {}", self.title, speed)
+            }
+            _ => self.title.clone(),
+        };
+        self.window.set_title(&title);
+        self.present(cpu.framebuffer());
+    }
+
+    fn poll_input(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let (keymap, pixels, modifiers) = (&self.keymap, &mut self.pixels, &mut self.modifiers);
+        let title = &mut self.title;
+        let status = self
+            .event_loop
+            .pump_events(Some(Duration::ZERO), |event, _| {
+                let Event::WindowEvent { event, .. } = event else {
+                    return;
+                };
+                match event {
+                    WindowEvent::CloseRequested => events.push(InputEvent::Quit),
+                    WindowEvent::Resized(size) => {
+                        if let Err(err) = pixels.resize_surface(size.width, size.height) {
+                            error!("Couldn't resize the window surface: {}", err);
+                        }
+                    }
+                    WindowEvent::ModifiersChanged(changed) => *modifiers = changed.state(),
+                    WindowEvent::DroppedFile(path) => {
+                        let name = path.file_name().unwrap_or(path.as_os_str());
+                        *title = format!("[Chip8-RS] {}", name.to_string_lossy());
+                        events.push(InputEvent::Open(path));
+                    }
+                    WindowEvent::KeyboardInput {
+                        event:
+                            KeyEvent {
+                                logical_key,
+                                state,
+                                repeat,
+                                ..
+                            },
+                        ..
+                    } => {
+                        let shift = modifiers.shift_key();
+                        if let Some(event) = input_event(keymap, &logical_key, state, repeat, shift)
+                        {
+                            events.push(event);
+                        }
+                    }
+                    _ => {}
+                }
+            });
+        if let PumpStatus::Exit(_) = status {
+            events.push(InputEvent::Quit);
+        }
+        events
+    }
+
+    fn beep(&mut self, on: bool) {
+        self.beeper.set_beeping(on);
+    }
+
+    fn play_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.beeper.set_pattern(pattern);
+    }
+
+    fn play_sound(&mut self, sound: Option<&DigitizedSound>) {
+        self.beeper.set_sound(sound);
+    }
+}
+
+/// The same controls as the terminal UI, plus Escape to quit.
+fn input_event(
+    keymap: &Keymap,
+    key: &Key,
+    state: ElementState,
+    repeat: bool,
+    shift: bool,
+) -> Option<InputEvent> {
+    let pressed = state == ElementState::Pressed;
+    let event = match key {
+        Key::Named(NamedKey::Escape) if pressed => InputEvent::Quit,
+        Key::Named(NamedKey::Space) if pressed && !repeat => {
+            InputEvent::Control(Command::TogglePause)
+        }
+        Key::Named(NamedKey::Backspace) if pressed => InputEvent::Control(Command::Rewind),
+        Key::Named(NamedKey::Tab) if !repeat => InputEvent::Turbo(pressed),
+        Key::Named(NamedKey::F5) if pressed && !repeat => InputEvent::SaveState,
+        Key::Named(NamedKey::F7) if pressed && !repeat => InputEvent::LoadState,
+        Key::Named(NamedKey::F8) if pressed && !repeat => InputEvent::Reload,
+        Key::Named(NamedKey::F9) if pressed && !repeat && shift => InputEvent::DumpMemory,
+        Key::Named(NamedKey::F9) if pressed && !repeat => InputEvent::DumpTrace,
+        #[cfg(feature = "video")]
+        Key::Named(NamedKey::F12) if pressed && !repeat && shift => InputEvent::ToggleVideo,
+        Key::Named(NamedKey::F12) if pressed && !repeat => InputEvent::Screenshot,
+        Key::Named(NamedKey::F6) if pressed && !repeat => InputEvent::Reset { hard: !shift },
+        Key::Character(c) => match c.chars().next()?.to_ascii_lowercase() {
+            'n' if pressed => InputEvent::Control(Command::Step),
+            'o' if pressed => InputEvent::Control(Command::StepOver),
+            '.' if pressed => InputEvent::Control(Command::AdvanceFrame),
+            '+' | '=' if pressed => InputEvent::Faster,
+            '-' if pressed => InputEvent::Slower,
+            'n' | 'o' | '.' | '+' | '=' | '-' => return None,
+            c => {
+                let key = keymap.key_for(c)?;
+                if pressed {
+                    InputEvent::KeyDown(key)
+                } else {
+                    InputEvent::KeyUp(key)
+                }
+            }
+        },
+        _ => return None,
+    };
+    Some(event)
+}
@@ -0,0 +1,206 @@
+use std::io;
+use std::path::Path;
+
+use crate::capture::{self, GifRecorder};
+use crate::error::Result;
+#[cfg(feature = "lua")]
+use crate::script::Script;
+#[cfg(feature = "video")]
+use crate::video::VideoRecorder;
+use crate::{Color, FrameBuffer, Movie, Theme, CPU};
+
+/// Runs a CPU flat out with no frontend attached: no terminal, no sound, no input besides what
+/// the caller presses on the keypad. Meant for scripting and for testing ROMs automatically.
+pub struct HeadlessRunner {
+    cpu: CPU,
+    gif: Option<GifRecorder>,
+    #[cfg(feature = "video")]
+    video: Option<VideoRecorder>,
+    #[cfg(feature = "lua")]
+    script: Option<Script>,
+}
+
+impl HeadlessRunner {
+    pub fn new(cpu: CPU) -> Self {
+        Self {
+            cpu,
+            gif: None,
+            #[cfg(feature = "video")]
+            video: None,
+            #[cfg(feature = "lua")]
+            script: None,
+        }
+    }
+
+    /// Calls `script`'s callbacks after every frame. A breakpoint callback that returns true
+    /// carries on past the breakpoint rather than stopping there. If the script errors, it's
+    /// stopped, and the ROM carries on without it.
+    #[cfg(feature = "lua")]
+    pub fn set_script(&mut self, script: Script) {
+        self.script = Some(script);
+    }
+
+    /// Records every frame run from now on to `gif`, until `take_gif`.
+    pub fn record_gif(&mut self, gif: GifRecorder) {
+        self.gif = Some(gif);
+    }
+
+    /// Stops recording the GIF, if one's being recorded, and hands it back to be finished.
+    pub fn take_gif(&mut self) -> Option<GifRecorder> {
+        self.gif.take()
+    }
+
+    /// Records every frame run from now on, with the sound, to `video`, until `take_video`.
+    #[cfg(feature = "video")]
+    pub fn record_video(&mut self, video: VideoRecorder) {
+        self.video = Some(video);
+    }
+
+    /// Stops recording video, if it's being recorded, and hands it back to be finished.
+    #[cfg(feature = "video")]
+    pub fn take_video(&mut self) -> Option<VideoRecorder> {
+        self.video.take()
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn into_cpu(self) -> CPU {
+        self.cpu
+    }
+
+    /// Runs up to `frames` 60Hz frames, stopping early if the ROM exits or hits a breakpoint.
+    /// Returns the number of frames actually run.
+    pub fn run(&mut self, frames: u32) -> Result<u32> {
+        for frame in 0..frames {
+            if self.cpu.has_exited() {
+                return Ok(frame);
+            }
+            self.cpu.run_frame()?;
+            self.record_frame();
+            if self.stops() {
+                return Ok(frame + 1);
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Runs `frames` frames as `run` does, and returns the display as it is at the end, for
+    /// checking against a snapshot with the `snapshot` module.
+    pub fn run_frames(&mut self, frames: u32) -> Result<FrameBuffer> {
+        self.run(frames)?;
+        Ok(self.cpu.framebuffer().clone())
+    }
+
+    /// Plays `movie` back from the start, which assumes the CPU has only just been set up, with
+    /// the same early stops as `run`. Returns the number of frames actually run.
+    pub fn play(&mut self, movie: &Movie) -> Result<u32> {
+        movie.start_playback(&mut self.cpu);
+        let mut frame = 0;
+        while !self.cpu.has_exited() && movie.play_frame(frame, &mut self.cpu)? {
+            frame += 1;
+            self.record_frame();
+            if self.stops() {
+                break;
+            }
+        }
+        Ok(frame as u32)
+    }
+
+    /// Adds the frame just run to the GIF and video, if they're being recorded, giving up on
+    /// either if it can't be written.
+    fn record_frame(&mut self) {
+        if let Some(gif) = &mut self.gif {
+            if let Err(err) = gif.add_frame(self.cpu.framebuffer()) {
+                tracing::error!("Stopped recording the GIF: {}", err);
+                self.gif = None;
+            }
+        }
+        #[cfg(feature = "video")]
+        if let Some(video) = &mut self.video {
+            let (fb, beeping, pattern) =
+                (self.cpu.framebuffer(), self.cpu.beeping(), self.cpu.audio());
+            if let Err(err) = video.add_frame(fb, beeping, pattern) {
+                tracing::error!("Stopped recording video: {}", err);
+                self.video = None;
+            }
+        }
+    }
+
+    /// Whether to stop after a frame: at a breakpoint, unless the script says to carry on.
+    fn stops(&mut self) -> bool {
+        #[cfg(feature = "lua")]
+        if let Some(script) = &mut self.script {
+            match script.after_frame(&mut self.cpu) {
+                Ok(true) => return false,
+                Ok(false) => {}
+                Err(err) => {
+                    tracing::error!("Script stopped: {}", err);
+                    self.script = None;
+                }
+            }
+        }
+        self.cpu.breakpoint_hit().is_some()
+    }
+
+    /// Renders the display as text, as `FrameBuffer::to_text` does.
+    pub fn display_text(&self) -> String {
+        self.cpu.framebuffer().to_text()
+    }
+
+    /// Writes the display to `path` as a black and white PNG, with every pixel scaled up to a
+    /// `scale` x `scale` square. Pixels lit on XO-CHIP's second plane are grey.
+    pub fn write_png<P: AsRef<Path>>(&self, path: P, scale: u32) -> io::Result<()> {
+        let theme = Theme {
+            fg: Color::new(0xff, 0xff, 0xff),
+            bg: Color::new(0x00, 0x00, 0x00),
+            fg2: Color::new(0x55, 0x55, 0x55),
+            blend: Color::new(0xaa, 0xaa, 0xaa),
+        };
+        capture::write_png(self.cpu.framebuffer(), theme, scale, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with;
+
+    fn runner_with(program: &[u16]) -> HeadlessRunner {
+        HeadlessRunner::new(cpu_with(program))
+    }
+
+    #[test]
+    fn draws_to_text() {
+        // Draw the top row of the "0" glyph at (0, 0), then spin.
+        let mut runner = runner_with(&[0xd001, 0x1202]);
+        assert_eq!(runner.run(2).unwrap(), 2);
+        let text = runner.display_text();
+        assert_eq!(text.lines().count(), 32);
+        assert!(text.starts_with("####...."));
+        assert_eq!(text.lines().nth(1).unwrap(), ".".repeat(64));
+    }
+
+    #[test]
+    fn stops_when_the_rom_exits() {
+        let mut runner = runner_with(&[0x00fd]);
+        runner.cpu_mut().set_variant(crate::Variant::Schip);
+        assert_eq!(runner.run(10).unwrap(), 1);
+        assert!(runner.cpu().has_exited());
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        // Spin for a few frames, then reach 0x206.
+        let mut runner = runner_with(&[0x7001, 0x3040, 0x1200, 0x1206]);
+        runner.cpu_mut().add_breakpoint(0x206);
+        let frames = runner.run(100).unwrap();
+        assert!(frames > 1 && frames < 100);
+        assert_eq!(runner.cpu().pc, 0x206);
+    }
+}
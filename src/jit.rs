@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use crate::core::Registers;
+use crate::disasm::Instr;
+use crate::{Memory, Quirks, Variant};
+
+/// The most instructions compiled into one block, so that a long block still fits in a frame's
+/// budget at low clock speeds.
+const MAX_BLOCK_LEN: usize = 32;
+
+/// An instruction compiled to a closure over the registers, with the quirks it ran with baked in.
+/// It runs them with `Registers::execute`, like the interpreter.
+type Op = Box<dyn Fn(&mut Registers) + Send + Sync>;
+
+/// A straight run of instructions that only touch the registers, compiled to closures that run
+/// one after another without fetching or decoding anything. It ends before the first instruction
+/// that jumps, skips, draws, touches memory or otherwise needs the interpreter.
+pub struct Block {
+    /// The bytes it was compiled from, for telling when the ROM has rewritten them.
+    bytes: Vec<u8>,
+    /// Each instruction's opcode, for the trace, and what it compiled to.
+    ops: Vec<(u16, Op)>,
+}
+
+impl Block {
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub fn ops(&self) -> impl Iterator<Item = (u16, &Op)> {
+        self.ops.iter().map(|(opcode, op)| (*opcode, op))
+    }
+}
+
+/// Blocks compiled so far, by the address they start at. A block is only run while memory still
+/// holds the bytes it was compiled from, so any write into code, whether the ROM's own or a
+/// debugger's, sends it back to be compiled again.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: Vec<Option<Arc<Block>>>,
+    /// What the blocks were compiled for.
+    compiled_for: Option<(Quirks, Variant)>,
+}
+
+impl BlockCache {
+    /// The block starting at `pc`, compiling it if it isn't already, or `None` if the instruction
+    /// at `pc` can't be compiled.
+    pub fn get(
+        &mut self,
+        pc: u16,
+        memory: &Memory,
+        quirks: Quirks,
+        variant: Variant,
+    ) -> Option<Arc<Block>> {
+        if self.compiled_for != Some((quirks, variant)) {
+            self.blocks.clear();
+            self.compiled_for = Some((quirks, variant));
+        }
+        let start = pc as usize;
+        if start >= self.blocks.len() {
+            self.blocks.resize(start + 1, None);
+        }
+        let block = match &self.blocks[start] {
+            Some(block) if memory.get(start..start + block.bytes.len()) == Some(&block.bytes) => {
+                block
+            }
+            _ => self.blocks[start].insert(Arc::new(compile(start, memory, quirks, variant))),
+        };
+        (!block.is_empty()).then(|| block.clone())
+    }
+}
+
+/// Compiles as many instructions from `start` as it can.
+fn compile(start: usize, memory: &Memory, quirks: Quirks, variant: Variant) -> Block {
+    let mut ops = Vec::new();
+    let mut addr = start;
+    while ops.len() < MAX_BLOCK_LEN {
+        let Some(&[hi, lo]) = memory.get(addr..addr + 2) else {
+            break;
+        };
+        let opcode = u16::from_be_bytes([hi, lo]);
+        let Some(op) = Instr::decode(opcode, variant).and_then(|instr| compile_op(instr, quirks))
+        else {
+            break;
+        };
+        ops.push((opcode, op));
+        addr += 2;
+    }
+    // An empty block still keeps the first instruction's bytes, so that it's tried again if the
+    // ROM rewrites it.
+    let end = if ops.is_empty() { start + 2 } else { addr };
+    Block {
+        bytes: memory
+            .get(start..end.min(memory.len()))
+            .unwrap_or_default()
+            .to_vec(),
+        ops,
+    }
+}
+
+/// `instr` as a closure, if it only touches the registers. Whether it does is whether a spare
+/// set of registers will run it.
+fn compile_op(instr: Instr, quirks: Quirks) -> Option<Op> {
+    Registers::new().execute(instr, quirks).then(|| {
+        Box::new(move |r: &mut Registers| {
+            r.execute(instr, quirks);
+        }) as Op
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_end_before_control_flow_and_recompile_when_rewritten() {
+        let mut memory = crate::cpu::cpu_with(&[0x6105, 0x8114, 0xa300, 0x1200]).memory;
+        let mut cache = BlockCache::default();
+        let block = cache
+            .get(0x200, &memory, Quirks::default(), Variant::Chip8)
+            .unwrap();
+        assert_eq!(block.len(), 3);
+        assert!(cache
+            .get(0x206, &memory, Quirks::default(), Variant::Chip8)
+            .is_none());
+
+        let mut registers = Registers::new();
+        for (_, op) in block.ops() {
+            op(&mut registers);
+        }
+        assert_eq!(
+            (registers.v[1], registers.v[0xf], registers.i),
+            (10, 0, 0x300)
+        );
+
+        memory[0x203] = 0x15;
+        let block = cache
+            .get(0x200, &memory, Quirks::default(), Variant::Chip8)
+            .unwrap();
+        assert_eq!(block.ops().nth(1).unwrap().0, 0x8115);
+    }
+}
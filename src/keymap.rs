@@ -0,0 +1,121 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Maps keyboard characters onto the 16-key hex keypad.
+///
+/// The default is the usual QWERTY layout, which puts the keypad's 4x4 grid on the left-hand block
+/// of the keyboard:
+///
+/// ```text
+/// 1 2 3 4        1 2 3 C
+/// Q W E R   =>   4 5 6 D
+/// A S D F        7 8 9 E
+/// Z X C V        A 0 B F
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keymap {
+    /// The character for each keypad key, indexed by key value.
+    keys: [char; 16],
+}
+
+impl Keymap {
+    pub const QWERTY: Keymap = Keymap {
+        keys: [
+            'x', '1', '2', '3', 'q', 'w', 'e', 'a', 's', 'd', 'z', 'c', '4', 'r', 'f', 'v',
+        ],
+    };
+
+    /// Each keypad key on the keyboard key with the same hex digit.
+    pub const HEX: Keymap = Keymap {
+        keys: [
+            '0', '1', '2', '3', '4', '5', '6', '7', '8', '9', 'a', 'b', 'c', 'd', 'e', 'f',
+        ],
+    };
+
+    /// The characters the terminal UI keeps for its own controls, which keymaps can't use.
+    pub const RESERVED: [char; 9] = [' ', 'n', 'o', '.', ':', '?', '+', '=', '-'];
+
+    /// The keypad key that `c` is mapped to, ignoring case.
+    pub fn key_for(&self, c: char) -> Option<u8> {
+        let c = c.to_ascii_lowercase();
+        self.keys.iter().position(|&k| k == c).map(|k| k as u8)
+    }
+
+    /// The character that keypad `key` is mapped to.
+    pub fn char_for(&self, key: u8) -> char {
+        self.keys[(key & 0xf) as usize]
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::QWERTY
+    }
+}
+
+impl fmt::Display for Keymap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.keys.iter().try_for_each(|c| write!(f, "{}", c))
+    }
+}
+
+/// Parses either a named layout (`qwerty` or `hex`) or 16 distinct characters giving the keyboard
+/// key for keypad keys 0 through F in order, e.g. `x123qweasdzc4rfv` for QWERTY. None of them can
+/// be `RESERVED`.
+impl FromStr for Keymap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "qwerty" => return Ok(Self::QWERTY),
+            "hex" => return Ok(Self::HEX),
+            _ => {}
+        }
+
+        let chars: Vec<char> = s.chars().map(|c| c.to_ascii_lowercase()).collect();
+        if chars.len() != 16 {
+            return Err(format!(
+                "keymap must be 'qwerty', 'hex' or 16 characters, got {}",
+                chars.len()
+            ));
+        }
+        let mut keys = ['\0'; 16];
+        for (i, &c) in chars.iter().enumerate() {
+            if chars[..i].contains(&c) {
+                return Err(format!("'{}' is mapped to more than one key", c));
+            }
+            if Self::RESERVED.contains(&c) {
+                return Err(format!("'{}' is kept for the emulator's own controls", c));
+            }
+            keys[i] = c;
+        }
+        Ok(Self { keys })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qwerty_layout() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.key_for('1'), Some(0x1));
+        assert_eq!(keymap.key_for('4'), Some(0xc));
+        assert_eq!(keymap.key_for('X'), Some(0x0));
+        assert_eq!(keymap.key_for('v'), Some(0xf));
+        assert_eq!(keymap.key_for('p'), None);
+        assert_eq!(keymap.char_for(0xd), 'r');
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!("qwerty".parse(), Ok(Keymap::QWERTY));
+        assert_eq!("HEX".parse(), Ok(Keymap::HEX));
+        assert_eq!(Keymap::QWERTY.to_string().parse(), Ok(Keymap::QWERTY));
+        assert!("abc".parse::<Keymap>().is_err());
+        assert!("x123qweasdzc4rfx".parse::<Keymap>().is_err());
+        assert!("x123qweasdzc4rfn".parse::<Keymap>().is_err());
+        assert!("x123qweasdzc4rf ".parse::<Keymap>().is_err());
+    }
+}
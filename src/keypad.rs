@@ -0,0 +1,61 @@
+use std::sync::atomic::{AtomicU16, Ordering};
+
+/// The 16-key hexadecimal keypad, laid out on the original COSMAC VIP as:
+///
+/// ```text
+/// 1 2 3 C
+/// 4 5 6 D
+/// 7 8 9 E
+/// A 0 B F
+/// ```
+///
+/// Each key is one bit of an atomic bitmask, so a single `Keypad` can be shared (via `Arc`)
+/// between the frontend, which presses and releases keys, and the interpreter, which reads them.
+pub struct Keypad {
+    state: AtomicU16,
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Self {
+            state: AtomicU16::new(0),
+        }
+    }
+
+    pub fn press(&self, key: u8) {
+        self.state.fetch_or(Self::mask(key), Ordering::AcqRel);
+    }
+
+    pub fn release(&self, key: u8) {
+        self.state.fetch_and(!Self::mask(key), Ordering::AcqRel);
+    }
+
+    pub fn release_all(&self) {
+        self.state.store(0, Ordering::Release);
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        self.state.load(Ordering::Acquire) & Self::mask(key) != 0
+    }
+
+    /// Every key's state as a bitmask, bit `n` set if key `n` is held.
+    pub fn state(&self) -> u16 {
+        self.state.load(Ordering::Acquire)
+    }
+
+    pub fn set_state(&self, state: u16) {
+        self.state.store(state, Ordering::Release);
+    }
+
+    /// Only the low nibble of `key` is significant, mirroring how Ex9E/ExA1 only look at the low
+    /// nibble of Vx on the original hardware.
+    fn mask(key: u8) -> u16 {
+        1 << (key & 0xf)
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,51 +1,207 @@
+//! A CHIP-8, SUPER-CHIP and XO-CHIP emulator. With the `std` feature off, only the `core`
+//! module is built, along with the instruction decoder and quirks it shares with `CPU`, and the
+//! crate is `no_std`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
 use std::path::PathBuf;
 
-use crossbeam_channel::{Receiver, Sender, TryRecvError};
+pub mod core;
+pub mod disasm;
+mod error;
+mod quirks;
+mod rng;
+mod variant;
+pub use error::Chip8Error;
+pub use quirks::Quirks;
+pub use rng::Rng;
+pub use variant::Variant;
+// ffi and the other optional features all turn std on.
+#[cfg(feature = "ffi")]
+pub mod ffi;
 
+#[cfg(feature = "std")]
+pub mod asm;
+#[cfg(feature = "std")]
+pub mod audio;
+#[cfg(feature = "std")]
+mod breakpoint;
+#[cfg(feature = "std")]
+pub mod builtin;
+#[cfg(feature = "std")]
+mod bus;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+mod cartridge;
+#[cfg(feature = "cli")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod console;
+#[cfg(feature = "std")]
+mod control;
+#[cfg(feature = "std")]
+mod coverage;
+#[cfg(feature = "std")]
 mod cpu;
+#[cfg(feature = "std")]
+pub mod crash;
+#[cfg(feature = "std")]
+mod decode;
+#[cfg(feature = "std")]
+pub mod differential;
+#[cfg(feature = "std")]
+mod emulator;
+#[cfg(feature = "std")]
+mod framebuffer;
+#[cfg(feature = "std")]
+mod frontend;
+#[cfg(feature = "gdb")]
+pub mod gdb;
+#[cfg(feature = "std")]
+mod headless;
+#[cfg(feature = "std")]
+mod jit;
+#[cfg(feature = "std")]
+mod keymap;
+#[cfg(feature = "std")]
+mod keypad;
+#[cfg(feature = "cli")]
 pub mod logger;
+#[cfg(feature = "megachip")]
+mod megachip;
+#[cfg(feature = "std")]
+mod memimage;
+#[cfg(feature = "std")]
 mod memory;
-pub use cpu::CPU;
-pub use memory::Memory;
-
-#[derive(Clone)]
-pub struct KillSignal {
-    tx: Sender<()>,
-    rx: Receiver<()>,
-}
-
-impl KillSignal {
-    pub fn new() -> Self {
-        let (tx, rx) = crossbeam_channel::unbounded();
-        Self { tx, rx }
-    }
-
-    /// NOTE: A kill signal is a one-shot signal. Once it's received, it's gone.
-    /// Thus, it is not guaranteed that if `received()` is true, it will be true subsequently.
-    pub fn received(&self) -> bool {
-        match self.rx.try_recv() {
-            Err(TryRecvError::Empty) => false,
-            _ => true,
-        }
-    }
+#[cfg(feature = "std")]
+mod movie;
+#[cfg(feature = "std")]
+mod netplay;
+#[cfg(feature = "std")]
+pub mod octo;
+#[cfg(feature = "std")]
+mod pacing;
+#[cfg(feature = "std")]
+mod profile;
+#[cfg(feature = "std")]
+mod region;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "std")]
+mod render;
+#[cfg(feature = "std")]
+mod rewind;
+#[cfg(feature = "std")]
+mod romdb;
+#[cfg(feature = "std")]
+mod savestate;
+#[cfg(feature = "lua")]
+pub mod script;
+#[cfg(feature = "std")]
+pub mod snapshot;
+#[cfg(feature = "std")]
+mod symbols;
+#[cfg(feature = "std")]
+pub mod testroms;
+#[cfg(feature = "std")]
+mod theme;
+#[cfg(feature = "std")]
+mod timing;
+#[cfg(feature = "std")]
+mod trace;
+#[cfg(feature = "video")]
+pub mod video;
+#[cfg(feature = "std")]
+mod watchpoint;
+#[cfg(feature = "std")]
+pub use breakpoint::Breakpoint;
+#[cfg(feature = "std")]
+pub use bus::{ControlBus, ControlMessage, Subscription};
+#[cfg(feature = "std")]
+pub use cartridge::Cartridge;
+#[cfg(feature = "std")]
+pub use control::{Command, CpuControl, Debugger};
+#[cfg(feature = "std")]
+pub use coverage::{Coverage, Usage};
+#[cfg(feature = "std")]
+pub use cpu::{
+    is_two_page, Registers, Resolution, Snapshot, CPU, DEFAULT_IPS, DISPLAY_HEIGHT, DISPLAY_WIDTH,
+    FRAMES_PER_SECOND,
+};
+#[cfg(feature = "std")]
+pub use emulator::{Emulator, EmulatorBuilder};
+#[cfg(feature = "std")]
+pub use framebuffer::{DirtyRegion, FrameBuffer, Phosphor};
+#[cfg(feature = "std")]
+pub use frontend::{Frontend, InputEvent, RomSetup, RunLoop, RunStatus, Speed};
+#[cfg(feature = "std")]
+pub use headless::HeadlessRunner;
+#[cfg(feature = "std")]
+pub use keymap::Keymap;
+#[cfg(feature = "std")]
+pub use keypad::Keypad;
+#[cfg(feature = "megachip")]
+pub use megachip::{MEGACHIP_MEMORY_SIZE, MEGA_DISPLAY_HEIGHT, MEGA_DISPLAY_WIDTH};
+#[cfg(feature = "std")]
+pub use memimage::MemoryImage;
+#[cfg(feature = "std")]
+pub use memory::{
+    AccessPolicy, LoadAddress, Memory, OutOfBounds, RomError, FONTS_END, MAX_MEMORY_SIZE,
+    PROGRAM_START,
+};
+#[cfg(feature = "std")]
+pub use movie::Movie;
+#[cfg(feature = "std")]
+pub use netplay::{KeySet, Netplay};
+#[cfg(feature = "std")]
+pub use profile::{Cost, HotAddress, HotLoop, ProfileReport, Profiler};
+#[cfg(feature = "std")]
+pub use region::{Region, WriteProtection};
+#[cfg(feature = "std")]
+pub use render::{Cell, RenderStyle};
+#[cfg(feature = "std")]
+pub use rewind::{Rewind, DEFAULT_REWIND_FRAMES};
+#[cfg(feature = "std")]
+pub use romdb::RomInfo;
+#[cfg(feature = "std")]
+pub use savestate::{SaveState, StateError, SAVE_STATE_VERSION};
+#[cfg(feature = "std")]
+pub use symbols::Symbols;
+#[cfg(feature = "std")]
+pub use theme::{Color, Theme};
+#[cfg(feature = "std")]
+pub use timing::{Timing, VIP_CYCLES_PER_FRAME};
+#[cfg(feature = "std")]
+pub use trace::{TraceEntry, Tracer, DEFAULT_TRACE_LEN};
+#[cfg(feature = "std")]
+pub use watchpoint::{Access, WatchHit, Watchpoint};
 
-    pub fn send(&self) {
-        self.tx.send(()).unwrap();
-    }
+/// The SHA-1 of a ROM's contents in hex, which is how the ROM database and `[roms]` tables in
+/// the config file know it.
+#[cfg(feature = "std")]
+pub fn rom_sha1(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
 }
 
+/// A ROM file and the bus for controlling whatever's running it, which is all the `chip8` binary
+/// needs. Embedders should use `Emulator` instead.
+#[cfg(feature = "std")]
+#[deprecated(note = "use `Emulator::builder()` to set up a machine")]
 pub struct GameShell {
     pub rom: PathBuf,
-    pub shiftquirk: bool,
-    killsignal_internal: KillSignal,
+    pub quirks: Quirks,
+    bus: ControlBus,
 }
 
+#[cfg(feature = "std")]
+#[allow(deprecated)]
 impl GameShell {
-    pub fn new(rom: PathBuf, shiftquirk: bool) -> Self {
+    pub fn new(rom: PathBuf, quirks: Quirks) -> Self {
         Self {
             rom,
-            shiftquirk,
-            killsignal_internal: KillSignal::new(),
+            quirks,
+            bus: ControlBus::new(),
         }
     }
 
@@ -53,11 +209,28 @@ impl GameShell {
         &self.rom
     }
 
+    /// Where the TUI's quick save state for this ROM lives, next to the ROM itself.
+    pub fn state_path(&self) -> PathBuf {
+        self.rom.with_extension("state")
+    }
+
+    /// Starts `cpu` over with the ROM read afresh from disk, e.g. after reassembling it, or
+    /// compiled afresh if it's Octo source. Returns how big it is now.
+    pub fn reload(&self, cpu: &mut CPU) -> Result<usize, RomError> {
+        let cart = Cartridge::load(&self.rom)?;
+        cpu.reload(&cart.rom)?;
+        if !cart.symbols.is_empty() {
+            cpu.set_symbols(cart.symbols);
+        }
+        Ok(cart.rom.len())
+    }
+
     pub fn print_rom_title(&self) -> String {
         self.rom.display().to_string()
     }
 
-    pub fn clone_killsignal(&self) -> KillSignal {
-        self.killsignal_internal.clone()
+    /// The bus for telling everything running this game what to do, e.g. to shut down.
+    pub fn control_bus(&self) -> ControlBus {
+        self.bus.clone()
     }
 }
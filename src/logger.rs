@@ -1,11 +1,39 @@
-use anyhow::Result;
+//! Logging to a file with `tracing`. `CHIP8_LOG` picks what's logged, in the same syntax as
+//! `RUST_LOG`: a level for everything and levels for particular subsystems, like
+//! `CHIP8_LOG=cpu=debug,display=warn`. It's `info` if unset.
+//!
+//! The subsystems are `frame` (a span for each frame run), `cpu` (a span for each instruction),
+//! `display` (spans for drawing sprites and presenting the display), `input` (a span for each
+//! input event handled), `memory` and `audio`. Spans log how long they took as they close, so
+//! `CHIP8_LOG=frame=debug` times every frame. `CHIP8_LOG_FORMAT=json` writes each line as a
+//! JSON object instead of text.
+use anyhow::{anyhow, Result};
+use std::sync::Mutex;
 use std::{fs, io, path::Path};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
 pub fn init<P: AsRef<Path>>(log_file: P) -> Result<()> {
     let log_file = log_file.as_ref();
     rm_rf(log_file)?;
-    simple_logging::log_to_file(log_file, log::LevelFilter::Info)?;
-    Ok(())
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .with_env_var("CHIP8_LOG")
+        .from_env()
+        .map_err(|err| anyhow!("CHIP8_LOG isn't a valid filter: {}", err))?;
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(Mutex::new(fs::File::create(log_file)?))
+        .with_ansi(false)
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE);
+    let json = std::env::var("CHIP8_LOG_FORMAT").is_ok_and(|format| format == "json");
+    if json {
+        subscriber.json().try_init()
+    } else {
+        subscriber.try_init()
+    }
+    .map_err(|err| anyhow!("{}", err))
 }
 
 fn rm_rf<P: AsRef<Path>>(path: P) -> Result<(), io::Error> {
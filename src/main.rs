@@ -1,471 +1,1281 @@
-/// TODO:
-/// - Refactor display
-/// - Refactor CPU
-/// - Get rid of outdated multithreading logic
-/// - Keypad test
-/// - Quirks test
-/// - Beep test
-/// - Run an actual game
-/// - Maybe implement super-chip or xo-chip
-/// - Maybe implement better GUI controls and/or opcode debugging
-use std::io::Cursor;
-use std::path::PathBuf;
-use std::sync::atomic::{AtomicU8, Ordering};
-use std::sync::{Arc, RwLock};
+#[cfg(feature = "gui")]
+mod gui;
+mod picker;
+mod terminal;
+mod tui;
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::Duration;
 
-use byteorder::{BigEndian, ReadBytesExt};
-use chip8::{logger, GameShell, Memory};
-use clap::Parser;
-use crossterm::event;
-use crossterm::{
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
-    ExecutableCommand,
-};
-use log::info;
-use ratatui::{
-    prelude::*,
-    widgets::{Block, Paragraph},
+use chip8::audio::{AudioMode, Beeper, NullBeeper, TerminalBell, VisualBeeper};
+use chip8::builtin::{BuiltinRom, BUILTIN_ROMS};
+use chip8::capture::{self, GifRecorder, DEFAULT_CAPTURE_SCALE};
+use chip8::config::{Config, RecentRoms, Settings};
+use chip8::differential::Lockstep;
+use chip8::disasm::{Analysis, Disassembly};
+use chip8::snapshot;
+use chip8::testroms::TEST_ROMS;
+#[cfg(feature = "video")]
+use chip8::video::{VideoFormat, VideoRecorder};
+use chip8::{
+    logger, AccessPolicy, Breakpoint, Cartridge, ControlMessage, Coverage, FrameBuffer,
+    HeadlessRunner, InputEvent, KeySet, Keymap, Keypad, LoadAddress, Memory, MemoryImage, Movie,
+    Netplay, Phosphor, Quirks, Region, RenderStyle, RomInfo, RomSetup, RunLoop, Symbols, Theme,
+    Timing, Variant, WriteProtection, CPU,
 };
-use std::io::stdout;
+// The binary still runs ROMs from files through a GameShell.
+#[allow(deprecated)]
+use chip8::GameShell;
+use clap::Parser;
+use crossbeam_channel::Sender;
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{error, info, warn};
+
+use crate::picker::Pick;
+use crate::terminal::TerminalGuard;
+use crate::tui::Tui;
+
+const DEFAULT_VOLUME: f32 = 0.25;
 
+/// `chip8 rom.ch8` is short for `chip8 run rom.ch8`.
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
-    /// The ROM to load into the emulator
-    rom: PathBuf,
+    #[command(subcommand)]
+    tool: Option<Tool>,
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(clap::Args, Clone)]
+struct RunArgs {
+    /// The ROM to load into the emulator, - to read it from stdin, or an http(s) URL to download
+    /// it from with the http feature. Without one, the terminal UI starts on a screen to pick one
+    rom: Option<PathBuf>,
+    /// Run one of the ROMs built into the emulator instead: pong, ibm-logo, chip8-logo, corax+,
+    /// flags or keypad
+    #[arg(long, value_name = "NAME", conflicts_with = "rom")]
+    builtin: Option<String>,
+    /// Where the start screen looks for ROMs when there's none given [default: the config file's
+    /// rom_dir, or the working directory]
+    #[arg(long, value_name = "DIR")]
+    rom_dir: Option<PathBuf>,
+    /// The quirk profile to start from: chip8, chip48, schip or xochip. Defaults to the one matching
+    /// --variant. Individual --*quirk flags override it.
+    #[arg(long, value_name = "PROFILE")]
+    quirks: Option<Variant>,
     /// Whether or not to enable the quirk for the 8XY6/8XYE instructions
     /// where shifting happens directly in the Vx register. Needed for some games.
     /// See https://tobiasvl.github.io/blog/write-a-chip-8-emulator/#logical-and-arithmetic-instructions
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    shiftquirk: Option<bool>,
+    /// Whether or not BNNN jumps to XNN + VX rather than NNN + V0
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    jumpquirk: Option<bool>,
+    /// Whether or not FX55/FX65 increment I past the registers they save/load, as on the COSMAC VIP
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    loadstorequirk: Option<bool>,
+    /// Whether or not FX55/FX65 leave I on the last register instead of past it, as on CHIP-48.
+    /// Only matters with --loadstorequirk
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    loadstorexquirk: Option<bool>,
+    /// Whether or not DXYN waits for the next 60Hz frame, limiting drawing to one sprite per frame
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    displaywaitquirk: Option<bool>,
+    /// Whether or not sprites are clipped at the screen edges rather than wrapping around
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    clipquirk: Option<bool>,
+    /// Whether or not 8XY1/8XY2/8XY3 reset VF to 0, as on the COSMAC VIP
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    vfresetquirk: Option<bool>,
+    /// What happens when a ROM reads or writes past the end of memory: wrap, clamp or error
+    /// [default: error]
+    #[arg(long, value_name = "POLICY")]
+    memory_policy: Option<AccessPolicy>,
+    /// Where the ROM is loaded and starts running: chip8 for 0x200, eti660 for 0x600, as ROMs
+    /// for the ETI-660 expect, or a hex address like 0x300 [default: chip8]
+    #[arg(long, value_name = "ADDR")]
+    load_addr: Option<LoadAddress>,
+    /// What happens when a ROM writes to the interpreter area below 0x200, or a region given with
+    /// --protect: allow, warn or deny. Warnings and denied writes go to the log [default: allow]
+    #[arg(long, value_name = "MODE")]
+    write_protect: Option<WriteProtection>,
+    /// Protect a hex address or range of memory, like 300-3ff, as well as the interpreter area.
+    /// Can be given more than once
+    #[arg(long = "protect", value_name = "REGION")]
+    protected: Vec<Region>,
+    /// CPU clock speed in instructions per second. Timers always run at 60Hz regardless.
+    /// [default: 700]
+    #[arg(long)]
+    ips: Option<u32>,
+    /// How much runs each frame: ips, for --ips instructions a second whatever they are, or vip,
+    /// for each instruction to take as long as it did on the COSMAC VIP and each sprite drawn to
+    /// wait for the next frame, which is how fast games written for it ran [default: ips]
+    #[arg(long)]
+    timing: Option<Timing>,
+    /// Run straight runs of register-only instructions as blocks compiled to closures rather than
+    /// one at a time, which mostly helps in turbo. Experimental
+    #[arg(long)]
+    jit: bool,
+    /// Which CHIP-8 dialect to interpret: chip8, chip48 (CHIP-8 with the HP-48's quirks), schip
+    /// (SUPER-CHIP 1.1), xochip, or megachip with the megachip feature [default: chip8]
+    #[arg(long)]
+    variant: Option<Variant>,
+    /// Keyboard layout for the hex keypad: qwerty (1234/QWER/ASDF/ZXCV), hex (0-9, A-F), or 16
+    /// characters giving the key for each of 0 through F in turn. Space, N, O, ., :, ?, +, = and -
+    /// are taken by the emulator's own controls. [default: qwerty]
+    #[arg(long)]
+    keymap: Option<Keymap>,
+    /// How to draw the display in the terminal: block (a character per pixel), wide (two
+    /// characters per pixel, for square pixels), halfblock (two pixels per character, for smaller
+    /// terminals and square pixels) or braille (eight pixels per character, for the smallest
+    /// terminals). By default the terminal UI picks whichever of wide, halfblock and braille draws
+    /// the biggest pixels that fit, as the terminal's resized, and text art uses block
+    #[arg(long, value_name = "STYLE")]
+    render_style: Option<RenderStyle>,
+    /// Display colours: default (light blue on black), octo, gameboy, amber, c64, or colours as
+    /// FG/BG or FG/BG/FG2/BLEND, e.g. #ffffff/#000000. --fg, --bg, --fg2 and --blend override it
+    #[arg(long)]
+    theme: Option<Theme>,
+    /// The colour of lit pixels, as #rrggbb
+    #[arg(long, value_name = "COLOR")]
+    fg: Option<chip8::Color>,
+    /// The colour of unlit pixels, as #rrggbb
+    #[arg(long, value_name = "COLOR")]
+    bg: Option<chip8::Color>,
+    /// The colour of pixels lit only on XO-CHIP's second plane, as #rrggbb
+    #[arg(long, value_name = "COLOR")]
+    fg2: Option<chip8::Color>,
+    /// The colour of pixels lit on both XO-CHIP planes, as #rrggbb
+    #[arg(long, value_name = "COLOR")]
+    blend: Option<chip8::Color>,
+    /// Fade pixels out over this many frames instead of turning them off at once, to hide
+    /// flicker. 0, the default, turns fading off
+    #[arg(long, value_name = "FRAMES")]
+    phosphor: Option<u8>,
+    /// Pause when a condition is met: an address (234), an opcode pattern with x for wildcards
+    /// (dxyn), a register taking a value (v3=1f), or a memory read, write or either within a
+    /// range (r:300, w:300-30f, rw:300). Can be given more than once.
+    #[arg(long = "break", value_name = "BREAKPOINT")]
+    breakpoints: Vec<Breakpoint>,
+    /// Run a Lua script alongside the ROM, with callbacks on every frame, breakpoint and memory
+    /// write, for bots and automated tests. Works headless too
+    #[cfg(feature = "lua")]
+    #[arg(long, value_name = "PATH")]
+    script: Option<PathBuf>,
+    /// Wait for gdb to connect to ADDR (like :3333, for localhost) and debug the ROM from there,
+    /// with V0 to VF, I, PC, SP and the timers as registers, instead of running the terminal UI
+    #[cfg(feature = "gdb")]
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["headless", "record", "playback", "watch"])]
+    gdb: Option<String>,
+    /// Serve the display and machine state to WebSocket clients on ADDR (like :8080, for
+    /// localhost) instead of running the terminal UI, and take their input and debugger commands
+    #[cfg(feature = "remote")]
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["headless", "record"])]
+    remote: Option<String>,
+    /// Wait for a second player to join on ADDR (like :4000, for localhost) and play with them,
+    /// each on their own machine, with the keys in --netplay-keys theirs
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["netplay_join", "headless", "record", "playback"])]
+    netplay_host: Option<String>,
+    /// Join a game hosted with --netplay-host at ADDR, running the same ROM
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["headless", "record", "playback"])]
+    netplay_join: Option<String>,
+    /// The keypad keys the second player controls, as hex digits. The host has the rest
+    #[arg(long, value_name = "KEYS", default_value_t = KeySet::default())]
+    netplay_keys: KeySet,
+    /// Run without the terminal UI, for scripting and CI. Stops early at the first breakpoint hit
+    #[arg(long, default_value_t = false)]
+    headless: bool,
+    /// Open a window instead of drawing in the terminal
+    #[cfg(feature = "gui")]
+    #[arg(long, default_value_t = false, conflicts_with = "headless")]
+    gui: bool,
+    /// In headless mode, how many 60Hz frames to run before stopping
+    #[arg(long, default_value_t = 600)]
+    frames: u32,
+    /// Where to write the display on exiting: a .png file, a .ans file for ANSI colour text art,
+    /// any other path for a text grid, or - for a text grid on stdout. F12 saves a PNG next to the
+    /// ROM at any time, and Ctrl+F12 a text grid
+    #[arg(long, value_name = "PATH")]
+    dump: Option<PathBuf>,
+    /// Where to write a memory image on exiting: all of RAM byte for byte, then the registers,
+    /// stack and timers. Shift+F9 saves one next to the ROM at any time
+    #[arg(long, value_name = "PATH")]
+    dump_memory: Option<PathBuf>,
+    /// A memory image to write into RAM from address 0 once the ROM's loaded, putting back the
+    /// registers too if it has them. Plain binary files are loaded as RAM alone
+    #[arg(long, value_name = "PATH")]
+    load_memory: Option<PathBuf>,
+    /// A symbol file naming addresses in the ROM, for the debugger to show the call stack and
+    /// disassembly with. Defaults to the ROM's path with a .sym extension, as chip8 asm writes
+    #[arg(long, value_name = "PATH")]
+    symbols: Option<PathBuf>,
+    /// How many times to scale the display up in PNGs and GIFs
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_CAPTURE_SCALE)]
+    capture_scale: u32,
+    /// Record the display to an animated GIF as the ROM runs, headless too
+    #[arg(long, value_name = "PATH")]
+    record_gif: Option<PathBuf>,
+    /// How many frames to leave out of --record-gif after each one recorded. Most viewers can't
+    /// play GIFs at 60 frames a second, so the default of 1 records 30
+    #[arg(long, value_name = "N", default_value_t = 1)]
+    gif_skip: u32,
+    /// Record video of the whole run, headless too: WebM with sound through ffmpeg, or a silent
+    /// APNG for a path ending .png or .apng. Shift+F12 starts and stops recordings by hand
+    #[cfg(feature = "video")]
+    #[arg(long, value_name = "PATH")]
+    record_video: Option<PathBuf>,
+    /// What Shift+F12 records video as: webm, through ffmpeg, or apng
+    #[cfg(feature = "video")]
+    #[arg(long, value_name = "FORMAT", default_value_t = VideoFormat::default())]
+    video_format: VideoFormat,
+    /// How many frames of history to keep for rewinding with Backspace. 0 turns rewinding off
+    #[arg(long, value_name = "FRAMES", default_value_t = chip8::DEFAULT_REWIND_FRAMES)]
+    rewind: usize,
+    /// How many of the last instructions executed to keep for the F4 trace panel, which F9 saves
+    /// to a file next to the quick save state. 0 turns tracing off
+    #[arg(long, value_name = "N", default_value_t = chip8::DEFAULT_TRACE_LEN)]
+    trace_len: usize,
+    /// Count every instruction executed and time it, by address and opcode, with the hot loops
+    /// shown in the F10 panel
+    #[arg(long)]
+    profile: bool,
+    /// Write the profile to a JSON file on exiting. Implies --profile
+    #[arg(long, value_name = "PATH")]
+    profile_out: Option<PathBuf>,
+    /// Write a map of which ROM bytes ran as code and which were read as data to a file on
+    /// exiting, for `chip8 disasm --coverage`. F11 shows the same in the memory panel
+    #[arg(long, value_name = "PATH")]
+    coverage_out: Option<PathBuf>,
+    /// Record the keypad at every frame to a movie file that --playback can replay exactly. Pausing,
+    /// stepping, rewinding and loading states are disabled while recording
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["playback", "headless"])]
+    record: Option<PathBuf>,
+    /// Play back a movie made with --record instead of reading the keypad from the keyboard, then
+    /// carry on as normal. In headless mode, the whole movie is run instead of --frames
+    #[arg(long, value_name = "PATH")]
+    playback: Option<PathBuf>,
+    /// Reload the ROM whenever its file changes, e.g. after reassembling it. F8 reloads it by hand
+    #[arg(long, conflicts_with_all = ["builtin", "headless"])]
+    watch: bool,
+    /// How to make the sound: cpal plays a tone, terminal rings the terminal bell, visual flashes
+    /// the display, and off is silent. cpal falls back to visual where there's nothing to play the
+    /// tone on [default: cpal]
+    #[arg(long, value_name = "MODE")]
+    audio: Option<AudioMode>,
+    /// Disable sound entirely, the same as --audio off
+    #[arg(long, default_value_t = false)]
+    mute: bool,
+    /// Beep volume, from 0.0 to 1.0 [default: 0.25]
+    #[arg(long)]
+    volume: Option<f32>,
+    /// Pause while the terminal doesn't have focus, in terminals that say when it changes
+    #[arg(long, default_value_t = false)]
+    pause_on_focus_loss: bool,
+    /// Leave the terminal's window title alone, rather than setting it to the ROM's name
     #[arg(long, default_value_t = false)]
-    shiftquirk: bool,
+    no_window_title: bool,
+    /// Where to read settings from instead of ~/.config/chip8-rs/config.toml. Flags given on the
+    /// command line override the file
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
 }
 
-/// https://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.2
-struct Registers {
-    pub v: [u8; 16],
-    pub i: u16,
-    pub delay: Arc<AtomicU8>,
-    pub sound: Arc<AtomicU8>,
+#[derive(clap::Subcommand)]
+enum Tool {
+    /// Run a ROM. This is what happens without a subcommand, too
+    Run(Box<RunArgs>),
+    /// Assemble a source file into a ROM, and write its labels to a .sym file beside it for the
+    /// debugger. Octo source (.8o) is compiled as Octo
+    Asm {
+        source: PathBuf,
+        /// Where to write the ROM. Defaults to the source path with a .ch8 extension
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Print an annotated disassembly of a ROM
+    Disasm {
+        rom: PathBuf,
+        /// Which CHIP-8 dialect to decode: chip8, chip48, schip, xochip or megachip
+        #[arg(long, default_value_t = Variant::Chip8)]
+        variant: Variant,
+        /// A coverage map from running the ROM with --coverage-out, to tell code from data by
+        #[arg(long, value_name = "PATH")]
+        coverage: Option<PathBuf>,
+        /// Where the ROM is loaded: chip8, eti660 or a hex address
+        #[arg(long, value_name = "ADDR", default_value_t = LoadAddress::CHIP8)]
+        load_addr: LoadAddress,
+    },
+    /// Print what's known about a ROM: its size and hash, the variant it needs, which
+    /// instructions it uses, and what the ROM database says about it
+    Info { rom: PathBuf },
+    /// Run the bundled test ROMs and check each one's final screen against a known-good one
+    Test {
+        /// Which tests to run, by name. Runs all of them if none are given
+        names: Vec<String>,
+    },
+    /// Run a ROM headlessly and print its display as text, for bug reports: a grid of # and . as
+    /// in golden files, or drawn with a render style, in colour with --ansi
+    Text {
+        rom: PathBuf,
+        /// How many 60Hz frames to run first
+        #[arg(long, default_value_t = 60)]
+        frames: u32,
+        /// Which CHIP-8 dialect to run it as [default: detected from the ROM]
+        #[arg(long)]
+        variant: Option<Variant>,
+        /// Draw it with block, halfblock or braille characters instead of a grid
+        #[arg(long, value_name = "STYLE")]
+        render_style: Option<RenderStyle>,
+        /// Colour it in with ANSI escapes, in the default theme or another
+        #[arg(long, value_name = "THEME", num_args = 0..=1, default_missing_value = "default")]
+        ansi: Option<Theme>,
+        /// Write it to a file instead of printing it
+        #[arg(short, long, value_name = "PATH")]
+        output: Option<PathBuf>,
+    },
+    /// Run a CHIP-8 ROM on this emulator and on the library's reference interpreter side by
+    /// side, and report the first place they disagree
+    Diff {
+        rom: PathBuf,
+        /// How many frames to run with no keys pressed
+        #[arg(long, default_value_t = 600)]
+        frames: u32,
+        /// Run with the keys, seed and speed recorded in a movie instead
+        #[arg(long, value_name = "PATH")]
+        playback: Option<PathBuf>,
+        /// Seed for both random number generators
+        #[arg(long, default_value_t = 1)]
+        seed: u32,
+    },
 }
 
-impl Registers {
-    pub fn new() -> Self {
-        Self {
-            v: [0; 16],
-            i: 0,
-            delay: Arc::new(AtomicU8::new(0)),
-            sound: Arc::new(AtomicU8::new(0)),
+impl RunArgs {
+    fn resolve_quirks(&self) -> Quirks {
+        let mut quirks = Quirks::preset(self.quirks.unwrap_or(self.variant()));
+        if let Some(shift) = self.shiftquirk {
+            quirks.shift = shift;
+        }
+        if let Some(jump) = self.jumpquirk {
+            quirks.jump = jump;
+        }
+        if let Some(loadstore) = self.loadstorequirk {
+            quirks.loadstore = loadstore;
+        }
+        if let Some(loadstore_x) = self.loadstorexquirk {
+            quirks.loadstore_x = loadstore_x;
+        }
+        if let Some(display_wait) = self.displaywaitquirk {
+            quirks.display_wait = display_wait;
+        }
+        if let Some(clip) = self.clipquirk {
+            quirks.clip = clip;
+        }
+        if let Some(vf_reset) = self.vfresetquirk {
+            quirks.vf_reset = vf_reset;
+        }
+        quirks
+    }
+
+    fn resolve_theme(&self) -> Theme {
+        let theme = self.theme.unwrap_or_default();
+        Theme {
+            fg: self.fg.unwrap_or(theme.fg),
+            bg: self.bg.unwrap_or(theme.bg),
+            fg2: self.fg2.unwrap_or(theme.fg2),
+            blend: self.blend.unwrap_or(theme.blend),
+        }
+    }
+
+    fn variant(&self) -> Variant {
+        self.variant.unwrap_or_default()
+    }
+
+    /// Takes anything that wasn't given on the command line from `settings`.
+    fn apply(&mut self, settings: &Settings) {
+        self.variant = self.variant.or(settings.variant);
+        self.quirks = self.quirks.or(settings.quirks.profile);
+        self.shiftquirk = self.shiftquirk.or(settings.quirks.shift);
+        self.jumpquirk = self.jumpquirk.or(settings.quirks.jump);
+        self.loadstorequirk = self.loadstorequirk.or(settings.quirks.loadstore);
+        self.loadstorexquirk = self.loadstorexquirk.or(settings.quirks.loadstore_x);
+        self.displaywaitquirk = self.displaywaitquirk.or(settings.quirks.display_wait);
+        self.clipquirk = self.clipquirk.or(settings.quirks.clip);
+        self.vfresetquirk = self.vfresetquirk.or(settings.quirks.vf_reset);
+        self.memory_policy = self.memory_policy.or(settings.memory_policy);
+        self.load_addr = self.load_addr.or(settings.load_addr);
+        self.write_protect = self.write_protect.or(settings.write_protect);
+        self.ips = self.ips.or(settings.ips);
+        self.timing = self.timing.or(settings.timing);
+        self.keymap = self.keymap.or(settings.keymap);
+        self.render_style = self.render_style.or(settings.render_style);
+        self.theme = self.theme.or(settings.theme);
+        self.fg = self.fg.or(settings.fg);
+        self.bg = self.bg.or(settings.bg);
+        self.fg2 = self.fg2.or(settings.fg2);
+        self.blend = self.blend.or(settings.blend);
+        self.phosphor = self.phosphor.or(settings.phosphor);
+        self.audio = self.audio.or(settings.audio);
+        self.mute |= settings.mute.unwrap_or(false);
+        self.pause_on_focus_loss |= settings.pause_on_focus_loss.unwrap_or(false);
+        self.no_window_title |= !settings.window_title.unwrap_or(true);
+        self.volume = self.volume.or(settings.volume);
+    }
+
+    /// How to run `cart`, read from `rom`, with these settings: what it's run as, the memory to
+    /// load it into and the labels to use.
+    fn rom_setup(&self, rom: &Path, cart: &Cartridge) -> RomSetup {
+        let mut memory = Memory::with_size(self.variant().memory_size());
+        memory.set_load_addr(self.load_addr.unwrap_or_default());
+        memory.set_policy(self.memory_policy.unwrap_or_default());
+        memory.set_write_protection(self.write_protect.unwrap_or_default());
+        for &region in &self.protected {
+            memory.protect(region);
+        }
+        RomSetup {
+            variant: self.variant(),
+            quirks: self.resolve_quirks(),
+            ips: self.ips.unwrap_or(chip8::DEFAULT_IPS),
+            timing: self.timing.unwrap_or_default(),
+            memory,
+            // Octo source brings its own labels, which are newer than any .sym file beside it.
+            symbols: self.symbols.clone().or_else(|| {
+                Some(rom.with_extension("sym"))
+                    .filter(|path| cart.symbols.is_empty() && path.exists())
+            }),
         }
     }
+
+    /// The config file: the one from --config if it was given, or the one in the usual place if
+    /// there's a file there, or an empty one.
+    fn load_config(&self) -> anyhow::Result<Config> {
+        let path = match &self.config {
+            Some(path) => path.clone(),
+            None => match Config::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        Config::load(&path)
+            .map_err(|err| anyhow::anyhow!("couldn't use {}: {}", path.display(), err))
+    }
 }
 
-/// Everything is taken from http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#2.1
 fn main() {
-    logger::init("chip8.log").unwrap();
+    if let Err(err) = logger::init("chip8.log") {
+        eprintln!("Error: {:#}", err);
+        std::process::exit(1);
+    }
 
     let cli = Cli::parse();
-    let gameshell = GameShell::new(cli.rom, cli.shiftquirk);
-
-    // Set up memory
-    let mut memory = Memory::new();
+    TerminalGuard::install_panic_hook();
+    match cli.tool {
+        Some(Tool::Run(args)) => run(*args),
+        Some(tool) => {
+            if let Err(err) = run_tool(&tool) {
+                eprintln!("Error: {:#}", err);
+                std::process::exit(1);
+            }
+        }
+        None => run(cli.run),
+    }
+}
 
-    // Set up registers
-    let mut registers = Registers::new();
-    let mut pc: u16 = 0x200;
-    let mut sp: u8 = 0;
-    // TODO: Make mut and implement stack
-    let mut stack: [u16; 16] = [0; 16];
+#[allow(deprecated)]
+fn run(mut args: RunArgs) {
+    let config = match args.load_config() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    if args.rom.is_none() && args.builtin.is_none() && !pick_rom(&mut args, &config) {
+        return;
+    }
+    let builtin = args.builtin.as_deref().map(|name| {
+        BuiltinRom::find(name).unwrap_or_else(|| {
+            let names: Vec<_> = BUILTIN_ROMS.iter().map(|rom| rom.name).collect();
+            eprintln!(
+                "Error: there's no built-in ROM called '{}' (expected {})",
+                name,
+                names.join(", ")
+            );
+            std::process::exit(1);
+        })
+    });
+    let (rom, cart) = match (builtin, args.rom.clone()) {
+        (Some(builtin), _) => (
+            PathBuf::from(format!("{}.ch8", builtin.name)),
+            Cartridge::plain(builtin.rom.to_vec()),
+        ),
+        (None, Some(rom)) => match read_rom(&rom) {
+            // There's no file to reload, so it's named like a built-in ROM.
+            Ok(cart) if is_stdin(&rom) => (PathBuf::from("stdin.ch8"), cart),
+            Ok(cart) => (rom, cart),
+            Err(err) => {
+                eprintln!("Error: couldn't read {}: {:#}", rom.display(), err);
+                std::process::exit(1);
+            }
+        },
+        (None, None) => unreachable!("there's always a ROM once one's been picked"),
+    };
+    let piped = builtin.is_none() && args.rom.as_deref().is_some_and(is_stdin);
+    let bytes = &cart.rom;
+    let known = RomInfo::lookup(&chip8::rom_sha1(bytes));
+    // Flags given on the command line go for every ROM opened later too.
+    let flags = args.clone();
+    args.apply(&rom_settings(&config, &rom, &cart));
+    let setup = args.rom_setup(&rom, &cart);
+    let gameshell = GameShell::new(rom, setup.quirks);
 
-    // Set up display
-    let rom_title = gameshell.print_rom_title();
-    let display = Arc::new(RwLock::new([false; 64 * 32]));
+    // Set up memory
+    let mut memory = setup.memory;
+    if let Err(err) = memory.load_rom_bytes(bytes) {
+        eprintln!("Error: couldn't load {}: {}", rom_name(&gameshell), err);
+        std::process::exit(1);
+    }
+    info!("Load ROM: {} ({} bytes)", rom_name(&gameshell), bytes.len());
 
-    memory.load_rom(gameshell.rom_path()).unwrap();
+    // Set up keypad
+    let keypad = Arc::new(Keypad::new());
 
-    // Main program loop / CPU
-    let mainkill = gameshell.clone_killsignal();
-    let mut previous = std::time::Instant::now();
-    let mut lag = std::time::Duration::from_millis(0);
-    /// 60Hz
-    const FRAMERATE: Duration = std::time::Duration::from_millis(16);
-
-    stdout().execute(EnterAlternateScreen).unwrap();
-    enable_raw_mode().unwrap();
-
-    let backend = CrosstermBackend::new(stdout());
-    let mut terminal = Terminal::new(backend).unwrap();
-    terminal.clear().unwrap();
-
-    loop {
-        let current = std::time::Instant::now();
-        let elapsed = current - previous;
-        previous = current;
-        lag += elapsed;
-
-        if mainkill.received() {
-            break;
-        }
-
-        // If read ctrl+c from crossterm, send kill signal
-        match event::poll(std::time::Duration::from_millis(0)) {
-            Ok(true) => {
-                if let Ok(evt) = event::read() {
-                    match evt {
-                        event::Event::Key(event::KeyEvent {
-                            code: event::KeyCode::Char('c'),
-                            modifiers: event::KeyModifiers::CONTROL,
-                            ..
-                        }) => {
-                            break;
-                        }
-                        // TODO: Get other keyboard inputs working
-                        _ => {}
-                    }
-                }
+    // Set up CPU
+    let mut cpu = CPU::new(memory, Arc::clone(&keypad), setup.quirks);
+    cpu.set_ips(setup.ips);
+    cpu.set_timing(setup.timing);
+    cpu.set_jit(args.jit);
+    cpu.set_trace_len(args.trace_len);
+    cpu.set_profiling(args.profile || args.profile_out.is_some());
+    cpu.set_variant(setup.variant);
+    for &breakpoint in &args.breakpoints {
+        cpu.add_breakpoint_on(breakpoint);
+    }
+    if !cart.symbols.is_empty() {
+        cpu.set_symbols(cart.symbols.clone());
+    }
+    if let Some(path) = setup.symbols {
+        match Symbols::load(&path) {
+            Ok(symbols) => {
+                info!("Loaded {} symbols from {}", symbols.len(), path.display());
+                cpu.set_symbols(symbols);
             }
-            _ => {}
-        }
-
-        while lag >= FRAMERATE {
-            update(
-                &mut memory,
-                &mut pc,
-                &display,
-                &mut sp,
-                &mut stack,
-                &mut registers,
-                cli.shiftquirk,
+            Err(err) => {
+                eprintln!("Error: couldn't load {}: {:#}", path.display(), err);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.load_memory {
+        let loaded = MemoryImage::load(path).and_then(|image| {
+            cpu.load_memory_image(&image)
+                .map_err(|err| anyhow::anyhow!("it runs past the end of memory at {:#x}", err.addr))
+        });
+        if let Err(err) = loaded {
+            eprintln!("Error: couldn't load {}: {:#}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+    let rom_title = match known {
+        Some(info) => {
+            info!(
+                "Recognised {} as {} by {}",
+                gameshell.print_rom_title(),
+                info.title,
+                info.author
             );
-            lag -= FRAMERATE
+            format!("{} by {}", info.title, info.author)
         }
+        None => match (&cart.title, &cart.author) {
+            (Some(title), Some(author)) => format!("{} by {}", title, author),
+            (Some(title), None) => title.clone(),
+            _ => builtin.map_or_else(|| gameshell.print_rom_title(), |rom| rom.title.to_string()),
+        },
+    };
+
+    #[cfg(feature = "lua")]
+    let script = args.script.as_ref().map(|path| {
+        chip8::script::Script::load(path, &mut cpu).unwrap_or_else(|err| {
+            eprintln!("Error: couldn't run {}: {}", path.display(), err);
+            std::process::exit(1);
+        })
+    });
 
-        let mut display_str = String::new();
-        let display = display.read().unwrap();
-        for (i, &pixel) in display.iter().enumerate() {
-            display_str.push(if pixel { '█' } else { ' ' });
-            if i % 64 == 63 {
-                display_str.push('\n');
+    #[cfg(feature = "gdb")]
+    if let Some(addr) = &args.gdb {
+        use chip8::gdb::GdbServer;
+        eprintln!("Waiting for gdb on {}", addr);
+        let mut server = GdbServer::new(cpu);
+        if let Err(err) =
+            GdbServer::accept(listen_addr(addr)).and_then(|stream| server.serve(stream))
+        {
+            eprintln!("Error: {:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let theme = args.resolve_theme();
+    let gif = args.record_gif.as_ref().map(|path| {
+        let size = cpu.max_display_size();
+        GifRecorder::create(path, size, theme, args.capture_scale, args.gif_skip).unwrap_or_else(
+            |err| {
+                eprintln!("Error: couldn't write {}: {}", path.display(), err);
+                std::process::exit(1);
+            },
+        )
+    });
+    #[cfg(feature = "video")]
+    let video = args.record_video.as_ref().map(|path| {
+        let size = cpu.max_display_size();
+        VideoRecorder::create(path, size, theme, args.capture_scale).unwrap_or_else(|err| {
+            eprintln!("Error: couldn't record to {}: {}", path.display(), err);
+            std::process::exit(1);
+        })
+    });
+    if args.headless {
+        let mut runner = HeadlessRunner::new(cpu);
+        if let Some(gif) = gif {
+            runner.record_gif(gif);
+        }
+        #[cfg(feature = "video")]
+        if let Some(video) = video {
+            runner.record_video(video);
+        }
+        #[cfg(feature = "lua")]
+        if let Some(script) = script {
+            runner.set_script(script);
+        }
+        if let Err(err) = run_headless(runner, &args) {
+            eprintln!("Error: {:#}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if builtin.is_none() && !piped {
+        remember(gameshell.rom_path());
+    }
+    let netplay = match (&args.netplay_host, &args.netplay_join) {
+        (Some(addr), _) => {
+            eprintln!("Waiting for the second player on {}", addr);
+            Some(Netplay::host(
+                listen_addr(addr),
+                &mut cpu,
+                args.netplay_keys,
+            ))
+        }
+        (None, Some(addr)) => Some(Netplay::join(listen_addr(addr), &mut cpu)),
+        (None, None) => None,
+    }
+    .map(|netplay| {
+        netplay.unwrap_or_else(|err| {
+            eprintln!("Error: couldn't start netplay: {}", err);
+            std::process::exit(1);
+        })
+    });
+    let mut run_loop = RunLoop::new(cpu);
+    run_loop.set_capture(theme, args.capture_scale);
+    if let Some(gif) = gif {
+        run_loop.record_gif(gif);
+    }
+    #[cfg(feature = "video")]
+    {
+        run_loop.set_video_format(args.video_format);
+        if let Some(video) = video {
+            run_loop.record_video(video);
+        }
+    }
+    if let Some(netplay) = netplay {
+        run_loop.play_netplay(netplay);
+    }
+    #[cfg(feature = "lua")]
+    if let Some(script) = script {
+        run_loop.set_script(script);
+    }
+    run_loop.debugger_mut().set_rewind_depth(args.rewind);
+    run_loop.save_crash_reports(PathBuf::from("."));
+    // Built-in, piped and downloaded ROMs keep their states in the working directory.
+    run_loop.set_state_path(
+        if builtin.is_some() || piped || is_url(gameshell.rom_path()) {
+            Path::new(&rom_name(&gameshell)).with_extension("state")
+        } else {
+            gameshell.state_path()
+        },
+    );
+    run_loop.set_rom_setup(move |rom, cart| {
+        let mut args = flags.clone();
+        // --symbols only goes for the ROM it was given with.
+        args.symbols = None;
+        args.apply(&rom_settings(&config, rom, cart));
+        args.rom_setup(rom, cart)
+    });
+    if args.record.is_some() {
+        run_loop.record();
+    }
+    if let Some(path) = &args.playback {
+        match Movie::load(path) {
+            Ok(movie) => run_loop.play(movie),
+            Err(err) => error!("Couldn't load {}: {}", path.display(), err),
+        }
+    }
+
+    // Main program loop / CPU
+    let bus = gameshell.control_bus();
+    run_loop.listen(&bus);
+    // Keeps watching until the end of the program.
+    let mut _watcher = None;
+    if builtin.is_none() && !piped && !is_url(gameshell.rom_path()) {
+        if args.watch {
+            match watch(gameshell.rom_path(), run_loop.input_sender()) {
+                Ok(watcher) => _watcher = Some(watcher),
+                Err(err) => error!("Couldn't watch {}: {}", rom_name(&gameshell), err),
             }
         }
-        terminal
-            .draw(|f| {
-                f.render_widget(Block::new().on_black(), f.size());
-
-                let layout = Layout::default()
-                    .direction(Direction::Vertical)
-                    .constraints(vec![
-                        Constraint::Length(3),
-                        Constraint::Length(32),
-                        Constraint::Fill(1),
-                    ])
-                    .split(f.size());
-
-                let title = layout[0];
-                f.render_widget(
-                    Paragraph::new(format!("[Chip8-RS] {}", rom_title))
-                        .white()
-                        .centered()
-                        .block(Block::bordered()),
-                    title,
-                );
-
-                let emu_layout = Layout::default()
-                    .direction(Direction::Horizontal)
-                    .constraints(vec![
-                        Constraint::Fill(1),
-                        Constraint::Length(64),
-                        Constraint::Fill(1),
-                    ])
-                    .split(layout[1]);
-                let emu = emu_layout[1];
-                f.render_widget(Paragraph::new(display_str).light_blue().on_black(), emu);
-            })
-            .unwrap();
-
-        std::thread::sleep(FRAMERATE - lag);
+        run_loop.set_game(gameshell);
+    }
+    #[cfg(feature = "remote")]
+    if let Some(addr) = &args.remote {
+        let mut server =
+            chip8::remote::RemoteServer::bind(listen_addr(addr)).unwrap_or_else(|err| {
+                eprintln!("Error: couldn't listen on {}: {}", addr, err);
+                std::process::exit(1);
+            });
+        eprintln!("Serving on ws://{}; Ctrl-C stops", server.local_addr());
+        run_loop.run(&mut server);
+        save_profile(run_loop.cpu(), &args);
+        save_coverage(run_loop.cpu(), &args);
+        save_memory(run_loop.cpu(), &args);
+        save_gif(run_loop.take_gif(), &args);
+        #[cfg(feature = "video")]
+        save_video(run_loop.take_video());
+        if let Err(err) = dump(run_loop.cpu().framebuffer(), &args) {
+            eprintln!("Error: {:#}", err);
+        }
+        return;
     }
+    let audio = if args.mute {
+        AudioMode::Off
+    } else {
+        args.audio.unwrap_or_default()
+    };
+    let beeper = audio_beeper(audio, args.volume.unwrap_or(DEFAULT_VOLUME));
+    let phosphor = || {
+        args.phosphor
+            .filter(|&frames| frames > 0)
+            .map(Phosphor::new)
+    };
+    // The terminal is only taken over once nothing left can exit the process, which would skip
+    // giving it back.
+    let run_tui = |run_loop: RunLoop, beeper| {
+        let tui = TerminalGuard::enter(true).and_then(|guard| {
+            if !args.no_window_title {
+                guard.set_title(&format!("[Chip8-RS] {}", rom_title));
+            }
+            Tui::new(
+                rom_title.clone(),
+                args.keymap.unwrap_or_default(),
+                args.render_style,
+                theme,
+                phosphor(),
+                beeper,
+                guard,
+            )
+        });
+        match tui {
+            Ok(mut tui) => {
+                tui.set_pause_on_focus_loss(args.pause_on_focus_loss);
+                run_loop.run_threaded(&mut tui)
+            }
+            Err(err) => {
+                eprintln!("Error: couldn't take over the terminal: {:#}", err);
+                std::process::exit(1);
+            }
+        }
+    };
+    #[cfg(feature = "gui")]
+    let mut run_loop = if args.gui {
+        match gui::Gui::new(
+            rom_title.clone(),
+            args.keymap.unwrap_or_default(),
+            theme,
+            phosphor(),
+            beeper,
+        ) {
+            Ok(mut gui) => run_loop.run_threaded(&mut gui),
+            Err(err) => {
+                eprintln!("Error: {:#}", err);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        run_tui(run_loop, beeper)
+    };
+    #[cfg(not(feature = "gui"))]
+    let mut run_loop = run_tui(run_loop, beeper);
 
     // end program
-    stdout().execute(LeaveAlternateScreen).unwrap();
-    disable_raw_mode().unwrap();
-    mainkill.send();
+    bus.send(ControlMessage::Kill);
     println!();
+    if let (Some(movie), Some(path)) = (run_loop.recording(), &args.record) {
+        match movie.save(path) {
+            Ok(()) => println!("Recorded {} frames to {}", movie.len(), path.display()),
+            Err(err) => eprintln!("Couldn't save {}: {}", path.display(), err),
+        }
+    }
+    save_profile(run_loop.cpu(), &args);
+    save_coverage(run_loop.cpu(), &args);
+    save_memory(run_loop.cpu(), &args);
+    save_gif(run_loop.take_gif(), &args);
+    #[cfg(feature = "video")]
+    save_video(run_loop.take_video());
+    if let Err(err) = dump(run_loop.cpu().framebuffer(), &args) {
+        eprintln!("Error: {:#}", err);
+    }
 }
 
-fn update(
-    memory: &mut Memory,
-    pc: &mut u16,
-    display: &Arc<RwLock<[bool; 64 * 32]>>,
-    sp: &mut u8,
-    stack: &mut [u16; 16],
-    registers: &mut Registers,
-    shiftquirk: bool,
-) {
-    // NOTE: I think this should happen *before* an opcode update, as if the opcode sets the delay to
-    // 8, we do not want to then decrement it immediately to 7, and instead wait until the next loop...
-    // but have to check.
-    let vdelay = registers.delay.load(Ordering::Acquire);
-    if vdelay > 0 {
-        registers.delay.store(vdelay - 1, Ordering::Release);
-    }
-
-    let vsound = registers.sound.load(Ordering::Acquire);
-    if vsound > 0 {
-        // TODO: Make actual sound
-        registers.sound.store(vsound - 1, Ordering::Release);
-    }
-
-    let opcode = Cursor::new(&memory[*pc as usize..])
-        .read_u16::<BigEndian>()
-        .unwrap();
-    info!("{:04x}: {:04x}", pc, opcode);
-    *pc += 2;
-    match opcode {
-        // clear the screen
-        0x00e0 => {
-            let mut display = display.write().unwrap();
-            for pixel in display.iter_mut() {
-                *pixel = false;
-            }
+/// Finishes the GIF being recorded to --record-gif, if one was.
+fn save_gif(gif: Option<GifRecorder>, args: &RunArgs) {
+    let (Some(gif), Some(path)) = (gif, &args.record_gif) else {
+        return;
+    };
+    match gif.finish() {
+        Ok(frames) => println!("Recorded {} frames to {}", frames, path.display()),
+        Err(err) => eprintln!("Couldn't save {}: {}", path.display(), err),
+    }
+}
+
+/// Finishes the video being recorded, from --record-video or Shift+F12, if there is one.
+#[cfg(feature = "video")]
+fn save_video(video: Option<VideoRecorder>) {
+    let Some(video) = video else {
+        return;
+    };
+    let path = video.path().to_path_buf();
+    match video.finish() {
+        Ok(frames) => println!("Recorded {} frames to {}", frames, path.display()),
+        Err(err) => eprintln!("Couldn't save {}: {}", path.display(), err),
+    }
+}
+
+/// Writes the display to --dump, if it was given.
+fn dump(fb: &FrameBuffer, args: &RunArgs) -> anyhow::Result<()> {
+    match &args.dump {
+        Some(path) if path.as_os_str() == "-" => print!("{}", fb.to_text()),
+        Some(path) if path.extension().is_some_and(|ext| ext == "png") => {
+            capture::write_png(fb, args.resolve_theme(), args.capture_scale, path)?
+        }
+        Some(path) if path.extension().is_some_and(|ext| ext == "ans") => {
+            let style = args.render_style.unwrap_or_default();
+            std::fs::write(
+                path,
+                capture::text_art(fb, style, Some(args.resolve_theme())),
+            )?
         }
-        // return from subroutine
-        0x00ee => {
-            *sp -= 1;
-            *pc = stack[*sp as usize];
-        }
-        // 0x1nnn - jump to address nnn
-        0x1000..=0x1fff => {
-            *pc = opcode & 0x0fff;
-        }
-        // 2nnn - call addr
-        // call subroutine at nnn.
-        // the interpreter increments the stack pointer, then puts the current pc on the top of the stack. the pc is then set to nnn.
-        0x2000..=0x2fff => {
-            stack[*sp as usize] = *pc;
-            *sp += 1;
-            *pc = opcode & 0x0fff;
-        }
-        // 3xkk - se vx, byte
-        // skip next instruction if vx = kk.
-        // the interpreter compares register vx to kk, and if they are equal, increments the program counter by 2.
-        0x3000..=0x3fff => {
-            let x = (opcode & 0x0f00) >> 8;
-            let kk = (opcode & 0x00ff) as u8;
-            if registers.v[x as usize] == kk {
-                *pc += 2;
+        Some(path) => std::fs::write(path, fb.to_text())?,
+        None => {}
+    }
+    Ok(())
+}
+
+/// Writes a memory image to --dump-memory, if it was given.
+fn save_memory(cpu: &CPU, args: &RunArgs) {
+    let Some(path) = &args.dump_memory else {
+        return;
+    };
+    if let Err(err) = cpu.memory_image().save(path) {
+        eprintln!("Couldn't save {}: {}", path.display(), err);
+    }
+}
+
+/// Writes the profile to --profile-out, if it was given.
+fn save_profile(cpu: &CPU, args: &RunArgs) {
+    let Some(path) = &args.profile_out else {
+        return;
+    };
+    let report = cpu.profiler().report(cpu.variant());
+    match report.save(path) {
+        Ok(()) => println!(
+            "Profiled {} instructions to {}",
+            report.total.count,
+            path.display()
+        ),
+        Err(err) => eprintln!("Couldn't save {}: {}", path.display(), err),
+    }
+}
+
+fn run_tool(tool: &Tool) -> anyhow::Result<()> {
+    match tool {
+        Tool::Asm { source, output } => {
+            let source_text = std::fs::read_to_string(source)?;
+            let (rom, symbols) = if chip8::octo::is_source(source) {
+                chip8::octo::compile(&source_text)?
+            } else {
+                chip8::asm::assemble_with_symbols(&source_text)?
+            };
+            let output = output
+                .clone()
+                .unwrap_or_else(|| source.with_extension("ch8"));
+            std::fs::write(&output, &rom)?;
+            println!("Wrote {} bytes to {}", rom.len(), output.display());
+            // The labels go next to the ROM, where running it picks them up.
+            if !symbols.is_empty() {
+                let path = output.with_extension("sym");
+                symbols.save(&path)?;
+                println!("Wrote {} labels to {}", symbols.len(), path.display());
             }
         }
-        //4xkk - sne vx, byte
-        // skip next instruction if vx != kk.
-        // the interpreter compares register vx to kk, and if they are not equal, increments the program counter by 2.
-        0x4000..=0x4fff => {
-            let x = (opcode & 0x0f00) >> 8;
-            let kk = (opcode & 0x00ff) as u8;
-            if registers.v[x as usize] != kk {
-                *pc += 2;
+        Tool::Run(_) => unreachable!("main runs ROMs itself"),
+        Tool::Disasm {
+            rom,
+            variant,
+            coverage,
+            load_addr,
+        } => {
+            let bytes = read_rom(rom)?.rom;
+            let coverage = match coverage {
+                Some(path) => Coverage::load(path)
+                    .map_err(|err| anyhow::anyhow!("couldn't read {}: {}", path.display(), err))?,
+                None => Coverage::new(),
+            };
+            print!(
+                "{}",
+                Disassembly::with_coverage(&bytes, load_addr.0, *variant, &coverage)
+            );
+        }
+        Tool::Info { rom } => {
+            let bytes = read_rom(rom)?.rom;
+            let sha1 = chip8::rom_sha1(&bytes);
+            let analysis = Analysis::new(&bytes);
+            println!("File      {}", rom.display());
+            println!("Size      {} bytes", bytes.len());
+            println!("SHA-1     {}", sha1);
+            if let Some(info) = RomInfo::lookup(&sha1) {
+                println!("Title     {}", info.title);
+                println!("Author    {}", info.author);
+                println!("Variant   {} (detected {})", info.variant, analysis.variant);
+                if let Some(ips) = info.ips {
+                    println!("Speed     {} ips", ips);
+                }
+            } else {
+                println!("Variant   {} (detected)", analysis.variant);
+            }
+            if chip8::is_two_page(&bytes) {
+                println!("Display   64x64, for the HIRES CHIP-8 interpreter");
+            }
+            println!(
+                "Contents  {} bytes of reachable code, {} of data",
+                analysis.code_bytes, analysis.data_bytes
+            );
+            println!();
+            let mut histogram: Vec<_> = analysis.histogram.into_iter().collect();
+            histogram.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            for (pattern, count) in histogram {
+                println!("  {}  {:>5}", pattern, count);
             }
         }
-        // 5xy0 - se vx, vy
-        // skip next instruction if vx = vy.
-        // the interpreter compares register vx to register vy, and if they are equal, increments the program counter by 2.
-        0x5000..=0x5fff => {
-            let x = (opcode & 0x0f00) >> 8;
-            let y = (opcode & 0x00f0) >> 4;
-            if x == y {
-                *pc += 2;
+        Tool::Test { names } => run_tests(names)?,
+        Tool::Text {
+            rom,
+            frames,
+            variant,
+            render_style,
+            ansi,
+            output,
+        } => {
+            let bytes = read_rom(rom)?.rom;
+            let variant = variant.unwrap_or_else(|| Analysis::new(&bytes).variant);
+            let mut memory = Memory::with_size(variant.memory_size());
+            memory.load_rom_bytes(&bytes)?;
+            let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::preset(variant));
+            cpu.set_variant(variant);
+            let fb = HeadlessRunner::new(cpu).run_frames(*frames)?;
+            let text = match (render_style, ansi) {
+                (None, None) => fb.to_text(),
+                (style, theme) => capture::text_art(&fb, style.unwrap_or_default(), *theme),
+            };
+            match output {
+                Some(path) => std::fs::write(path, text)?,
+                None => print!("{}", text),
             }
         }
-        // set vx to nn
-        0x6000..=0x6fff => {
-            let x = (opcode & 0x0f00) >> 8;
-            let nn = (opcode & 0x00ff) as u8;
-            registers.v[x as usize] = nn;
-        }
-        0x7000..=0x7fff => {
-            let x = (opcode & 0x0f00) >> 8;
-            let kk = (opcode & 0x00ff) as u8;
-            registers.v[x as usize] = registers.v[x as usize].wrapping_add(kk);
-        }
-        // 8xy0 - ld vx, vy
-        // set vx = vy.
-        // stores the value of register vy in register vx.
-        0x8000..=0x8fff => {
-            let x = (opcode & 0x0f00) >> 8;
-            let y = (opcode & 0x00f0) >> 4;
-            let op = opcode & 0x000f;
-            match op {
-                // ld vx, vy
-                0x0 => {
-                    registers.v[x as usize] = registers.v[y as usize];
-                }
-                // or vx, vy
-                0x1 => {
-                    registers.v[x as usize] |= registers.v[y as usize];
-                }
-                // and vx, vy
-                0x2 => {
-                    registers.v[x as usize] &= registers.v[y as usize];
+        Tool::Diff {
+            rom,
+            frames,
+            playback,
+            seed,
+        } => {
+            let bytes = read_rom(rom)?.rom;
+            let movie = playback.as_ref().map(Movie::load).transpose()?;
+            let seed = movie.as_ref().map_or(*seed, Movie::seed);
+            let mut lockstep = Lockstep::new(&bytes, Quirks::preset(Variant::Chip8), seed)?;
+            let keys = match &movie {
+                Some(movie) => {
+                    lockstep.set_ips(movie.ips());
+                    movie.frames().to_vec()
                 }
-                // xor vx, vy
-                0x3 => {
-                    registers.v[x as usize] ^= registers.v[y as usize];
+                None => vec![0; *frames as usize],
+            };
+            match lockstep.run(keys) {
+                Ok(ran) => println!("No divergence in {} frames", ran),
+                Err(divergence) => {
+                    print!("{}", divergence);
+                    anyhow::bail!("the emulator and the reference disagree");
                 }
-                // add vx, vy
-                0x4 => {
-                    let (res, overflow) =
-                        registers.v[x as usize].overflowing_add(registers.v[y as usize]);
-                    registers.v[x as usize] = res;
-                    registers.v[0xf] = overflow as u8;
-                }
-                // sub vx, vy
-                0x5 => {
-                    let (res, overflow) =
-                        registers.v[x as usize].overflowing_sub(registers.v[y as usize]);
-                    registers.v[x as usize] = res;
-                    // not borrow
-                    registers.v[0xf] = !overflow as u8;
-                }
-                // shr vx {, vy} ... todo will maybe have to revisit this
-                0x6 => {
-                    if !shiftquirk {
-                        registers.v[x as usize] = registers.v[y as usize];
-                    }
-                    let flag = registers.v[x as usize] & 0x1;
-                    registers.v[x as usize] >>= 1;
-                    registers.v[0xf] = flag;
-                }
-                // subn vx, vy
-                0x7 => {
-                    let (res, overflow) =
-                        registers.v[y as usize].overflowing_sub(registers.v[x as usize]);
-                    registers.v[x as usize] = res;
-                    registers.v[0xf] = !overflow as u8;
-                }
-                // shl vx {, vy}
-                0xe => {
-                    if !shiftquirk {
-                        registers.v[x as usize] = registers.v[y as usize];
-                    }
-                    let flag = (registers.v[x as usize] & 0x80) >> 7;
-                    registers.v[x as usize] <<= 1;
-                    registers.v[0xf] = flag;
-                }
-                _ => panic!("unknown opcode instruction {:04x}", opcode),
             }
         }
-        // 9xy0 - sne vx, vy
-        // skip next instruction if vx != vy.
-        // the values of vx and vy are compared, and if they are not equal, the program counter is increased by 2.
-        0x9000..=0x9fff => {
-            let x = (opcode & 0x0f00) >> 8;
-            let y = (opcode & 0x00f0) >> 4;
-            if registers.v[x as usize] != registers.v[y as usize] {
-                *pc += 2;
+    }
+    Ok(())
+}
+
+fn run_tests(names: &[String]) -> anyhow::Result<()> {
+    if let Some(name) = names
+        .iter()
+        .find(|name| !TEST_ROMS.iter().any(|test| test.name == *name))
+    {
+        anyhow::bail!("there's no test called '{}'", name);
+    }
+    let tests: Vec<_> = TEST_ROMS
+        .iter()
+        .filter(|test| names.is_empty() || names.contains(&test.name.to_string()))
+        .collect();
+    let mut failed = 0;
+    for test in &tests {
+        match test.run() {
+            Ok(screen) if screen == test.expected => println!("PASS  {}", test.name),
+            Ok(screen) => {
+                failed += 1;
+                println!("FAIL  {}: the screen doesn't match", test.name);
+                print!("{}", snapshot::diff(test.expected, &screen));
+            }
+            Err(err) => {
+                failed += 1;
+                println!("FAIL  {}: {}", test.name, err);
             }
         }
-        // set i to nnn
-        0xa000..=0xafff => {
-            registers.i = opcode & 0x0fff;
-        }
-        // dxyn - display n-byte sprite starting at memory location i at (vx, vy), set vf = collision.
-        0xd000..=0xdfff => {
-            let x = (opcode & 0x0f00) >> 8;
-            let y = (opcode & 0x00f0) >> 4;
-            let n = opcode & 0x000f;
-            let vx = registers.v[x as usize] as usize;
-            let vy = registers.v[y as usize] as usize;
-            let mut collision = false;
-
-            let mut display = display.write().unwrap();
-            for byteidx in 0..n {
-                let byte = memory[(registers.i + byteidx) as usize];
-                for bitidx in 0..8 {
-                    let bit = (byte >> (7 - bitidx)) & 1;
-                    // wrap around the screen if needed
-                    let idx = (vx + bitidx as usize) % 64 + ((vy + byteidx as usize) % 32) * 64;
-                    if display[idx] && bit == 1 {
-                        collision = true;
-                    }
-                    display[idx] ^= bit == 1;
-                }
+    }
+    if failed > 0 {
+        anyhow::bail!("{} of {} tests failed", failed, tests.len());
+    }
+    println!("All {} tests passed", tests.len());
+    Ok(())
+}
+
+/// Asks which ROM to run on the start screen, and sets it in `args`. Returns false if the user
+/// would rather not run anything.
+fn pick_rom(args: &mut RunArgs, config: &Config) -> bool {
+    #[cfg(feature = "gui")]
+    let windowed = args.gui;
+    #[cfg(not(feature = "gui"))]
+    let windowed = false;
+    if args.headless || windowed {
+        eprintln!("Error: no ROM given; pass a ROM file or --builtin");
+        std::process::exit(1);
+    }
+    let root = args
+        .rom_dir
+        .clone()
+        .or_else(|| config.rom_dir.clone())
+        .unwrap_or_else(|| PathBuf::from("."));
+    let recent = RecentRoms::default_path()
+        .and_then(|path| RecentRoms::load(path).ok())
+        .unwrap_or_default();
+    match picker::pick_rom(root, recent.roms().to_vec()) {
+        Some(Pick::Builtin(rom)) => args.builtin = Some(rom.name.to_string()),
+        Some(Pick::File(path)) => args.rom = Some(path),
+        None => return false,
+    }
+    true
+}
+
+/// Puts `rom` at the top of the start screen's recently run ROMs.
+fn remember(rom: &Path) {
+    let Some(path) = RecentRoms::default_path() else {
+        return;
+    };
+    // URLs don't canonicalize, so they're kept as they are.
+    let rom = std::fs::canonicalize(rom).unwrap_or_else(|_| rom.to_path_buf());
+    let mut recent = RecentRoms::load(&path).unwrap_or_default();
+    recent.add(rom);
+    if let Err(err) = recent.save(&path) {
+        warn!("Couldn't save recent ROMs to {}: {}", path.display(), err);
+    }
+}
+
+fn is_stdin(rom: &Path) -> bool {
+    rom == Path::new("-")
+}
+
+fn is_url(rom: &Path) -> bool {
+    rom.to_str()
+        .is_some_and(|rom| rom.starts_with("http://") || rom.starts_with("https://"))
+}
+
+#[allow(deprecated)]
+fn rom_name(gameshell: &GameShell) -> String {
+    gameshell
+        .rom_path()
+        .file_name()
+        .map_or_else(|| "(Unknown)".into(), |name| name.to_string_lossy().into())
+}
+
+/// Sends `InputEvent::Reload` to `inputs` whenever the ROM at `rom` changes. Editors and
+/// assemblers often replace files rather than write to them, so it's the directory that's watched,
+/// and a change is only sent on once things have been quiet for a moment, so that a ROM isn't
+/// reloaded half written.
+fn watch(rom: &Path, inputs: Sender<InputEvent>) -> notify::Result<RecommendedWatcher> {
+    const QUIET: Duration = Duration::from_millis(100);
+    let rom = std::fs::canonicalize(rom).map_err(notify::Error::io)?;
+    let dir = rom.parent().unwrap_or(Path::new(".")).to_path_buf();
+    let (changes, changed) = crossbeam_channel::unbounded();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            if (event.kind.is_create() || event.kind.is_modify()) && event.paths.contains(&rom) {
+                let _ = changes.send(());
             }
-            registers.v[0xf] = collision as u8;
-        }
-        0xf000..=0xffff => {
-            let x = (opcode & 0x0f00) >> 8;
-            let op = opcode & 0x00ff;
-            match op {
-                // fx07 - ld vx, dt
-                // set vx = delay timer value.
-                // the value of dt is placed into vx.
-                0x07 => {
-                    registers.v[x as usize] = registers.delay.load(Ordering::Acquire);
-                }
-                // fx0a - ld vx, k
-                // wait for a key press, store the value of the key in vx.
-                // all execution stops until a key is pressed, then the value of that key is stored in vx.
-                0x0a => {
-                    // todo: keypress
-                }
-                // fx15 - ld dt, vx
-                // set delay timer = vx.
-                // dt is set equal to the value of vx.
-                0x15 => {
-                    registers
-                        .delay
-                        .store(registers.v[x as usize], Ordering::Relaxed);
-                }
-                // fx18 - ld st, vx
-                // set sound timer = vx.
-                // st is set equal to the value of vx.
-                0x18 => {
-                    registers
-                        .sound
-                        .store(registers.v[x as usize], Ordering::Relaxed);
-                }
-                // fx1e - add i, vx
-                // set i = i + vx.
-                // the values of i and vx are added, and the results are stored in i.
-                0x1e => {
-                    registers.i += registers.v[x as usize] as u16;
-                }
-                // fx29 - ld f, vx
-                // set i = location of sprite for digit vx.
-                // the value of i is set to the location for the hexadecimal sprite corresponding to the value of vx.
-                0x29 => {
-                    // sprites are indexed from 0x0000 in memory
-                    registers.i = registers.v[x as usize] as u16 * 5;
-                }
-                // fx33 - ld b, vx
-                // store bcd representation of vx in memory locations i, i+1, and i+2.
-                // the interpreter takes the decimal value of vx, and places the hundreds digit in memory at location in i,
-                // the tens digit at location i+1, and the ones digit at location i+2.
-                0x33 => {
-                    let vx = registers.v[x as usize];
-                    memory[registers.i as usize] = vx / 100;
-                    memory[(registers.i + 1) as usize] = (vx / 10) % 10;
-                    memory[(registers.i + 2) as usize] = vx % 10;
-                }
-                // fx55 - ld [i], vx
-                // store registers v0 through vx in memory starting at location i.
-                // the interpreter copies the values of registers v0 through vx into memory, starting at the address in i.
-                0x55 => {
-                    for i in 0..=x {
-                        memory[(registers.i + i) as usize] = registers.v[i as usize];
-                    }
-                }
-                // Fx65 - LD Vx, [I]
-                // Read registers V0 through Vx from memory starting at location I.
-                // The interpreter reads values from memory starting at location I into registers V0 through Vx.
-                0x65 => {
-                    for i in 0..=x {
-                        registers.v[i as usize] = memory[(registers.i + i) as usize];
-                    }
-                }
-                op => panic!("Unknown opcode instruction {:04X}", op),
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    std::thread::spawn(move || {
+        while changed.recv().is_ok() {
+            while changed.recv_timeout(QUIET).is_ok() {}
+            if inputs.send(InputEvent::Reload).is_err() {
+                return;
             }
         }
-        op => panic!("Unknown opcode: {:04X}", op),
+    });
+    Ok(watcher)
+}
+
+/// `cart`'s settings: the ROM database's, overridden by its file's, then the config's for `rom`.
+fn rom_settings(config: &Config, rom: &Path, cart: &Cartridge) -> Settings {
+    let sha1 = chip8::rom_sha1(&cart.rom);
+    let file_name = rom.file_name().unwrap_or_default().to_string_lossy();
+    RomInfo::lookup(&sha1)
+        .map(Settings::from)
+        .unwrap_or_default()
+        .merge(&Settings::from(cart))
+        .merge(&config.settings_for(&file_name, &sha1))
+}
+
+/// Reads the ROM at `rom`, which can also be - for stdin, or an http:// or https:// URL with the
+/// http feature.
+fn read_rom(rom: &Path) -> anyhow::Result<Cartridge> {
+    if is_stdin(rom) {
+        let mut bytes = Vec::new();
+        std::io::stdin().read_to_end(&mut bytes)?;
+        return Ok(Cartridge::plain(bytes));
+    }
+    if !is_url(rom) {
+        return Ok(Cartridge::load(rom)?);
+    }
+    #[cfg(feature = "http")]
+    {
+        // Nothing bigger than the largest memory will load, so there's no point fetching more,
+        // but a ROM cut short at that size mustn't run either.
+        let mut bytes = Vec::new();
+        ureq::get(&rom.to_string_lossy())
+            .call()?
+            .into_reader()
+            .take(chip8::MAX_MEMORY_SIZE as u64 + 1)
+            .read_to_end(&mut bytes)?;
+        if bytes.len() > chip8::MAX_MEMORY_SIZE {
+            anyhow::bail!(
+                "{} is bigger than the {} bytes of memory there are to load it into",
+                rom.display(),
+                chip8::MAX_MEMORY_SIZE
+            );
+        }
+        Ok(Cartridge::read(rom, bytes)?)
+    }
+    #[cfg(not(feature = "http"))]
+    anyhow::bail!("downloading ROMs needs a chip8 built with the http feature")
+}
+
+fn run_headless(mut runner: HeadlessRunner, args: &RunArgs) -> anyhow::Result<()> {
+    let frames = match &args.playback {
+        Some(path) => runner.play(&Movie::load(path)?),
+        None => runner.run(args.frames),
+    };
+    // A profile's as useful when the ROM faults as when it doesn't, and so's a GIF of it.
+    save_profile(runner.cpu(), args);
+    save_coverage(runner.cpu(), args);
+    save_memory(runner.cpu(), args);
+    save_gif(runner.take_gif(), args);
+    #[cfg(feature = "video")]
+    save_video(runner.take_video());
+    let frames = frames.map_err(|err| match runner.cpu().call_stack() {
+        [] => anyhow::Error::from(err),
+        _ => anyhow::anyhow!("{}, called from {}", err, runner.cpu().backtrace()),
+    })?;
+    info!("Headless run finished after {} frames", frames);
+    if let Some(breakpoint) = runner.cpu().breakpoint_hit() {
+        eprintln!(
+            "Stopped at breakpoint {} (pc {:03x}) after {} frames",
+            breakpoint,
+            runner.cpu().pc,
+            frames
+        );
+        if let Some(hit) = runner.cpu().memory.watch_hit() {
+            eprintln!("The last instruction {}", hit);
+        }
+    }
+
+    dump(runner.cpu().framebuffer(), args)
+}
+
+/// Writes the coverage map of the ROM to --coverage-out, if it was given.
+fn save_coverage(cpu: &CPU, args: &RunArgs) {
+    let Some(path) = &args.coverage_out else {
+        return;
+    };
+    let start = cpu.memory.load_addr();
+    let rom = start..start + cpu.memory.rom().len();
+    match cpu.coverage().save(path, rom) {
+        Ok(()) => println!("Wrote the coverage map to {}", path.display()),
+        Err(err) => eprintln!("Couldn't save {}: {}", path.display(), err),
+    }
+}
+
+/// Where to listen or connect for a network flag's `addr`, which can leave out the host (`:3333`)
+/// for localhost.
+fn listen_addr(addr: &str) -> String {
+    match addr.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{}", port),
+        None => addr.to_string(),
+    }
+}
+
+fn audio_beeper(mode: AudioMode, volume: f32) -> Box<dyn Beeper> {
+    match mode {
+        AudioMode::Cpal => {}
+        AudioMode::Terminal => return Box::new(TerminalBell::default()),
+        AudioMode::Visual => return Box::new(VisualBeeper::default()),
+        AudioMode::Off => return Box::new(NullBeeper),
+    }
+    #[cfg(feature = "audio")]
+    match chip8::audio::SquareWaveBeeper::new(volume) {
+        Ok(beeper) => return Box::new(beeper),
+        Err(err) => tracing::warn!("Audio unavailable, flashing the display instead: {}", err),
+    }
+    #[cfg(not(feature = "audio"))]
+    let _ = volume;
+    Box::new(VisualBeeper::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listens_on_localhost_without_a_host() {
+        assert_eq!(listen_addr(":3333"), "127.0.0.1:3333");
+        assert_eq!(listen_addr("0.0.0.0:3333"), "0.0.0.0:3333");
     }
 }
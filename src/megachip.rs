@@ -0,0 +1,122 @@
+//! MegaChip, the SUPER-CHIP extension the Mega8 demos are written for. Its 0011 instruction
+//! switches to a 256x192 display of sprites whose pixels are indices into a 256-colour palette,
+//! drawn on a back buffer that 00E0 then shows. It also plays digitised sound, and its I is 24
+//! bits wide, which reaches 16M of memory.
+use crate::audio::DigitizedSound;
+use crate::memory::OutOfBounds;
+use crate::{Color, FrameBuffer};
+
+pub const MEGA_DISPLAY_WIDTH: usize = 256;
+pub const MEGA_DISPLAY_HEIGHT: usize = 192;
+/// As much as a 24-bit I reaches.
+pub const MEGACHIP_MEMORY_SIZE: usize = 0x100_0000;
+/// A digitised sound's samples follow a 16-bit sample rate, a 24-bit length and a padding byte.
+const SOUND_HEADER_LEN: usize = 6;
+
+/// What MegaChip adds to the machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MegaChip {
+    /// The colours sprite pixels index, loaded by 02NN. Index 0 is transparent.
+    pub palette: Vec<Color>,
+    /// The size of the sprites DXYN draws, set by 03NN and 04NN.
+    pub sprite_width: usize,
+    pub sprite_height: usize,
+    /// Set by 05NN and 080N, but see `draw`.
+    pub alpha: u8,
+    pub blend: u8,
+    /// The palette index DXYN reports collisions with, set by 09NN.
+    pub collision: u8,
+    /// The top 8 bits of I, set by 01NN NNNN and cleared by everything else that sets I.
+    pub i_high: u8,
+    /// What DXYN draws on and scrolling moves, until 00E0 shows it.
+    pub back: FrameBuffer,
+    /// What 060N last started playing, until 0700 stops it.
+    pub sound: Option<PlayingSound>,
+}
+
+/// A digitised sound, and where its header is in memory, for save states to find it again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PlayingSound {
+    pub header: usize,
+    pub sound: DigitizedSound,
+}
+
+impl Default for MegaChip {
+    fn default() -> Self {
+        Self {
+            palette: vec![Color::new(0, 0, 0); 256],
+            sprite_width: 256,
+            sprite_height: 256,
+            alpha: 0xff,
+            blend: 0,
+            collision: 0xff,
+            i_high: 0,
+            back: FrameBuffer::new(MEGA_DISPLAY_WIDTH, MEGA_DISPLAY_HEIGHT),
+            sound: None,
+        }
+    }
+}
+
+impl MegaChip {
+    /// Sets entries 1 onwards of the palette from `argb`, four bytes a colour. The alpha byte is
+    /// ignored.
+    pub fn load_palette(&mut self, argb: &[u8]) {
+        for (entry, color) in self.palette[1..].iter_mut().zip(argb.chunks_exact(4)) {
+            *entry = Color::new(color[1], color[2], color[3]);
+        }
+    }
+
+    /// How many bytes DXYN reads: a palette index for each pixel of the sprite.
+    pub fn sprite_len(&self) -> usize {
+        self.sprite_width * self.sprite_height
+    }
+
+    /// Draws `sprite`, a palette index per pixel and `sprite_width` of them per row, on the back
+    /// buffer at (`x`, `y`), clipping it at the edges. Index 0 is transparent. Returns whether it
+    /// drew over any pixel in the collision colour.
+    ///
+    /// The alpha and blend mode aren't applied: the display holds palette indices rather than
+    /// colours, so there's nothing to blend, and sprites are always drawn opaque.
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let mut collided = false;
+        for (row, line) in sprite.chunks(self.sprite_width).enumerate() {
+            for (col, &index) in line.iter().enumerate() {
+                let (px, py) = (x + col, y + row);
+                if index == 0 || px >= MEGA_DISPLAY_WIDTH || py >= MEGA_DISPLAY_HEIGHT {
+                    continue;
+                }
+                collided |= self.back.get(px, py) == self.collision;
+                self.back.set(px, py, index);
+            }
+        }
+        collided
+    }
+
+    /// Shows the back buffer on `display` in the palette as it is now, and starts a blank one.
+    pub fn present(&mut self, display: &mut FrameBuffer) {
+        display.copy_from(self.back.pixels());
+        display.set_palette(Some(self.palette.as_slice().into()));
+        self.back.clear();
+    }
+}
+
+/// Reads the digitised sound whose header is at `header`, straight from `memory`.
+pub(crate) fn read_sound(
+    memory: &[u8],
+    header: usize,
+    looping: bool,
+) -> Result<DigitizedSound, OutOfBounds> {
+    let bytes = |start: usize, len: usize| {
+        memory.get(start..start + len).ok_or(OutOfBounds {
+            addr: start.max(memory.len()),
+        })
+    };
+    let fields = bytes(header, SOUND_HEADER_LEN)?;
+    let rate = u16::from_be_bytes([fields[0], fields[1]]);
+    let len = u32::from_be_bytes([0, fields[2], fields[3], fields[4]]) as usize;
+    Ok(DigitizedSound {
+        samples: bytes(header + SOUND_HEADER_LEN, len)?.into(),
+        rate,
+        looping,
+    })
+}
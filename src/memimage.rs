@@ -0,0 +1,169 @@
+use std::io::{self, Cursor, Read, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::memory::MAX_MEMORY_SIZE;
+use crate::{Snapshot, StateError};
+
+const MAGIC: &[u8; 4] = b"C8RG";
+/// The magic number, V0-VF, I, PC, SP, the stack and the two timers.
+const REGISTERS_LEN: usize = 4 + 16 + 2 + 2 + 1 + 16 * 2 + 1 + 1;
+
+/// RAM as it is at some point, and optionally the registers, stack and timers with it: less than
+/// a `SaveState`, but in a form that can be read and written by hand.
+///
+/// On disk it's the contents of RAM byte for byte, so it opens in a hex editor with offsets
+/// matching addresses, followed by the registers if there are any. A plain binary file, such as
+/// one written by another emulator or assembled to be loaded at 0, works too: it's taken as RAM
+/// from address 0, and the registers are left as they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryImage {
+    pub(crate) memory: Vec<u8>,
+    pub(crate) registers: Option<Snapshot>,
+}
+
+impl MemoryImage {
+    /// An image of `memory` from address 0, without registers.
+    pub fn new(memory: Vec<u8>) -> Self {
+        Self {
+            memory,
+            registers: None,
+        }
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// The registers, stack and timers, if the image has them.
+    pub fn registers(&self) -> Option<&Snapshot> {
+        self.registers.as_ref()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + REGISTERS_LEN);
+        out.extend_from_slice(&self.memory);
+        if let Some(registers) = &self.registers {
+            write_registers(&mut out, registers).expect("writing to a Vec can't fail");
+        }
+        out
+    }
+
+    /// Parses an image written by `to_bytes`, or plain RAM with no registers after it.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let split = bytes.len().saturating_sub(REGISTERS_LEN);
+        let (memory, registers) =
+            if bytes.len() >= REGISTERS_LEN && bytes[split..].starts_with(MAGIC) {
+                (&bytes[..split], Some(read_registers(&bytes[split..])?))
+            } else {
+                (bytes, None)
+            };
+        if memory.len() > MAX_MEMORY_SIZE {
+            return Err(StateError::Corrupt("bigger than any CHIP-8's memory"));
+        }
+        Ok(Self {
+            memory: memory.to_vec(),
+            registers,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(Self::from_bytes(&std::fs::read(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+}
+
+fn write_registers(out: &mut Vec<u8>, registers: &Snapshot) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&registers.v)?;
+    out.write_u16::<BigEndian>(registers.i)?;
+    out.write_u16::<BigEndian>(registers.pc)?;
+    out.write_u8(registers.sp)?;
+    for &addr in &registers.stack {
+        out.write_u16::<BigEndian>(addr)?;
+    }
+    out.write_u8(registers.delay)?;
+    out.write_u8(registers.sound)?;
+    Ok(())
+}
+
+fn read_registers(bytes: &[u8]) -> Result<Snapshot, StateError> {
+    let mut r = Cursor::new(&bytes[MAGIC.len()..]);
+    let mut v = [0; 16];
+    r.read_exact(&mut v)?;
+    let i = r.read_u16::<BigEndian>()?;
+    let pc = r.read_u16::<BigEndian>()?;
+    let sp = r.read_u8()?;
+    if sp as usize > 16 {
+        return Err(StateError::Corrupt("stack pointer out of range"));
+    }
+    let mut stack = [0; 16];
+    for addr in &mut stack {
+        *addr = r.read_u16::<BigEndian>()?;
+    }
+    Ok(Snapshot {
+        v,
+        i,
+        pc,
+        sp,
+        stack,
+        delay: r.read_u8()?,
+        sound: r.read_u8()?,
+        last_instruction: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Keypad, Memory, Quirks, CPU};
+    use std::sync::Arc;
+
+    #[test]
+    fn images_carry_registers_and_plain_ram_doesnt() {
+        let mut memory = Memory::new();
+        // Count up in V3 and call a subroutine that returns straight away.
+        memory[0x200..0x208].copy_from_slice(&[0x73, 0x01, 0x22, 0x06, 0x12, 0x00, 0x00, 0xee]);
+        let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::default());
+        cpu.run_frame().unwrap();
+        let image = cpu.memory_image();
+        assert_eq!(image.memory().len(), 0x1000);
+        let bytes = image.to_bytes();
+        assert_eq!(&bytes[0x200..0x202], &[0x73, 0x01]);
+
+        let mut restored = CPU::new(Memory::new(), Arc::new(Keypad::new()), Quirks::default());
+        restored
+            .load_memory_image(&MemoryImage::from_bytes(&bytes).unwrap())
+            .unwrap();
+        assert_eq!(restored.snapshot().v, cpu.snapshot().v);
+        assert_eq!(restored.snapshot().pc, cpu.snapshot().pc);
+        restored.run_frame().unwrap();
+        cpu.run_frame().unwrap();
+        assert_eq!(restored.snapshot().v[3], cpu.snapshot().v[3]);
+
+        let plain = MemoryImage::from_bytes(&bytes[..0x1000]).unwrap();
+        assert_eq!(plain.registers(), None);
+        let mut fresh = CPU::new(Memory::new(), Arc::new(Keypad::new()), Quirks::default());
+        fresh.load_memory_image(&plain).unwrap();
+        assert_eq!(fresh.snapshot().pc, 0x200);
+        assert_eq!(fresh.memory[0x200], 0x73);
+    }
+
+    #[test]
+    fn images_must_fit() {
+        let mut cpu = CPU::new(Memory::new(), Arc::new(Keypad::new()), Quirks::default());
+        let image = MemoryImage::new(vec![0; 0x1001]);
+        assert_eq!(cpu.load_memory_image(&image).unwrap_err().addr, 0x1000);
+        let mut bytes = cpu.memory_image().to_bytes();
+        let sp = bytes.len() - REGISTERS_LEN + 24;
+        bytes[sp] = 17;
+        assert_eq!(
+            MemoryImage::from_bytes(&bytes),
+            Err(StateError::Corrupt("stack pointer out of range"))
+        );
+    }
+}
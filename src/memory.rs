@@ -1,72 +1,600 @@
 use std::{
+    cell::Cell,
+    fmt,
     fs::File,
     io::{self, Read},
     ops::{Deref, DerefMut},
     path::Path,
+    str::FromStr,
 };
 
-use log::info;
+use tracing::{info, warn};
+
+use crate::asm::AsmError;
+use crate::core::FONT;
+pub use crate::core::{BIG_FONT_ADDR, FONT_ADDR, MEMORY_SIZE, PROGRAM_START};
+use crate::region::{Region, WriteProtection};
+use crate::watchpoint::{WatchHit, Watchpoint};
+
+/// The end of the font sprites; everything from here to 0x200 is free.
+pub const FONTS_END: usize = BIG_FONT_ADDR + 16 * 10;
+
+/// XO-CHIP extends the address space to the full 16 bits.
+pub const XOCHIP_MEMORY_SIZE: usize = 0x10000;
+
+/// The most RAM any variant addresses.
+#[cfg(not(feature = "megachip"))]
+pub const MAX_MEMORY_SIZE: usize = XOCHIP_MEMORY_SIZE;
+#[cfg(feature = "megachip")]
+pub const MAX_MEMORY_SIZE: usize = crate::megachip::MEGACHIP_MEMORY_SIZE;
+
+/// What the interpreter does with an address past the end of RAM, which ROMs reach with a
+/// corrupted or carelessly advanced I.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessPolicy {
+    /// Wrap around to the start of RAM, the way the address lines of the original hardware did.
+    Wrap,
+    /// Use the last byte of RAM instead.
+    Clamp,
+    /// Fault with `Chip8Error::MemoryOutOfBounds`.
+    #[default]
+    Error,
+}
+
+impl fmt::Display for AccessPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AccessPolicy::Wrap => "wrap",
+            AccessPolicy::Clamp => "clamp",
+            AccessPolicy::Error => "error",
+        })
+    }
+}
+
+impl FromStr for AccessPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wrap" => Ok(AccessPolicy::Wrap),
+            "clamp" => Ok(AccessPolicy::Clamp),
+            "error" => Ok(AccessPolicy::Error),
+            _ => Err(format!(
+                "unknown memory policy '{}' (expected wrap, clamp or error)",
+                s
+            )),
+        }
+    }
+}
+
+/// Where a ROM is loaded and starts running. Nearly everything loads at 0x200, right after the
+/// interpreter, but ROMs for the ETI-660, whose interpreter was bigger, load at 0x600.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadAddress(pub u16);
+
+impl LoadAddress {
+    /// The COSMAC VIP's, and every interpreter since.
+    pub const CHIP8: LoadAddress = LoadAddress(PROGRAM_START as u16);
+    pub const ETI660: LoadAddress = LoadAddress(0x600);
+
+    pub fn addr(self) -> usize {
+        self.0 as usize
+    }
+}
+
+impl Default for LoadAddress {
+    fn default() -> Self {
+        LoadAddress::CHIP8
+    }
+}
+
+impl fmt::Display for LoadAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            LoadAddress::CHIP8 => f.write_str("chip8"),
+            LoadAddress::ETI660 => f.write_str("eti660"),
+            LoadAddress(addr) => write!(f, "{:#x}", addr),
+        }
+    }
+}
+
+impl FromStr for LoadAddress {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        match lower.as_str() {
+            "chip8" | "vip" => return Ok(LoadAddress::CHIP8),
+            "eti660" | "eti-660" => return Ok(LoadAddress::ETI660),
+            _ => {}
+        }
+        match lower
+            .strip_prefix("0x")
+            .map(|hex| u16::from_str_radix(hex, 16))
+        {
+            Some(Ok(addr)) => Ok(LoadAddress(addr)),
+            _ => Err(format!(
+                "unknown load address '{}' (expected chip8, eti660 or an address like 0x600)",
+                s
+            )),
+        }
+    }
+}
+
+/// An access past the end of RAM, under `AccessPolicy::Error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfBounds {
+    pub addr: usize,
+}
+
+/// Why a ROM couldn't be loaded.
+#[derive(Debug)]
+pub enum RomError {
+    Io(io::Error),
+    /// The ROM is `size` bytes, but only `max` fit between the load address and the end of RAM.
+    TooBig {
+        size: usize,
+        max: usize,
+    },
+    /// The ROM is Octo source, and it doesn't compile.
+    Source(AsmError),
+    /// The ROM's in a container, like an Octo cartridge, that can't be made sense of.
+    Invalid(String),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RomError::Io(err) => write!(f, "{}", err),
+            RomError::TooBig { size, max } => write!(
+                f,
+                "the ROM is {} bytes, but only {} fit in memory",
+                size, max
+            ),
+            RomError::Source(err) => write!(f, "{}", err),
+            RomError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        RomError::Io(err)
+    }
+}
+
+impl From<AsmError> for RomError {
+    fn from(err: AsmError) -> Self {
+        RomError::Source(err)
+    }
+}
 
 /// Stores the RAM memory, can be used as proxy access to the underlying buffer.
+///
+/// The backing buffer is always big enough for XO-CHIP, but only the first `len` bytes are
+/// addressable, so 4K programs still see (and fault on) a 4K address space.
+///
+/// Indexing goes straight to the buffer, and panics out of bounds. The interpreter accesses data
+/// through `read` and `write` instead, which follow the `AccessPolicy` and write protection, and
+/// are what watchpoints see.
 pub struct Memory {
-    buf: [u8; 4096],
+    buf: Vec<u8>,
+    len: usize,
+    policy: AccessPolicy,
+    protection: WriteProtection,
+    /// The regions `protection` applies to. The interpreter area is always one of them.
+    protected: Vec<Region>,
+    watchpoints: Vec<Watchpoint>,
+    /// The first watched access since the last `clear_watch_hit`.
+    watch_hit: Cell<Option<WatchHit>>,
+    /// Every write since the last `take_writes`, as address and value, if they're being logged.
+    write_log: Option<Vec<(u16, u8)>>,
+    /// A copy of the last ROM loaded, for `reset` to start it over from.
+    rom: Vec<u8>,
+    load_addr: LoadAddress,
 }
 
 impl Memory {
     pub fn new() -> Self {
-        let mut buf = [0; 4096];
+        Self::with_size(MEMORY_SIZE)
+    }
+
+    /// Creates memory with `len` addressable bytes, clamped to `MAX_MEMORY_SIZE`.
+    pub fn with_size(len: usize) -> Self {
+        let len = len.min(MAX_MEMORY_SIZE);
+        let mut buf = vec![0; len.max(XOCHIP_MEMORY_SIZE)];
         Self::fill_hex_sprites(&mut buf);
-        Self { buf }
+        Self::fill_big_hex_sprites(&mut buf);
+        Self {
+            buf,
+            len,
+            policy: AccessPolicy::default(),
+            protection: WriteProtection::default(),
+            protected: vec![Region::INTERPRETER],
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+            write_log: None,
+            rom: Vec::new(),
+            load_addr: LoadAddress::default(),
+        }
+    }
+
+    /// Replaces the contents of RAM with `bytes`, which become the addressable size. Watchpoints
+    /// are kept.
+    pub(crate) fn restore(&mut self, bytes: &[u8]) {
+        self.len = bytes.len().min(MAX_MEMORY_SIZE);
+        if self.buf.len() < self.len {
+            self.buf.resize(self.len, 0);
+        }
+        self.buf[..self.len].copy_from_slice(&bytes[..self.len]);
+        self.clear_watch_hit();
+    }
+
+    /// Overwrites RAM from address 0 with `bytes`, leaving anything after them as it is. Nothing
+    /// changes if they don't fit.
+    pub(crate) fn load_image(&mut self, bytes: &[u8]) -> Result<(), OutOfBounds> {
+        if bytes.len() > self.len {
+            return Err(OutOfBounds { addr: self.len });
+        }
+        self.buf[..bytes.len()].copy_from_slice(bytes);
+        self.clear_watch_hit();
+        Ok(())
+    }
+
+    pub fn policy(&self) -> AccessPolicy {
+        self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: AccessPolicy) {
+        self.policy = policy;
+    }
+
+    pub fn write_protection(&self) -> WriteProtection {
+        self.protection
+    }
+
+    pub fn set_write_protection(&mut self, protection: WriteProtection) {
+        self.protection = protection;
+    }
+
+    /// Adds `region` to those writes are protected in.
+    pub fn protect(&mut self, region: Region) {
+        if !self.protected.contains(&region) {
+            self.protected.push(region);
+        }
+    }
+
+    pub fn protected(&self) -> &[Region] {
+        &self.protected
+    }
+
+    /// Where in RAM an access to `addr` goes under the access policy.
+    pub fn resolve(&self, addr: usize) -> Result<usize, OutOfBounds> {
+        match self.policy {
+            _ if addr < self.len => Ok(addr),
+            AccessPolicy::Wrap => Ok(addr % self.len),
+            AccessPolicy::Clamp => Ok(self.len - 1),
+            AccessPolicy::Error => Err(OutOfBounds { addr }),
+        }
+    }
+
+    /// Errors if any of the `len` bytes starting at `addr` can't be accessed, so an instruction
+    /// can fault before it's changed anything.
+    pub fn check_range(&self, addr: usize, len: usize) -> Result<(), OutOfBounds> {
+        if self.policy == AccessPolicy::Error && addr + len > self.len {
+            return Err(OutOfBounds {
+                addr: addr.max(self.len),
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the byte at `addr`.
+    pub fn read(&self, addr: usize) -> Result<u8, OutOfBounds> {
+        let addr = self.resolve(addr)?;
+        let value = self[addr];
+        self.watch(addr, false, value);
+        Ok(value)
+    }
+
+    /// Writes the byte at `addr`, unless it's protected and protection is set to deny it.
+    pub fn write(&mut self, addr: usize, value: u8) -> Result<(), OutOfBounds> {
+        let addr = self.resolve(addr)?;
+        if self.protection != WriteProtection::Allow
+            && self.protected.iter().any(|region| region.contains(addr))
+        {
+            warn!(target: "memory", "Write of {:02x} to protected address {:03x}", value, addr);
+            if self.protection == WriteProtection::Deny {
+                return Ok(());
+            }
+        }
+        self[addr] = value;
+        self.watch(addr, true, value);
+        if let Some(log) = &mut self.write_log {
+            log.push((addr as u16, value));
+        }
+        Ok(())
+    }
+
+    /// Starts or stops keeping a log of the interpreter's writes for `take_writes`.
+    pub fn log_writes(&mut self, on: bool) {
+        match (on, &self.write_log) {
+            (true, None) => self.write_log = Some(Vec::new()),
+            (false, _) => self.write_log = None,
+            (true, Some(_)) => {}
+        }
+    }
+
+    /// The writes made since the last call, oldest first, if `log_writes` is on.
+    pub fn take_writes(&mut self) -> Vec<(u16, u8)> {
+        self.write_log
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    fn watch(&self, addr: usize, write: bool, value: u8) {
+        if self.watchpoints.is_empty() || self.watch_hit.get().is_some() {
+            return;
+        }
+        if let Some(&watchpoint) = self.watchpoints.iter().find(|w| w.hits(addr, write)) {
+            self.watch_hit.set(Some(WatchHit {
+                watchpoint,
+                addr: addr as u16,
+                write,
+                value,
+            }));
+        }
     }
 
-    pub fn load_rom<P: AsRef<Path>>(&mut self, rom_path: P) -> io::Result<()> {
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.retain(|&w| w != watchpoint);
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// The first watched access since the hit was last cleared.
+    pub fn watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit.get()
+    }
+
+    pub fn clear_watch_hit(&self) {
+        self.watch_hit.set(None);
+    }
+
+    /// Where ROMs are loaded from now on, and where the CPU starts running them. Set it before
+    /// loading the ROM.
+    pub fn set_load_addr(&mut self, load_addr: LoadAddress) {
+        self.load_addr = load_addr;
+    }
+
+    pub fn load_addr(&self) -> usize {
+        self.load_addr.addr()
+    }
+
+    /// How big a ROM can be: everything from the load address to the end of RAM.
+    pub fn max_rom_size(&self) -> usize {
+        self.len.saturating_sub(self.load_addr())
+    }
+
+    /// Loads the ROM at `rom_path` at the load address.
+    pub fn load_rom<P: AsRef<Path>>(&mut self, rom_path: P) -> Result<(), RomError> {
         let rom_path = rom_path.as_ref();
         let rom_name = rom_path
             .file_name()
             .and_then(|name| name.to_str())
             .unwrap_or("(Unknown)");
-        let mut rom = File::open(rom_path)?;
-        let nb = rom.read(&mut self.buf[0x200..])?;
-        info!("Load ROM: {} ({} bytes)", rom_name, nb);
+        let nb = self.load_rom_reader(File::open(rom_path)?)?;
+        info!(target: "memory", "Load ROM: {} ({} bytes)", rom_name, nb);
+        Ok(())
+    }
+
+    /// Loads all of `rom` at the load address.
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        self.check_fits(rom)?;
+        let start = self.load_addr();
+        self.buf[start..start + rom.len()].copy_from_slice(rom);
+        self.rom = rom.to_vec();
+        Ok(())
+    }
+
+    /// Wipes RAM back to how `with_size` left it, fonts and all, and loads `rom` at the load address, for
+    /// starting a ROM over. RAM is left alone if `rom` doesn't fit.
+    pub fn reload_rom(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        self.check_fits(rom)?;
+        self.rom = rom.to_vec();
+        self.reset();
         Ok(())
     }
 
-    fn fill_hex_sprites(memory: &mut [u8; 4096]) {
-        const HEX_SPRITES: [u8; 80] = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+    /// The last ROM loaded, as it was before anything ran.
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// Wipes RAM back to how `with_size` left it and loads the last ROM again.
+    pub fn reset(&mut self) {
+        self.buf.fill(0);
+        Self::fill_hex_sprites(&mut self.buf);
+        Self::fill_big_hex_sprites(&mut self.buf);
+        let start = self.load_addr();
+        self.buf[start..start + self.rom.len()].copy_from_slice(&self.rom);
+        self.clear_watch_hit();
+    }
+
+    fn check_fits(&self, rom: &[u8]) -> Result<(), RomError> {
+        if rom.len() > self.max_rom_size() {
+            return Err(RomError::TooBig {
+                size: rom.len(),
+                max: self.max_rom_size(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Loads everything `reader` has at the load address, and returns how many bytes that was.
+    pub fn load_rom_reader<R: Read>(&mut self, reader: R) -> Result<usize, RomError> {
+        // Reading one byte past the limit is enough to tell the ROM doesn't fit.
+        let mut rom = Vec::new();
+        reader
+            .take(self.max_rom_size() as u64 + 1)
+            .read_to_end(&mut rom)?;
+        self.load_rom_bytes(&rom)?;
+        Ok(rom.len())
+    }
+
+    fn fill_hex_sprites(memory: &mut [u8]) {
+        for (i, &byte) in FONT.iter().enumerate() {
+            memory[FONT_ADDR + i] = byte;
+        }
+    }
+
+    fn fill_big_hex_sprites(memory: &mut [u8]) {
+        // SUPER-CHIP only shipped 0-9; A-F are the commonly used Octo extension.
+        const BIG_HEX_SPRITES: [u8; 160] = [
+            0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+            0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+            0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+            0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+            0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+            0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+            0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+            0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+            0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+            0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+            0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
         ];
 
-        for (i, &byte) in HEX_SPRITES.iter().enumerate() {
-            memory[i] = byte;
+        for (i, &byte) in BIG_HEX_SPRITES.iter().enumerate() {
+            memory[BIG_FONT_ADDR + i] = byte;
         }
     }
 }
 
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Deref for Memory {
-    type Target = [u8; 4096];
+    type Target = [u8];
 
     fn deref(&self) -> &Self::Target {
-        &self.buf
+        &self.buf[..self.len]
     }
 }
 
 impl DerefMut for Memory {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.buf
+        &mut self.buf[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policies_decide_where_stray_accesses_go() {
+        let mut memory = Memory::new();
+        memory[0xfff] = 0xaa;
+        assert_eq!(memory.read(0x1000), Err(OutOfBounds { addr: 0x1000 }));
+        assert_eq!(
+            memory.check_range(0xffe, 3),
+            Err(OutOfBounds { addr: 0x1000 })
+        );
+
+        memory.set_policy(AccessPolicy::Clamp);
+        assert_eq!(memory.read(0x1234), Ok(0xaa));
+        assert_eq!(memory.check_range(0xffe, 3), Ok(()));
+
+        memory.set_policy(AccessPolicy::Wrap);
+        memory.write(0x1001, 7).unwrap();
+        assert_eq!(memory[1], 7);
+    }
+
+    #[test]
+    fn rejects_roms_that_dont_fit() {
+        let mut memory = Memory::new();
+        assert_eq!(memory.max_rom_size(), 3584);
+        memory.load_rom_reader(&[0x12, 0x00][..]).unwrap();
+        assert_eq!(&memory[0x200..0x202], &[0x12, 0x00]);
+        assert!(matches!(
+            memory.load_rom_reader(&[0; 3585][..]),
+            Err(RomError::TooBig {
+                size: 3585,
+                max: 3584
+            })
+        ));
+        assert!(Memory::with_size(XOCHIP_MEMORY_SIZE)
+            .load_rom_bytes(&[0; 3585])
+            .is_ok());
+    }
+
+    #[test]
+    fn roms_load_at_the_load_address() {
+        assert_eq!("eti660".parse(), Ok(LoadAddress::ETI660));
+        assert_eq!("VIP".parse(), Ok(LoadAddress::CHIP8));
+        assert_eq!("0x300".parse(), Ok(LoadAddress(0x300)));
+        assert_eq!(LoadAddress(0x300).to_string(), "0x300");
+        assert_eq!(
+            "600".parse::<LoadAddress>(),
+            Err(
+                "unknown load address '600' (expected chip8, eti660 or an address like 0x600)"
+                    .to_string()
+            )
+        );
+
+        let mut memory = Memory::new();
+        memory.set_load_addr(LoadAddress::ETI660);
+        assert_eq!(memory.max_rom_size(), 0xa00);
+        memory.load_rom_bytes(&[0x16, 0x00]).unwrap();
+        assert_eq!(&memory[0x600..0x602], &[0x16, 0x00]);
+        memory.reset();
+        assert_eq!(&memory[0x600..0x602], &[0x16, 0x00]);
+        assert_eq!(&memory[0x200..0x202], &[0, 0]);
+    }
+
+    #[test]
+    fn denied_writes_are_dropped() {
+        let mut memory = Memory::new();
+        memory.set_write_protection(WriteProtection::Deny);
+        memory.protect(Region::new(0x300, 0x30f));
+        memory.write(0x000, 0).unwrap();
+        memory.write(0x30f, 1).unwrap();
+        memory.write(0x310, 2).unwrap();
+        assert_eq!(memory[0x000], 0xf0);
+        assert_eq!(&memory[0x30f..0x311], &[0, 2]);
+
+        memory.set_write_protection(WriteProtection::Warn);
+        memory.write(0x30f, 1).unwrap();
+        assert_eq!(memory[0x30f], 1);
+    }
+
+    #[test]
+    fn logs_writes_on_request() {
+        let mut memory = Memory::new();
+        memory.write(0x300, 1).unwrap();
+        memory.log_writes(true);
+        memory.write(0x301, 2).unwrap();
+        memory.write(0x300, 3).unwrap();
+        assert_eq!(memory.take_writes(), [(0x301, 2), (0x300, 3)]);
+        assert!(memory.take_writes().is_empty());
     }
 }
@@ -0,0 +1,171 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::error::Result;
+use crate::rng::Rng;
+use crate::CPU;
+
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u16 = 1;
+
+/// A recording of the keypad state at every frame of a run, along with the random seed and clock
+/// speed it ran with. Playing it back against the same ROM and quirks reproduces the run exactly,
+/// since those are the only inputs the interpreter has.
+///
+/// Movies run whole frames with `CPU::run_frame`, so pausing and single stepping don't mix with
+/// them: a paused frame would have nowhere to go in the recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    seed: u32,
+    ips: u32,
+    /// The keypad state during each frame, as a bitmask with bit `n` set while key `n` is held.
+    frames: Vec<u16>,
+}
+
+impl Movie {
+    /// Starts an empty recording of `cpu`, which should have just been reset, reseeding its random
+    /// number generator so that the seed can be saved with the movie.
+    pub fn start_recording(cpu: &mut CPU) -> Self {
+        let seed = Rng::from_time().state();
+        cpu.seed_rng(seed);
+        Self {
+            seed,
+            ips: cpu.ips(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn ips(&self) -> u32 {
+        self.ips
+    }
+
+    pub fn frames(&self) -> &[u16] {
+        &self.frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Runs one frame of `cpu` with whatever keys are currently held, and records them.
+    pub fn record_frame(&mut self, cpu: &mut CPU) -> Result<()> {
+        self.frames.push(cpu.keypad().state());
+        cpu.run_frame()
+    }
+
+    /// Gets `cpu`, which should have just been reset, ready to play the movie back from the
+    /// start.
+    pub fn start_playback(&self, cpu: &mut CPU) {
+        cpu.seed_rng(self.seed);
+        cpu.set_ips(self.ips);
+    }
+
+    /// Runs frame `n` of the movie on `cpu` with the keys that were held when it was recorded.
+    /// Returns false without running anything once `n` is past the end.
+    pub fn play_frame(&self, n: usize, cpu: &mut CPU) -> Result<bool> {
+        let Some(&keys) = self.frames.get(n) else {
+            return Ok(false);
+        };
+        cpu.keypad().set_state(keys);
+        cpu.run_frame()?;
+        Ok(true)
+    }
+
+    pub fn write_to(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u16::<BigEndian>(VERSION)?;
+        w.write_u32::<BigEndian>(self.seed)?;
+        w.write_u32::<BigEndian>(self.ips)?;
+        w.write_u32::<BigEndian>(self.frames.len() as u32)?;
+        for &keys in &self.frames {
+            w.write_u16::<BigEndian>(keys)?;
+        }
+        w.flush()
+    }
+
+    pub fn read_from(mut r: impl Read) -> io::Result<Self> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a movie".to_string()));
+        }
+        let version = r.read_u16::<BigEndian>()?;
+        if version != VERSION {
+            return Err(invalid(format!(
+                "movie is version {}, but only version {} is supported",
+                version, VERSION
+            )));
+        }
+        let seed = r.read_u32::<BigEndian>()?;
+        let ips = r.read_u32::<BigEndian>()?;
+        let len = r.read_u32::<BigEndian>()?;
+        let frames = (0..len)
+            .map(|_| r.read_u16::<BigEndian>())
+            .collect::<io::Result<_>>()?;
+        Ok(Self { seed, ips, frames })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_to(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with;
+
+    #[test]
+    fn playback_reproduces_the_recording() {
+        // Add a random number to V1 while key 5 is held.
+        let program = [0xc0ff, 0x6505, 0xe5a1, 0x8104, 0x1200];
+        let mut cpu = cpu_with(&program);
+        let mut movie = Movie::start_recording(&mut cpu);
+        for frame in 0..30 {
+            if frame % 7 == 0 {
+                cpu.keypad().press(5);
+            } else {
+                cpu.keypad().release(5);
+            }
+            movie.record_frame(&mut cpu).unwrap();
+        }
+        let recorded = cpu.save_state();
+
+        let mut bytes = Vec::new();
+        movie.write_to(&mut bytes).unwrap();
+        let movie = Movie::read_from(&bytes[..]).unwrap();
+        assert_eq!(movie.len(), 30);
+
+        let mut cpu = cpu_with(&program);
+        movie.start_playback(&mut cpu);
+        let mut frame = 0;
+        while movie.play_frame(frame, &mut cpu).unwrap() {
+            frame += 1;
+        }
+        assert_eq!(frame, 30);
+        assert_eq!(cpu.save_state(), recorded);
+        assert_ne!(cpu.registers.v[1], 0);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let err = Movie::read_from(&b"C8ST\x00\x01"[..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
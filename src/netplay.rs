@@ -0,0 +1,343 @@
+use std::fmt;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::time::Duration;
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::rng::Rng;
+use crate::{Quirks, CPU};
+
+const MAGIC: &[u8; 4] = b"C8NP";
+const VERSION: u16 = 2;
+/// How long to wait for the other side's keys before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A set of keypad keys, as a bitmask with bit `n` set for key `n`. Written as their hex digits,
+/// like `cd`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySet(pub u16);
+
+impl KeySet {
+    /// The keys for the right paddle in Pong, which is most of what two players play.
+    pub const PONG_RIGHT: KeySet = KeySet(1 << 0xc | 1 << 0xd);
+}
+
+impl Default for KeySet {
+    fn default() -> Self {
+        Self::PONG_RIGHT
+    }
+}
+
+impl fmt::Display for KeySet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for key in (0..16).filter(|key| self.0 & 1 << key != 0) {
+            write!(f, "{:x}", key)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for KeySet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut keys = 0;
+        for c in s.chars().filter(|&c| c != ',') {
+            let key = c
+                .to_digit(16)
+                .ok_or_else(|| format!("'{}' isn't a set of hex keys like cd", s))?;
+            keys |= 1 << key;
+        }
+        if keys == 0 {
+            return Err("no keys given".to_string());
+        }
+        Ok(KeySet(keys))
+    }
+}
+
+/// Two players on one keypad, each with their own emulator, over TCP. The guest controls a set of
+/// keys the host picks and the host controls the rest. Before each frame, both sides send the keys
+/// they're holding and wait for the other's, so that every frame runs on both with the same
+/// keypad: since the ROM, variant, quirks, timing, random seed and clock speed match too, so does
+/// everything else.
+///
+/// Like movies, netplay runs whole frames, so pausing, stepping, rewinding and loading states
+/// don't mix with it: either side doing them on its own would leave the two out of step.
+pub struct Netplay {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+    host: bool,
+    guest_keys: KeySet,
+    peer: String,
+}
+
+impl Netplay {
+    /// Waits for a guest to connect to `addr`, then has it run `cpu`'s ROM, which should have just
+    /// been reset, with the same seed and clock speed, controlling `guest_keys`.
+    pub fn host(addr: impl ToSocketAddrs, cpu: &mut CPU, guest_keys: KeySet) -> io::Result<Self> {
+        let (stream, peer) = TcpListener::bind(addr)?.accept()?;
+        let mut netplay = Self::new(stream, true, guest_keys, peer.to_string())?;
+        let seed = Rng::from_time().state();
+        cpu.seed_rng(seed);
+        let w = &mut netplay.writer;
+        Self::write_hello(w, cpu)?;
+        w.write_u32::<BigEndian>(seed)?;
+        w.write_u32::<BigEndian>(cpu.ips())?;
+        w.write_u16::<BigEndian>(guest_keys.0)?;
+        w.flush()?;
+        Self::read_hello(&mut netplay.reader, cpu)?;
+        Ok(netplay)
+    }
+
+    /// Connects to a host at `addr` and gets `cpu`, which should have just been reset, ready to run
+    /// the same ROM in step with it.
+    pub fn join(addr: impl ToSocketAddrs, cpu: &mut CPU) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let peer = stream.peer_addr()?.to_string();
+        let mut netplay = Self::new(stream, false, KeySet(0), peer)?;
+        let r = &mut netplay.reader;
+        Self::read_hello(r, cpu)?;
+        cpu.seed_rng(r.read_u32::<BigEndian>()?);
+        cpu.set_ips(r.read_u32::<BigEndian>()?);
+        netplay.guest_keys = KeySet(r.read_u16::<BigEndian>()?);
+        Self::write_hello(&mut netplay.writer, cpu)?;
+        netplay.writer.flush()?;
+        Ok(netplay)
+    }
+
+    fn new(stream: TcpStream, host: bool, guest_keys: KeySet, peer: String) -> io::Result<Self> {
+        // Every frame waits on a two-byte message, which mustn't sit in a buffer.
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+            host,
+            guest_keys,
+            peer,
+        })
+    }
+
+    /// Identifies the protocol, the ROM and how it's being run, so that players running different
+    /// ROMs or settings find out up front rather than from the games drifting apart.
+    fn write_hello(w: &mut impl Write, cpu: &CPU) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u16::<BigEndian>(VERSION)?;
+        w.write_all(crate::rom_sha1(cpu.memory.rom()).as_bytes())?;
+        w.write_u8(cpu.variant() as u8)?;
+        w.write_u8(quirk_bits(cpu.quirks()))?;
+        w.write_u8(cpu.timing() as u8)
+    }
+
+    fn read_hello(r: &mut impl Read, cpu: &CPU) -> io::Result<()> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("the other side isn't a chip8 netplay session"));
+        }
+        if r.read_u16::<BigEndian>()? != VERSION {
+            return Err(invalid(
+                "the other side runs a different version of netplay",
+            ));
+        }
+        let mut sha1 = [0; 40];
+        r.read_exact(&mut sha1)?;
+        if sha1 != crate::rom_sha1(cpu.memory.rom()).as_bytes() {
+            return Err(invalid("the other side is running a different ROM"));
+        }
+        if r.read_u8()? != cpu.variant() as u8 {
+            return Err(invalid(
+                "the other side is running the ROM as a different variant",
+            ));
+        }
+        if r.read_u8()? != quirk_bits(cpu.quirks()) {
+            return Err(invalid(
+                "the other side is running the ROM with different quirks",
+            ));
+        }
+        if r.read_u8()? != cpu.timing() as u8 {
+            return Err(invalid(
+                "the other side is running the ROM with different timing",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether this side is the host, which controls every key but the guest's.
+    pub fn is_host(&self) -> bool {
+        self.host
+    }
+
+    /// The keys the guest controls.
+    pub fn guest_keys(&self) -> KeySet {
+        self.guest_keys
+    }
+
+    /// The keys this side controls.
+    fn ours(&self) -> u16 {
+        if self.host {
+            !self.guest_keys.0
+        } else {
+            self.guest_keys.0
+        }
+    }
+
+    /// Who's on the other side.
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    /// Swaps the keys each side is holding, and sets `cpu`'s keypad to both sets together for the
+    /// next frame. If the other side's gone, its keys are let go.
+    pub fn sync_keys(&mut self, cpu: &CPU) -> io::Result<()> {
+        let ours = cpu.keypad().state() & self.ours();
+        let theirs = self.swap(ours);
+        cpu.keypad()
+            .set_state(ours | theirs.as_ref().map_or(0, |keys| keys & !self.ours()));
+        theirs.map(|_| ())
+    }
+
+    fn swap(&mut self, ours: u16) -> io::Result<u16> {
+        let mut swap = || {
+            self.writer.write_u16::<BigEndian>(ours)?;
+            self.writer.flush()?;
+            self.reader.read_u16::<BigEndian>()
+        };
+        swap().map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset => io::Error::new(err.kind(), "the other player left"),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                io::Error::new(err.kind(), "the other player stopped responding")
+            }
+            _ => err,
+        })
+    }
+}
+
+/// `quirks` as a bitmask, one bit per quirk in the order they're declared.
+fn quirk_bits(quirks: Quirks) -> u8 {
+    [
+        quirks.shift,
+        quirks.jump,
+        quirks.loadstore,
+        quirks.loadstore_x,
+        quirks.display_wait,
+        quirks.vf_reset,
+        quirks.clip,
+    ]
+    .into_iter()
+    .enumerate()
+    .fold(0, |bits, (n, on)| bits | (on as u8) << n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with;
+
+    #[test]
+    fn parses_key_sets() {
+        assert_eq!("cd".parse(), Ok(KeySet::PONG_RIGHT));
+        assert_eq!("1,4".parse(), Ok(KeySet(0b10010)));
+        assert_eq!(KeySet(0b10010).to_string(), "14");
+        assert!("cx".parse::<KeySet>().is_err());
+        assert!("".parse::<KeySet>().is_err());
+    }
+
+    #[test]
+    fn runs_both_sides_in_step() {
+        // rnd v0, ff; jp 0x200
+        let program = [0xc0ff, 0x1200];
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let host = std::thread::spawn(move || {
+            let mut cpu = cpu_with(&program);
+            let mut netplay = Netplay::host(addr, &mut cpu, KeySet::PONG_RIGHT).unwrap();
+            cpu.keypad().press(1);
+            cpu.keypad().press(0xc);
+            netplay.sync_keys(&cpu).unwrap();
+            cpu.run_frame().unwrap();
+            (cpu.keypad().state(), cpu.registers.v[0])
+        });
+        let mut cpu = cpu_with(&program);
+        // The host might not be listening yet.
+        let mut netplay = loop {
+            match Netplay::join(addr, &mut cpu) {
+                Ok(netplay) => break netplay,
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Err(err) => panic!("{}", err),
+            }
+        };
+        assert_eq!(netplay.guest_keys(), KeySet::PONG_RIGHT);
+        assert!(!netplay.is_host());
+        cpu.keypad().press(0xd);
+        netplay.sync_keys(&cpu).unwrap();
+        cpu.run_frame().unwrap();
+
+        // The guest's d and the host's 1 get through, but the host can't press c for the guest.
+        let both = 1 << 1 | 1 << 0xd;
+        assert_eq!(host.join().unwrap(), (both, cpu.registers.v[0]));
+        assert_eq!(cpu.keypad().state(), both);
+
+        // Once the host's gone, its keys are let go.
+        let err = netplay.sync_keys(&cpu).unwrap_err();
+        assert_eq!(err.to_string(), "the other player left");
+        assert_eq!(cpu.keypad().state(), 1 << 0xd);
+    }
+
+    /// Has a guest running `guest` join a host running `host`, and returns why it couldn't.
+    fn refusal(mut host: CPU, mut guest: CPU) -> io::Error {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let host = std::thread::spawn(move || {
+            Netplay::host(addr, &mut host, KeySet::default()).map(|_| ())
+        });
+        let err = loop {
+            match Netplay::join(addr, &mut guest) {
+                Err(err) if err.kind() == io::ErrorKind::ConnectionRefused => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Err(err) => break err,
+                Ok(_) => panic!("joined a host running something different"),
+            }
+        };
+        assert!(host.join().unwrap().is_err());
+        err
+    }
+
+    #[test]
+    fn refuses_a_different_rom() {
+        let err = refusal(cpu_with(&[0x1200]), cpu_with(&[0x1202]));
+        assert_eq!(err.to_string(), "the other side is running a different ROM");
+    }
+
+    #[test]
+    fn refuses_different_settings() {
+        let mut guest = cpu_with(&[0x1200]);
+        guest.set_quirks(Quirks {
+            clip: true,
+            ..Quirks::default()
+        });
+        let err = refusal(cpu_with(&[0x1200]), guest);
+        assert_eq!(
+            err.to_string(),
+            "the other side is running the ROM with different quirks"
+        );
+
+        let mut guest = cpu_with(&[0x1200]);
+        guest.set_timing(crate::Timing::Vip);
+        let err = refusal(cpu_with(&[0x1200]), guest);
+        assert_eq!(
+            err.to_string(),
+            "the other side is running the ROM with different timing"
+        );
+    }
+}
@@ -0,0 +1,1033 @@
+//! A compiler for Octo, the assembly language most CHIP-8 programs are written in nowadays, so
+//! that `chip8 game.8o` runs a program straight from its source.
+//!
+//! ```text
+//! :alias x v1                # names for registers
+//! :const SPEED 2
+//! :calc LIMIT { 64 - 8 }     # constants worked out as it compiles
+//! : main
+//!     x := 0
+//!     loop
+//!         i := ship
+//!         sprite x x 3
+//!         x += SPEED
+//!         if x >= LIMIT then x := 0
+//!     again
+//! : ship  0x18 0x3c 0x7e
+//! ```
+//!
+//! It covers the language in the Octo manual: labels and `:next`, `:alias`, `:const`, `:calc`,
+//! `:byte`, `:org`, `:unpack`, `:call`, `:macro` and `:assert`; `if … then`, `if … begin … else
+//! … end` and `loop … while … again`, including the `<`, `>`, `<=` and `>=` comparisons that work
+//! through vf; and the SUPER-CHIP and XO-CHIP instructions. `:breakpoint` and `:monitor` are read
+//! and ignored. As in Octo, the program starts at `main`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::asm::{AsmError, ORIGIN};
+use crate::Symbols;
+
+/// How many macro expansions a program can make, so that one which expands itself fails rather
+/// than running forever.
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// Whether `path` is Octo source, going by its extension.
+pub fn is_source(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("8o"))
+}
+
+/// Compiles Octo `source` into a ROM, along with the addresses of its labels for the debugger.
+pub fn compile(source: &str) -> Result<(Vec<u8>, Symbols), AsmError> {
+    let mut tokens = tokenize(source);
+    tokens.reverse();
+    let mut compiler = Compiler {
+        tokens,
+        line: 1,
+        rom: Vec::new(),
+        here: ORIGIN as u32,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        expansions: 0,
+        fixups: Vec::new(),
+        flow: Vec::new(),
+        next: None,
+        symbols: Symbols::new(),
+    };
+    match compiler.run() {
+        Ok(()) => Ok((compiler.rom, compiler.symbols)),
+        Err(message) => Err(AsmError {
+            line: compiler.line,
+            message,
+        }),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+/// Splits `source` into whitespace-separated tokens, dropping `#` comments. A double-quoted
+/// string is one token, spaces and all.
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let mut rest = line;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() || rest.starts_with('#') {
+                break;
+            }
+            let end = match rest.strip_prefix('"') {
+                Some(string) => string.find('"').map_or(rest.len(), |end| end + 2),
+                None => rest.find(char::is_whitespace).unwrap_or(rest.len()),
+            };
+            tokens.push(Token {
+                text: rest[..end].to_string(),
+                line: i + 1,
+            });
+            rest = &rest[end..];
+        }
+    }
+    tokens
+}
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// How a label's address goes into the instructions that refer to it.
+#[derive(Clone, Copy)]
+enum Patch {
+    /// The low 12 bits of an instruction, as in `jump` and `i :=`.
+    Address,
+    /// The 16-bit word after `i := long`.
+    Long,
+    /// The bytes of `v0 := …` and `v1 := …` from `:unpack`, with the nibble to put above the
+    /// address's top four bits.
+    Unpack(u8),
+    /// The bytes of `v0 := …` and `v1 := …` from `:unpack long`.
+    UnpackLong,
+}
+
+impl Patch {
+    fn max(self) -> i64 {
+        match self {
+            Patch::Address | Patch::Unpack(_) => 0xfff,
+            Patch::Long | Patch::UnpackLong => 0xffff,
+        }
+    }
+}
+
+/// A reference to a label that hadn't been defined when it was compiled.
+struct Fixup {
+    at: u32,
+    patch: Patch,
+    label: String,
+    line: usize,
+}
+
+/// An `if … begin` or `loop` waiting for its end.
+enum Flow {
+    /// The jump past the block, to point at wherever it ends.
+    Branch(u32),
+    /// Where the loop starts, and the jumps out of it from its `while`s.
+    Loop { start: u32, exits: Vec<u32> },
+}
+
+/// What a condition compiles to: whatever works it out, then an instruction that skips the next
+/// one unless the condition holds, and one that skips it if it does.
+struct Condition {
+    setup: Vec<u16>,
+    unless: u16,
+    when: u16,
+}
+
+struct Compiler {
+    /// What's left to compile, backwards, so the next token is popped off the end.
+    tokens: Vec<Token>,
+    /// The line of the last token taken, for errors.
+    line: usize,
+    rom: Vec<u8>,
+    here: u32,
+    labels: HashMap<String, u32>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+    /// A `:next` label for the instruction about to be compiled.
+    next: Option<String>,
+    symbols: Symbols,
+}
+
+impl Compiler {
+    fn run(&mut self) -> Result<(), String> {
+        // Room for a jump to main, which goes again if main comes first.
+        self.reference("main", Patch::Address);
+        self.op(0x1000)?;
+        while let Some(token) = self.take() {
+            self.statement(&token.text)?;
+        }
+
+        match self.flow.last() {
+            Some(Flow::Branch(_)) => return Err("an 'if … begin' is missing its 'end'".to_string()),
+            Some(Flow::Loop { .. }) => return Err("a 'loop' is missing its 'again'".to_string()),
+            None => {}
+        }
+        if let Some(name) = &self.next {
+            return Err(format!("':next {}' needs an instruction after it", name));
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let addr = match self.labels.get(&fixup.label) {
+                Some(&addr) => addr as i64,
+                None if fixup.label == "main" => {
+                    return Err("there's no main label for the program to start at".to_string())
+                }
+                None => return Err(format!("'{}' isn't defined", fixup.label)),
+            };
+            let addr = ranged(addr, 0, fixup.patch.max(), &fixup.label)? as u16;
+            let at = (fixup.at - ORIGIN as u32) as usize;
+            match fixup.patch {
+                Patch::Address => {
+                    self.rom[at] |= (addr >> 8) as u8;
+                    self.rom[at + 1] = addr as u8;
+                }
+                Patch::Long => self.rom[at..at + 2].copy_from_slice(&addr.to_be_bytes()),
+                Patch::Unpack(nibble) => {
+                    self.rom[at + 1] = nibble << 4 | (addr >> 8) as u8;
+                    self.rom[at + 3] = addr as u8;
+                }
+                Patch::UnpackLong => {
+                    self.rom[at + 1] = (addr >> 8) as u8;
+                    self.rom[at + 3] = addr as u8;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn take(&mut self) -> Option<Token> {
+        let token = self.tokens.pop()?;
+        self.line = token.line;
+        Some(token)
+    }
+
+    fn next_token(&mut self) -> Result<Token, String> {
+        self.take()
+            .ok_or_else(|| "the program ends in the middle of a statement".to_string())
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.last().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next_token()?;
+        if token.text == expected {
+            Ok(())
+        } else {
+            Err(format!("expected '{}', not '{}'", expected, token.text))
+        }
+    }
+
+    fn statement(&mut self, token: &str) -> Result<(), String> {
+        if let Some(x) = self.register(token) {
+            return self.assignment(x);
+        }
+        match token {
+            ":" => {
+                let name = self.name()?;
+                if name == "main" && self.here == ORIGIN as u32 + 2 && self.rom.len() == 2 {
+                    // Nothing comes before main, so there's no need to jump to it.
+                    self.rom.clear();
+                    self.fixups.clear();
+                    self.here = ORIGIN as u32;
+                }
+                self.define(name, self.here, true)
+            }
+            ":next" => {
+                self.next = Some(self.name()?);
+                Ok(())
+            }
+            ":alias" => {
+                let name = self.name()?;
+                let x = self.reg()?;
+                self.aliases.insert(name, x);
+                Ok(())
+            }
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next_token()?;
+                let value = self.value(&value.text)?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":calc" => {
+                let name = self.name()?;
+                self.expect("{")?;
+                let value = self.calc()?;
+                self.constants.insert(name, value);
+                Ok(())
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    self.take();
+                    self.calc()?
+                } else {
+                    let value = self.next_token()?;
+                    self.value(&value.text)?
+                };
+                let byte = ranged(value as i64, -0x80, 0xff, &value.to_string())?;
+                self.write(byte as u8)
+            }
+            ":org" => {
+                let addr = self.next_token()?;
+                let value = ranged(self.value(&addr.text)? as i64, 0, 0xffff, &addr.text)?;
+                if value < ORIGIN as i64 {
+                    return Err(format!(
+                        "':org {}' is below where programs start",
+                        addr.text
+                    ));
+                }
+                self.here = value as u32;
+                Ok(())
+            }
+            ":unpack" => {
+                let high = self.next_token()?;
+                let label = self.next_token()?;
+                let (patch, high) = match high.text.as_str() {
+                    "long" => (Patch::UnpackLong, 0),
+                    text => {
+                        let nibble = self.nibble(text)?;
+                        (Patch::Unpack(nibble), nibble)
+                    }
+                };
+                let addr = self.address(&label.text, patch)?;
+                let (v0, v1) = match patch {
+                    Patch::UnpackLong => (addr >> 8, addr & 0xff),
+                    _ => ((high as u16) << 4 | addr >> 8, addr & 0xff),
+                };
+                self.op(0x6000 | v0)?;
+                self.op(0x6100 | v1)
+            }
+            ":call" => self.address_op(0x2000),
+            ":macro" => self.define_macro(),
+            ":assert" => {
+                let mut message = "assertion failed".to_string();
+                if let Some(text) = self.peek().and_then(|text| text.strip_prefix('"')) {
+                    message = text.trim_end_matches('"').to_string();
+                    self.take();
+                }
+                self.expect("{")?;
+                if self.calc()? == 0.0 {
+                    return Err(message);
+                }
+                Ok(())
+            }
+            ":breakpoint" => self.next_token().map(drop),
+            ":monitor" => {
+                self.next_token()?;
+                self.next_token().map(drop)
+            }
+            ";" | "return" => self.op(0x00ee),
+            "clear" => self.op(0x00e0),
+            "exit" => self.op(0x00fd),
+            "lores" => self.op(0x00fe),
+            "hires" => self.op(0x00ff),
+            "scroll-right" => self.op(0x00fb),
+            "scroll-left" => self.op(0x00fc),
+            "scroll-down" | "scroll-up" => {
+                let rows = self.next_token()?;
+                let rows = self.nibble(&rows.text)? as u16;
+                self.op(if token == "scroll-down" {
+                    0x00c0
+                } else {
+                    0x00d0
+                } | rows)
+            }
+            "audio" => self.op(0xf002),
+            "plane" => {
+                let planes = self.next_token()?;
+                let planes = ranged(self.value(&planes.text)? as i64, 0, 3, &planes.text)?;
+                self.op(0xf001 | (planes as u16) << 8)
+            }
+            "bcd" => self.register_op(0xf033),
+            "saveflags" => self.register_op(0xf075),
+            "loadflags" => self.register_op(0xf085),
+            "save" | "load" => {
+                let x = self.reg()? as u16;
+                if self.peek() == Some("-") {
+                    self.take();
+                    let y = self.reg()? as u16;
+                    let op = if token == "save" { 0x5002 } else { 0x5003 };
+                    self.op(op | x << 8 | y << 4)
+                } else {
+                    self.op(if token == "save" { 0xf055 } else { 0xf065 } | x << 8)
+                }
+            }
+            "sprite" => {
+                let x = self.reg()? as u16;
+                let y = self.reg()? as u16;
+                let rows = self.next_token()?;
+                let rows = self.nibble(&rows.text)? as u16;
+                self.op(0xd000 | x << 8 | y << 4 | rows)
+            }
+            "jump" => self.address_op(0x1000),
+            "jump0" => self.address_op(0xb000),
+            "native" => self.address_op(0x0000),
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let op = match token {
+                    "delay" => 0xf015,
+                    "buzzer" => 0xf018,
+                    _ => 0xf03a,
+                };
+                self.register_op(op)
+            }
+            "i" => {
+                let op = self.next_token()?;
+                match op.text.as_str() {
+                    "+=" => self.register_op(0xf01e),
+                    ":=" => match self.peek() {
+                        Some("hex") => {
+                            self.take();
+                            self.register_op(0xf029)
+                        }
+                        Some("bighex") => {
+                            self.take();
+                            self.register_op(0xf030)
+                        }
+                        Some("long") => {
+                            self.take();
+                            let addr = self.next_token()?;
+                            self.op(0xf000)?;
+                            let addr = self.address(&addr.text, Patch::Long)?;
+                            addr.to_be_bytes()
+                                .into_iter()
+                                .try_for_each(|byte| self.write(byte))
+                        }
+                        _ => self.address_op(0xa000),
+                    },
+                    other => Err(format!("'i {}' isn't an instruction", other)),
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                self.setup(&condition)?;
+                let then = self.next_token()?;
+                match then.text.as_str() {
+                    "then" => self.op(condition.unless),
+                    "begin" => {
+                        self.op(condition.when)?;
+                        self.flow.push(Flow::Branch(self.here));
+                        self.op(0x1000)
+                    }
+                    other => Err(format!(
+                        "expected 'then' or 'begin' after the condition, not '{}'",
+                        other
+                    )),
+                }
+            }
+            "else" => {
+                let Some(Flow::Branch(at)) = self.flow.pop() else {
+                    return Err("'else' without an 'if … begin'".to_string());
+                };
+                let end = self.here;
+                self.op(0x1000)?;
+                self.patch_jump(at, self.here)?;
+                self.flow.push(Flow::Branch(end));
+                Ok(())
+            }
+            "end" => {
+                let Some(Flow::Branch(at)) = self.flow.pop() else {
+                    return Err("'end' without an 'if … begin'".to_string());
+                };
+                self.patch_jump(at, self.here)
+            }
+            "loop" => {
+                self.flow.push(Flow::Loop {
+                    start: self.here,
+                    exits: Vec::new(),
+                });
+                Ok(())
+            }
+            "while" => {
+                if !self
+                    .flow
+                    .iter()
+                    .any(|flow| matches!(flow, Flow::Loop { .. }))
+                {
+                    return Err("'while' outside a loop".to_string());
+                }
+                let condition = self.condition()?;
+                self.setup(&condition)?;
+                self.op(condition.when)?;
+                let exit = self.here;
+                self.op(0x1000)?;
+                if let Some(Flow::Loop { exits, .. }) = self
+                    .flow
+                    .iter_mut()
+                    .rev()
+                    .find(|flow| matches!(flow, Flow::Loop { .. }))
+                {
+                    exits.push(exit);
+                }
+                Ok(())
+            }
+            "again" => {
+                let Some(Flow::Loop { start, exits }) = self.flow.pop() else {
+                    return Err("'again' without a 'loop'".to_string());
+                };
+                let jump = self.here;
+                self.op(0x1000)?;
+                self.patch_jump(jump, start)?;
+                exits
+                    .into_iter()
+                    .try_for_each(|exit| self.patch_jump(exit, self.here))
+            }
+            _ if self.macros.contains_key(token) => self.expand(token),
+            // A number on its own is a byte of data, and a name on its own calls a subroutine.
+            _ if number(token).is_some() || self.constants.contains_key(token) => {
+                let byte = ranged(self.value(token)? as i64, -0x80, 0xff, token)?;
+                self.write(byte as u8)
+            }
+            _ if is_name(token) => {
+                let addr = self.address(token, Patch::Address)?;
+                self.op(0x2000 | addr)
+            }
+            _ => Err(format!("'{}' isn't an instruction", token)),
+        }
+    }
+
+    /// Compiles a statement starting with register `x`, like `vx += 1`.
+    fn assignment(&mut self, x: u8) -> Result<(), String> {
+        let op = self.next_token()?;
+        let value = self.next_token()?;
+        let x = (x as u16) << 8;
+        let code = match (op.text.as_str(), self.register(&value.text)) {
+            (":=", Some(y)) => 0x8000 | x | (y as u16) << 4,
+            (":=", None) => match value.text.as_str() {
+                "random" => {
+                    let mask = self.next_token()?;
+                    0xc000 | x | self.byte(&mask.text)?
+                }
+                "key" => 0xf00a | x,
+                "delay" => 0xf007 | x,
+                text => 0x6000 | x | self.byte(text)?,
+            },
+            ("+=", Some(y)) => 0x8004 | x | (y as u16) << 4,
+            ("+=", None) => 0x7000 | x | self.byte(&value.text)?,
+            ("-=", Some(y)) => 0x8005 | x | (y as u16) << 4,
+            ("-=", None) => 0x7000 | x | (self.byte(&value.text)? as u8).wrapping_neg() as u16,
+            ("=-", Some(y)) => 0x8007 | x | (y as u16) << 4,
+            ("|=", Some(y)) => 0x8001 | x | (y as u16) << 4,
+            ("&=", Some(y)) => 0x8002 | x | (y as u16) << 4,
+            ("^=", Some(y)) => 0x8003 | x | (y as u16) << 4,
+            (">>=", Some(y)) => 0x8006 | x | (y as u16) << 4,
+            ("<<=", Some(y)) => 0x800e | x | (y as u16) << 4,
+            (op, _) => {
+                return Err(format!(
+                    "'v{:x} {} {}' isn't an instruction",
+                    x >> 8,
+                    op,
+                    value.text
+                ))
+            }
+        };
+        self.op(code)
+    }
+
+    /// Reads a condition like `v0 == 3` or `v1 key`, for `if` and `while`.
+    fn condition(&mut self) -> Result<Condition, String> {
+        let x = self.reg()? as u16;
+        let op = self.next_token()?;
+        let (key, not_key) = (0xe09e | x << 8, 0xe0a1 | x << 8);
+        let op = op.text.as_str();
+        match op {
+            "key" => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    unless: not_key,
+                    when: key,
+                })
+            }
+            "-key" => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    unless: key,
+                    when: not_key,
+                })
+            }
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => {}
+            other => {
+                return Err(format!(
+                    "'{}' isn't a comparison (expected ==, !=, <, >, <=, >=, key or -key)",
+                    other
+                ))
+            }
+        }
+        let value = self.next_token()?;
+        let y = self.register(&value.text).map(u16::from);
+        // Instructions that skip the next one when the two are equal, and when they aren't.
+        let (equal, unequal) = match y {
+            Some(y) => (0x5000 | x << 8 | y << 4, 0x9000 | x << 8 | y << 4),
+            None => {
+                let n = self.byte(&value.text)?;
+                (0x3000 | x << 8 | n, 0x4000 | x << 8 | n)
+            }
+        };
+        let (setup, flag_means_true) = match (op, y) {
+            ("==", _) => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    unless: unequal,
+                    when: equal,
+                })
+            }
+            ("!=", _) => {
+                return Ok(Condition {
+                    setup: Vec::new(),
+                    unless: equal,
+                    when: unequal,
+                })
+            }
+            // The rest subtract one side from the other in vf, which leaves it 1 if there was no
+            // borrow, i.e. if the side subtracted from was at least as big as the other.
+            ("<" | ">=", Some(y)) => (vec![0x8f00 | x << 4, 0x8f05 | y << 4], op == ">="),
+            ("<" | ">=", None) => (vec![0x6f00 | (equal & 0xff), 0x8f07 | x << 4], op == ">="),
+            (_, Some(y)) => (vec![0x8f00 | y << 4, 0x8f05 | x << 4], op == "<="),
+            (_, None) => (vec![0x6f00 | (equal & 0xff), 0x8f05 | x << 4], op == "<="),
+        };
+        let (vf_zero, vf_set) = (0x3f00, 0x4f00);
+        Ok(if flag_means_true {
+            Condition {
+                setup,
+                unless: vf_zero,
+                when: vf_set,
+            }
+        } else {
+            Condition {
+                setup,
+                unless: vf_set,
+                when: vf_zero,
+            }
+        })
+    }
+
+    fn setup(&mut self, condition: &Condition) -> Result<(), String> {
+        condition.setup.iter().try_for_each(|&op| self.op(op))
+    }
+
+    /// Evaluates a `{ … }` expression, the opening brace already taken. As in Octo, operators all
+    /// bind equally tightly and group to the right, so `2 * 3 + 1` is 8; brackets group too.
+    fn calc(&mut self) -> Result<f64, String> {
+        let value = self.expression()?;
+        self.expect("}")?;
+        Ok(value)
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        const BINARY: [&str; 20] = [
+            "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", "<=",
+            "==", "!=", ">", ">=", "&&",
+        ];
+        let left = self.term()?;
+        let Some(op) = self.peek().filter(|op| BINARY.contains(op)) else {
+            return Ok(left);
+        };
+        let op = op.to_string();
+        self.take();
+        let right = self.expression()?;
+        let (a, b) = (left as i64, right as i64);
+        let shift = |b: i64| u32::try_from(b).unwrap_or(u32::MAX);
+        Ok(match op.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" | "%" if right == 0.0 => return Err("division by zero".to_string()),
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(shift(b)).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(shift(b)).unwrap_or(0) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => f64::from(left < right),
+            "<=" => f64::from(left <= right),
+            "==" => f64::from(left == right),
+            "!=" => f64::from(left != right),
+            ">" => f64::from(left > right),
+            ">=" => f64::from(left >= right),
+            _ => f64::from(left != 0.0 && right != 0.0),
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let token = self.next_token()?;
+        Ok(match token.text.as_str() {
+            "(" => {
+                let value = self.expression()?;
+                self.expect(")")?;
+                value
+            }
+            "HERE" => self.here as f64,
+            "PI" => std::f64::consts::PI,
+            "E" => std::f64::consts::E,
+            "@" => {
+                let addr = self.term()? as i64 - ORIGIN as i64;
+                let byte = usize::try_from(addr).ok().and_then(|i| self.rom.get(i));
+                byte.copied().unwrap_or_default() as f64
+            }
+            "-" => -self.term()?,
+            "~" => !(self.term()? as i64) as f64,
+            "!" => f64::from(self.term()? == 0.0),
+            "sin" => self.term()?.sin(),
+            "cos" => self.term()?.cos(),
+            "tan" => self.term()?.tan(),
+            "exp" => self.term()?.exp(),
+            "log" => self.term()?.ln(),
+            "abs" => self.term()?.abs(),
+            "sqrt" => self.term()?.sqrt(),
+            "sign" => self.term()?.signum(),
+            "ceil" => self.term()?.ceil(),
+            "floor" => self.term()?.floor(),
+            text => self.value(text)?,
+        })
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop {
+            let param = self.next_token()?;
+            if param.text == "{" {
+                break;
+            }
+            params.push(param.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next_token()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 1 => break,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { params, body });
+        Ok(())
+    }
+
+    /// Replaces a use of macro `name` with its body, its parameters replaced by the tokens after
+    /// the name.
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return Err(format!("'{}' expands forever", name));
+        }
+        let Macro { params, body } = self.macros[name].clone();
+        let args = params
+            .iter()
+            .map(|_| self.next_token())
+            .collect::<Result<Vec<_>, _>>()?;
+        let expanded = body.into_iter().map(|token| {
+            match params.iter().position(|param| *param == token.text) {
+                Some(i) => args[i].clone(),
+                None => token,
+            }
+        });
+        let expanded: Vec<Token> = expanded.collect();
+        self.tokens.extend(expanded.into_iter().rev());
+        Ok(())
+    }
+
+    fn define(&mut self, name: String, addr: u32, named: bool) -> Result<(), String> {
+        if self.labels.contains_key(&name) {
+            return Err(format!("'{}' is defined more than once", name));
+        }
+        // `:next` labels point into the middle of an instruction, so they make poor names.
+        if named {
+            self.symbols.insert(addr as u16, name.as_str());
+        }
+        self.labels.insert(name, addr);
+        Ok(())
+    }
+
+    /// The name for a label, constant, alias or macro.
+    fn name(&mut self) -> Result<String, String> {
+        let token = self.next_token()?;
+        if is_name(&token.text) && self.register(&token.text).is_none() {
+            Ok(token.text)
+        } else {
+            Err(format!("'{}' can't be used as a name", token.text))
+        }
+    }
+
+    fn register(&self, text: &str) -> Option<u8> {
+        if let Some(&x) = self.aliases.get(text) {
+            return Some(x);
+        }
+        let digit = text.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn reg(&mut self) -> Result<u8, String> {
+        let token = self.next_token()?;
+        self.register(&token.text)
+            .ok_or_else(|| format!("'{}' isn't a register", token.text))
+    }
+
+    /// Compiles `op` with a register from the next token in its second nibble.
+    fn register_op(&mut self, op: u16) -> Result<(), String> {
+        let x = self.reg()? as u16;
+        self.op(op | x << 8)
+    }
+
+    /// Compiles `op` with an address from the next token in its low 12 bits.
+    fn address_op(&mut self, op: u16) -> Result<(), String> {
+        let token = self.next_token()?;
+        let addr = self.address(&token.text, Patch::Address)?;
+        self.op(op | addr)
+    }
+
+    /// The value of a number, constant or label that's already been defined.
+    fn value(&self, text: &str) -> Result<f64, String> {
+        if let Some(value) = number(text).or_else(|| self.constants.get(text).copied()) {
+            return Ok(value);
+        }
+        match self.labels.get(text) {
+            Some(&addr) => Ok(addr as f64),
+            None if is_name(text) => Err(format!("'{}' isn't defined", text)),
+            None => Err(format!("'{}' isn't a number", text)),
+        }
+    }
+
+    fn byte(&self, text: &str) -> Result<u16, String> {
+        Ok(ranged(self.value(text)? as i64, -0x80, 0xff, text)? as u8 as u16)
+    }
+
+    fn nibble(&self, text: &str) -> Result<u8, String> {
+        Ok(ranged(self.value(text)? as i64, 0, 0xf, text)? as u8)
+    }
+
+    /// The address `text` refers to, or 0 for now if it's a label that hasn't been defined yet,
+    /// to be patched in at `here` once it is.
+    fn address(&mut self, text: &str, patch: Patch) -> Result<u16, String> {
+        match self.value(text) {
+            Ok(addr) => Ok(ranged(addr as i64, 0, patch.max(), text)? as u16),
+            Err(_) if is_name(text) => {
+                self.reference(text, patch);
+                Ok(0)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn reference(&mut self, label: &str, patch: Patch) {
+        self.fixups.push(Fixup {
+            at: self.here,
+            patch,
+            label: label.to_string(),
+            line: self.line,
+        });
+    }
+
+    /// Points the jump at `at` to `target`.
+    fn patch_jump(&mut self, at: u32, target: u32) -> Result<(), String> {
+        let target = ranged(target as i64, 0, 0xfff, "the end of the block")? as u16;
+        let at = (at - ORIGIN as u32) as usize;
+        self.rom[at..at + 2].copy_from_slice(&(0x1000 | target).to_be_bytes());
+        Ok(())
+    }
+
+    /// Compiles an instruction, defining the label from a `:next` before it.
+    fn op(&mut self, op: u16) -> Result<(), String> {
+        if let Some(name) = self.next.take() {
+            self.define(name, self.here + 1, false)?;
+        }
+        op.to_be_bytes()
+            .into_iter()
+            .try_for_each(|byte| self.write(byte))
+    }
+
+    fn write(&mut self, byte: u8) -> Result<(), String> {
+        if self.here > 0xffff {
+            return Err("the program doesn't fit in memory".to_string());
+        }
+        let at = (self.here - ORIGIN as u32) as usize;
+        if self.rom.len() <= at {
+            self.rom.resize(at + 1, 0);
+        }
+        self.rom[at] = byte;
+        self.here += 1;
+        Ok(())
+    }
+}
+
+/// Parses a decimal, hex (`0x1f`) or binary (`0b101`) number, which can be negative.
+fn number(text: &str) -> Option<f64> {
+    let (sign, digits) = match text.strip_prefix('-') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(sign * value)
+}
+
+/// Whether `text` could name something. Octo's names can have almost anything in them, dashes
+/// included, as long as they start with a letter or an underscore.
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_alphabetic() || c == '_')
+        && !text.contains(['{', '}', '(', ')', '"'])
+}
+
+fn ranged(value: i64, min: i64, max: i64, expr: &str) -> Result<i64, String> {
+    if (min..=max).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("'{}' is out of range ({})", expr, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(source: &str) -> Vec<u16> {
+        let (rom, _) = compile(source).unwrap();
+        rom.chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1.min(word.len() - 1)]]))
+            .collect()
+    }
+
+    #[test]
+    fn compiles_statements() {
+        let source = "
+            : main
+                clear  v3 := 0x12  v3 += v4  v3 -= 1  v3 =- v4  v3 >>= v3
+                v2 := random 0x0f  v2 := key  v2 := delay  delay := v2  buzzer := v2
+                i := main  i := hex v1  i += v1  sprite v1 v2 5
+                save v3  load v1 - v4  bcd v0  scroll-down 4  plane 3  ;
+        ";
+        assert_eq!(
+            words(source),
+            [
+                0x00e0, 0x6312, 0x8344, 0x73ff, 0x8347, 0x8336, 0xc20f, 0xf20a, 0xf207, 0xf215,
+                0xf218, 0xa200, 0xf129, 0xf11e, 0xd125, 0xf355, 0x5143, 0xf033, 0x00c4, 0xf301,
+                0x00ee,
+            ]
+        );
+    }
+
+    #[test]
+    fn jumps_to_main_unless_it_comes_first() {
+        let (rom, symbols) = compile(": data 1 2\n: main jump main").unwrap();
+        assert_eq!(rom, [0x12, 0x04, 1, 2, 0x12, 0x04]);
+        assert_eq!(
+            symbols.iter().collect::<Vec<_>>(),
+            [(0x202, "data"), (0x204, "main")]
+        );
+        assert_eq!(
+            compile(": start ;").unwrap_err().message,
+            "there's no main label for the program to start at"
+        );
+    }
+
+    #[test]
+    fn compiles_control_flow() {
+        let source = "
+            : main
+                loop
+                    while v0 != 5
+                    v0 += 1
+                    if v0 == v1 then v2 := 1
+                    if v0 < 3 begin v2 := 2 else v2 := 3 end
+                    if v1 key then return
+                again
+        ";
+        assert_eq!(
+            words(source),
+            [
+                0x4005, 0x121e, // while: out of the loop unless v0 != 5
+                0x7001, 0x9010, 0x6201, // if v0 == v1 then
+                0x6f03, 0x8f07, 0x3f00, 0x1216, // if v0 < 3 begin: vf = v0 - 3 borrows
+                0x6202, 0x1218, 0x6203, // else … end
+                0xe1a1, 0x00ee, 0x1200, // again
+            ]
+        );
+    }
+
+    #[test]
+    fn compiles_directives() {
+        let source = "
+            :alias x v4
+            :const WIDTH 64
+            :calc HALF { WIDTH / 2 }
+            :calc MIXED { 2 * 3 + 1 }
+            :macro twice reg value { reg += value reg += value }
+            : main
+                x := HALF
+                twice x 1
+                :unpack 0xa sprite
+                :next target v0 := 0
+                i := long target
+                :byte { MIXED }
+            :org 0x300
+            : sprite 0x18
+        ";
+        let (rom, symbols) = compile(source).unwrap();
+        assert_eq!(
+            &rom[..20],
+            [
+                0x64, 32, 0x74, 1, 0x74, 1, 0x60, 0xa3, 0x61, 0x00, 0x60, 0x00, 0xf0, 0x00, 0x02,
+                0x0b, 8, 0, 0, 0
+            ]
+        );
+        assert_eq!(rom.len(), 0x101);
+        assert_eq!(rom[0x100], 0x18);
+        assert_eq!(symbols.name(0x300), Some("sprite"));
+        assert_eq!(symbols.name(0x20b), None);
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = compile(": main\n  v0 := 1\n  jump nowhere").unwrap_err();
+        assert_eq!(error.line, 3);
+        assert_eq!(error.message, "'nowhere' isn't defined");
+        let error = compile(": main\n  v0 := 256").unwrap_err();
+        assert_eq!(
+            (error.line, error.message.as_str()),
+            (2, "'256' is out of range (256)")
+        );
+        assert!(compile(": main loop v0 += 1").is_err());
+        assert!(compile(": main : main").is_err());
+        assert!(compile(": main :assert \"too big\" { 2 > 3 }").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use std::time::{Duration, Instant};
+
+use crate::FRAMES_PER_SECOND;
+
+/// A 60th of a second, exactly. Rounding it to 16ms runs everything 4% fast.
+pub const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / FRAMES_PER_SECOND as u64);
+/// The most real frames' worth of time `FramePacer` will make up for after a stall, e.g. when the
+/// process was suspended, rather than running them all at once.
+const MAX_FRAMES_BEHIND: u32 = 6;
+/// How often the measured rates are updated.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Keeps a loop to 60 real frames a second, and works out how many emulated frames are due in
+/// each one at a given speed.
+///
+/// Real frames are paced against fixed deadlines rather than by sleeping a frame at a time, so
+/// sleeps that overshoot, and frames that take too long, don't add up to drift. A frame that
+/// runs late just means the next wait is shorter, or skipped.
+pub struct FramePacer {
+    /// When the next real frame is due.
+    deadline: Instant,
+    /// When `frames_due` was last called.
+    last: Instant,
+    /// Emulated time that's passed but hasn't been run yet.
+    owed: Duration,
+    rates: Rates,
+}
+
+impl FramePacer {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            deadline: now + FRAME_DURATION,
+            last: now,
+            owed: Duration::ZERO,
+            rates: Rates::new(),
+        }
+    }
+
+    /// How many emulated frames are due, with emulated time running at `percent` of real time.
+    pub fn frames_due(&mut self, now: Instant, percent: u32) -> u32 {
+        let elapsed = (now - self.last).min(FRAME_DURATION * MAX_FRAMES_BEHIND);
+        self.owed += elapsed * percent / 100;
+        self.last = now;
+        let due = (self.owed.as_nanos() / FRAME_DURATION.as_nanos()) as u32;
+        self.owed -= FRAME_DURATION * due;
+        due
+    }
+
+    /// When the next real frame is due.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Sleeps until the next real frame is due, or not at all if it's already late. If it's more
+    /// than a frame late, the deadlines start over from now rather than trying to catch up.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        match self.deadline.checked_duration_since(now) {
+            Some(wait) => std::thread::sleep(wait),
+            None if now - self.deadline > FRAME_DURATION => self.deadline = now,
+            None => {}
+        }
+        self.deadline += FRAME_DURATION;
+    }
+
+    /// Counts a frame that's been run, with the CPU's instruction count after it.
+    pub fn ran_frame(&mut self, now: Instant, instructions: u64) {
+        self.rates.count(now, instructions);
+    }
+
+    /// Emulated frames and instructions per second, as last measured.
+    pub fn rates(&self) -> (u32, u32) {
+        (self.rates.fps, self.rates.ips)
+    }
+}
+
+/// Frames and instructions counted over a window, to measure how fast emulation is really going.
+struct Rates {
+    /// When the window started and the instruction count then, once the first frame has run.
+    window: Option<(Instant, u64)>,
+    frames: u32,
+    fps: u32,
+    ips: u32,
+}
+
+impl Rates {
+    fn new() -> Self {
+        Self {
+            window: None,
+            frames: 0,
+            fps: 0,
+            ips: 0,
+        }
+    }
+
+    fn count(&mut self, now: Instant, instructions: u64) {
+        let Some((start, start_instructions)) = self.window else {
+            self.window = Some((now, instructions));
+            return;
+        };
+        self.frames += 1;
+        let elapsed = now - start;
+        if elapsed >= RATE_WINDOW {
+            let per_second = |n: u64| (n as f64 / elapsed.as_secs_f64()).round() as u32;
+            self.fps = per_second(self.frames as u64);
+            self.ips = per_second(instructions - start_instructions);
+            self.window = Some((now, instructions));
+            self.frames = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paces_emulated_time_by_speed() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(start);
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION * 3, 100), 3);
+        // Half speed owes a frame every two.
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION * 4, 50), 0);
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION * 5, 50), 1);
+        assert_eq!(pacer.frames_due(start + FRAME_DURATION * 6, 1000), 10);
+        // A long stall isn't made up all at once, or at all.
+        assert_eq!(pacer.frames_due(start + Duration::from_secs(10), 100), 6);
+        let later = start + Duration::from_secs(10) + FRAME_DURATION;
+        assert_eq!(pacer.frames_due(later, 100), 1);
+    }
+
+    #[test]
+    fn measures_rates() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::new(start);
+        for frame in 0..=61 {
+            pacer.ran_frame(start + FRAME_DURATION * frame, frame as u64 * 10);
+        }
+        assert_eq!(pacer.rates(), (60, 600));
+    }
+}
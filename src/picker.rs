@@ -0,0 +1,253 @@
+//! The start screen shown when there's no ROM on the command line: the built-in ROMs, recently
+//! run ones, and a browser for the ROM directory, with each known ROM's title from the database.
+use std::io::stdout;
+use std::path::{Path, PathBuf};
+
+use crate::terminal::TerminalGuard;
+use chip8::builtin::{BuiltinRom, BUILTIN_ROMS};
+use chip8::RomInfo;
+use crossterm::event::{self, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::{
+    prelude::*,
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+};
+
+/// File extensions ROMs commonly have, and those of Octo source and .c8b files, which run too.
+const ROM_EXTENSIONS: [&str; 6] = ["ch8", "c8", "sc8", "xo8", "8o", "c8b"];
+
+/// What the user picked.
+pub enum Pick {
+    Builtin(&'static BuiltinRom),
+    File(PathBuf),
+}
+
+/// What a key press comes to.
+enum Action {
+    Stay,
+    Run(Pick),
+    Quit,
+}
+
+enum Entry {
+    Builtin(&'static BuiltinRom),
+    Recent(PathBuf),
+    Parent,
+    Dir(PathBuf),
+    File(PathBuf, Option<&'static RomInfo>),
+}
+
+impl Entry {
+    fn label(&self) -> String {
+        let name = |path: &Path| {
+            path.file_name().map_or_else(
+                || path.display().to_string(),
+                |name| name.to_string_lossy().into(),
+            )
+        };
+        match self {
+            Entry::Builtin(rom) => rom.name.to_string(),
+            Entry::Recent(path) => path.display().to_string(),
+            Entry::Parent => "..".to_string(),
+            Entry::Dir(path) => format!("{}/", name(path)),
+            Entry::File(path, _) => name(path),
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            Entry::Builtin(rom) => format!("{} (built in)", rom.title),
+            Entry::Recent(_) => "recently run".to_string(),
+            Entry::File(_, Some(info)) => format!("{} by {}", info.title, info.author),
+            Entry::Parent | Entry::Dir(_) | Entry::File(_, None) => String::new(),
+        }
+    }
+}
+
+/// The directory being browsed, and what's listed from it.
+struct Browser {
+    root: PathBuf,
+    dir: PathBuf,
+    recent: Vec<PathBuf>,
+    entries: Vec<Entry>,
+    query: String,
+    state: ListState,
+}
+
+impl Browser {
+    fn new(root: PathBuf, recent: Vec<PathBuf>) -> Self {
+        let mut browser = Self {
+            dir: root.clone(),
+            root,
+            recent,
+            entries: Vec::new(),
+            query: String::new(),
+            state: ListState::default(),
+        };
+        browser.open(browser.dir.clone());
+        browser
+    }
+
+    /// Lists `dir`. At the top of the ROM directory, the built-in and recent ROMs come first.
+    fn open(&mut self, dir: PathBuf) {
+        self.entries.clear();
+        if dir == self.root {
+            self.entries
+                .extend(self.recent.iter().cloned().map(Entry::Recent));
+            self.entries.extend(BUILTIN_ROMS.iter().map(Entry::Builtin));
+        } else {
+            self.entries.push(Entry::Parent);
+        }
+        let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect();
+        paths.sort();
+        for path in paths {
+            if path.is_dir() {
+                self.entries.push(Entry::Dir(path));
+            } else if path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ROM_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+            {
+                let info = std::fs::read(&path)
+                    .ok()
+                    .and_then(|rom| RomInfo::lookup(&chip8::rom_sha1(&rom)));
+                self.entries.push(Entry::File(path, info));
+            }
+        }
+        self.dir = dir;
+        self.query.clear();
+        self.state.select(Some(0));
+    }
+
+    /// The entries matching the search, by index into `entries`.
+    fn visible(&self) -> Vec<usize> {
+        let query = self.query.to_lowercase();
+        (0..self.entries.len())
+            .filter(|&i| {
+                let entry = &self.entries[i];
+                query.is_empty()
+                    || entry.label().to_lowercase().contains(&query)
+                    || entry.detail().to_lowercase().contains(&query)
+            })
+            .collect()
+    }
+
+    fn draw(&mut self, f: &mut Frame) {
+        let layout = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Length(3),
+                Constraint::Fill(1),
+                Constraint::Length(1),
+            ])
+            .split(f.size());
+        f.render_widget(
+            Paragraph::new(format!("[Chip8-RS] Pick a ROM from {}", self.dir.display()))
+                .white()
+                .centered()
+                .block(Block::bordered()),
+            layout[0],
+        );
+
+        let visible = self.visible();
+        let width = visible
+            .iter()
+            .map(|&i| self.entries[i].label().chars().count())
+            .max()
+            .unwrap_or(0);
+        let items: Vec<ListItem> = visible
+            .iter()
+            .map(|&i| {
+                let entry = &self.entries[i];
+                ListItem::new(Line::from(vec![
+                    Span::raw(format!("{:width$}  ", entry.label())),
+                    Span::raw(entry.detail()).dark_gray(),
+                ]))
+            })
+            .collect();
+        let list = List::new(items)
+            .highlight_style(Style::new().reversed())
+            .highlight_symbol("> ");
+        f.render_stateful_widget(list, layout[1], &mut self.state);
+
+        let footer = if self.query.is_empty() {
+            "type to search, up and down to choose, enter to run, esc to quit".to_string()
+        } else {
+            format!("search: {} (esc to clear)", self.query)
+        };
+        f.render_widget(Paragraph::new(footer).dark_gray(), layout[2]);
+    }
+
+    fn handle(&mut self, key: event::KeyEvent) -> Action {
+        let visible = self.visible();
+        let selected = self.state.selected().unwrap_or(0);
+        match key.code {
+            KeyCode::Char('c') if key.modifiers == KeyModifiers::CONTROL => return Action::Quit,
+            KeyCode::Esc if self.query.is_empty() => return Action::Quit,
+            KeyCode::Esc => self.query.clear(),
+            KeyCode::Up => self.state.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down => self
+                .state
+                .select(Some((selected + 1).min(visible.len().saturating_sub(1)))),
+            KeyCode::Backspace => {
+                self.query.pop();
+            }
+            KeyCode::Char(c) => self.query.push(c),
+            KeyCode::Enter => {
+                let Some(&i) = visible.get(selected) else {
+                    return Action::Stay;
+                };
+                match &self.entries[i] {
+                    Entry::Builtin(rom) => return Action::Run(Pick::Builtin(rom)),
+                    Entry::Recent(path) | Entry::File(path, _) => {
+                        return Action::Run(Pick::File(path.clone()))
+                    }
+                    Entry::Parent => {
+                        let parent = self.dir.parent().unwrap_or(&self.root).to_path_buf();
+                        self.open(parent);
+                    }
+                    Entry::Dir(dir) => {
+                        let dir = dir.clone();
+                        self.open(dir);
+                    }
+                }
+                return Action::Stay;
+            }
+            _ => return Action::Stay,
+        }
+        // The search changes what's listed, so keep the selection on the list.
+        let count = self.visible().len();
+        let selected = self.state.selected().unwrap_or(0);
+        self.state
+            .select(Some(selected.min(count.saturating_sub(1))));
+        Action::Stay
+    }
+}
+
+/// Takes over the terminal to ask which ROM to run: one from under `root`, a built-in one, or one
+/// of `recent`. Returns `None` if the user backs out.
+pub fn pick_rom(root: PathBuf, recent: Vec<PathBuf>) -> Option<Pick> {
+    let _guard = TerminalGuard::enter(false).ok()?;
+    run(Browser::new(root, recent))
+}
+
+fn run(mut browser: Browser) -> Option<Pick> {
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout())).ok()?;
+    loop {
+        terminal.draw(|f| browser.draw(f)).ok()?;
+        let event::Event::Key(key) = event::read().ok()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        match browser.handle(key) {
+            Action::Run(pick) => return Some(pick),
+            Action::Stay => {}
+            Action::Quit => return None,
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::disasm::Instr;
+use crate::Variant;
+
+/// How many times something ran, and how much host time it took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cost {
+    pub count: u64,
+    pub time: Duration,
+}
+
+impl Cost {
+    fn add(&mut self, other: Cost) {
+        self.count += other.count;
+        self.time += other.time;
+    }
+}
+
+/// Counts every instruction executed, and the host time it took, by address and opcode, along
+/// with the backward jumps taken, which is where a ROM's loops are. Turned off, it records
+/// nothing and costs nothing.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    enabled: bool,
+    /// By address and the opcode there, since self-modifying ROMs can run more than one.
+    instructions: HashMap<(u16, u16), Cost>,
+    /// How many times each backward jump was taken, by its address and its target.
+    jumps: HashMap<(u16, u16), u64>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Starts or stops recording. What's been recorded so far is kept either way.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Counts the instruction `opcode` at `pc`, which took `time` and left the PC at `next`.
+    pub fn record(&mut self, pc: u16, opcode: u16, next: u16, time: Duration) {
+        if !self.enabled {
+            return;
+        }
+        self.instructions
+            .entry((pc, opcode))
+            .or_default()
+            .add(Cost { count: 1, time });
+        // Only jumps: returns go backwards too, but they don't close a loop.
+        if next < pc && matches!(opcode >> 12, 0x1 | 0xb) {
+            *self.jumps.entry((pc, next)).or_default() += 1;
+        }
+    }
+
+    /// Forgets everything recorded so far.
+    pub fn clear(&mut self) {
+        self.instructions.clear();
+        self.jumps.clear();
+    }
+
+    /// Sums up what's been recorded, with opcodes decoded as `variant`.
+    pub fn report(&self, variant: Variant) -> ProfileReport {
+        let mut total = Cost::default();
+        let mut opcodes: HashMap<&'static str, Cost> = HashMap::new();
+        let mut addresses: Vec<_> = self
+            .instructions
+            .iter()
+            .map(|(&(pc, opcode), &cost)| {
+                let pattern =
+                    Instr::decode(opcode, variant).map_or("????", |instr| instr.pattern());
+                total.add(cost);
+                opcodes.entry(pattern).or_default().add(cost);
+                HotAddress {
+                    pc,
+                    opcode,
+                    pattern,
+                    cost,
+                }
+            })
+            .collect();
+        addresses.sort_by_key(|address| (std::cmp::Reverse(address.cost.count), address.pc));
+
+        let mut opcodes: Vec<_> = opcodes.into_iter().collect();
+        opcodes.sort_by_key(|&(pattern, cost)| (std::cmp::Reverse(cost.count), pattern));
+
+        let mut loops: Vec<_> = self
+            .jumps
+            .iter()
+            .map(|(&(end, start), &iterations)| {
+                let mut cost = Cost::default();
+                for (&(pc, _), &instruction) in &self.instructions {
+                    if (start..=end).contains(&pc) {
+                        cost.add(instruction);
+                    }
+                }
+                HotLoop {
+                    start,
+                    end,
+                    iterations,
+                    cost,
+                }
+            })
+            .collect();
+        loops.sort_by_key(|hot| (std::cmp::Reverse(hot.cost.count), hot.start));
+
+        ProfileReport {
+            total,
+            addresses,
+            opcodes,
+            loops,
+        }
+    }
+}
+
+/// An instruction at a particular address, and what it cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotAddress {
+    pub pc: u16,
+    pub opcode: u16,
+    /// The opcode's pattern, like `8XY4`, or `????` if it doesn't decode.
+    pub pattern: &'static str,
+    pub cost: Cost,
+}
+
+/// A backward jump from `end` to `start`, and what everything from `start` to `end` cost,
+/// including any loops inside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotLoop {
+    pub start: u16,
+    pub end: u16,
+    /// How many times the jump back was taken.
+    pub iterations: u64,
+    pub cost: Cost,
+}
+
+/// Where a run spent its time, busiest first in every list.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileReport {
+    pub total: Cost,
+    pub addresses: Vec<HotAddress>,
+    /// By opcode pattern, like `8XY4`.
+    pub opcodes: Vec<(&'static str, Cost)>,
+    pub loops: Vec<HotLoop>,
+}
+
+impl ProfileReport {
+    /// What fraction of all instructions executed `cost` accounts for, as a percentage.
+    pub fn percent(&self, cost: Cost) -> f64 {
+        if self.total.count == 0 {
+            return 0.0;
+        }
+        cost.count as f64 * 100.0 / self.total.count as f64
+    }
+
+    /// The report as JSON, with times in nanoseconds.
+    pub fn to_json(&self) -> String {
+        fn cost(out: &mut String, cost: Cost) {
+            let _ = write!(
+                out,
+                "\"count\":{},\"time_ns\":{}",
+                cost.count,
+                cost.time.as_nanos()
+            );
+        }
+        fn list<T>(
+            out: &mut String,
+            name: &str,
+            items: &[T],
+            mut item: impl FnMut(&mut String, &T),
+        ) {
+            let _ = write!(out, ",\"{}\":[", name);
+            for (i, it) in items.iter().enumerate() {
+                out.push_str(if i == 0 { "{" } else { ",{" });
+                item(out, it);
+                out.push('}');
+            }
+            out.push(']');
+        }
+
+        let mut out = String::from("{");
+        cost(&mut out, self.total);
+        list(&mut out, "addresses", &self.addresses, |out, address| {
+            let _ = write!(
+                out,
+                "\"pc\":{},\"opcode\":{},\"pattern\":\"{}\",",
+                address.pc, address.opcode, address.pattern
+            );
+            cost(out, address.cost);
+        });
+        list(
+            &mut out,
+            "opcodes",
+            &self.opcodes,
+            |out, &(pattern, opcode)| {
+                let _ = write!(out, "\"pattern\":\"{}\",", pattern);
+                cost(out, opcode);
+            },
+        );
+        list(&mut out, "loops", &self.loops, |out, hot| {
+            let _ = write!(
+                out,
+                "\"start\":{},\"end\":{},\"iterations\":{},",
+                hot.start, hot.end, hot.iterations
+            );
+            cost(out, hot.cost);
+        });
+        out.push_str("}\n");
+        out
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_hot_loop() {
+        let mut profiler = Profiler::new();
+        let us = Duration::from_micros(1);
+        profiler.record(0x200, 0x6000, 0x202, us);
+        profiler.set_enabled(true);
+        // ld v0, 0 ... then three times round add v0, 1; jp 0x202
+        profiler.record(0x200, 0x6000, 0x202, us);
+        for _ in 0..3 {
+            profiler.record(0x202, 0x7001, 0x204, us);
+            profiler.record(0x204, 0x1202, 0x202, us);
+        }
+        // ret, which goes back but isn't a loop
+        profiler.record(0x206, 0x00ee, 0x200, us);
+
+        let report = profiler.report(Variant::Chip8);
+        assert_eq!(
+            report.total,
+            Cost {
+                count: 8,
+                time: us * 8
+            }
+        );
+        assert_eq!(
+            report.addresses[0],
+            HotAddress {
+                pc: 0x202,
+                opcode: 0x7001,
+                pattern: "7XKK",
+                cost: Cost {
+                    count: 3,
+                    time: us * 3
+                },
+            }
+        );
+        assert_eq!(
+            report.opcodes[0],
+            (
+                "1NNN",
+                Cost {
+                    count: 3,
+                    time: us * 3
+                }
+            )
+        );
+        assert_eq!(
+            report.loops,
+            [HotLoop {
+                start: 0x202,
+                end: 0x204,
+                iterations: 3,
+                cost: Cost {
+                    count: 6,
+                    time: us * 6
+                },
+            }]
+        );
+        assert_eq!(report.percent(report.loops[0].cost), 75.0);
+
+        let json = report.to_json();
+        assert!(json.starts_with("{\"count\":8,\"time_ns\":8000,\"addresses\":[{\"pc\":514,"));
+        assert!(json.contains(
+            "\"loops\":[{\"start\":514,\"end\":516,\"iterations\":3,\"count\":6,\"time_ns\":6000}]"
+        ));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::Variant;
+
+/// Behaviours that differ between historical interpreters, which ROMs written for one of them
+/// tend to rely on. See https://github.com/Timendus/chip8-test-suite#quirks-test for the details.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift Vx in place, ignoring Vy (CHIP-48 and SUPER-CHIP).
+    pub shift: bool,
+    /// BNNN jumps to XNN + VX instead of NNN + V0 (CHIP-48 and SUPER-CHIP).
+    pub jump: bool,
+    /// FX55/FX65 leave I pointing just past the last register saved or loaded, like the COSMAC
+    /// VIP did. Without it, I is left untouched (SUPER-CHIP).
+    pub loadstore: bool,
+    /// With `loadstore`, FX55/FX65 leave I on the last register rather than past it, CHIP-48's
+    /// off-by-one.
+    pub loadstore_x: bool,
+    /// DXYN waits for the next 60Hz vertical blank before drawing, so at most one sprite is drawn
+    /// per frame (COSMAC VIP). Games tuned for the VIP rely on it to regulate their speed.
+    pub display_wait: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0 afterwards, as a side effect of how the COSMAC VIP
+    /// implemented them.
+    pub vf_reset: bool,
+    /// Sprites drawn partly off-screen are clipped at the edges instead of wrapping around to the
+    /// other side. The starting position still wraps either way.
+    pub clip: bool,
+}
+
+impl Quirks {
+    /// The quirks of the interpreter that defined `variant`, which is what most ROMs targeting it
+    /// will expect.
+    pub const fn preset(variant: Variant) -> Self {
+        match variant {
+            Variant::Chip8 => Self {
+                shift: false,
+                jump: false,
+                loadstore: true,
+                loadstore_x: false,
+                vf_reset: true,
+                display_wait: true,
+                clip: true,
+            },
+            Variant::Chip48 => Self {
+                shift: true,
+                jump: true,
+                loadstore: true,
+                loadstore_x: true,
+                vf_reset: false,
+                display_wait: false,
+                clip: true,
+            },
+            Variant::Schip => Self {
+                shift: true,
+                jump: true,
+                loadstore: false,
+                loadstore_x: false,
+                vf_reset: false,
+                display_wait: false,
+                clip: true,
+            },
+            Variant::XoChip => Self {
+                shift: false,
+                jump: false,
+                loadstore: true,
+                loadstore_x: false,
+                vf_reset: false,
+                display_wait: false,
+                clip: false,
+            },
+            // Mega8 is built on SUPER-CHIP 1.1, and behaves the same.
+            #[cfg(feature = "megachip")]
+            Variant::MegaChip => Self::preset(Variant::Schip),
+        }
+    }
+
+    /// How far FX55/FX65 move I after saving or loading V0 through Vx.
+    pub fn loadstore_step(self, x: u8) -> u16 {
+        match (self.loadstore, self.loadstore_x) {
+            (false, _) => 0,
+            (true, false) => x as u16 + 1,
+            (true, true) => x as u16,
+        }
+    }
+
+    /// The variant these are the preset for, or `None` if they're a mix of their own. MegaChip's
+    /// are SUPER-CHIP's, so they're reported as that.
+    pub fn profile(self) -> Option<Variant> {
+        [
+            Variant::Chip8,
+            Variant::Chip48,
+            Variant::Schip,
+            Variant::XoChip,
+        ]
+        .into_iter()
+        .find(|&variant| Self::preset(variant) == self)
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// What happens when an instruction writes to a protected region of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WriteProtection {
+    /// The write goes through, as it did on the COSMAC VIP, where it was all just RAM.
+    #[default]
+    Allow,
+    /// The write goes through, but it's logged as a warning.
+    Warn,
+    /// The write is dropped and logged, as on interpreters that kept themselves in ROM.
+    Deny,
+}
+
+impl fmt::Display for WriteProtection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WriteProtection::Allow => "allow",
+            WriteProtection::Warn => "warn",
+            WriteProtection::Deny => "deny",
+        })
+    }
+}
+
+impl FromStr for WriteProtection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "allow" => Ok(WriteProtection::Allow),
+            "warn" => Ok(WriteProtection::Warn),
+            "deny" => Ok(WriteProtection::Deny),
+            _ => Err(format!(
+                "unknown write protection '{}' (expected allow, warn or deny)",
+                s
+            )),
+        }
+    }
+}
+
+/// The addresses `start..=end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Region {
+    /// Where the interpreter and its fonts lived on the original machines. Programs start right
+    /// after it, at 0x200.
+    pub const INTERPRETER: Region = Region::new(0x000, 0x1ff);
+
+    pub const fn new(start: u16, end: u16) -> Self {
+        if start <= end {
+            Self { start, end }
+        } else {
+            Self {
+                start: end,
+                end: start,
+            }
+        }
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        (self.start as usize..=self.end as usize).contains(&addr)
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:03x}-{:03x}", self.start, self.end)
+    }
+}
+
+/// Parses a hex address or inclusive range, e.g. `300-3ff`.
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex =
+            |s: &str| u16::from_str_radix(s.to_ascii_lowercase().trim_start_matches("0x"), 16);
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        match (hex(start), hex(end)) {
+            (Ok(start), Ok(end)) => Ok(Region::new(start, end)),
+            _ => Err(format!("'{}' isn't a region like 300-3ff", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_regions() {
+        assert_eq!("0x300-3FF".parse(), Ok(Region::new(0x300, 0x3ff)));
+        assert_eq!("ea0".parse(), Ok(Region::new(0xea0, 0xea0)));
+        assert_eq!("3ff-300".parse::<Region>().map(|r| r.start), Ok(0x300));
+        assert!("300-".parse::<Region>().is_err());
+        assert_eq!(Region::INTERPRETER.to_string(), "000-1ff");
+    }
+}
@@ -0,0 +1,317 @@
+//! A WebSocket server that streams the machine to any number of clients and takes input from
+//! them, for showing an instance with no display of its own in a browser, or debugging one
+//! together from elsewhere. It's a `Frontend`, so `RunLoop` drives it like any other.
+//!
+//! Each frame, every client is sent the display as a binary message whenever it's changed: its
+//! width and height as a byte each, then a byte per pixel, row by row, with bit 0 set for the
+//! first XO-CHIP plane and bit 1 for the second. Then it's sent the machine's state as a JSON
+//! text message, like:
+//!
+//! ```json
+//! {"pc":514,"i":0,"v":[0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0],"sp":0,"stack":[],"delay":0,"sound":0,
+//!  "beeping":false,"paused":false,"stopped_at":null,"halted":null,"speed":"1x","fps":60,"ips":700}
+//! ```
+//!
+//! Clients send JSON text messages back, each with a `type`: `key_down` and `key_up` with a `key`
+//! from 0 to 15, `pause`, `resume`, `step`, `step_over`, `advance_frame`, `rewind`, `reset` (with
+//! `"hard": true` to reload the ROM too), `save_state`, `load_state`, `faster` and `slower`.
+use std::io;
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info, warn};
+use tungstenite::{Message, WebSocket};
+
+use crate::{Command, FrameBuffer, Frontend, InputEvent, RunStatus, CPU};
+
+/// How long a new connection gets to finish the WebSocket handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+type Socket = WebSocket<std::net::TcpStream>;
+
+/// A connected client.
+struct Client {
+    socket: Socket,
+    /// Set until it's been sent the whole display.
+    fresh: bool,
+}
+
+/// The machine's state as clients are sent it.
+#[derive(Serialize)]
+struct State<'a> {
+    pc: u16,
+    i: u16,
+    v: [u8; 16],
+    sp: u8,
+    stack: &'a [u16],
+    delay: u8,
+    sound: u8,
+    beeping: bool,
+    paused: bool,
+    stopped_at: Option<String>,
+    halted: Option<String>,
+    speed: String,
+    fps: u32,
+    ips: u32,
+}
+
+/// What clients can ask for.
+#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    KeyDown {
+        key: u8,
+    },
+    KeyUp {
+        key: u8,
+    },
+    Pause,
+    Resume,
+    Step,
+    StepOver,
+    AdvanceFrame,
+    Rewind,
+    Reset {
+        #[serde(default)]
+        hard: bool,
+    },
+    SaveState,
+    LoadState,
+    Faster,
+    Slower,
+}
+
+impl Request {
+    fn parse(text: &str) -> Result<InputEvent, String> {
+        let request = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        Ok(match request {
+            Request::KeyDown { key } | Request::KeyUp { key } if key > 0xf => {
+                return Err(format!("keys go from 0 to 15, not {}", key))
+            }
+            Request::KeyDown { key } => InputEvent::KeyDown(key),
+            Request::KeyUp { key } => InputEvent::KeyUp(key),
+            Request::Pause => InputEvent::Control(Command::Pause),
+            Request::Resume => InputEvent::Control(Command::Resume),
+            Request::Step => InputEvent::Control(Command::Step),
+            Request::StepOver => InputEvent::Control(Command::StepOver),
+            Request::AdvanceFrame => InputEvent::Control(Command::AdvanceFrame),
+            Request::Rewind => InputEvent::Control(Command::Rewind),
+            Request::Reset { hard } => InputEvent::Reset { hard },
+            Request::SaveState => InputEvent::SaveState,
+            Request::LoadState => InputEvent::LoadState,
+            Request::Faster => InputEvent::Faster,
+            Request::Slower => InputEvent::Slower,
+        })
+    }
+}
+
+/// The display as a binary message.
+fn display_message(fb: &FrameBuffer) -> Message {
+    let mut bytes = Vec::with_capacity(2 + fb.pixels().len());
+    bytes.extend([fb.width() as u8, fb.height() as u8]);
+    bytes.extend_from_slice(fb.pixels());
+    Message::binary(bytes)
+}
+
+/// Whether `result` means the client's still there. Messages that can't be written yet are
+/// buffered, and go out with the next flush.
+fn still_connected(result: tungstenite::Result<()>) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(tungstenite::Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => true,
+        Err(err) => {
+            debug!(target: "remote", "Dropping a client: {}", err);
+            false
+        }
+    }
+}
+
+/// Serves the machine to WebSocket clients. Connections are accepted on a thread of their own.
+pub struct RemoteServer {
+    addr: SocketAddr,
+    clients: Arc<Mutex<Vec<Client>>>,
+    beeping: bool,
+}
+
+impl RemoteServer {
+    /// Starts listening for clients on `addr`.
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let accepted = Arc::clone(&clients);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let peer = stream.peer_addr().ok();
+                // A client that never finishes the handshake mustn't hold the others up forever.
+                let _ = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT));
+                let socket = match tungstenite::accept(stream) {
+                    Ok(socket) => socket,
+                    Err(err) => {
+                        warn!(target: "remote", "Handshake with {:?} failed: {}", peer, err);
+                        continue;
+                    }
+                };
+                if socket.get_ref().set_nonblocking(true).is_err() {
+                    continue;
+                }
+                info!(target: "remote", "Client connected from {:?}", peer);
+                let client = Client {
+                    socket,
+                    fresh: true,
+                };
+                accepted.lock().unwrap().push(client);
+            }
+        });
+        Ok(Self {
+            addr,
+            clients,
+            beeping: false,
+        })
+    }
+
+    /// Where it's listening, with the port filled in if it was picked by the system.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// How many clients are connected.
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+}
+
+impl Frontend for RemoteServer {
+    fn present(&mut self, fb: &FrameBuffer) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain_mut(|client| {
+            if !(client.fresh || fb.is_dirty()) {
+                return true;
+            }
+            client.fresh = false;
+            still_connected(client.socket.send(display_message(fb)))
+        });
+    }
+
+    fn poll_input(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain_mut(|client| loop {
+            match client.socket.read() {
+                Ok(Message::Text(text)) => match Request::parse(&text) {
+                    Ok(event) => events.push(event),
+                    Err(err) => warn!(target: "remote", "Ignoring {:?}: {}", text.as_str(), err),
+                },
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => {
+                    return true
+                }
+                Err(err) => break still_connected(Err(err)),
+            }
+        });
+        events
+    }
+
+    fn beep(&mut self, on: bool) {
+        self.beeping = on;
+    }
+
+    fn present_machine(&mut self, cpu: &CPU, status: &RunStatus) {
+        self.present(cpu.framebuffer());
+        let state = State {
+            pc: cpu.pc,
+            i: cpu.registers.i,
+            v: cpu.registers.v,
+            sp: cpu.sp,
+            stack: cpu.call_stack(),
+            delay: status.delay,
+            sound: status.sound,
+            beeping: self.beeping,
+            paused: status.paused,
+            stopped_at: status.stopped_at.map(|breakpoint| breakpoint.to_string()),
+            halted: status.halted.as_ref().map(ToString::to_string),
+            speed: status.speed.to_string(),
+            fps: status.fps,
+            ips: status.ips,
+        };
+        let json = serde_json::to_string(&state).expect("the state always serializes");
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain_mut(|client| still_connected(client.socket.send(Message::text(&json))));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Keypad, Memory, Quirks};
+    use std::net::TcpStream;
+
+    #[test]
+    fn parses_requests() {
+        assert_eq!(
+            Request::parse(r#"{"type":"key_down","key":5}"#),
+            Ok(InputEvent::KeyDown(5))
+        );
+        assert_eq!(
+            Request::parse(r#"{"type":"reset"}"#),
+            Ok(InputEvent::Reset { hard: false })
+        );
+        assert_eq!(
+            Request::parse(r#"{"type":"step_over"}"#),
+            Ok(InputEvent::Control(Command::StepOver))
+        );
+        assert!(Request::parse(r#"{"type":"key_up","key":16}"#).is_err());
+        assert!(Request::parse(r#"{"type":"quit"}"#).is_err());
+    }
+
+    #[test]
+    fn streams_the_machine_and_takes_input() {
+        let mut server = RemoteServer::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(server.local_addr()).unwrap();
+        let url = format!("ws://{}", server.local_addr());
+        let (mut client, _) = tungstenite::client(url, stream).unwrap();
+        while server.clients() == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let mut cpu = CPU::new(Memory::new(), Arc::new(Keypad::new()), Quirks::default());
+        cpu.registers.v[3] = 7;
+        server.present_machine(&cpu, &RunStatus::default());
+        match client.read().unwrap() {
+            Message::Binary(display) => {
+                assert_eq!(display[..2], [64, 32]);
+                assert_eq!(display.len(), 2 + 64 * 32);
+            }
+            other => panic!("expected the display, got {:?}", other),
+        }
+        let state: serde_json::Value = match client.read().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            other => panic!("expected the state, got {:?}", other),
+        };
+        assert_eq!(
+            (state["pc"].as_u64(), state["v"][3].as_u64()),
+            (Some(0x200), Some(7))
+        );
+        // The display hasn't changed since, so only the state is sent this time.
+        cpu.clear_dirty();
+        server.present_machine(&cpu, &RunStatus::default());
+        assert!(matches!(client.read().unwrap(), Message::Text(_)));
+
+        client
+            .send(Message::text(r#"{"type":"key_down","key":10}"#))
+            .unwrap();
+        let mut events = Vec::new();
+        while events.is_empty() {
+            events = server.poll_input();
+        }
+        assert_eq!(events, [InputEvent::KeyDown(10)]);
+
+        client.close(None).unwrap();
+        while server.clients() > 0 {
+            server.poll_input();
+        }
+    }
+}
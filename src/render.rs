@@ -0,0 +1,257 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::FrameBuffer;
+
+/// How the display is drawn with text characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderStyle {
+    /// A full block per pixel, so pixels come out twice as tall as they are wide.
+    #[default]
+    Block,
+    /// Two full blocks per pixel, side by side, so pixels come out close to square at the height
+    /// of a character. The display takes twice the columns.
+    Wide,
+    /// Two pixels per character, one above the other, with half blocks. Pixels come out close to
+    /// square and the display takes half the rows.
+    HalfBlock,
+    /// Eight pixels per character, two across and four down, with Braille patterns. A hi-res
+    /// display fits in 64x16 characters.
+    Braille,
+}
+
+impl RenderStyle {
+    /// How many display pixels across and down each character covers. Wide pixels are two
+    /// characters across, which this counts as one.
+    pub fn cell_size(self) -> (usize, usize) {
+        match self {
+            RenderStyle::Block | RenderStyle::Wide => (1, 1),
+            RenderStyle::HalfBlock => (1, 2),
+            RenderStyle::Braille => (2, 4),
+        }
+    }
+
+    /// How many characters across and down it takes to draw `fb`.
+    pub fn text_size(self, fb: &FrameBuffer) -> (usize, usize) {
+        self.text_size_for(fb.width(), fb.height())
+    }
+
+    /// How many characters across and down it takes to draw a `width` x `height` display.
+    pub fn text_size_for(self, width: usize, height: usize) -> (usize, usize) {
+        if self == RenderStyle::Wide {
+            return (width * 2, height);
+        }
+        let (cell_width, cell_height) = self.cell_size();
+        (width.div_ceil(cell_width), height.div_ceil(cell_height))
+    }
+
+    /// The style that draws a `width` x `height` display with the biggest square pixels that fit
+    /// in `columns` x `rows` characters: wide, then halfblock, then braille, which is used even if
+    /// it doesn't fit.
+    pub fn fitting(width: usize, height: usize, columns: usize, rows: usize) -> RenderStyle {
+        [RenderStyle::Wide, RenderStyle::HalfBlock]
+            .into_iter()
+            .find(|style| {
+                let (text_width, text_height) = style.text_size_for(width, height);
+                text_width <= columns && text_height <= rows
+            })
+            .unwrap_or(RenderStyle::Braille)
+    }
+
+    /// Line `row` of `fb` drawn as text.
+    pub fn line(self, fb: &FrameBuffer, row: usize) -> String {
+        let lit = |x, y| if fb.get(x, y) != 0 { u8::MAX } else { 0 };
+        self.cells(self.text_size(fb).0, row, lit)
+            .map(|cell| cell.glyph)
+            .collect()
+    }
+
+    /// Line `row` of a `columns` character wide display, with pixels at the brightness `level`
+    /// gives them, from 0 for off to 255 for fully lit.
+    pub fn cells(
+        self,
+        columns: usize,
+        row: usize,
+        level: impl Fn(usize, usize) -> u8,
+    ) -> impl Iterator<Item = Cell> {
+        let (cell_width, cell_height) = self.cell_size();
+        let y = row * cell_height;
+        (0..columns).map(move |col| {
+            let x = match self {
+                RenderStyle::Wide => col / 2,
+                _ => col * cell_width,
+            };
+            match self {
+                RenderStyle::Block | RenderStyle::Wide => {
+                    Cell::new(block(level(x, y) != 0), level(x, y), 0)
+                }
+                RenderStyle::HalfBlock => half_block(level(x, y), level(x, y + 1)),
+                RenderStyle::Braille => {
+                    let brightest = (0..cell_width * cell_height)
+                        .map(|i| level(x + i % cell_width, y + i / cell_width))
+                        .max()
+                        .unwrap_or(0);
+                    let glyph = braille(|dx, dy| level(x + dx, y + dy) != 0);
+                    Cell::new(glyph, brightest, 0)
+                }
+            }
+        })
+    }
+
+    /// The lines that display rows `rows` are drawn on.
+    pub fn lines_for(self, rows: std::ops::Range<usize>) -> std::ops::Range<usize> {
+        let cell_height = self.cell_size().1;
+        rows.start / cell_height..rows.end.div_ceil(cell_height)
+    }
+}
+
+/// A character of rendered display, and how bright its foreground and background are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub glyph: char,
+    pub fg: u8,
+    pub bg: u8,
+}
+
+impl Cell {
+    fn new(glyph: char, fg: u8, bg: u8) -> Self {
+        Self { glyph, fg, bg }
+    }
+}
+
+fn block(lit: bool) -> char {
+    if lit {
+        '█'
+    } else {
+        ' '
+    }
+}
+
+/// The top pixel is drawn in the foreground and the bottom one in the background, unless only the
+/// bottom one is lit.
+fn half_block(top: u8, bottom: u8) -> Cell {
+    match (top, bottom) {
+        (0, 0) => Cell::new(' ', 0, 0),
+        (0, _) => Cell::new('▄', bottom, 0),
+        _ if top == bottom => Cell::new('█', top, 0),
+        _ => Cell::new('▀', top, bottom),
+    }
+}
+
+/// The Braille pattern with a dot for each lit pixel in a 2x4 cell. The dots aren't numbered in
+/// reading order: the bottom row was added later, so it's dots 7 and 8.
+fn braille(lit: impl Fn(usize, usize) -> bool) -> char {
+    const DOTS: [(usize, usize); 8] = [
+        (0, 0),
+        (0, 1),
+        (0, 2),
+        (1, 0),
+        (1, 1),
+        (1, 2),
+        (0, 3),
+        (1, 3),
+    ];
+    let bits = DOTS
+        .iter()
+        .enumerate()
+        .filter(|(_, &(x, y))| lit(x, y))
+        .fold(0, |bits, (dot, _)| bits | 1 << dot);
+    if bits == 0 {
+        ' '
+    } else {
+        char::from_u32(0x2800 + bits).unwrap()
+    }
+}
+
+impl fmt::Display for RenderStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RenderStyle::Block => "block",
+            RenderStyle::Wide => "wide",
+            RenderStyle::HalfBlock => "halfblock",
+            RenderStyle::Braille => "braille",
+        })
+    }
+}
+
+impl FromStr for RenderStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "block" => Ok(RenderStyle::Block),
+            "wide" => Ok(RenderStyle::Wide),
+            "halfblock" | "half-block" => Ok(RenderStyle::HalfBlock),
+            "braille" => Ok(RenderStyle::Braille),
+            _ => Err(format!(
+                "unknown render style '{}' (expected block, wide, halfblock or braille)",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_blocks_pack_two_rows() {
+        let mut fb = FrameBuffer::new(4, 4);
+        fb.set(0, 0, 1);
+        fb.set(1, 1, 1);
+        fb.set(2, 0, 1);
+        fb.set(2, 1, 2);
+        fb.set(3, 3, 1);
+        let style = RenderStyle::HalfBlock;
+        assert_eq!(style.text_size(&fb), (4, 2));
+        assert_eq!(style.line(&fb, 0), "▀▄█ ");
+        assert_eq!(style.line(&fb, 1), "   ▄");
+        assert_eq!(style.lines_for(1..4), 0..2);
+        assert_eq!(RenderStyle::Block.line(&fb, 3), "   █");
+    }
+
+    #[test]
+    fn wide_blocks_double_every_pixel() {
+        let mut fb = FrameBuffer::new(3, 2);
+        fb.set(1, 0, 1);
+        assert_eq!(RenderStyle::Wide.text_size(&fb), (6, 2));
+        assert_eq!(RenderStyle::Wide.line(&fb, 0), "  ██  ");
+        assert_eq!("wide".parse(), Ok(RenderStyle::Wide));
+    }
+
+    #[test]
+    fn picks_the_biggest_pixels_that_fit() {
+        assert_eq!(RenderStyle::fitting(64, 32, 200, 50), RenderStyle::Wide);
+        assert_eq!(
+            RenderStyle::fitting(128, 64, 200, 50),
+            RenderStyle::HalfBlock
+        );
+        assert_eq!(RenderStyle::fitting(128, 64, 100, 30), RenderStyle::Braille);
+        assert_eq!(RenderStyle::fitting(128, 64, 10, 5), RenderStyle::Braille);
+    }
+
+    #[test]
+    fn braille_gives_every_pixel_its_own_dot() {
+        let mut fb = FrameBuffer::new(128, 64);
+        assert_eq!(RenderStyle::Braille.text_size(&fb), (64, 16));
+        let mut seen = 0;
+        for y in 0..4 {
+            for x in 0..2 {
+                fb.clear();
+                fb.set(2 + x, 4 + y, 1);
+                let line = RenderStyle::Braille.line(&fb, 1);
+                assert_eq!(line.chars().filter(|&c| c != ' ').count(), 1);
+                let c = line.chars().nth(1).unwrap() as u32 - 0x2800;
+                assert_eq!(c.count_ones(), 1);
+                assert_eq!(seen & c, 0);
+                seen |= c;
+            }
+        }
+        fb.clear();
+        for (x, y) in [(0, 0), (1, 0), (0, 3)] {
+            fb.set(x, y, 1);
+        }
+        assert_eq!(RenderStyle::Braille.line(&fb, 0).chars().next(), Some('⡉'));
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+
+use crate::{SaveState, CPU};
+
+/// Ten seconds at 60 frames per second.
+pub const DEFAULT_REWIND_FRAMES: usize = 600;
+
+/// A ring buffer of the last few seconds of save states, for stepping execution backwards one
+/// frame at a time.
+///
+/// Only the most recent state is kept whole. Each older one is stored as the difference from the
+/// state after it, which is small because a frame rarely touches more than a handful of bytes,
+/// so the oldest can be dropped without having to rebuild anything.
+pub struct Rewind {
+    depth: usize,
+    /// The state recorded most recently, serialized.
+    latest: Option<Vec<u8>>,
+    /// Deltas back through time, oldest first. Applying the last one to `latest` gives the state
+    /// recorded before it.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Rewind {
+    /// Keeps up to `depth` frames of history. A depth of 0 records nothing.
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Changes how much history is kept, dropping the oldest frames if there are now too many.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        if depth == 0 {
+            self.clear();
+        }
+        while self.deltas.len() >= depth.max(1) {
+            self.deltas.pop_front();
+        }
+    }
+
+    /// How many frames back `step_back` can go from the most recent one.
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Records `cpu`'s state, which should be at a frame boundary.
+    pub fn record(&mut self, cpu: &CPU) {
+        if self.depth == 0 {
+            return;
+        }
+        let state = cpu.save_state().to_bytes();
+        if let Some(latest) = self.latest.take() {
+            if self.deltas.len() + 1 >= self.depth {
+                self.deltas.pop_front();
+            }
+            self.deltas.push_back(delta(&state, &latest));
+        }
+        self.latest = Some(state);
+    }
+
+    /// Puts `cpu` back to the previous recorded frame. If it has been single stepped since the
+    /// most recent frame was recorded, that frame is the one it goes back to. Returns false, and
+    /// leaves `cpu` alone, if there's no history to go back to.
+    pub fn step_back(&mut self, cpu: &mut CPU) -> bool {
+        let Some(latest) = self.latest.take() else {
+            return false;
+        };
+        let target = if cpu.save_state().to_bytes() != latest {
+            latest
+        } else if let Some(delta) = self.deltas.pop_back() {
+            apply(&latest, &delta)
+        } else {
+            self.latest = Some(latest);
+            return false;
+        };
+        // Everything recorded came from `save_state`, so it always decodes.
+        let state = SaveState::from_bytes(&target).expect("corrupt rewind history");
+        cpu.load_state(&state);
+        self.latest = Some(target);
+        true
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_REWIND_FRAMES)
+    }
+}
+
+/// Encodes the changes from `from` to `to`: the length of `to`, then runs of a count of bytes
+/// that are unchanged followed by a count of bytes XORed with `from` and the XORed bytes
+/// themselves.
+fn delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let xor = |i: usize| to[i] ^ from.get(i).copied().unwrap_or(0);
+    let mut out = (to.len() as u32).to_be_bytes().to_vec();
+    let mut i = 0;
+    while i < to.len() {
+        let same = (i..to.len())
+            .take(0xffff)
+            .take_while(|&j| xor(j) == 0)
+            .count();
+        i += same;
+        let changed = (i..to.len())
+            .take(0xffff)
+            .take_while(|&j| xor(j) != 0)
+            .count();
+        out.extend_from_slice(&(same as u16).to_be_bytes());
+        out.extend_from_slice(&(changed as u16).to_be_bytes());
+        out.extend((i..i + changed).map(xor));
+        i += changed;
+    }
+    out
+}
+
+/// Applies a delta made by `delta(from, to)` to `from`, giving `to`.
+fn apply(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let word = |at: usize| u16::from_be_bytes([delta[at], delta[at + 1]]) as usize;
+    let len = u32::from_be_bytes([delta[0], delta[1], delta[2], delta[3]]) as usize;
+    let mut out: Vec<u8> = (0..len)
+        .map(|i| from.get(i).copied().unwrap_or(0))
+        .collect();
+    let (mut at, mut i) = (4, 0);
+    while at < delta.len() {
+        let (same, changed) = (word(at), word(at + 2));
+        at += 4;
+        i += same;
+        for (byte, &x) in out[i..i + changed].iter_mut().zip(&delta[at..at + changed]) {
+            *byte ^= x;
+        }
+        at += changed;
+        i += changed;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with;
+
+    #[test]
+    fn deltas_round_trip() {
+        let from = vec![1, 2, 3, 4, 5, 6];
+        for to in [
+            vec![1, 2, 0, 4, 5, 9, 7, 8],
+            vec![1, 2],
+            vec![],
+            from.clone(),
+        ] {
+            assert_eq!(apply(&from, &delta(&from, &to)), to);
+        }
+        // Unchanged bytes cost nothing beyond the run headers.
+        assert_eq!(delta(&[0; 1000], &[0; 1000]).len(), 8);
+    }
+
+    #[test]
+    fn steps_back_through_recorded_frames() {
+        // Count frames in V0 forever, one instruction per frame.
+        let mut cpu = cpu_with(&[0x7001, 0x1200]);
+        cpu.set_ips(60);
+        let mut rewind = Rewind::new(4);
+        rewind.record(&cpu);
+        for _ in 0..10 {
+            cpu.run_frame().unwrap();
+            rewind.record(&cpu);
+        }
+        assert_eq!(rewind.len(), 3);
+        // The jump back to 0x200 every other frame leaves V0 unchanged.
+        for count in [5, 4, 4] {
+            assert!(rewind.step_back(&mut cpu));
+            assert_eq!(cpu.registers.v[0], count);
+        }
+        assert!(!rewind.step_back(&mut cpu));
+
+        // Stepping mid-frame goes back to the start of that frame first.
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        assert_eq!(cpu.registers.v[0], 5);
+        assert!(rewind.step_back(&mut cpu));
+        assert_eq!(cpu.registers.v[0], 4);
+        assert!(!rewind.step_back(&mut cpu));
+    }
+}
@@ -0,0 +1,58 @@
+/// A tiny xorshift32 generator backing Cxkk. It doesn't need to be good, just cheap and seedable
+/// so that runs can be reproduced.
+#[derive(Clone)]
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        // xorshift gets stuck at zero forever
+        Self {
+            state: if seed == 0 { 0x9e37_79b9 } else { seed },
+        }
+    }
+
+    /// Seeds from the system clock. In the browser there's no clock to read, so the seed is fixed
+    /// and it's up to the embedder to call `CPU::seed_rng`.
+    #[cfg(all(feature = "std", target_arch = "wasm32", target_os = "unknown"))]
+    pub fn from_time() -> Self {
+        Self::new(0)
+    }
+
+    /// Seeds from the system clock.
+    #[cfg(all(
+        feature = "std",
+        not(all(target_arch = "wasm32", target_os = "unknown"))
+    ))]
+    pub fn from_time() -> Self {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos() ^ d.as_secs() as u32)
+            .unwrap_or_default();
+        Self::new(nanos)
+    }
+
+    /// The generator's internal state, which `new` accepts back to carry on the same sequence.
+    pub fn state(&self) -> u32 {
+        self.state
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        (x >> 24) as u8
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for Rng {
+    fn default() -> Self {
+        Self::from_time()
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{Quirks, Variant};
+
+/// What's known about a ROM: who made it, and how it needs to be run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomInfo {
+    /// The SHA-1 of the ROM, as `rom_sha1` gives it.
+    pub sha1: &'static str,
+    pub title: &'static str,
+    pub author: &'static str,
+    pub variant: Variant,
+    pub quirks: Quirks,
+    /// The clock speed it was written for, if it's particular about it.
+    pub ips: Option<u32>,
+}
+
+impl RomInfo {
+    /// The ROM that hashes to `sha1`, if it's a known one.
+    pub fn lookup(sha1: &str) -> Option<&'static RomInfo> {
+        ROMS.iter().find(|rom| rom.sha1.eq_ignore_ascii_case(sha1))
+    }
+}
+
+/// ROMs known by hash. Entries for more games are welcome: the CHIP-8 community's database at
+/// https://github.com/chip-8/chip-8-database has titles, authors and quirks for most of them.
+const ROMS: &[RomInfo] = &[
+    RomInfo {
+        sha1: "8e96555ee62ed3c4dcd082fdef5d16450dcb99af",
+        title: "CHIP-8 splash screen",
+        author: "Timendus",
+        variant: Variant::Chip8,
+        quirks: Quirks::preset(Variant::Chip8),
+        ips: None,
+    },
+    RomInfo {
+        sha1: "e670ac22abbfe46a3bcf98e36ac5a34074c43693",
+        title: "IBM logo",
+        author: "Timendus",
+        variant: Variant::Chip8,
+        quirks: Quirks::preset(Variant::Chip8),
+        ips: None,
+    },
+    RomInfo {
+        sha1: "55eab50c53a102bea5d2848d29d6546fb79ae0c0",
+        title: "Corax+ opcode test",
+        author: "corax89, Timendus",
+        variant: Variant::Chip8,
+        quirks: Quirks::preset(Variant::Chip8),
+        ips: None,
+    },
+    RomInfo {
+        sha1: "e0596d264ead3c71cf76b352f71959c82c748519",
+        title: "Flags test",
+        author: "Timendus",
+        variant: Variant::Chip8,
+        quirks: Quirks::preset(Variant::Chip8),
+        ips: None,
+    },
+    RomInfo {
+        sha1: "9909082230fd33218ac374acaeaaefbb786e3194",
+        title: "Keypad test",
+        author: "Timendus",
+        variant: Variant::Chip8,
+        quirks: Quirks::preset(Variant::Chip8),
+        ips: None,
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom_sha1;
+
+    #[test]
+    fn finds_roms_by_hash() {
+        let ibm =
+            std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/roms/2-ibm-logo.ch8")).unwrap();
+        let info = RomInfo::lookup(&rom_sha1(&ibm)).unwrap();
+        assert_eq!(info.title, "IBM logo");
+        assert!(RomInfo::lookup(&rom_sha1(b"not a rom")).is_none());
+    }
+}
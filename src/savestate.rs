@@ -0,0 +1,404 @@
+use std::fmt;
+use std::io::{self, Cursor, Read, Write};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::cpu::Resolution;
+#[cfg(feature = "megachip")]
+use crate::megachip::{self, MegaChip, PlayingSound, MEGA_DISPLAY_HEIGHT, MEGA_DISPLAY_WIDTH};
+use crate::memory::MAX_MEMORY_SIZE;
+#[cfg(feature = "megachip")]
+use crate::Color;
+use crate::Variant;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+/// Bumped whenever the layout changes. States from other versions are rejected rather than
+/// misread.
+pub const SAVE_STATE_VERSION: u16 = 3;
+
+/// A complete copy of the machine: memory, registers, stack, timers, display, keypad and the
+/// interpreter's own bookkeeping, enough to carry on exactly where it left off. Settings that
+/// aren't part of the machine itself (quirks, clock speed and breakpoints) aren't included.
+///
+/// Taken with `CPU::save_state` and put back with `CPU::load_state`. `to_bytes` and
+/// `from_bytes` convert to and from a compact versioned binary format for saving to disk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub(crate) variant: Variant,
+    pub(crate) memory: Vec<u8>,
+    pub(crate) v: [u8; 16],
+    pub(crate) i: u16,
+    pub(crate) pc: u16,
+    pub(crate) sp: u8,
+    pub(crate) stack: [u16; 16],
+    pub(crate) delay: u8,
+    pub(crate) sound: u8,
+    pub(crate) display: Vec<u8>,
+    pub(crate) resolution: Resolution,
+    pub(crate) planes: u8,
+    pub(crate) keys: u16,
+    pub(crate) held_key: Option<u8>,
+    pub(crate) rpl: [u8; 16],
+    pub(crate) audio_pattern: [u8; 16],
+    pub(crate) pitch: u8,
+    pub(crate) rng: u32,
+    pub(crate) drawn_this_frame: bool,
+    pub(crate) waiting_for_vblank: bool,
+    pub(crate) exited: bool,
+    pub(crate) cycle_remainder: u32,
+    /// MegaChip's state and the palette the display was last shown in, for machines running as
+    /// MegaChip.
+    #[cfg(feature = "megachip")]
+    pub(crate) mega: Option<(MegaChip, Option<Vec<Color>>)>,
+}
+
+impl SaveState {
+    /// The variant the machine was running as.
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Serializes the state, prefixed with a magic number and `SAVE_STATE_VERSION`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.memory.len() + self.display.len() + 128);
+        self.write(&mut out).expect("writing to a Vec can't fail");
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_u16::<BigEndian>(SAVE_STATE_VERSION)?;
+        out.write_u8(match self.variant {
+            Variant::Chip8 => 0,
+            Variant::Schip => 1,
+            Variant::XoChip => 2,
+            Variant::Chip48 => 3,
+            #[cfg(feature = "megachip")]
+            Variant::MegaChip => 4,
+        })?;
+        out.write_u32::<BigEndian>(self.memory.len() as u32)?;
+        out.write_all(&self.memory)?;
+        out.write_all(&self.v)?;
+        out.write_u16::<BigEndian>(self.i)?;
+        out.write_u16::<BigEndian>(self.pc)?;
+        out.write_u8(self.sp)?;
+        for &addr in &self.stack {
+            out.write_u16::<BigEndian>(addr)?;
+        }
+        out.write_u8(self.delay)?;
+        out.write_u8(self.sound)?;
+        out.write_u8(match self.resolution {
+            Resolution::Low => 0,
+            Resolution::High => 1,
+            Resolution::TwoPage => 2,
+            #[cfg(feature = "megachip")]
+            Resolution::Mega => 3,
+        })?;
+        out.write_all(&self.display)?;
+        out.write_u8(self.planes)?;
+        out.write_u16::<BigEndian>(self.keys)?;
+        out.write_u8(self.held_key.unwrap_or(0xff))?;
+        out.write_all(&self.rpl)?;
+        out.write_all(&self.audio_pattern)?;
+        out.write_u8(self.pitch)?;
+        out.write_u32::<BigEndian>(self.rng)?;
+        out.write_u8(self.drawn_this_frame as u8)?;
+        out.write_u8(self.waiting_for_vblank as u8)?;
+        out.write_u8(self.exited as u8)?;
+        out.write_u32::<BigEndian>(self.cycle_remainder)?;
+        #[cfg(feature = "megachip")]
+        if let Some((mega, shown)) = &self.mega {
+            write_mega(out, mega, shown.as_deref())?;
+        }
+        Ok(())
+    }
+
+    /// Parses a state written by `to_bytes`, checking the header and that every field is in
+    /// range before anything is handed to a CPU.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StateError> {
+        let mut r = Cursor::new(bytes);
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)
+            .map_err(|_| StateError::NotAState)?;
+        if &magic != MAGIC {
+            return Err(StateError::NotAState);
+        }
+        let version = r
+            .read_u16::<BigEndian>()
+            .map_err(|_| StateError::NotAState)?;
+        if version != SAVE_STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        let state = Self::read(&mut r)?;
+        if r.position() as usize != bytes.len() {
+            return Err(StateError::Corrupt("trailing bytes"));
+        }
+        Ok(state)
+    }
+
+    fn read(r: &mut Cursor<&[u8]>) -> Result<Self, StateError> {
+        let variant = match r.read_u8()? {
+            0 => Variant::Chip8,
+            1 => Variant::Schip,
+            2 => Variant::XoChip,
+            3 => Variant::Chip48,
+            #[cfg(feature = "megachip")]
+            4 => Variant::MegaChip,
+            _ => return Err(StateError::Corrupt("unknown variant")),
+        };
+        let len = r.read_u32::<BigEndian>()? as usize;
+        if !(0x200..=MAX_MEMORY_SIZE).contains(&len) {
+            return Err(StateError::Corrupt("memory size out of range"));
+        }
+        let mut memory = vec![0; len];
+        r.read_exact(&mut memory)?;
+        let mut v = [0; 16];
+        r.read_exact(&mut v)?;
+        let i = r.read_u16::<BigEndian>()?;
+        let pc = r.read_u16::<BigEndian>()?;
+        let sp = r.read_u8()?;
+        if sp > 16 {
+            return Err(StateError::Corrupt("stack pointer out of range"));
+        }
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = r.read_u16::<BigEndian>()?;
+        }
+        let delay = r.read_u8()?;
+        let sound = r.read_u8()?;
+        let resolution = match r.read_u8()? {
+            0 => Resolution::Low,
+            1 => Resolution::High,
+            2 => Resolution::TwoPage,
+            #[cfg(feature = "megachip")]
+            3 => Resolution::Mega,
+            _ => return Err(StateError::Corrupt("unknown resolution")),
+        };
+        let (width, height) = resolution.size();
+        let mut display = vec![0; width * height];
+        r.read_exact(&mut display)?;
+        let planes = r.read_u8()?;
+        if planes > 3 {
+            return Err(StateError::Corrupt("display planes out of range"));
+        }
+        let keys = r.read_u16::<BigEndian>()?;
+        let held_key = match r.read_u8()? {
+            0xff => None,
+            key @ 0..=0xf => Some(key),
+            _ => return Err(StateError::Corrupt("held key out of range")),
+        };
+        let mut rpl = [0; 16];
+        r.read_exact(&mut rpl)?;
+        let mut audio_pattern = [0; 16];
+        r.read_exact(&mut audio_pattern)?;
+        let pitch = r.read_u8()?;
+        let rng = r.read_u32::<BigEndian>()?;
+        let drawn_this_frame = r.read_u8()? != 0;
+        let waiting_for_vblank = r.read_u8()? != 0;
+        let exited = r.read_u8()? != 0;
+        let cycle_remainder = r.read_u32::<BigEndian>()?;
+        #[cfg(feature = "megachip")]
+        let mega = match variant {
+            Variant::MegaChip => Some(read_mega(r, &memory)?),
+            _ => None,
+        };
+
+        Ok(Self {
+            variant,
+            memory,
+            v,
+            i,
+            pc,
+            sp,
+            stack,
+            delay,
+            sound,
+            display,
+            resolution,
+            planes,
+            keys,
+            held_key,
+            rpl,
+            audio_pattern,
+            pitch,
+            rng,
+            drawn_this_frame,
+            waiting_for_vblank,
+            exited,
+            cycle_remainder,
+            #[cfg(feature = "megachip")]
+            mega,
+        })
+    }
+}
+
+#[cfg(feature = "megachip")]
+fn write_mega(out: &mut Vec<u8>, mega: &MegaChip, shown: Option<&[Color]>) -> io::Result<()> {
+    let write_palette = |out: &mut Vec<u8>, palette: &[Color]| {
+        for color in palette {
+            out.extend_from_slice(&[color.r, color.g, color.b]);
+        }
+    };
+    write_palette(out, &mega.palette);
+    out.write_u8(shown.is_some() as u8)?;
+    if let Some(shown) = shown {
+        write_palette(out, shown);
+    }
+    out.write_u16::<BigEndian>(mega.sprite_width as u16)?;
+    out.write_u16::<BigEndian>(mega.sprite_height as u16)?;
+    out.write_u8(mega.alpha)?;
+    out.write_u8(mega.blend)?;
+    out.write_u8(mega.collision)?;
+    out.write_u8(mega.i_high)?;
+    out.write_all(mega.back.pixels())?;
+    match &mega.sound {
+        Some(playing) => {
+            out.write_u8(if playing.sound.looping { 1 } else { 2 })?;
+            out.write_u32::<BigEndian>(playing.header as u32)?;
+        }
+        None => out.write_u8(0)?,
+    }
+    Ok(())
+}
+
+/// Reads what `write_mega` wrote. The sound's samples are read back out of `memory`.
+#[cfg(feature = "megachip")]
+fn read_mega(
+    r: &mut Cursor<&[u8]>,
+    memory: &[u8],
+) -> Result<(MegaChip, Option<Vec<Color>>), StateError> {
+    let read_palette = |r: &mut Cursor<&[u8]>| -> Result<Vec<Color>, StateError> {
+        let mut rgb = [0; 256 * 3];
+        r.read_exact(&mut rgb)?;
+        Ok(rgb
+            .chunks(3)
+            .map(|c| Color::new(c[0], c[1], c[2]))
+            .collect())
+    };
+    let mut mega = MegaChip {
+        palette: read_palette(r)?,
+        ..MegaChip::default()
+    };
+    let shown = match r.read_u8()? {
+        0 => None,
+        _ => Some(read_palette(r)?),
+    };
+    mega.sprite_width = r.read_u16::<BigEndian>()? as usize;
+    mega.sprite_height = r.read_u16::<BigEndian>()? as usize;
+    if !(1..=256).contains(&mega.sprite_width) || !(1..=256).contains(&mega.sprite_height) {
+        return Err(StateError::Corrupt("sprite size out of range"));
+    }
+    mega.alpha = r.read_u8()?;
+    mega.blend = r.read_u8()?;
+    mega.collision = r.read_u8()?;
+    mega.i_high = r.read_u8()?;
+    let mut back = vec![0; MEGA_DISPLAY_WIDTH * MEGA_DISPLAY_HEIGHT];
+    r.read_exact(&mut back)?;
+    mega.back.copy_from(&back);
+    mega.back.clear_dirty();
+    mega.sound = match r.read_u8()? {
+        0 => None,
+        mode @ (1 | 2) => {
+            let header = r.read_u32::<BigEndian>()? as usize;
+            let sound = megachip::read_sound(memory, header, mode == 1)
+                .map_err(|_| StateError::Corrupt("sound out of range"))?;
+            Some(PlayingSound { header, sound })
+        }
+        _ => return Err(StateError::Corrupt("unknown sound mode")),
+    };
+    Ok((mega, shown))
+}
+
+/// Why a buffer couldn't be read as a `SaveState`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    /// The buffer doesn't start with the save state header.
+    NotAState,
+    /// The state was written by a different version of the format.
+    UnsupportedVersion(u16),
+    /// The header is fine but the contents are truncated or out of range.
+    Corrupt(&'static str),
+}
+
+impl From<io::Error> for StateError {
+    fn from(_: io::Error) -> Self {
+        StateError::Corrupt("truncated")
+    }
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotAState => f.write_str("not a save state"),
+            StateError::UnsupportedVersion(version) => write!(
+                f,
+                "save state is version {}, but only version {} is supported",
+                version, SAVE_STATE_VERSION
+            ),
+            StateError::Corrupt(reason) => write!(f, "corrupt save state: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with;
+    use crate::CPU;
+
+    /// `cpu_with`, with the RNG seeded so that runs can be compared.
+    fn seeded_cpu_with(program: &[u16]) -> CPU {
+        let mut cpu = cpu_with(program);
+        cpu.seed_rng(1);
+        cpu
+    }
+
+    #[test]
+    fn restoring_carries_on_identically() {
+        // Draw random sprites at random positions forever, counting in V2 and the delay timer.
+        let program = [0xc0ff, 0xc1ff, 0xa000, 0xd015, 0x7201, 0xf215, 0x1200];
+        let mut cpu = seeded_cpu_with(&program);
+        cpu.keypad().press(0x7);
+        for _ in 0..3 {
+            cpu.run_frame().unwrap();
+        }
+        let state = cpu.save_state();
+        let bytes = state.to_bytes();
+
+        let mut restored = seeded_cpu_with(&[]);
+        restored.load_state(&SaveState::from_bytes(&bytes).unwrap());
+        assert_eq!(restored.save_state(), state);
+        assert!(restored.keypad().is_pressed(0x7));
+
+        for _ in 0..3 {
+            cpu.run_frame().unwrap();
+            restored.run_frame().unwrap();
+        }
+        assert_eq!(restored.save_state(), cpu.save_state());
+        assert_eq!(restored.display(), cpu.display());
+    }
+
+    #[test]
+    fn rejects_other_formats_and_versions() {
+        let mut bytes = seeded_cpu_with(&[]).save_state().to_bytes();
+        assert_eq!(
+            SaveState::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(StateError::Corrupt("truncated"))
+        );
+        assert_eq!(
+            SaveState::from_bytes(b"\x00\xe0\x12\x00"),
+            Err(StateError::NotAState)
+        );
+        bytes[5] = 99;
+        assert_eq!(
+            SaveState::from_bytes(&bytes),
+            Err(StateError::UnsupportedVersion(99))
+        );
+    }
+}
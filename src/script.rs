@@ -0,0 +1,357 @@
+//! Lua scripts that run alongside a ROM, for bots, automated testers and accessibility helpers.
+//!
+//! A script registers callbacks on the global `chip8` table when it's loaded:
+//!
+//! - `chip8.on_frame(function(frame) ... end)` after every frame, counting from 1.
+//! - `chip8.on_breakpoint(function(breakpoint, pc) ... end)` when execution stops at a
+//!   breakpoint. Returning `true` carries on past it instead of pausing.
+//! - `chip8.on_write(start, finish, function(addr, value) ... end)` for each write the ROM made to
+//!   an address from `start` to `finish` during the frame, in order, once the frame's over.
+//!
+//! Both at load time and in callbacks, it can look at and change the machine with
+//! `chip8.reg(name)` and `chip8.set_reg(name, value)` for `v0` to `vf`, `i`, `pc`, `sp`, `dt` and
+//! `st`; `chip8.read(addr)` and `chip8.write(addr, value)`; `chip8.press(key)` and
+//! `chip8.release(key)`; and `chip8.add_breakpoint(spec)` and `chip8.remove_breakpoint(spec)`,
+//! with breakpoints written as for `--break`. `print` goes to the log.
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use mlua::{Function, Lua, Table, Value, Variadic};
+use tracing::info;
+
+use crate::{Breakpoint, CPU};
+
+pub use mlua::Error as ScriptError;
+
+/// The callbacks a script has registered.
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<Function>,
+    breakpoint: Vec<Function>,
+    /// With the first and last address each is for.
+    write: Vec<(u16, u16, Function)>,
+}
+
+/// A loaded script. Hand it to `RunLoop::set_script` or `HeadlessRunner::set_script` to have its
+/// callbacks called.
+pub struct Script {
+    lua: Lua,
+    hooks: Arc<Mutex<Hooks>>,
+    frames: u64,
+}
+
+impl Script {
+    /// Loads and runs the script at `path`, with `cpu` as the machine it works on.
+    pub fn load<P: AsRef<Path>>(path: P, cpu: &mut CPU) -> Result<Self, ScriptError> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(ScriptError::external)?;
+        Self::new(&source, &path.display().to_string(), cpu)
+    }
+
+    /// Loads and runs `source`, called `name` in error messages.
+    pub fn new(source: &str, name: &str, cpu: &mut CPU) -> Result<Self, ScriptError> {
+        let lua = Lua::new();
+        let hooks = Arc::new(Mutex::new(Hooks::default()));
+        let api = lua.create_table()?;
+        let on = hooks.clone();
+        api.set(
+            "on_frame",
+            lua.create_function(move |_, hook: Function| {
+                on.lock().unwrap().frame.push(hook);
+                Ok(())
+            })?,
+        )?;
+        let on = hooks.clone();
+        api.set(
+            "on_breakpoint",
+            lua.create_function(move |_, hook: Function| {
+                on.lock().unwrap().breakpoint.push(hook);
+                Ok(())
+            })?,
+        )?;
+        let on = hooks.clone();
+        api.set(
+            "on_write",
+            lua.create_function(move |_, (start, finish, hook): (u16, u16, Function)| {
+                on.lock().unwrap().write.push((start, finish, hook));
+                Ok(())
+            })?,
+        )?;
+        lua.globals().set("chip8", api)?;
+        // Printing would scribble over the terminal UI.
+        lua.globals().set(
+            "print",
+            lua.create_function(|_, values: Variadic<Value>| {
+                let values = values
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Result<Vec<_>, _>>()?;
+                info!(target: "script", "{}", values.join("\t"));
+                Ok(())
+            })?,
+        )?;
+
+        let script = Self {
+            lua,
+            hooks,
+            frames: 0,
+        };
+        script.with_machine(cpu, |lua| lua.load(source).set_name(name).exec())?;
+        script.log_writes(cpu);
+        Ok(script)
+    }
+
+    /// Calls the callbacks for the frame that's just run on `cpu`: `on_write` for each write it
+    /// made, then `on_breakpoint` if it stopped at a breakpoint, then `on_frame`. Returns whether
+    /// a breakpoint callback asked to carry on past the breakpoint.
+    pub fn after_frame(&mut self, cpu: &mut CPU) -> Result<bool, ScriptError> {
+        self.frames += 1;
+        let frame = self.frames;
+        let writes = cpu.memory.take_writes();
+        let (pc, hit) = (cpu.pc, cpu.breakpoint_hit());
+        // Copied out, so that callbacks can register more without deadlocking.
+        let (frame_hooks, breakpoint_hooks, write_hooks) = {
+            let hooks = self.hooks.lock().unwrap();
+            (
+                hooks.frame.clone(),
+                hooks.breakpoint.clone(),
+                hooks.write.clone(),
+            )
+        };
+        let resume = self.with_machine(cpu, |_| {
+            for (addr, value) in writes {
+                for (start, finish, hook) in &write_hooks {
+                    if (*start..=*finish).contains(&addr) {
+                        hook.call::<()>((addr, value))?;
+                    }
+                }
+            }
+            let mut resume = false;
+            if let Some(hit) = hit {
+                for hook in &breakpoint_hooks {
+                    let carry_on = hook.call::<Value>((hit.to_string(), pc))?;
+                    resume |= !matches!(carry_on, Value::Nil | Value::Boolean(false));
+                }
+            }
+            for hook in &frame_hooks {
+                hook.call::<()>(frame)?;
+            }
+            Ok(resume)
+        })?;
+        self.log_writes(cpu);
+        Ok(resume)
+    }
+
+    /// Only keeps a log of writes while there's a callback to give them to.
+    fn log_writes(&self, cpu: &mut CPU) {
+        cpu.memory
+            .log_writes(!self.hooks.lock().unwrap().write.is_empty());
+    }
+
+    /// Runs `f` with the functions in `chip8` that work on the machine pointed at `cpu`. They stop
+    /// working once it returns.
+    fn with_machine<R>(
+        &self,
+        cpu: &mut CPU,
+        f: impl FnOnce(&Lua) -> mlua::Result<R>,
+    ) -> mlua::Result<R> {
+        let cpu = &Mutex::new(cpu);
+        self.lua.scope(|scope| {
+            let api: Table = self.lua.globals().get("chip8")?;
+            api.set(
+                "reg",
+                scope.create_function(|_, name: String| {
+                    Ok(Register::parse(&name)?.get(&cpu.lock().unwrap()))
+                })?,
+            )?;
+            api.set(
+                "set_reg",
+                scope.create_function(|_, (name, value): (String, u16)| {
+                    Register::parse(&name)?.set(&mut cpu.lock().unwrap(), value);
+                    Ok(())
+                })?,
+            )?;
+            api.set(
+                "read",
+                scope.create_function(|_, addr: usize| {
+                    let cpu = cpu.lock().unwrap();
+                    Ok(cpu.memory[check_addr(&cpu, addr)?])
+                })?,
+            )?;
+            api.set(
+                "write",
+                scope.create_function(|_, (addr, value): (usize, u8)| {
+                    let mut cpu = cpu.lock().unwrap();
+                    let addr = check_addr(&cpu, addr)?;
+                    cpu.memory[addr] = value;
+                    Ok(())
+                })?,
+            )?;
+            api.set(
+                "press",
+                scope.create_function(|_, key: u8| {
+                    cpu.lock().unwrap().keypad().press(check_key(key)?);
+                    Ok(())
+                })?,
+            )?;
+            api.set(
+                "release",
+                scope.create_function(|_, key: u8| {
+                    cpu.lock().unwrap().keypad().release(check_key(key)?);
+                    Ok(())
+                })?,
+            )?;
+            api.set(
+                "add_breakpoint",
+                scope.create_function(|_, spec: String| {
+                    let breakpoint = spec.parse::<Breakpoint>().map_err(ScriptError::runtime)?;
+                    cpu.lock().unwrap().add_breakpoint_on(breakpoint);
+                    Ok(())
+                })?,
+            )?;
+            api.set(
+                "remove_breakpoint",
+                scope.create_function(|_, spec: String| {
+                    let breakpoint = spec.parse::<Breakpoint>().map_err(ScriptError::runtime)?;
+                    cpu.lock().unwrap().remove_breakpoint(breakpoint);
+                    Ok(())
+                })?,
+            )?;
+            f(&self.lua)
+        })
+    }
+}
+
+/// A register, by the name scripts give it.
+enum Register {
+    V(usize),
+    I,
+    Pc,
+    Sp,
+    Delay,
+    Sound,
+}
+
+impl Register {
+    fn parse(name: &str) -> mlua::Result<Self> {
+        let name = name.to_ascii_lowercase();
+        Ok(match name.as_str() {
+            "i" => Register::I,
+            "pc" => Register::Pc,
+            "sp" => Register::Sp,
+            "dt" => Register::Delay,
+            "st" => Register::Sound,
+            _ => match name.strip_prefix('v').filter(|x| x.len() == 1) {
+                Some(x) if u8::from_str_radix(x, 16).is_ok() => {
+                    Register::V(usize::from_str_radix(x, 16).unwrap())
+                }
+                _ => {
+                    return Err(ScriptError::runtime(format!(
+                        "there's no register called '{}' (expected v0 to vf, i, pc, sp, dt or st)",
+                        name
+                    )))
+                }
+            },
+        })
+    }
+
+    fn get(&self, cpu: &CPU) -> u16 {
+        match *self {
+            Register::V(x) => cpu.registers.v[x] as u16,
+            Register::I => cpu.registers.i,
+            Register::Pc => cpu.pc,
+            Register::Sp => cpu.sp as u16,
+            Register::Delay => cpu.registers.delay as u16,
+            Register::Sound => cpu.registers.sound as u16,
+        }
+    }
+
+    /// Sets the register, truncating `value` to 8 bits for all but I and PC.
+    fn set(&self, cpu: &mut CPU, value: u16) {
+        match *self {
+            Register::V(x) => cpu.registers.v[x] = value as u8,
+            Register::I => cpu.registers.i = value,
+            Register::Pc => cpu.pc = value,
+            Register::Sp => cpu.sp = value as u8,
+            Register::Delay => cpu.registers.delay = value as u8,
+            Register::Sound => cpu.registers.sound = value as u8,
+        }
+    }
+}
+
+fn check_addr(cpu: &CPU, addr: usize) -> mlua::Result<usize> {
+    if addr < cpu.memory.len() {
+        Ok(addr)
+    } else {
+        Err(ScriptError::runtime(format!(
+            "{:x} is past the end of memory",
+            addr
+        )))
+    }
+}
+
+fn check_key(key: u8) -> mlua::Result<u8> {
+    if key < 16 {
+        Ok(key)
+    } else {
+        Err(ScriptError::runtime(format!(
+            "keys go from 0 to f, not {:x}",
+            key
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::cpu_with;
+
+    #[test]
+    fn calls_back_on_frames_breakpoints_and_writes() {
+        // Wait for key 5, clear the screen, then store V0 at 0x300 and count it up forever.
+        let mut cpu = cpu_with(&[
+            0x6105, 0xe19e, 0x1202, 0x00e0, 0xa300, 0xf055, 0x7001, 0x1208,
+        ]);
+        let mut script = Script::new(
+            r#"
+            frames, stops, last_write = 0, 0, nil
+            chip8.add_breakpoint("206")
+            chip8.on_frame(function(frame) frames = frame end)
+            chip8.on_breakpoint(function(breakpoint, pc)
+                stops = stops + 1
+                chip8.set_reg("v0", 0x41)
+                return true
+            end)
+            chip8.on_write(0x300, 0x300, function(addr, value) last_write = value end)
+            chip8.press(5)
+            "#,
+            "test",
+            &mut cpu,
+        )
+        .unwrap();
+        assert!(cpu.keypad().is_pressed(5));
+
+        cpu.run_frame().unwrap();
+        assert_eq!(cpu.breakpoint_hit(), Some(Breakpoint::Address(0x206)));
+        assert!(script.after_frame(&mut cpu).unwrap());
+        assert_eq!(cpu.registers.v[0], 0x41);
+        cpu.run_frame().unwrap();
+        assert!(!script.after_frame(&mut cpu).unwrap());
+
+        let globals = script.lua.globals();
+        assert_eq!(globals.get::<u64>("frames").unwrap(), 2);
+        assert_eq!(globals.get::<u32>("stops").unwrap(), 1);
+        assert_eq!(globals.get::<u8>("last_write").unwrap(), cpu.memory[0x300]);
+        assert!(cpu.memory[0x300] > 0x41);
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        let mut cpu = cpu_with(&[0x1200]);
+        let err = Script::new(r#"chip8.reg("vg")"#, "test", &mut cpu)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("no register called 'vg'"));
+        assert!(Script::new("chip8.read(0x1000)", "test", &mut cpu).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+//! Snapshot tests for ROMs: run a ROM headlessly, then check the display against a text file of
+//! what it should look like, in the `FrameBuffer::to_text` format.
+//!
+//! ```no_run
+//! # use chip8::HeadlessRunner;
+//! # fn runner() -> HeadlessRunner { unimplemented!() }
+//! let fb = runner().run_frames(60).unwrap();
+//! chip8::snapshot::assert_matches(&fb, "tests/golden/my-rom.txt");
+//! ```
+//!
+//! Run the tests with `CHIP8_BLESS=1` in the environment to write the snapshots instead of
+//! checking them, after making sure the displays are what they should be.
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::FrameBuffer;
+
+/// Set to anything to have `check` write snapshots rather than compare against them.
+pub const BLESS_VAR: &str = "CHIP8_BLESS";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(PathBuf, io::Error),
+    /// The display doesn't match the snapshot at `path`. `diff` is the two side by side.
+    Mismatch {
+        path: PathBuf,
+        diff: String,
+    },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(path, err) => write!(
+                f,
+                "couldn't use snapshot {}: {} (run with {}=1 to write it)",
+                path.display(),
+                err,
+                BLESS_VAR
+            ),
+            SnapshotError::Mismatch { path, diff } => write!(
+                f,
+                "the display doesn't match {}; expected on the left, got on the right:\n{}",
+                path.display(),
+                diff
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// Checks `fb` against the snapshot at `path`, or writes it there if `BLESS_VAR` is set.
+pub fn check<P: AsRef<Path>>(fb: &FrameBuffer, path: P) -> Result<(), SnapshotError> {
+    let path = path.as_ref();
+    let actual = fb.to_text();
+    let io_error = |err| SnapshotError::Io(path.to_path_buf(), err);
+    if std::env::var_os(BLESS_VAR).is_some() {
+        return std::fs::write(path, actual).map_err(io_error);
+    }
+    let expected = std::fs::read_to_string(path).map_err(io_error)?;
+    // Snapshots checked out on Windows may have picked up carriage returns.
+    if expected.replace("\r\n", "\n") == actual {
+        Ok(())
+    } else {
+        Err(SnapshotError::Mismatch {
+            path: path.to_path_buf(),
+            diff: diff(&expected, &actual),
+        })
+    }
+}
+
+/// `check`, panicking with the diff if the display doesn't match.
+#[track_caller]
+pub fn assert_matches<P: AsRef<Path>>(fb: &FrameBuffer, path: P) {
+    if let Err(err) = check(fb, path) {
+        panic!("{}", err);
+    }
+}
+
+/// Two screens of text side by side, with `>` by each row that differs.
+pub fn diff(expected: &str, actual: &str) -> String {
+    let (expected, actual): (Vec<_>, Vec<_>) =
+        (expected.lines().collect(), actual.lines().collect());
+    let width = expected.iter().map(|line| line.len()).max().unwrap_or(0);
+    let mut diff = String::new();
+    for row in 0..expected.len().max(actual.len()) {
+        let (left, right) = (
+            expected.get(row).copied().unwrap_or(""),
+            actual.get(row).copied().unwrap_or(""),
+        );
+        let marker = if left == right { ' ' } else { '>' };
+        diff.push_str(&format!("{} {:width$}  {}\n", marker, left, right));
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn diffs_mark_the_rows_that_differ() {
+        let diff = diff("#.\n..\n", "#.\n.#\n.#\n");
+        assert_eq!(diff, "  #.  #.\n> ..  .#\n>     .#\n");
+    }
+}
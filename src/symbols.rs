@@ -0,0 +1,123 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Names for addresses in a ROM, like the labels in its source, for the debugger to show in
+/// place of bare addresses.
+///
+/// On disk it's a line per name, address first in hex, as `chip8 asm` writes next to the ROM:
+///
+/// ```text
+/// ; comments start with a semicolon or #
+/// 200 main
+/// 2a4 draw_paddle
+/// ```
+///
+/// Lines of the form `draw_paddle = 0x2a4` are read too, for symbol tables from other tools.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Symbols {
+    names: BTreeMap<u16, String>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Names `addr`, replacing any name it had.
+    pub fn insert(&mut self, addr: u16, name: impl Into<String>) {
+        self.names.insert(addr, name.into());
+    }
+
+    /// The name of exactly `addr`, if it has one.
+    pub fn name(&self, addr: u16) -> Option<&str> {
+        self.names.get(&addr).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Every name, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &str)> {
+        self.names.iter().map(|(&addr, name)| (addr, name.as_str()))
+    }
+
+    /// `addr` as the nearest name at or before it, like `draw_paddle` or `update+6`, or in hex if
+    /// there's no name before it.
+    pub fn describe(&self, addr: u16) -> String {
+        match self.names.range(..=addr).next_back() {
+            Some((&at, name)) if at == addr => name.clone(),
+            Some((&at, name)) => format!("{}+{:x}", name, addr - at),
+            None => format!("{:03x}", addr),
+        }
+    }
+
+    /// Parses a symbol file, failing with the line number of anything that isn't a name and an
+    /// address.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split([';', '#']).next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (addr, name) = match line.split_once('=') {
+                Some((name, addr)) => (addr.trim(), name.trim()),
+                None => line.split_once(char::is_whitespace).unwrap_or((line, "")),
+            };
+            let name = name.trim();
+            let addr = u16::from_str_radix(addr.trim_start_matches("0x"), 16);
+            match addr {
+                Ok(addr) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                    symbols.insert(addr, name)
+                }
+                _ => {
+                    return Err(format!(
+                        "line {}: '{}' isn't an address and a name, like 2a4 draw_paddle",
+                        i + 1,
+                        line
+                    ))
+                }
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?).map_err(anyhow::Error::msg)
+    }
+
+    /// Writes the symbols in the format `parse` reads.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let text: String = self
+            .iter()
+            .map(|(addr, name)| format!("{:03x} {}\n", addr, name))
+            .collect();
+        std::fs::write(path, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing_and_describing() {
+        let symbols =
+            Symbols::parse("; pong\n200 main\n\n0x2a4 draw_paddle # the left one\nscore = 0x2f0\n")
+                .unwrap();
+        assert_eq!(symbols.len(), 3);
+        assert_eq!(symbols.name(0x2a4), Some("draw_paddle"));
+        assert_eq!(symbols.describe(0x2a4), "draw_paddle");
+        assert_eq!(symbols.describe(0x2ae), "draw_paddle+a");
+        assert_eq!(symbols.describe(0x1ff), "1ff");
+        assert_eq!(
+            Symbols::parse("main 200"),
+            Err("line 1: 'main 200' isn't an address and a name, like 2a4 draw_paddle".to_string())
+        );
+    }
+}
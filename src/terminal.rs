@@ -0,0 +1,120 @@
+//! Taking the terminal over for a full-screen UI, and making sure it's given back however the
+//! program ends: normally, with an error, or with a panic.
+use std::io::{self, stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crossterm::event::{
+    DisableFocusChange, EnableFocusChange, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement, EnterAlternateScreen,
+    LeaveAlternateScreen, SetTitle,
+};
+use crossterm::ExecutableCommand;
+use tracing::info;
+
+/// Whether a `TerminalGuard` has the terminal, whether it pushed keyboard flags that need
+/// popping, and whether it saved the window title to put back. They're global so the panic hook
+/// can see them without the guard.
+static ENTERED: AtomicBool = AtomicBool::new(false);
+static PUSHED_FLAGS: AtomicBool = AtomicBool::new(false);
+static PUSHED_TITLE: AtomicBool = AtomicBool::new(false);
+
+/// Holds the terminal in raw mode on the alternate screen for as long as it's alive, and puts it
+/// back the way it was when it's dropped.
+pub struct TerminalGuard {
+    reports_releases: bool,
+}
+
+impl TerminalGuard {
+    /// Switches to the alternate screen and raw mode, and asks to be told when the terminal gains
+    /// and loses focus. With `key_releases`, also asks terminals that speak the kitty keyboard
+    /// protocol to report keys being released.
+    pub fn enter(key_releases: bool) -> io::Result<Self> {
+        ENTERED.store(true, Ordering::SeqCst);
+        let entered = stdout()
+            .execute(EnterAlternateScreen)
+            .and_then(|_| enable_raw_mode());
+        if let Err(err) = entered {
+            Self::restore();
+            return Err(err);
+        }
+        // Terminals that can't report focus ignore the request.
+        let _ = stdout().execute(EnableFocusChange);
+        Ok(Self {
+            reports_releases: key_releases && push_keyboard_flags(),
+        })
+    }
+
+    /// Whether key release events will be reported.
+    pub fn reports_releases(&self) -> bool {
+        self.reports_releases
+    }
+
+    /// Sets the terminal's window title, saving the one it had first on the terminal's title
+    /// stack for `restore` to put back. Terminals without a stack keep the new title.
+    pub fn set_title(&self, title: &str) {
+        let mut out = stdout();
+        if !PUSHED_TITLE.swap(true, Ordering::SeqCst) {
+            let _ = write!(out, "\x1b[22;0t");
+        }
+        let _ = out.execute(SetTitle(title));
+    }
+
+    /// Puts the terminal back, if a guard has it. Does nothing if it's already been put back, so
+    /// it's safe to call from a panic hook as well as when the guard is dropped.
+    pub fn restore() {
+        if !ENTERED.swap(false, Ordering::SeqCst) {
+            return;
+        }
+        if PUSHED_FLAGS.swap(false, Ordering::SeqCst) {
+            let _ = stdout().execute(PopKeyboardEnhancementFlags);
+        }
+        if PUSHED_TITLE.swap(false, Ordering::SeqCst) {
+            let _ = write!(stdout(), "\x1b[23;0t");
+        }
+        let _ = stdout().execute(DisableFocusChange);
+        let _ = stdout().execute(LeaveAlternateScreen);
+        let _ = disable_raw_mode();
+    }
+
+    /// Has panics put the terminal back before reporting themselves, or neither the message nor
+    /// the shell afterwards can be read.
+    pub fn install_panic_hook() {
+        let default_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            Self::restore();
+            default_hook(info);
+        }));
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        Self::restore();
+    }
+}
+
+/// Key release events are far more accurate than guessing from auto-repeat, where the terminal
+/// can send them.
+fn push_keyboard_flags() -> bool {
+    let pushed = supports_keyboard_enhancement().unwrap_or(false)
+        && stdout()
+            .execute(PushKeyboardEnhancementFlags(
+                KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                    | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                    | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES,
+            ))
+            .is_ok();
+    PUSHED_FLAGS.store(pushed, Ordering::SeqCst);
+    info!(
+        "Key release events {}",
+        if pushed {
+            "enabled"
+        } else {
+            "unsupported, guessing from auto-repeat"
+        }
+    );
+    pushed
+}
@@ -0,0 +1,70 @@
+//! The community test ROMs in `roms/`, bundled with what the display should look like once
+//! they've run. Checking the emulator against them is what `chip8 test` does.
+//!
+//! The quirks test isn't among them: it needs the ROM's own menu to pick a platform, and isn't
+//! in `roms/` to begin with.
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::{HeadlessRunner, Keypad, Memory, Quirks, Variant, CPU};
+
+/// A test ROM and the display it should end up with.
+#[derive(Debug, Clone, Copy)]
+pub struct TestRom {
+    pub name: &'static str,
+    pub rom: &'static [u8],
+    pub variant: Variant,
+    /// How many frames it takes to get to the final screen.
+    pub frames: u32,
+    /// The final screen, in the `FrameBuffer::to_text` format.
+    pub expected: &'static str,
+}
+
+macro_rules! test_rom {
+    ($name:literal, $file:literal) => {
+        TestRom {
+            name: $name,
+            rom: include_bytes!(concat!("../roms/", $file, ".ch8")),
+            variant: Variant::Chip8,
+            frames: 120,
+            expected: include_str!(concat!("../roms/expected/", $file, ".txt")),
+        }
+    };
+}
+
+pub const TEST_ROMS: &[TestRom] = &[
+    test_rom!("chip8-logo", "1-chip8-logo"),
+    test_rom!("ibm-logo", "2-ibm-logo"),
+    test_rom!("corax+", "3-corax+"),
+    test_rom!("flags", "4-flags"),
+];
+
+impl TestRom {
+    /// Runs the ROM on a fresh machine with its variant's quirks, and returns the final screen.
+    pub fn run(&self) -> Result<String> {
+        let mut memory = Memory::with_size(self.variant.memory_size());
+        memory
+            .load_rom_bytes(self.rom)
+            .expect("test ROMs fit in memory");
+        let mut cpu = CPU::new(
+            memory,
+            Arc::new(Keypad::new()),
+            Quirks::preset(self.variant),
+        );
+        cpu.set_variant(self.variant);
+        let mut runner = HeadlessRunner::new(cpu);
+        Ok(runner.run_frames(self.frames)?.to_text())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_test_rom_passes() {
+        for test in TEST_ROMS {
+            assert_eq!(test.run().unwrap(), test.expected, "{}", test.name);
+        }
+    }
+}
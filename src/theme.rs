@@ -0,0 +1,217 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// A 24-bit colour, written `#rrggbb`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    pub fn rgba(self) -> [u8; 4] {
+        [self.r, self.g, self.b, 0xff]
+    }
+
+    /// This colour blended `amount` of the way towards `other`, from 0 for none of it to 255 for
+    /// all of it.
+    pub fn mix(self, other: Color, amount: u8) -> Color {
+        let channel = |from: u8, to: u8| {
+            let (from, to, amount) = (from as u32, to as u32, amount as u32);
+            ((from * (255 - amount) + to * amount) / 255) as u8
+        };
+        Color::new(
+            channel(self.r, other.r),
+            channel(self.g, other.g),
+            channel(self.b, other.b),
+        )
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// Parses `#rrggbb`, with or without the `#`.
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("expected a colour like #5c5cff, got '{}'", s));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Self::new(channel(0), channel(2), channel(4)))
+    }
+}
+
+/// The colours lit and unlit pixels are drawn in. XO-CHIP has two display planes, so a pixel can
+/// be lit on the first, the second or both; `fg` is for the first, which is all other ROMs use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub fg: Color,
+    pub bg: Color,
+    /// Pixels lit on the second plane only.
+    pub fg2: Color,
+    /// Pixels lit on both planes.
+    pub blend: Color,
+}
+
+impl Theme {
+    /// Light blue on black, with Octo's colours for the second plane.
+    pub const DEFAULT: Theme = Theme {
+        fg: Color::new(0x5c, 0x5c, 0xff),
+        bg: Color::new(0x00, 0x00, 0x00),
+        fg2: Color::new(0xff, 0x66, 0x00),
+        blend: Color::new(0x66, 0x22, 0x00),
+    };
+
+    /// Octo's own yellow on brown.
+    pub const OCTO: Theme = Theme {
+        fg: Color::new(0xff, 0xcc, 0x00),
+        bg: Color::new(0x99, 0x66, 0x00),
+        fg2: Color::new(0xff, 0x66, 0x00),
+        blend: Color::new(0x66, 0x22, 0x00),
+    };
+
+    /// The original Game Boy's four greens, darkest lit.
+    pub const GAMEBOY: Theme = Theme {
+        fg: Color::new(0x0f, 0x38, 0x0f),
+        bg: Color::new(0x9b, 0xbc, 0x0f),
+        fg2: Color::new(0x8b, 0xac, 0x0f),
+        blend: Color::new(0x30, 0x62, 0x30),
+    };
+
+    /// An amber monochrome monitor, at four brightnesses.
+    pub const AMBER: Theme = Theme {
+        fg: Color::new(0xff, 0xb0, 0x00),
+        bg: Color::new(0x1a, 0x0f, 0x00),
+        fg2: Color::new(0x80, 0x58, 0x00),
+        blend: Color::new(0xff, 0xe0, 0x80),
+    };
+
+    /// The Commodore 64's light blue on dark blue, with its cyan and white.
+    pub const C64: Theme = Theme {
+        fg: Color::new(0x86, 0x7a, 0xde),
+        bg: Color::new(0x48, 0x3a, 0xaa),
+        fg2: Color::new(0x5c, 0xab, 0x5e),
+        blend: Color::new(0xff, 0xff, 0xff),
+    };
+
+    const NAMED: [(&'static str, Theme); 5] = [
+        ("default", Theme::DEFAULT),
+        ("octo", Theme::OCTO),
+        ("gameboy", Theme::GAMEBOY),
+        ("amber", Theme::AMBER),
+        ("c64", Theme::C64),
+    ];
+
+    /// The colour of a display pixel lit on the planes in `planes`.
+    pub fn color(self, planes: u8) -> Color {
+        match planes & 3 {
+            0 => self.bg,
+            1 => self.fg,
+            2 => self.fg2,
+            _ => self.blend,
+        }
+    }
+
+    /// The colours of pixels lit on no planes, the first, the second and both, in that order.
+    pub fn colors(self) -> [Color; 4] {
+        [self.bg, self.fg, self.fg2, self.blend]
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Themes are shown by name if they have one, and as `fg/bg/fg2/blend` if not.
+impl fmt::Display for Theme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match Self::NAMED.iter().find(|(_, theme)| theme == self) {
+            Some((name, _)) => f.write_str(name),
+            None => write!(f, "{}/{}/{}/{}", self.fg, self.bg, self.fg2, self.blend),
+        }
+    }
+}
+
+/// Parses a named theme (`default`, `octo`, `gameboy`, `amber` or `c64`), or colours as `fg/bg`
+/// or `fg/bg/fg2/blend`. With two, the second plane gets the default theme's colours.
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        if let Some((_, theme)) = Self::NAMED.iter().find(|(name, _)| *name == lower) {
+            return Ok(*theme);
+        }
+        let colors = s
+            .split('/')
+            .map(str::parse)
+            .collect::<Result<Vec<Color>, _>>();
+        match colors.as_deref() {
+            Ok(&[fg, bg]) => Ok(Self {
+                fg,
+                bg,
+                ..Self::DEFAULT
+            }),
+            Ok(&[fg, bg, fg2, blend]) => Ok(Self { fg, bg, fg2, blend }),
+            Err(err) if s.contains('/') => Err(err.clone()),
+            _ => Err(format!(
+                "unknown theme '{}' (expected default, octo, gameboy, amber, c64, fg/bg or \
+                 fg/bg/fg2/blend colours)",
+                s
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_colours() {
+        assert_eq!("#5c5cff".parse(), Ok(Color::new(0x5c, 0x5c, 0xff)));
+        assert_eq!("FFb000".parse(), Ok(Color::new(0xff, 0xb0, 0x00)));
+        assert!("#5c5cf".parse::<Color>().is_err());
+        assert!("#5c5cfg".parse::<Color>().is_err());
+        assert_eq!(Color::new(1, 2, 3).to_string(), "#010203");
+    }
+
+    #[test]
+    fn parses_themes() {
+        assert_eq!("GameBoy".parse(), Ok(Theme::GAMEBOY));
+        assert_eq!(Theme::C64.to_string(), "c64");
+        let custom: Theme = "#ffffff/#000080".parse().unwrap();
+        assert_eq!(custom.bg, Color::new(0, 0, 0x80));
+        assert_eq!(custom.to_string().parse(), Ok(custom));
+        assert!("sepia".parse::<Theme>().is_err());
+        assert!("#ffffff/#000080/#ff0000".parse::<Theme>().is_err());
+    }
+
+    #[test]
+    fn colours_each_plane() {
+        let theme: Theme = "#ffffff/#000000/#ff0000/#00ff00".parse().unwrap();
+        assert_eq!(theme.color(0), Color::new(0, 0, 0));
+        assert_eq!(theme.color(1), Color::new(0xff, 0xff, 0xff));
+        assert_eq!(theme.color(2), Color::new(0xff, 0, 0));
+        assert_eq!(theme.color(3), Color::new(0, 0xff, 0));
+        assert_eq!(theme.to_string(), "#ffffff/#000000/#ff0000/#00ff00");
+        assert_eq!(
+            "#ffffff/#000000".parse::<Theme>().unwrap().fg2,
+            Theme::DEFAULT.fg2
+        );
+    }
+}
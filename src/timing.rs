@@ -0,0 +1,107 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::disasm::Instr;
+
+/// The COSMAC VIP's 1.76MHz clock, in 1802 machine cycles of 8 clocks each, per 60Hz frame.
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+/// What the display interrupt takes out of every frame: a cycle for each of the 128 lines of 8
+/// bytes DMA'd to the video chip, and roughly 80 more for the interrupt routine itself, which
+/// also ticks the timers.
+pub const VIP_INTERRUPT_CYCLES: u32 = 128 * 8 + 80;
+/// The machine cycles left in a frame for the interpreter.
+pub const VIP_FRAME_BUDGET: u32 = VIP_CYCLES_PER_FRAME - VIP_INTERRUPT_CYCLES;
+
+/// How the CPU decides how much to run in a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    /// A flat number of instructions per second, whatever they are.
+    #[default]
+    Ips,
+    /// Each instruction costs what it took the COSMAC VIP's interpreter, and DXYN waits for the
+    /// display interrupt before drawing, so nothing else runs for the rest of the frame. Games
+    /// written for the VIP run at the speed they did on it.
+    Vip,
+}
+
+impl Timing {
+    /// Roughly how many machine cycles the VIP interpreter took to run `instr` with registers
+    /// `v`, fetch and decode included. Worked out from disassemblies of the interpreter, so
+    /// they're averages where the real cost depends on the data, except for DXYN's sprite size
+    /// and alignment. DXYN's wait for the interrupt isn't counted here.
+    pub fn vip_cycles(instr: Instr, v: &[u8; 16]) -> u32 {
+        match instr {
+            Instr::Cls => 24,
+            // Twice the display memory to clear.
+            Instr::HiresCls => 48,
+            Instr::Ret | Instr::Jump(_) | Instr::Call(_) | Instr::JumpV0(_) => 23,
+            Instr::SkipEqByte(..) | Instr::SkipNeByte(..) | Instr::LoadI(_) => 12,
+            Instr::SkipEqReg(..) | Instr::SkipNeReg(..) => 16,
+            Instr::SkipKey(_) | Instr::SkipNotKey(_) => 16,
+            Instr::LoadByte(..) => 6,
+            Instr::AddByte(..) => 10,
+            Instr::Move(..)
+            | Instr::Or(..)
+            | Instr::And(..)
+            | Instr::Xor(..)
+            | Instr::Add(..)
+            | Instr::Sub(..)
+            | Instr::Shr(..)
+            | Instr::SubN(..)
+            | Instr::Shl(..) => 44,
+            Instr::Rand(..) => 36,
+            // Sprites that don't start on a byte boundary are shifted across two bytes of the
+            // display a row at a time.
+            Instr::Draw(x, _, n) => {
+                let per_row = if v[x as usize].is_multiple_of(8) {
+                    10
+                } else {
+                    20
+                };
+                26 + per_row * n as u32
+            }
+            Instr::GetDelay(_) | Instr::WaitKey(_) | Instr::SetDelay(_) | Instr::SetSound(_) => 10,
+            Instr::AddI(_) => 19,
+            Instr::Font(_) => 20,
+            Instr::Bcd(_) => 204,
+            Instr::Store(x) | Instr::Restore(x) => 14 + 14 * (x as u32 + 1),
+            // The rest never ran on the VIP, so they cost what a jump does.
+            _ => 23,
+        }
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Timing::Ips => "ips",
+            Timing::Vip => "vip",
+        })
+    }
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "ips" => Ok(Timing::Ips),
+            "vip" => Ok(Timing::Vip),
+            _ => Err(format!("unknown timing '{}' (expected ips or vip)", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unaligned_sprites_cost_more() {
+        let mut v = [0; 16];
+        assert_eq!(Timing::vip_cycles(Instr::Draw(0, 1, 5), &v), 76);
+        v[0] = 3;
+        assert_eq!(Timing::vip_cycles(Instr::Draw(0, 1, 5), &v), 126);
+        assert_eq!(Timing::vip_cycles(Instr::Store(2), &v), 56);
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+use crate::disasm::Instr;
+use crate::Variant;
+
+/// How many instructions are kept by default, a few frames' worth at the usual clock speed.
+pub const DEFAULT_TRACE_LEN: usize = 256;
+
+/// An instruction that was executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub pc: u16,
+    pub opcode: u16,
+}
+
+impl TraceEntry {
+    /// The address, opcode and what it decodes to as `variant`, like `0200: 00e0  cls`.
+    pub fn describe(&self, variant: Variant) -> String {
+        match Instr::decode(self.opcode, variant) {
+            Some(instr) => format!("{:04x}: {:04x}  {}", self.pc, self.opcode, instr),
+            None => format!("{:04x}: {:04x}  ???", self.pc, self.opcode),
+        }
+    }
+}
+
+/// A ring buffer of the last instructions executed, cheap enough to record every one of them at
+/// any clock speed, for finding out how a ROM got to where it is.
+#[derive(Debug, Clone)]
+pub struct Tracer {
+    len: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+impl Tracer {
+    /// Keeps up to `len` instructions. A length of 0 records nothing.
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            entries: VecDeque::with_capacity(len),
+        }
+    }
+
+    /// How many instructions it keeps at most.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Changes how many instructions are kept, dropping the oldest if there are now too many.
+    pub fn set_capacity(&mut self, len: usize) {
+        self.len = len;
+        while self.entries.len() > len {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn record(&mut self, pc: u16, opcode: u16) {
+        if self.len == 0 {
+            return;
+        }
+        if self.entries.len() == self.len {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry { pc, opcode });
+    }
+
+    /// The instructions recorded, oldest first.
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &TraceEntry> + ExactSizeIterator {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Every instruction recorded, oldest first and one per line, decoded as `variant`.
+    pub fn dump(&self, variant: Variant) -> String {
+        let mut out = String::new();
+        for entry in &self.entries {
+            let _ = writeln!(out, "{}", entry.describe(variant));
+        }
+        out
+    }
+
+    /// Writes `dump` to a file.
+    pub fn save<P: AsRef<Path>>(&self, path: P, variant: Variant) -> io::Result<()> {
+        std::fs::write(path, self.dump(variant))
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new(DEFAULT_TRACE_LEN)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_instructions() {
+        let mut tracer = Tracer::new(3);
+        for (i, opcode) in [0x00e0, 0x6001, 0x7001, 0x1206].into_iter().enumerate() {
+            tracer.record(0x200 + i as u16 * 2, opcode);
+        }
+        assert_eq!(tracer.len(), 3);
+        assert_eq!(
+            tracer.dump(Variant::Chip8),
+            "0202: 6001  ld v0, 0x01\n0204: 7001  add v0, 0x01\n0206: 1206  jp 0x206\n"
+        );
+        tracer.set_capacity(1);
+        assert_eq!(tracer.entries().next().unwrap().opcode, 0x1206);
+        tracer.set_capacity(0);
+        tracer.record(0x208, 0x00e0);
+        assert!(tracer.is_empty());
+    }
+}
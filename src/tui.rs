@@ -0,0 +1,1449 @@
+//! The terminal frontend: the display drawn with block characters, with debugging panels beside
+//! it, a status line underneath and a bar of counters below that.
+use std::io::{self, stdout, Stdout, Write};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use chip8::audio::{AudioPattern, Beeper, DigitizedSound};
+use chip8::console::{self, ConsoleCommand};
+use chip8::disasm::Instr;
+use chip8::{
+    Breakpoint, Cell, Command, FrameBuffer, Frontend, InputEvent, Keymap, Phosphor, RenderStyle,
+    RunStatus, Snapshot, Speed, Symbols, Theme, Usage, CPU, FONTS_END,
+};
+use crossterm::event;
+use ratatui::{
+    prelude::*,
+    widgets::{Block, Clear, Padding, Paragraph, Wrap},
+};
+use tracing::debug_span;
+
+use crate::terminal::TerminalGuard;
+
+/// Takes over the terminal while it's alive, and puts it back the way it was when dropped.
+pub struct Tui {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    rom_title: String,
+    keymap: Keymap,
+    /// The style the display is being drawn in.
+    render_style: RenderStyle,
+    /// Whether `render_style` was chosen, rather than picked to fit the terminal each frame.
+    fixed_style: bool,
+    theme: Theme,
+    beeper: Box<dyn Beeper>,
+    held_keys: HeldKeys,
+    panels: Vec<Panel>,
+    /// The first row shown in the memory panel, or `None` to follow I.
+    memory_top: Option<usize>,
+    /// Whether the memory panel colours bytes by how they've been used, toggled with F11.
+    coverage: bool,
+    /// I and the size of memory as of the last frame, for scrolling the memory panel.
+    memory_i: usize,
+    memory_len: usize,
+    /// The display as text, a line at a time, kept between frames so only lines that have changed
+    /// need rebuilding.
+    display_rows: Vec<Line<'static>>,
+    /// Fades pixels out instead of turning them off, if it's turned on.
+    phosphor: Option<Phosphor>,
+    /// What was shown on the status line last time the terminal was drawn.
+    last_status: Option<RunStatus>,
+    /// Whether the display was last drawn inverted, to show the tone.
+    flashed: bool,
+    /// Set when something other than the machine changes what's on screen, like the terminal
+    /// being resized or a panel being opened.
+    needs_redraw: bool,
+    /// Set to copy the display to the clipboard as text the next time it's drawn.
+    copy_display: bool,
+    /// The debugger's command line, opened with `:`.
+    command_bar: CommandBar,
+    /// Whether the key reference is shown over everything, toggled with `?`.
+    help_open: bool,
+    /// Whether to pause while the terminal doesn't have focus.
+    pause_on_focus_loss: bool,
+    /// Set when losing focus paused the machine, so that only then does regaining it resume.
+    paused_for_focus: bool,
+    /// Gives the terminal back when the UI is dropped, after everything else.
+    _guard: TerminalGuard,
+}
+
+impl Tui {
+    pub fn new(
+        rom_title: String,
+        keymap: Keymap,
+        render_style: Option<RenderStyle>,
+        theme: Theme,
+        phosphor: Option<Phosphor>,
+        beeper: Box<dyn Beeper>,
+        guard: TerminalGuard,
+    ) -> io::Result<Self> {
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+        terminal.clear()?;
+
+        Ok(Self {
+            terminal,
+            rom_title,
+            keymap,
+            render_style: render_style.unwrap_or_default(),
+            fixed_style: render_style.is_some(),
+            theme,
+            beeper,
+            held_keys: HeldKeys::new(guard.reports_releases()),
+            panels: Vec::new(),
+            memory_top: None,
+            coverage: false,
+            memory_i: 0,
+            memory_len: 0,
+            display_rows: Vec::new(),
+            phosphor,
+            last_status: None,
+            flashed: false,
+            needs_redraw: true,
+            copy_display: false,
+            command_bar: CommandBar::default(),
+            help_open: false,
+            pause_on_focus_loss: false,
+            paused_for_focus: false,
+            _guard: guard,
+        })
+    }
+
+    /// Pauses whenever the terminal loses focus, resuming when it gets it back if it was running.
+    pub fn set_pause_on_focus_loss(&mut self, on: bool) {
+        self.pause_on_focus_loss = on;
+    }
+
+    /// Puts `text` on the clipboard with an OSC 52 escape, which terminals that don't support it
+    /// ignore.
+    fn copy_to_clipboard(&mut self, text: &str) {
+        let backend = self.terminal.backend_mut();
+        let _ = write!(backend, "\x1b]52;c;{}\x07", base64(text.as_bytes()));
+        let _ = io::Write::flush(backend);
+    }
+
+    /// Rebuilds the lines of `display_rows` that `fb` has changed since it was last drawn, along
+    /// with the `fading` rows the phosphor has changed.
+    fn update_display_rows(&mut self, fb: &FrameBuffer, fading: Option<Range<usize>>) {
+        let style = self.render_style;
+        let (columns, height) = style.text_size(fb);
+        let lines = if self.display_rows.len() != height {
+            0..height
+        } else {
+            match (fb.dirty().map(|dirty| dirty.rows()), fading) {
+                (Some(a), Some(b)) => style.lines_for(a.start.min(b.start)..a.end.max(b.end)),
+                (Some(rows), None) | (None, Some(rows)) => style.lines_for(rows),
+                (None, None) => return,
+            }
+        };
+        self.display_rows.resize(height, Line::default());
+        for line in lines {
+            self.display_rows[line] = match &self.phosphor {
+                Some(phosphor) => {
+                    let cells = style.cells(columns, line, |x, y| phosphor.level(x, y));
+                    shaded_line(cells, self.theme)
+                }
+                None => Line::raw(style.line(fb, line)),
+            };
+        }
+    }
+
+    /// Unless a style was chosen, switches to the one with the biggest square pixels that fits
+    /// `fb` in what's left of the terminal, beside any open panels and above the status lines.
+    fn fit_render_style(&mut self, fb: &FrameBuffer, panels_shown: bool) {
+        if self.fixed_style {
+            return;
+        }
+        let Some((columns, rows)) = self.display_space(panels_shown) else {
+            return;
+        };
+        let style = RenderStyle::fitting(fb.width(), fb.height(), columns, rows);
+        if style != self.render_style {
+            self.render_style = style;
+            // The lines are all different now, even where the display isn't.
+            self.display_rows.clear();
+        }
+    }
+
+    /// How many characters across and down the terminal has left for the display, or `None` if
+    /// its size can't be found.
+    fn display_space(&self, panels_shown: bool) -> Option<(usize, usize)> {
+        let area = self.terminal.size().ok()?;
+        let (columns, rows) = self.chrome_size(panels_shown);
+        Some((
+            area.width.saturating_sub(columns) as usize,
+            area.height.saturating_sub(rows) as usize,
+        ))
+    }
+
+    /// How many characters across and down everything but the display takes up: the open panels
+    /// beside it, and the title, status lines and console around it.
+    fn chrome_size(&self, panels_shown: bool) -> (u16, u16) {
+        let panels = if panels_shown {
+            self.panels.iter().map(|panel| 1 + panel.width()).sum()
+        } else {
+            0
+        };
+        let console = if self.command_bar.open {
+            CONSOLE_HEIGHT
+        } else {
+            0
+        };
+        (panels, CHROME_HEIGHT + console)
+    }
+
+    /// Draws the display with the status line under it, and the open panels beside it if there's
+    /// a machine to show in them.
+    ///
+    /// Nothing is drawn at all if nothing on screen would change. Open panels show the machine as
+    /// it runs, so they're redrawn every frame. If the terminal's too small for it all, it says
+    /// so instead.
+    fn draw(&mut self, fb: &FrameBuffer, cpu: Option<&CPU>, status: &RunStatus) {
+        let _present = debug_span!(target: "display", "present").entered();
+        if std::mem::take(&mut self.copy_display) {
+            self.copy_to_clipboard(&fb.to_text());
+        }
+        let panels_open = cpu.is_some() && !self.panels.is_empty();
+        let fading = self
+            .phosphor
+            .as_mut()
+            .and_then(|phosphor| phosphor.update(fb));
+        if !fb.is_dirty()
+            && fading.is_none()
+            && !self.needs_redraw
+            && !panels_open
+            && self.last_status.as_ref() == Some(status)
+            && self.flashed == self.beeper.flashing()
+        {
+            return;
+        }
+        self.needs_redraw = false;
+        self.last_status = Some(status.clone());
+        self.flashed = self.beeper.flashing();
+        self.fit_render_style(fb, cpu.is_some());
+        self.update_display_rows(fb, fading);
+
+        let (width, height) = self.render_style.text_size(fb);
+        if let Some((columns, rows)) = self.display_space(cpu.is_some()) {
+            if width > columns || height > rows {
+                let (chrome_width, chrome_height) = self.chrome_size(cpu.is_some());
+                let needed = (width as u16 + chrome_width, height as u16 + chrome_height);
+                let message = too_small_message(needed, panels_open);
+                self.terminal
+                    .draw(|f| {
+                        f.render_widget(Block::new().on_black(), f.size());
+                        let middle = Layout::default()
+                            .direction(Direction::Vertical)
+                            .constraints(vec![
+                                Constraint::Fill(1),
+                                Constraint::Length(2),
+                                Constraint::Fill(1),
+                            ])
+                            .split(f.size())[1];
+                        f.render_widget(message, middle);
+                    })
+                    .unwrap();
+                return;
+            }
+        }
+        let display = self.display_rows.clone();
+        let snapshot = cpu.map(CPU::snapshot);
+        let (rom_title, panels, memory_top) = (&self.rom_title, &self.panels, self.memory_top);
+        let coverage = self.coverage;
+        let command_bar = &self.command_bar;
+        let help = self.help_open.then(|| help_overlay(&self.keymap));
+        let (fg, bg) = (rgb(self.theme.fg), rgb(self.theme.bg));
+        // Show the tone by inverting the display, if it can't be heard.
+        let flash = if self.flashed {
+            Modifier::REVERSED
+        } else {
+            Modifier::empty()
+        };
+        self.terminal
+            .draw(|f| {
+                f.render_widget(Block::new().on_black(), f.size());
+
+                let layout = Layout::default()
+                    .direction(Direction::Vertical)
+                    .constraints(vec![
+                        Constraint::Length(3),
+                        Constraint::Length(height as u16),
+                        Constraint::Length(1),
+                        Constraint::Length(1),
+                        Constraint::Length(if command_bar.open { CONSOLE_HEIGHT } else { 0 }),
+                        Constraint::Fill(1),
+                    ])
+                    .split(f.size());
+
+                let title = layout[0];
+                f.render_widget(
+                    Paragraph::new(format!("[Chip8-RS] {}", rom_title))
+                        .white()
+                        .centered()
+                        .block(Block::bordered()),
+                    title,
+                );
+
+                // The display, then any open panels to its right, all centred together.
+                let panels = if cpu.is_some() { &panels[..] } else { &[] };
+                let mut emu_constraints =
+                    vec![Constraint::Fill(1), Constraint::Length(width as u16)];
+                for panel in panels {
+                    emu_constraints.push(Constraint::Length(1));
+                    emu_constraints.push(Constraint::Length(panel.width()));
+                }
+                emu_constraints.push(Constraint::Fill(1));
+                let emu_layout = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints(emu_constraints)
+                    .split(layout[1]);
+                let emu = emu_layout[1];
+                f.render_widget(
+                    Paragraph::new(display).fg(fg).bg(bg).add_modifier(flash),
+                    emu,
+                );
+                if let (Some(cpu), Some(snapshot)) = (cpu, &snapshot) {
+                    for (i, panel) in panels.iter().enumerate() {
+                        let area = emu_layout[3 + i * 2];
+                        let rows = area.height.saturating_sub(2) as usize;
+                        let widget = match panel {
+                            Panel::Registers => registers_panel(cpu, snapshot),
+                            Panel::Disassembly => disassembly_panel(cpu, rows),
+                            Panel::Memory => memory_panel(cpu, memory_top, coverage, rows),
+                            Panel::Trace => trace_panel(cpu, rows),
+                            Panel::Profile => profile_panel(cpu, rows),
+                        };
+                        f.render_widget(widget, area);
+                    }
+                }
+
+                let status_area = layout[2];
+                if let Some(status) = status_line(status) {
+                    f.render_widget(status, status_area);
+                }
+                f.render_widget(status_bar(status), layout[3]);
+                if command_bar.open {
+                    f.render_widget(command_bar.widget(&status.console), layout[4]);
+                }
+                if let Some((help, size)) = help {
+                    let area = centred(f.size(), size);
+                    f.render_widget(Clear, area);
+                    f.render_widget(help, area);
+                }
+            })
+            .unwrap();
+    }
+
+    fn scroll_memory(&mut self, code: event::KeyCode) {
+        let top = self
+            .memory_top
+            .unwrap_or_else(|| memory_row_for(self.memory_i));
+        let page = MEMORY_BYTES_PER_ROW * 16;
+        self.memory_top = match code {
+            event::KeyCode::PageUp => Some(top.saturating_sub(page)),
+            event::KeyCode::PageDown => {
+                Some((top + page).min(self.memory_len.saturating_sub(MEMORY_BYTES_PER_ROW)))
+            }
+            _ => None,
+        };
+    }
+
+    /// Does what `binding` does, for `key` going down, repeating or coming up.
+    fn act(
+        &mut self,
+        binding: &Binding,
+        key: event::KeyEvent,
+        now: Instant,
+        events: &mut Vec<InputEvent>,
+    ) {
+        match key.kind {
+            event::KeyEventKind::Release if binding.action == Action::Turbo => {
+                events.extend(self.held_keys.release(HeldKeys::TURBO));
+                return;
+            }
+            event::KeyEventKind::Release => return,
+            event::KeyEventKind::Repeat if !binding.repeats => return,
+            _ => {}
+        }
+        match &binding.action {
+            Action::Input(input) => events.push(input.clone()),
+            Action::Turbo => events.extend(self.held_keys.press(HeldKeys::TURBO, now)),
+            // Copied to the clipboard once it's next drawn.
+            Action::CopyScreenshot => {
+                self.copy_display = true;
+                events.push(InputEvent::TextScreenshot);
+            }
+            Action::Panel(panel) => {
+                Panel::toggle(&mut self.panels, *panel);
+                self.needs_redraw = true;
+            }
+            // Which is no use without the memory panel open.
+            Action::Coverage => {
+                self.coverage = !self.coverage;
+                if self.coverage && !self.panels.contains(&Panel::Memory) {
+                    Panel::toggle(&mut self.panels, Panel::Memory);
+                }
+                self.needs_redraw = true;
+            }
+            Action::ScrollMemory => self.scroll_memory(key.code),
+            Action::CommandLine => {
+                self.command_bar.open = true;
+                self.needs_redraw = true;
+            }
+            Action::Help => {
+                self.help_open = !self.help_open;
+                self.needs_redraw = true;
+            }
+        }
+    }
+}
+
+impl Frontend for Tui {
+    fn present(&mut self, fb: &FrameBuffer) {
+        self.draw(fb, None, &RunStatus::default());
+    }
+
+    fn present_machine(&mut self, cpu: &CPU, status: &RunStatus) {
+        self.memory_i = cpu.registers.i as usize;
+        self.memory_len = cpu.memory.len();
+        self.draw(cpu.framebuffer(), Some(cpu), status);
+    }
+
+    /// Drains pending terminal events: the keys in `BINDINGS` do what they say, and mapped keys
+    /// drive the keypad.
+    fn poll_input(&mut self) -> Vec<InputEvent> {
+        let now = Instant::now();
+        let mut events = Vec::new();
+        while let Ok(true) = event::poll(Duration::from_millis(0)) {
+            let key = match event::read() {
+                Ok(event::Event::Key(key)) => key,
+                Ok(event::Event::Resize(..)) => {
+                    self.needs_redraw = true;
+                    continue;
+                }
+                Ok(event::Event::FocusLost) => {
+                    let running = self.last_status.as_ref().is_some_and(|s| !s.paused);
+                    if self.pause_on_focus_loss && running {
+                        events.push(InputEvent::Control(Command::Pause));
+                        self.paused_for_focus = true;
+                    }
+                    continue;
+                }
+                Ok(event::Event::FocusGained) => {
+                    if std::mem::take(&mut self.paused_for_focus) {
+                        events.push(InputEvent::Control(Command::Resume));
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            let binding = BINDINGS.iter().find(|binding| binding.matches(&key));
+            let quit =
+                binding.is_some_and(|binding| binding.action == Action::Input(InputEvent::Quit));
+            match (key, binding) {
+                // While the command line's open, it gets all the typing but Ctrl+C. Releases still
+                // go to the keypad, so that keys held down when it opened don't stay down.
+                (key, _)
+                    if self.command_bar.open
+                        && !quit
+                        && key.kind != event::KeyEventKind::Release =>
+                {
+                    events.extend(self.command_bar.key(key));
+                    self.needs_redraw = true;
+                }
+                // Esc closes the key reference too.
+                (
+                    event::KeyEvent {
+                        code: event::KeyCode::Esc,
+                        kind: event::KeyEventKind::Press,
+                        ..
+                    },
+                    _,
+                ) if self.help_open => {
+                    self.help_open = false;
+                    self.needs_redraw = true;
+                }
+                // The controls take precedence over the keymap, which can't use their keys.
+                (key, Some(binding)) => self.act(binding, key, now, &mut events),
+                (
+                    event::KeyEvent {
+                        code: event::KeyCode::Char(c),
+                        kind,
+                        ..
+                    },
+                    None,
+                ) => {
+                    if let Some(k) = self.keymap.key_for(c) {
+                        events.extend(match kind {
+                            event::KeyEventKind::Release => self.held_keys.release(k as usize),
+                            _ => self.held_keys.press(k as usize, now),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        self.held_keys.expire(now, &mut events);
+        events
+    }
+
+    fn beep(&mut self, on: bool) {
+        self.beeper.set_beeping(on);
+    }
+
+    fn play_pattern(&mut self, pattern: Option<AudioPattern>) {
+        self.beeper.set_pattern(pattern);
+    }
+
+    fn play_sound(&mut self, sound: Option<&DigitizedSound>) {
+        self.beeper.set_sound(sound);
+    }
+}
+
+/// `bytes` in standard, padded base64.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn rgb(color: chip8::Color) -> Color {
+    Color::Rgb(color.r, color.g, color.b)
+}
+
+/// What's shown instead of the display when the terminal's too small for it: the size it needs
+/// to be, in characters, and that closing the panels would help if any are open.
+fn too_small_message((columns, rows): (u16, u16), panels_open: bool) -> Paragraph<'static> {
+    let mut needs = format!("Make it at least {}x{}", columns, rows);
+    if panels_open {
+        needs.push_str(", or close some panels");
+    }
+    Paragraph::new(vec![
+        Line::raw("The terminal is too small"),
+        Line::raw(needs),
+    ])
+    .white()
+    .centered()
+    .wrap(Wrap { trim: true })
+}
+
+/// What a key in `BINDINGS` does.
+#[derive(PartialEq)]
+enum Action {
+    /// Sent on to the run loop.
+    Input(InputEvent),
+    /// Runs flat out while the key's held down.
+    Turbo,
+    /// Saves the display as text and copies it to the clipboard.
+    CopyScreenshot,
+    Panel(Panel),
+    /// Colours the memory panel by how memory's been used.
+    Coverage,
+    /// Pages through the memory panel, or with Home, goes back to following I.
+    ScrollMemory,
+    CommandLine,
+    Help,
+}
+
+/// A key the terminal UI keeps for itself rather than the keypad, and what it does. Any characters
+/// bound here need to be in `Keymap::RESERVED` too, so that keymaps can't use them.
+struct Binding {
+    code: event::KeyCode,
+    /// The modifiers held with it. Shift is ignored for characters, which can need it to type.
+    modifiers: event::KeyModifiers,
+    action: Action,
+    /// Whether it acts again as the key repeats while held down.
+    repeats: bool,
+    /// What it does, for the help overlay.
+    description: &'static str,
+}
+
+impl Binding {
+    const fn new(
+        code: event::KeyCode,
+        modifiers: event::KeyModifiers,
+        action: Action,
+        description: &'static str,
+    ) -> Self {
+        Self {
+            code,
+            modifiers,
+            action,
+            repeats: false,
+            description,
+        }
+    }
+
+    /// A binding that acts again as the key repeats while held down.
+    const fn repeating(
+        code: event::KeyCode,
+        modifiers: event::KeyModifiers,
+        action: Action,
+        description: &'static str,
+    ) -> Self {
+        Self {
+            code,
+            modifiers,
+            action,
+            repeats: true,
+            description,
+        }
+    }
+
+    fn matches(&self, key: &event::KeyEvent) -> bool {
+        let mut modifiers = key.modifiers;
+        if let event::KeyCode::Char(_) = key.code {
+            modifiers.remove(event::KeyModifiers::SHIFT);
+        }
+        key.code == self.code && modifiers == self.modifiers
+    }
+
+    /// The key as the help overlay writes it, like `Shift+F9`.
+    fn name(&self) -> String {
+        let mut name = String::new();
+        if self.modifiers.contains(event::KeyModifiers::CONTROL) {
+            name.push_str("Ctrl+");
+        }
+        if self.modifiers.contains(event::KeyModifiers::SHIFT) {
+            name.push_str("Shift+");
+        }
+        match self.code {
+            event::KeyCode::Char(' ') => name.push_str("Space"),
+            event::KeyCode::Char(c) => name.extend(c.to_uppercase()),
+            event::KeyCode::F(n) => name.push_str(&format!("F{}", n)),
+            event::KeyCode::PageUp => name.push_str("PgUp"),
+            event::KeyCode::PageDown => name.push_str("PgDn"),
+            code => name.push_str(&format!("{:?}", code)),
+        }
+        name
+    }
+}
+
+const NONE: event::KeyModifiers = event::KeyModifiers::NONE;
+const SHIFT: event::KeyModifiers = event::KeyModifiers::SHIFT;
+const CONTROL: event::KeyModifiers = event::KeyModifiers::CONTROL;
+
+/// Every key `poll_input` handles besides the keypad's, in the order the help overlay lists them.
+static BINDINGS: &[Binding] = &[
+    Binding::new(
+        event::KeyCode::Char(' '),
+        NONE,
+        Action::Input(InputEvent::Control(Command::TogglePause)),
+        "pause or resume",
+    ),
+    Binding::new(
+        event::KeyCode::Char('n'),
+        NONE,
+        Action::Input(InputEvent::Control(Command::Step)),
+        "step an instruction",
+    ),
+    Binding::new(
+        event::KeyCode::Char('o'),
+        NONE,
+        Action::Input(InputEvent::Control(Command::StepOver)),
+        "step over a call",
+    ),
+    Binding::new(
+        event::KeyCode::Char('.'),
+        NONE,
+        Action::Input(InputEvent::Control(Command::AdvanceFrame)),
+        "advance a frame",
+    ),
+    Binding::repeating(
+        event::KeyCode::Backspace,
+        NONE,
+        Action::Input(InputEvent::Control(Command::Rewind)),
+        "rewind a frame, held to keep going",
+    ),
+    Binding::repeating(
+        event::KeyCode::Tab,
+        NONE,
+        Action::Turbo,
+        "run flat out while held",
+    ),
+    Binding::repeating(
+        event::KeyCode::Char('+'),
+        NONE,
+        Action::Input(InputEvent::Faster),
+        "faster",
+    ),
+    // The same key without shift, on most layouts.
+    Binding::repeating(
+        event::KeyCode::Char('='),
+        NONE,
+        Action::Input(InputEvent::Faster),
+        "faster",
+    ),
+    Binding::repeating(
+        event::KeyCode::Char('-'),
+        NONE,
+        Action::Input(InputEvent::Slower),
+        "slower",
+    ),
+    // Quick save and load, to a file next to the ROM.
+    Binding::new(
+        event::KeyCode::F(5),
+        NONE,
+        Action::Input(InputEvent::SaveState),
+        "save state",
+    ),
+    Binding::new(
+        event::KeyCode::F(7),
+        NONE,
+        Action::Input(InputEvent::LoadState),
+        "load state",
+    ),
+    Binding::new(
+        event::KeyCode::F(6),
+        NONE,
+        Action::Input(InputEvent::Reset { hard: true }),
+        "reset",
+    ),
+    Binding::new(
+        event::KeyCode::F(6),
+        SHIFT,
+        Action::Input(InputEvent::Reset { hard: false }),
+        "restart with RAM as it is",
+    ),
+    Binding::new(
+        event::KeyCode::F(8),
+        NONE,
+        Action::Input(InputEvent::Reload),
+        "reload the ROM from disk",
+    ),
+    // Dumps and screenshots go next to the quick save.
+    Binding::new(
+        event::KeyCode::F(9),
+        NONE,
+        Action::Input(InputEvent::DumpTrace),
+        "save the trace",
+    ),
+    Binding::new(
+        event::KeyCode::F(9),
+        SHIFT,
+        Action::Input(InputEvent::DumpMemory),
+        "save memory and the registers",
+    ),
+    Binding::new(
+        event::KeyCode::F(12),
+        NONE,
+        Action::Input(InputEvent::Screenshot),
+        "save a screenshot",
+    ),
+    Binding::new(
+        event::KeyCode::F(12),
+        CONTROL,
+        Action::CopyScreenshot,
+        "save a screenshot as text and copy it",
+    ),
+    #[cfg(feature = "video")]
+    Binding::new(
+        event::KeyCode::F(12),
+        SHIFT,
+        Action::Input(InputEvent::ToggleVideo),
+        "start / stop recording video",
+    ),
+    Binding::new(
+        event::KeyCode::F(1),
+        NONE,
+        Action::Panel(Panel::Registers),
+        "registers panel",
+    ),
+    Binding::new(
+        event::KeyCode::F(2),
+        NONE,
+        Action::Panel(Panel::Disassembly),
+        "disassembly panel",
+    ),
+    Binding::new(
+        event::KeyCode::F(3),
+        NONE,
+        Action::Panel(Panel::Memory),
+        "memory panel",
+    ),
+    Binding::new(
+        event::KeyCode::F(4),
+        NONE,
+        Action::Panel(Panel::Trace),
+        "trace panel",
+    ),
+    Binding::new(
+        event::KeyCode::F(10),
+        NONE,
+        Action::Panel(Panel::Profile),
+        "profile panel",
+    ),
+    Binding::new(
+        event::KeyCode::F(11),
+        NONE,
+        Action::Coverage,
+        "colour memory by how it's used",
+    ),
+    Binding::new(
+        event::KeyCode::PageUp,
+        NONE,
+        Action::ScrollMemory,
+        "scroll memory up",
+    ),
+    Binding::new(
+        event::KeyCode::PageDown,
+        NONE,
+        Action::ScrollMemory,
+        "scroll memory down",
+    ),
+    Binding::new(
+        event::KeyCode::Home,
+        NONE,
+        Action::ScrollMemory,
+        "memory follows I",
+    ),
+    Binding::new(
+        event::KeyCode::Char(':'),
+        NONE,
+        Action::CommandLine,
+        "debugger console",
+    ),
+    Binding::new(event::KeyCode::Char('?'), NONE, Action::Help, "this help"),
+    Binding::new(
+        event::KeyCode::Char('c'),
+        CONTROL,
+        Action::Input(InputEvent::Quit),
+        "quit",
+    ),
+];
+
+/// The `?` overlay: the keypad as laid out on the COSMAC VIP beside the keys it's mapped to,
+/// then the other controls. Returns it with the size it needs, borders included.
+fn help_overlay(keymap: &Keymap) -> (Paragraph<'static>, (u16, u16)) {
+    const KEYPAD: [[u8; 4]; 4] = [
+        [1, 2, 3, 0xc],
+        [4, 5, 6, 0xd],
+        [7, 8, 9, 0xe],
+        [0xa, 0, 0xb, 0xf],
+    ];
+    let mut lines = vec![Line::raw("Keypad    Keys").bold()];
+    for row in KEYPAD {
+        let keypad: Vec<String> = row.iter().map(|key| format!("{:X}", key)).collect();
+        let keys: Vec<String> = row
+            .iter()
+            .map(|&key| keymap.char_for(key).to_uppercase().to_string())
+            .collect();
+        lines.push(Line::raw(format!(
+            "{}   {}",
+            keypad.join(" "),
+            keys.join(" ")
+        )));
+    }
+    lines.push(Line::default());
+    let names: Vec<String> = BINDINGS.iter().map(Binding::name).collect();
+    let names_width = names.iter().map(String::len).max().unwrap_or(0);
+    for (name, binding) in names.into_iter().zip(BINDINGS) {
+        lines.push(Line::from(vec![
+            Span::raw(format!("{:<width$}  ", name, width = names_width)).cyan(),
+            Span::raw(binding.description),
+        ]));
+    }
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 4;
+    let height = lines.len() as u16 + 2;
+    let help = Paragraph::new(lines).white().on_black().block(
+        Block::bordered()
+            .title(" Keys (? or Esc to close) ")
+            .padding(Padding::horizontal(1)),
+    );
+    (help, (width, height))
+}
+
+/// A `width` x `height` rectangle in the middle of `area`, shrunk to fit in it if need be.
+fn centred(area: Rect, (width, height): (u16, u16)) -> Rect {
+    let (width, height) = (width.min(area.width), height.min(area.height));
+    Rect::new(
+        area.x + (area.width - width) / 2,
+        area.y + (area.height - height) / 2,
+        width,
+        height,
+    )
+}
+
+/// A line of the display with each character coloured by how bright it is, in runs of the same
+/// colour.
+fn shaded_line(cells: impl Iterator<Item = Cell>, theme: Theme) -> Line<'static> {
+    let shade = |level| rgb(theme.bg.mix(theme.fg, level));
+    let mut spans = Vec::new();
+    let mut run = String::new();
+    let mut run_style = Style::new();
+    for cell in cells {
+        let style = Style::new().fg(shade(cell.fg)).bg(shade(cell.bg));
+        if style != run_style && !run.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut run), run_style));
+        }
+        run_style = style;
+        run.push(cell.glyph);
+    }
+    spans.push(Span::styled(run, run_style));
+    Line::from(spans)
+}
+
+/// What's going on, if it's anything other than running normally. A fresh notice takes
+/// precedence over everything else.
+fn status_line(status: &RunStatus) -> Option<Paragraph<'static>> {
+    let line = if let Some(notice) = &status.notice {
+        Paragraph::new(notice.clone()).white()
+    } else if let Some(err) = &status.halted {
+        Paragraph::new(format!("Halted: {} (ctrl+c to quit)", err)).red()
+    } else if let Some(frames) = status.recording {
+        Paragraph::new(format!("Recording frame {}", frames)).red()
+    } else if let Some((frame, len)) = status.playback {
+        Paragraph::new(format!("Playing back frame {} of {}", frame, len)).yellow()
+    } else if status.paused {
+        let reason = match (status.stopped_at, status.watch_hit) {
+            (Some(Breakpoint::Watch(watchpoint)), Some(hit)) => {
+                format!("Watchpoint {} hit: {}", watchpoint, hit)
+            }
+            (Some(breakpoint), _) => format!("Breakpoint {} hit", breakpoint),
+            (None, _) => "Paused".to_string(),
+        };
+        Paragraph::new(format!(
+            "{} (space to resume, n to step, o to step over, . to advance a frame, backspace to \
+             rewind, : for commands)",
+            reason
+        ))
+        .yellow()
+    } else if status.speed == Speed::Turbo {
+        Paragraph::new(format!("Turbo, {} fps", status.fps)).cyan()
+    } else if status.speed != Speed::NORMAL {
+        Paragraph::new(format!(
+            "Speed {}, {} fps (+ and - to change)",
+            status.speed, status.fps
+        ))
+        .cyan()
+    } else {
+        return None;
+    };
+    Some(line.centered())
+}
+
+/// How fast the machine is going and what state it's in, always shown under the status line.
+fn status_bar(status: &RunStatus) -> Paragraph<'static> {
+    let state = if status.halted.is_some() {
+        "halted"
+    } else if status.paused {
+        "paused"
+    } else {
+        "running"
+    };
+    let quirks = status
+        .quirks
+        .map_or_else(|| "custom".to_string(), |variant| variant.to_string());
+    Paragraph::new(format!(
+        "{} | {} fps | {} ips | DT {:02X} ST {:02X} | sound {} | {} | {} quirks",
+        status.speed,
+        status.fps,
+        status.ips,
+        status.delay,
+        status.sound,
+        if status.sound > 0 { "on" } else { "off" },
+        state,
+        quirks
+    ))
+    .dark_gray()
+    .centered()
+}
+
+/// The debugging panels that can be opened beside the display, each toggled by a function key.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Panel {
+    /// F1
+    Registers,
+    /// F2
+    Disassembly,
+    /// F3
+    Memory,
+    /// F4
+    Trace,
+    /// F10
+    Profile,
+}
+
+impl Panel {
+    /// Panels are laid out in this order, whatever order they were opened in.
+    const ALL: [Panel; 5] = [
+        Panel::Registers,
+        Panel::Disassembly,
+        Panel::Memory,
+        Panel::Trace,
+        Panel::Profile,
+    ];
+
+    fn toggle(panels: &mut Vec<Panel>, panel: Panel) {
+        if panels.contains(&panel) {
+            panels.retain(|&p| p != panel);
+        } else {
+            panels.push(panel);
+            panels.sort_by_key(|p| Self::ALL.iter().position(|a| a == p));
+        }
+    }
+
+    fn width(self) -> u16 {
+        match self {
+            // Two columns of registers.
+            Panel::Registers => 24,
+            // The longest line is an address, two words and `ld i, long 0x1234`.
+            Panel::Disassembly => 38,
+            // An address, the hex bytes, then the same bytes as ASCII.
+            Panel::Memory => 6 + MEMORY_BYTES_PER_ROW as u16 * 4,
+            // An address, the opcode and the instruction, like the disassembly.
+            Panel::Trace => 32,
+            // An address, the opcode pattern, then the share of instructions and the time.
+            Panel::Profile => 32,
+        }
+    }
+}
+
+/// The F2 panel: the instructions around the PC, with the next one to run highlighted. Memory
+/// is decoded in steps of two bytes from a little before the PC, which is right as long as the
+/// code there is aligned with the PC.
+fn disassembly_panel(cpu: &CPU, rows: usize) -> Paragraph<'static> {
+    let before = rows / 3;
+    let mut addr = cpu.pc.saturating_sub(before as u16 * 2);
+    let mut lines = Vec::with_capacity(rows);
+    while lines.len() < rows {
+        let Some(opcode) = cpu.opcode_at(addr) else {
+            break;
+        };
+        if let Some(name) = cpu.symbols().name(addr) {
+            lines.push(Line::raw(format!("{}:", name)).yellow());
+            if lines.len() == rows {
+                break;
+            }
+        }
+        let (text, size) = match Instr::decode(opcode, cpu.variant()) {
+            Some(Instr::LoadILong) => {
+                let nnnn = cpu.opcode_at(addr.wrapping_add(2)).unwrap_or_default();
+                (
+                    format!(
+                        "{:04x} {:04x}  {} {:#06x}",
+                        opcode,
+                        nnnn,
+                        Instr::LoadILong,
+                        nnnn
+                    ),
+                    4,
+                )
+            }
+            #[cfg(feature = "megachip")]
+            Some(instr @ Instr::LoadIHigh(_)) => {
+                let nnnn = cpu.opcode_at(addr.wrapping_add(2)).unwrap_or_default();
+                (
+                    format!("{:04x} {:04x}  {}{:04x}", opcode, nnnn, instr, nnnn),
+                    4,
+                )
+            }
+            Some(instr) => (
+                format!("{:04x}       {}", opcode, named(instr, cpu.symbols())),
+                2,
+            ),
+            None => (
+                format!(
+                    "{:04x}       db {:#04x}, {:#04x}",
+                    opcode,
+                    opcode >> 8,
+                    opcode & 0xff
+                ),
+                2,
+            ),
+        };
+        let line = Line::raw(format!("{:03x}  {}", addr, text));
+        lines.push(if addr == cpu.pc {
+            line.black().on_light_blue()
+        } else if cpu.breakpoints().contains(&Breakpoint::Address(addr)) {
+            line.red()
+        } else {
+            line
+        });
+        addr = addr.wrapping_add(size);
+    }
+
+    Paragraph::new(lines)
+        .white()
+        .block(Block::bordered().title("Disassembly"))
+}
+
+/// `instr`, with the address it jumps to, calls or loads into I named if it has a name.
+fn named(instr: Instr, symbols: &Symbols) -> String {
+    let (mnemonic, target) = match instr {
+        Instr::Jump(target) => ("jp", target),
+        Instr::Call(target) => ("call", target),
+        Instr::LoadI(target) => ("ld i,", target),
+        Instr::JumpV0(target) => ("jp v0,", target),
+        _ => return instr.to_string(),
+    };
+    match symbols.name(target) {
+        Some(name) => format!("{} {}", mnemonic, name),
+        None => instr.to_string(),
+    }
+}
+
+/// The F4 panel: the last instructions executed, with the most recent at the bottom.
+fn trace_panel(cpu: &CPU, rows: usize) -> Paragraph<'static> {
+    let trace = cpu.trace();
+    let lines: Vec<Line> = trace
+        .entries()
+        .skip(trace.len().saturating_sub(rows))
+        .map(|entry| Line::raw(entry.describe(cpu.variant())))
+        .collect();
+    Paragraph::new(lines)
+        .white()
+        .block(Block::bordered().title("Trace (F9 to save)"))
+}
+
+/// The F10 panel: the loops that ran the most instructions, then the busiest addresses, each with
+/// its share of everything executed.
+fn profile_panel(cpu: &CPU, rows: usize) -> Paragraph<'static> {
+    let block = Block::bordered().title("Profile");
+    let profiler = cpu.profiler();
+    if !profiler.is_enabled() {
+        return Paragraph::new("Run with --profile")
+            .dark_gray()
+            .block(block);
+    }
+    let report = profiler.report(cpu.variant());
+    let mut lines = vec![Line::raw(format!(
+        "{} instrs in {:.1?}",
+        report.total.count, report.total.time
+    ))];
+    lines.extend(report.loops.iter().take(rows / 3).map(|hot| {
+        Line::raw(format!(
+            "loop {:03x}-{:03x} {:>5.1}% x{}",
+            hot.start,
+            hot.end,
+            report.percent(hot.cost),
+            hot.iterations
+        ))
+        .yellow()
+    }));
+    let left = rows.saturating_sub(lines.len());
+    lines.extend(report.addresses.iter().take(left).map(|address| {
+        Line::raw(format!(
+            "{:03x}  {}  {:>5.1}% {:>8.1?}",
+            address.pc,
+            address.pattern,
+            report.percent(address.cost),
+            address.cost.time
+        ))
+    }));
+    Paragraph::new(lines).white().block(block)
+}
+
+const MEMORY_BYTES_PER_ROW: usize = 8;
+
+/// The start of the memory panel row containing `addr`.
+fn memory_row_for(addr: usize) -> usize {
+    addr - addr % MEMORY_BYTES_PER_ROW
+}
+
+/// The F3 panel: a hex dump of RAM starting at row `top`, or a little before I if that's
+/// `None`. The bytes from I onwards are highlighted, and so are the font sprites, unless
+/// `coverage` is set, when every byte is coloured by whether it's been executed or read as data.
+fn memory_panel(cpu: &CPU, top: Option<usize>, coverage: bool, rows: usize) -> Paragraph<'static> {
+    let i = cpu.registers.i as usize;
+    let memory = &cpu.memory[..];
+    let top = top
+        .unwrap_or_else(|| memory_row_for(i).saturating_sub(MEMORY_BYTES_PER_ROW * (rows / 3)))
+        .min(memory_row_for(
+            memory.len().saturating_sub(MEMORY_BYTES_PER_ROW * rows),
+        ));
+
+    let lines: Vec<Line> = memory[top..]
+        .chunks(MEMORY_BYTES_PER_ROW)
+        .take(rows)
+        .enumerate()
+        .map(|(row, bytes)| {
+            let addr = top + row * MEMORY_BYTES_PER_ROW;
+            let mut spans = vec![Span::raw(format!("{:03x} ", addr))];
+            for (offset, &byte) in bytes.iter().enumerate() {
+                let addr = addr + offset;
+                let span = Span::raw(format!(" {:02x}", byte));
+                spans.push(if addr == i {
+                    span.black().on_light_blue()
+                } else if coverage {
+                    match cpu.coverage().usage(addr) {
+                        Usage::Unused => span.dark_gray(),
+                        Usage::Code => span.green(),
+                        Usage::Data => span.yellow(),
+                        Usage::Both => span.magenta(),
+                    }
+                } else if (i..i + 16).contains(&addr) {
+                    span.light_blue()
+                } else if addr < FONTS_END {
+                    span.dark_gray()
+                } else {
+                    span
+                });
+            }
+            let ascii: String = bytes
+                .iter()
+                .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+                .collect();
+            spans.push(Span::raw(format!("  {}", ascii)).dark_gray());
+            Line::from(spans)
+        })
+        .collect();
+
+    let title = if coverage {
+        Line::from(vec![
+            "Memory: ".into(),
+            "code ".green(),
+            "data ".yellow(),
+            "both".magenta(),
+        ])
+    } else {
+        Line::raw("Memory (PgUp/PgDn, Home)")
+    };
+    Paragraph::new(lines)
+        .white()
+        .block(Block::bordered().title(title))
+}
+
+/// The F1 panel: registers, timers, the last instruction and the subroutine calls on the stack,
+/// named by the ROM's symbols if it has any.
+fn registers_panel(cpu: &CPU, snapshot: &Snapshot) -> Paragraph<'static> {
+    let mut lines = vec![
+        format!("PC {:04x}   I  {:04x}", snapshot.pc, snapshot.i),
+        format!("DT {:02x}     ST {:02x}", snapshot.delay, snapshot.sound),
+        match snapshot.last_instruction {
+            Some((pc, opcode)) => format!("Op {:04x} @ {:04x}", opcode, pc),
+            None => "Op ----".to_string(),
+        },
+        String::new(),
+    ];
+    for row in 0..8 {
+        lines.push(format!(
+            "V{:X} {:02x}     V{:X} {:02x}",
+            row,
+            snapshot.v[row],
+            row + 8,
+            snapshot.v[row + 8]
+        ));
+    }
+    lines.push(String::new());
+    lines.push(format!("Calls (SP {})", snapshot.sp));
+    let symbols = cpu.symbols();
+    for (site, target) in cpu.calls() {
+        lines.push(match target {
+            Some(target) => format!(" {}", symbols.describe(target)),
+            None => " ?".to_string(),
+        });
+        lines.push(format!("   from {}", symbols.describe(site)));
+    }
+
+    Paragraph::new(lines.join("\n"))
+        .white()
+        .block(Block::bordered().title("Registers (: to set)"))
+}
+
+/// How tall the debugger console is, borders included.
+const CONSOLE_HEIGHT: u16 = 10;
+/// The rows around the display: the bordered title above it, and the status line and bar below.
+const CHROME_HEIGHT: u16 = 5;
+
+/// The debugger's command line: a line being edited at a `:` prompt, with the commands entered
+/// before it to go back through with Up and Down, and Tab to complete command and register
+/// names. The run loop runs the commands, and what they print comes back in `RunStatus`.
+#[derive(Default)]
+struct CommandBar {
+    open: bool,
+    line: String,
+    /// What's wrong with the last line entered, or what Tab could complete it to.
+    hint: Option<String>,
+    history: Vec<String>,
+    /// Where in `history` Up and Down have got to, or `None` for the line being typed.
+    browsing: Option<usize>,
+}
+
+impl CommandBar {
+    /// Edits the line, returning the command when one's entered.
+    fn key(&mut self, key: event::KeyEvent) -> Option<InputEvent> {
+        self.hint = None;
+        match key.code {
+            event::KeyCode::Esc => {
+                self.open = false;
+                self.line.clear();
+                self.browsing = None;
+            }
+            event::KeyCode::Enter => return self.enter(),
+            event::KeyCode::Backspace => {
+                self.line.pop();
+            }
+            event::KeyCode::Tab => self.complete(),
+            event::KeyCode::Up if !self.history.is_empty() => {
+                let at = self
+                    .browsing
+                    .unwrap_or(self.history.len())
+                    .saturating_sub(1);
+                self.browsing = Some(at);
+                self.line = self.history[at].clone();
+            }
+            event::KeyCode::Down => {
+                self.browsing = self
+                    .browsing
+                    .map(|at| at + 1)
+                    .filter(|&at| at < self.history.len());
+                self.line = self
+                    .browsing
+                    .map_or_else(String::new, |at| self.history[at].clone());
+            }
+            event::KeyCode::Char(c) => self.line.push(c),
+            _ => {}
+        }
+        None
+    }
+
+    fn enter(&mut self) -> Option<InputEvent> {
+        let line = std::mem::take(&mut self.line);
+        self.browsing = None;
+        if line.trim().is_empty() {
+            return None;
+        }
+        if self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        match line.parse::<ConsoleCommand>() {
+            Ok(command) => Some(InputEvent::Console(command)),
+            Err(err) => {
+                self.hint = Some(err);
+                None
+            }
+        }
+    }
+
+    /// Completes the line as far as it can go, listing the choices if there's more than one.
+    fn complete(&mut self) {
+        let choices = console::complete(&self.line);
+        let Some(first) = choices.first() else {
+            return;
+        };
+        let common = choices.iter().fold(first.as_str(), |common, choice| {
+            let len = common
+                .chars()
+                .zip(choice.chars())
+                .take_while(|(a, b)| a == b)
+                .count();
+            &common[..len]
+        });
+        if common.len() > self.line.len() {
+            self.line = common.to_string();
+        }
+        if choices.len() > 1 {
+            let names: Vec<_> = choices
+                .iter()
+                .filter_map(|choice| choice.split_whitespace().last())
+                .collect();
+            self.hint = Some(names.join(" "));
+        }
+    }
+
+    /// The console's output, as much as fits, with the prompt under it.
+    fn widget(&self, output: &[String]) -> Paragraph<'static> {
+        let rows = CONSOLE_HEIGHT.saturating_sub(3) as usize;
+        let mut lines: Vec<Line> = output
+            .iter()
+            .skip(output.len().saturating_sub(rows))
+            .map(|line| {
+                let styled = Line::raw(line.clone());
+                if line.starts_with("> ") {
+                    styled.dark_gray()
+                } else if line.starts_with("Error: ") {
+                    styled.red()
+                } else {
+                    styled
+                }
+            })
+            .collect();
+        lines.resize(rows, Line::default());
+        lines.push(Line::from(vec![
+            Span::raw(format!(":{}", self.line)),
+            Span::raw(" ").reversed(),
+        ]));
+        let mut block = Block::bordered().title("Console (Tab completes, Esc closes)");
+        if let Some(hint) = &self.hint {
+            block = block.title_bottom(Line::raw(hint.clone()).yellow());
+        }
+        Paragraph::new(lines).white().block(block)
+    }
+}
+
+/// Most terminals only report key presses, never releases. Holding a key down just sends more
+/// presses once the OS auto-repeat kicks in, so a key is treated as held until its presses stop
+/// arriving for a while. The first press has to outlast the auto-repeat delay; after that,
+/// presses come in quickly and a much shorter timeout keeps releases responsive.
+///
+/// None of that is needed when the terminal reports releases itself.
+struct HeldKeys {
+    /// When each key was last pressed, and whether it has started auto-repeating: the keypad
+    /// keys, then Tab for turbo.
+    last_press: [Option<(Instant, bool)>; 17],
+    reports_releases: bool,
+}
+
+impl HeldKeys {
+    const TURBO: usize = 16;
+
+    /// Comfortably longer than the usual 250-500ms auto-repeat delay.
+    const FIRST_PRESS_HOLD: Duration = Duration::from_millis(550);
+    /// Comfortably longer than the usual 30-50ms auto-repeat interval.
+    const REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+    fn new(reports_releases: bool) -> Self {
+        Self {
+            last_press: [None; 17],
+            reports_releases,
+        }
+    }
+
+    fn press(&mut self, key: usize, now: Instant) -> Option<InputEvent> {
+        let repeating = self.last_press[key].is_some();
+        self.last_press[key] = Some((now, repeating));
+        match key {
+            Self::TURBO if repeating => None,
+            Self::TURBO => Some(InputEvent::Turbo(true)),
+            _ => Some(InputEvent::KeyDown(key as u8)),
+        }
+    }
+
+    fn release(&mut self, key: usize) -> Option<InputEvent> {
+        self.last_press[key] = None;
+        Some(match key {
+            Self::TURBO => InputEvent::Turbo(false),
+            _ => InputEvent::KeyUp(key as u8),
+        })
+    }
+
+    /// Releases keys whose presses have stopped arriving.
+    fn expire(&mut self, now: Instant, events: &mut Vec<InputEvent>) {
+        if self.reports_releases {
+            return;
+        }
+        for key in 0..self.last_press.len() {
+            if let Some((at, repeating)) = self.last_press[key] {
+                let hold = if repeating {
+                    Self::REPEAT_HOLD
+                } else {
+                    Self::FIRST_PRESS_HOLD
+                };
+                if now - at > hold {
+                    events.extend(self.release(key));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::str::FromStr;
+
+#[cfg(feature = "std")]
+use crate::cpu::{DISPLAY_HEIGHT, DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT, HIRES_DISPLAY_WIDTH};
+#[cfg(feature = "std")]
+use crate::memory::{MEMORY_SIZE, XOCHIP_MEMORY_SIZE};
+
+/// Which dialect of CHIP-8 the interpreter speaks. Extended variants are strict supersets: opcodes
+/// that only exist in a later variant are rejected as unknown when running an earlier one, and
+/// variants compare in that order. CHIP-48 has the same instructions as CHIP-8, and differs only
+/// in its quirks. MegaChip comes last, but extends SUPER-CHIP rather than XO-CHIP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Variant {
+    /// The original COSMAC VIP instruction set.
+    #[default]
+    Chip8,
+    /// The HP-48's CHIP-48, which SUPER-CHIP grew out of.
+    Chip48,
+    /// SUPER-CHIP 1.1: 128x64 hi-res mode, scrolling, 16x16 sprites, big font and RPL flags.
+    Schip,
+    /// Octo's XO-CHIP: SUPER-CHIP plus a second display plane, audio patterns and 64K of RAM.
+    XoChip,
+    /// MegaChip: SUPER-CHIP plus a 256x192 display of colour sprites, digitised sound and 16M
+    /// of RAM.
+    #[cfg(feature = "megachip")]
+    MegaChip,
+}
+
+impl Variant {
+    /// Whether the SUPER-CHIP instructions are available (XO-CHIP includes them).
+    pub fn is_schip(self) -> bool {
+        self >= Variant::Schip
+    }
+
+    pub fn is_xochip(self) -> bool {
+        self == Variant::XoChip
+    }
+
+    /// Whether the MegaChip instructions are available. Always false without the `megachip`
+    /// feature.
+    pub fn is_megachip(self) -> bool {
+        #[cfg(feature = "megachip")]
+        return self == Variant::MegaChip;
+        #[cfg(not(feature = "megachip"))]
+        false
+    }
+
+    #[cfg(feature = "std")]
+    pub fn memory_size(self) -> usize {
+        match self {
+            Variant::XoChip => XOCHIP_MEMORY_SIZE,
+            #[cfg(feature = "megachip")]
+            Variant::MegaChip => crate::megachip::MEGACHIP_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
+        }
+    }
+
+    /// The biggest display the variant can switch to, as width and height.
+    #[cfg(feature = "std")]
+    pub fn max_display_size(self) -> (usize, usize) {
+        #[cfg(feature = "megachip")]
+        if self == Variant::MegaChip {
+            return (
+                crate::megachip::MEGA_DISPLAY_WIDTH,
+                crate::megachip::MEGA_DISPLAY_HEIGHT,
+            );
+        }
+        if self.is_schip() {
+            (HIRES_DISPLAY_WIDTH, HIRES_DISPLAY_HEIGHT)
+        } else {
+            (DISPLAY_WIDTH, DISPLAY_HEIGHT)
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Variant::Chip8 => "chip8",
+            Variant::Chip48 => "chip48",
+            Variant::Schip => "schip",
+            Variant::XoChip => "xochip",
+            #[cfg(feature = "megachip")]
+            Variant::MegaChip => "megachip",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chip8" | "chip-8" => Ok(Variant::Chip8),
+            "chip48" | "chip-48" => Ok(Variant::Chip48),
+            "schip" | "superchip" | "super-chip" => Ok(Variant::Schip),
+            "xochip" | "xo-chip" => Ok(Variant::XoChip),
+            #[cfg(feature = "megachip")]
+            "megachip" | "mega8" => Ok(Variant::MegaChip),
+            #[cfg(feature = "megachip")]
+            _ => Err(format!(
+                "unknown variant '{}' (expected chip8, chip48, schip, xochip or megachip)",
+                s
+            )),
+            #[cfg(not(feature = "megachip"))]
+            _ => Err(format!(
+                "unknown variant '{}' (expected chip8, chip48, schip or xochip)",
+                s
+            )),
+        }
+    }
+}
@@ -0,0 +1,417 @@
+//! Video recordings of whole sessions: WebM with the sound, through an `ffmpeg` on the `PATH`, or
+//! APNG, which needs nothing else but is silent.
+//!
+//! Frames are streamed to ffmpeg as they're run, at the size of the largest display the ROM
+//! might use, and ffmpeg scales them up. The sound goes to a WAV file beside the video as it
+//! plays, and is muxed in when the recording's finished. An APNG is written on finishing, so its
+//! frames are kept in memory until then, two bits per pixel; merging frames that don't change
+//! keeps that small, but WebM suits long sessions better.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::str::FromStr;
+
+use crate::audio::{AudioPattern, ToneGenerator};
+use crate::capture;
+use crate::{FrameBuffer, Theme, FRAMES_PER_SECOND};
+
+/// Samples a second of the recorded sound.
+const SAMPLE_RATE: u32 = 44100;
+/// The tone's volume in the recording, out of 1.
+const VOLUME: f32 = 0.25;
+
+/// What a recording is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoFormat {
+    /// VP9 and Opus in WebM, encoded by ffmpeg.
+    #[default]
+    WebM,
+    /// An animated PNG, with no sound.
+    Apng,
+}
+
+impl VideoFormat {
+    /// The format for a file called `path`: APNG for .png and .apng, and otherwise whatever
+    /// ffmpeg makes of the extension.
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("png" | "apng") => VideoFormat::Apng,
+            _ => VideoFormat::WebM,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::WebM => "webm",
+            VideoFormat::Apng => "png",
+        }
+    }
+}
+
+impl fmt::Display for VideoFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            VideoFormat::WebM => "webm",
+            VideoFormat::Apng => "apng",
+        })
+    }
+}
+
+impl FromStr for VideoFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "webm" => Ok(VideoFormat::WebM),
+            "apng" => Ok(VideoFormat::Apng),
+            _ => Err(format!(
+                "unknown video format '{}' (expected webm or apng)",
+                s
+            )),
+        }
+    }
+}
+
+/// Records every frame of the display, and the tone, to a video file.
+pub struct VideoRecorder {
+    path: PathBuf,
+    /// The largest display the ROM might use, which smaller ones are stretched to.
+    size: (usize, usize),
+    theme: Theme,
+    scale: u32,
+    encoder: Encoder,
+    /// Frames recorded so far.
+    frames: u32,
+}
+
+enum Encoder {
+    /// Each distinct frame, two bits per pixel, and how many frames it showed for.
+    Apng(Vec<(Vec<u8>, u32)>),
+    Ffmpeg(Ffmpeg),
+}
+
+impl VideoRecorder {
+    /// Starts recording to `path`, as `VideoFormat::for_path` says, at `width` x `height` scaled
+    /// up `scale` times.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        (width, height): (usize, usize),
+        theme: Theme,
+        scale: u32,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let scale = scale.max(1);
+        let encoder = match VideoFormat::for_path(&path) {
+            VideoFormat::Apng => {
+                // Find out now, rather than at the end, if the file can't be written.
+                File::create(&path)?;
+                Encoder::Apng(Vec::new())
+            }
+            VideoFormat::WebM => Encoder::Ffmpeg(Ffmpeg::start(&path, (width, height), scale)?),
+        };
+        Ok(Self {
+            path,
+            size: (width, height),
+            theme,
+            scale,
+            encoder,
+            frames: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Records a frame of `fb`, with the tone playing if `beeping`, or XO-CHIP's `pattern` in its
+    /// place, as from `CPU::beeping` and `CPU::audio`.
+    pub fn add_frame(
+        &mut self,
+        fb: &FrameBuffer,
+        beeping: bool,
+        pattern: Option<AudioPattern>,
+    ) -> io::Result<()> {
+        let (width, height) = self.size;
+        // As with GIFs, a display too big for the video is left out.
+        let pixels = if width.is_multiple_of(fb.width()) && height.is_multiple_of(fb.height()) {
+            Some(capture::scaled(fb, width, height))
+        } else {
+            None
+        };
+        match &mut self.encoder {
+            Encoder::Apng(frames) => {
+                if let Some(pixels) = pixels {
+                    let packed = pack(&pixels);
+                    match frames.last_mut() {
+                        Some((last, shown)) if *last == packed => *shown += 1,
+                        _ => frames.push((packed, 1)),
+                    }
+                }
+            }
+            Encoder::Ffmpeg(ffmpeg) => {
+                // Skipped frames repeat the last one, to keep the video in time with the sound.
+                if let Some(pixels) = pixels {
+                    ffmpeg.last = pixels
+                        .iter()
+                        .flat_map(|&p| {
+                            let color = self.theme.color(p);
+                            [color.r, color.g, color.b]
+                        })
+                        .collect();
+                }
+                ffmpeg.add_frame(beeping, pattern)?;
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Finishes the file, returning how many frames it has.
+    pub fn finish(self) -> io::Result<u32> {
+        match self.encoder {
+            Encoder::Apng(frames) => write_apng(
+                &self.path,
+                &frames,
+                self.size,
+                self.theme,
+                self.scale as usize,
+            )?,
+            Encoder::Ffmpeg(ffmpeg) => ffmpeg.finish(&self.path)?,
+        }
+        Ok(self.frames)
+    }
+}
+
+/// Palette indices, which are all 0 to 3, packed 4 to a byte.
+fn pack(pixels: &[u8]) -> Vec<u8> {
+    pixels
+        .chunks(4)
+        .map(|chunk| chunk.iter().fold(0, |byte, &p| byte << 2 | p) << (8 - chunk.len() * 2))
+        .collect()
+}
+
+fn write_apng(
+    path: &Path,
+    frames: &[(Vec<u8>, u32)],
+    (width, height): (usize, usize),
+    theme: Theme,
+    scale: usize,
+) -> io::Result<()> {
+    if frames.is_empty() {
+        return Err(io::Error::other("no frames were recorded"));
+    }
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, (width * scale) as u32, (height * scale) as u32);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(capture::palette(theme));
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .map_err(io::Error::other)?;
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    for (packed, shown) in frames {
+        let pixels: Vec<u8> = (0..width * height)
+            .map(|i| (packed[i / 4] >> (6 - i % 4 * 2)) & 3)
+            .collect();
+        let mut data = Vec::with_capacity(width * height * scale * scale);
+        for row in pixels.chunks(width) {
+            let line: Vec<u8> = row
+                .iter()
+                .flat_map(|&p| std::iter::repeat_n(p, scale))
+                .collect();
+            for _ in 0..scale {
+                data.extend_from_slice(&line);
+            }
+        }
+        writer
+            .set_frame_delay(
+                (*shown).min(u16::MAX as u32) as u16,
+                FRAMES_PER_SECOND as u16,
+            )
+            .map_err(io::Error::other)?;
+        writer.write_image_data(&data).map_err(io::Error::other)?;
+    }
+    writer.finish().map_err(io::Error::other)
+}
+
+/// An ffmpeg encoding raw frames from its standard input, and the sound being written beside it.
+struct Ffmpeg {
+    child: Child,
+    stdin: BufWriter<ChildStdin>,
+    /// Where ffmpeg writes the video, before the sound's muxed in.
+    video: PathBuf,
+    audio: Wav,
+    tone: ToneGenerator,
+    /// The last frame, as RGB.
+    last: Vec<u8>,
+}
+
+impl Ffmpeg {
+    fn start(path: &Path, (width, height): (usize, usize), scale: u32) -> io::Result<Self> {
+        let extension = path.extension().unwrap_or_default().to_string_lossy();
+        let video = path.with_extension(format!("video.{}", extension));
+        let audio = Wav::create(path.with_extension("audio.wav"))?;
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-loglevel", "error", "-y"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgb24"])
+            .arg("-video_size")
+            .arg(format!("{}x{}", width, height))
+            .arg("-framerate")
+            .arg(FRAMES_PER_SECOND.to_string())
+            .args(["-i", "-", "-vf"])
+            .arg(format!("scale=iw*{}:ih*{}:flags=neighbor", scale, scale));
+        if extension == "webm" {
+            // Lossless, so that the pixels stay sharp, and fast enough to keep up.
+            command.args(["-c:v", "libvpx-vp9", "-lossless", "1"]);
+            command.args(["-deadline", "realtime", "-cpu-used", "8"]);
+        }
+        let mut child = command
+            .arg(&video)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| io::Error::new(err.kind(), format!("couldn't run ffmpeg: {}", err)))?;
+        let stdin = BufWriter::new(child.stdin.take().expect("stdin is piped"));
+        Ok(Self {
+            child,
+            stdin,
+            video,
+            audio,
+            tone: ToneGenerator::new(),
+            last: vec![0; width * height * 3],
+        })
+    }
+
+    fn add_frame(&mut self, beeping: bool, pattern: Option<AudioPattern>) -> io::Result<()> {
+        self.stdin.write_all(&self.last)?;
+        for _ in 0..SAMPLE_RATE / FRAMES_PER_SECOND {
+            let sample = self
+                .tone
+                .next_sample(beeping, pattern, SAMPLE_RATE as f32, VOLUME);
+            self.audio.write_sample(sample)?;
+        }
+        Ok(())
+    }
+
+    /// Waits for ffmpeg to finish the video, then has it mux in the sound to make `path`.
+    fn finish(mut self, path: &Path) -> io::Result<()> {
+        let audio = self.audio.finish()?;
+        drop(self.stdin.into_inner().map_err(|err| err.into_error())?);
+        check(self.child.wait()?)?;
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-loglevel", "error", "-y", "-i"])
+            .arg(&self.video)
+            .arg("-i")
+            .arg(&audio)
+            .args(["-c:v", "copy"]);
+        if path.extension().is_some_and(|ext| ext == "webm") {
+            command.args(["-c:a", "libopus"]);
+        }
+        let status = command.arg(path).stdin(Stdio::null()).status();
+        let _ = std::fs::remove_file(&self.video);
+        let _ = std::fs::remove_file(&audio);
+        check(status?)
+    }
+}
+
+fn check(status: std::process::ExitStatus) -> io::Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("ffmpeg failed ({})", status)))
+    }
+}
+
+/// A 16-bit mono WAV file being written a sample at a time.
+struct Wav {
+    path: PathBuf,
+    file: BufWriter<File>,
+    samples: u32,
+}
+
+impl Wav {
+    fn create(path: PathBuf) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(&path)?);
+        // The sizes are filled in on finishing.
+        file.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        for field in [
+            16u32,
+            1 | 1 << 16,
+            SAMPLE_RATE,
+            SAMPLE_RATE * 2,
+            2 | 16 << 16,
+        ] {
+            file.write_all(&field.to_le_bytes())?;
+        }
+        file.write_all(b"data\0\0\0\0")?;
+        Ok(Self {
+            path,
+            file,
+            samples: 0,
+        })
+    }
+
+    fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        self.samples += 1;
+        self.file
+            .write_all(&((sample * i16::MAX as f32) as i16).to_le_bytes())
+    }
+
+    /// Fills in the sizes, returning where the file is.
+    fn finish(mut self) -> io::Result<PathBuf> {
+        let data = self.samples * 2;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&(36 + data).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&data.to_le_bytes())?;
+        self.file.flush()?;
+        Ok(self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apng_frames_are_merged_and_timed() {
+        let path = std::env::temp_dir().join(format!("chip8-video-{}.png", std::process::id()));
+        let mut fb = FrameBuffer::new(64, 32);
+        let mut video = VideoRecorder::create(&path, (64, 32), Theme::default(), 2).unwrap();
+        for frame in 0..90 {
+            if frame == 30 {
+                fb.set(1, 0, 1);
+            }
+            video.add_frame(&fb, false, None).unwrap();
+        }
+        assert_eq!(video.finish().unwrap(), 90);
+
+        let mut reader = png::Decoder::new(io::BufReader::new(File::open(&path).unwrap()))
+            .read_info()
+            .unwrap();
+        assert_eq!(reader.info().animation_control.unwrap().num_frames, 2);
+        let mut data = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut data).unwrap();
+        let delay = reader.info().frame_control.unwrap();
+        assert_eq!((delay.delay_num, delay.delay_den), (30, 60));
+        reader.next_frame(&mut data).unwrap();
+        let delay = reader.info().frame_control.unwrap();
+        assert_eq!((delay.delay_num, delay.delay_den), (60, 60));
+        // Pixel (1, 0), scaled up to 2x2.
+        assert_eq!(&data[..5], &[0, 0, 1, 1, 0]);
+        assert_eq!(&data[128..133], &[0, 0, 1, 1, 0]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn packs_pixels_into_bits() {
+        assert_eq!(pack(&[1, 0, 0, 3, 2, 1]), [0x43, 0x90]);
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Which kinds of memory access a `Watchpoint` fires on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    fn covers(self, write: bool) -> bool {
+        match self {
+            Access::Read => !write,
+            Access::Write => write,
+            Access::ReadWrite => true,
+        }
+    }
+}
+
+/// Fires when an instruction reads or writes any address in `start..=end`. Only data accesses
+/// count; fetching instructions doesn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub access: Access,
+}
+
+impl Watchpoint {
+    pub fn new(start: u16, end: u16, access: Access) -> Self {
+        Self {
+            start: start.min(end),
+            end: start.max(end),
+            access,
+        }
+    }
+
+    pub(crate) fn hits(&self, addr: usize, write: bool) -> bool {
+        (self.start as usize..=self.end as usize).contains(&addr) && self.access.covers(write)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.access {
+            Access::Read => "r:",
+            Access::Write => "w:",
+            Access::ReadWrite => "rw:",
+        })?;
+        if self.start == self.end {
+            write!(f, "{:03x}", self.start)
+        } else {
+            write!(f, "{:03x}-{:03x}", self.start, self.end)
+        }
+    }
+}
+
+/// Parses `r:`, `w:` or `rw:` followed by a hex address or inclusive range, e.g. `w:300-30f`.
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{}' isn't a watchpoint like w:300 or rw:300-30f", s);
+        let hex = |s: &str| u16::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+
+        let s = s.to_ascii_lowercase();
+        let (access, addrs) = s.split_once(':').ok_or_else(invalid)?;
+        let access = match access {
+            "r" => Access::Read,
+            "w" => Access::Write,
+            "rw" => Access::ReadWrite,
+            _ => return Err(invalid()),
+        };
+        let (start, end) = match addrs.split_once('-') {
+            Some((start, end)) => (hex(start), hex(end)),
+            None => (hex(addrs), hex(addrs)),
+        };
+        match (start, end) {
+            (Some(start), Some(end)) => Ok(Watchpoint::new(start, end, access)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The access that set off a watchpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+    pub watchpoint: Watchpoint,
+    pub addr: u16,
+    pub write: bool,
+    /// The byte read, or the byte written.
+    pub value: u8,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.write {
+            write!(f, "wrote {:02x} to {:03x}", self.value, self.addr)
+        } else {
+            write!(f, "read {:02x} from {:03x}", self.value, self.addr)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            "w:300-30F".parse(),
+            Ok(Watchpoint::new(0x300, 0x30f, Access::Write))
+        );
+        assert_eq!(
+            "r:0x2a0".parse(),
+            Ok(Watchpoint::new(0x2a0, 0x2a0, Access::Read))
+        );
+        assert!("x:300".parse::<Watchpoint>().is_err());
+        assert!("rw:300-".parse::<Watchpoint>().is_err());
+        for s in ["rw:300-30f", "w:2a0"] {
+            assert_eq!(s.parse::<Watchpoint>().unwrap().to_string(), s);
+        }
+    }
+}
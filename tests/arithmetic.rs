@@ -0,0 +1,134 @@
+//! Properties of the 8XYN arithmetic and shift instructions that should hold for every pair of
+//! operands, checked against plain integer arithmetic.
+use std::sync::Arc;
+
+use chip8::{Keypad, Memory, Quirks, CPU};
+use proptest::prelude::*;
+
+/// Runs `opcode` once with `vx` in VX and `vy` in VY (`vy` wins if they're the same register),
+/// and returns the registers afterwards.
+fn run(opcode: u16, vx: u8, vy: u8, quirks: Quirks) -> [u8; 16] {
+    let mut memory = Memory::new();
+    memory[0x200..0x202].copy_from_slice(&opcode.to_be_bytes());
+    let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), quirks);
+    let (x, y) = ((opcode >> 8 & 0xf) as usize, (opcode >> 4 & 0xf) as usize);
+    cpu.registers.v[x] = vx;
+    cpu.registers.v[y] = vy;
+    cpu.step().unwrap();
+    cpu.registers.v
+}
+
+fn opcode(x: usize, y: usize, n: u16) -> u16 {
+    0x8000 | (x as u16) << 8 | (y as u16) << 4 | n
+}
+
+/// Registers other than VF.
+fn register() -> impl Strategy<Value = usize> {
+    0usize..0xf
+}
+
+proptest! {
+    #[test]
+    fn add_wraps_and_sets_the_carry(x in register(), y in register(), vx: u8, vy: u8) {
+        prop_assume!(x != y);
+        let v = run(opcode(x, y, 4), vx, vy, Quirks::default());
+        prop_assert_eq!(v[x], vx.wrapping_add(vy));
+        prop_assert_eq!(v[y], vy);
+        prop_assert_eq!(v[0xf], (vx as u16 + vy as u16 > 0xff) as u8);
+    }
+
+    #[test]
+    fn sub_wraps_and_sets_not_borrow(x in register(), y in register(), vx: u8, vy: u8) {
+        prop_assume!(x != y);
+        let v = run(opcode(x, y, 5), vx, vy, Quirks::default());
+        prop_assert_eq!(v[x], vx.wrapping_sub(vy));
+        prop_assert_eq!(v[0xf], (vx >= vy) as u8);
+
+        let v = run(opcode(x, y, 7), vx, vy, Quirks::default());
+        prop_assert_eq!(v[x], vy.wrapping_sub(vx));
+        prop_assert_eq!(v[0xf], (vy >= vx) as u8);
+    }
+
+    #[test]
+    fn a_register_with_itself(x in register(), value: u8) {
+        let v = run(opcode(x, x, 4), value, value, Quirks::default());
+        prop_assert_eq!(v[x], value.wrapping_add(value));
+        prop_assert_eq!(v[0xf], (value >= 0x80) as u8);
+
+        let v = run(opcode(x, x, 5), value, value, Quirks::default());
+        prop_assert_eq!((v[x], v[0xf]), (0, 1));
+    }
+
+    #[test]
+    fn shifts_move_the_lost_bit_into_vf(
+        x in register(),
+        y in register(),
+        vx: u8,
+        vy: u8,
+        quirk: bool,
+    ) {
+        prop_assume!(x != y);
+        let quirks = Quirks { shift: quirk, ..Quirks::default() };
+        // Without the quirk, VY is shifted into VX.
+        let source = if quirk { vx } else { vy };
+
+        let v = run(opcode(x, y, 6), vx, vy, quirks);
+        prop_assert_eq!((v[x], v[0xf]), (source >> 1, source & 1));
+
+        let v = run(opcode(x, y, 0xe), vx, vy, quirks);
+        prop_assert_eq!((v[x], v[0xf]), (source << 1, source >> 7));
+    }
+
+    /// With VF as an operand, the flag is what's left in it: the result is overwritten.
+    #[test]
+    fn the_flag_wins_when_vf_is_the_destination(y in register(), vf: u8, vy: u8) {
+        let v = run(opcode(0xf, y, 4), vf, vy, Quirks::default());
+        prop_assert_eq!(v[0xf], (vf as u16 + vy as u16 > 0xff) as u8);
+
+        let v = run(opcode(0xf, y, 5), vf, vy, Quirks::default());
+        prop_assert_eq!(v[0xf], (vf >= vy) as u8);
+
+        let v = run(opcode(0xf, y, 6), vf, vy, Quirks::default());
+        prop_assert_eq!(v[0xf], vy & 1);
+    }
+
+    /// VF read as VY is its value from before the instruction, not the new flag.
+    #[test]
+    fn vf_as_the_source_is_read_before_the_flag_is_set(x in register(), vx: u8, vf: u8) {
+        let v = run(opcode(x, 0xf, 4), vx, vf, Quirks::default());
+        prop_assert_eq!(v[x], vx.wrapping_add(vf));
+        prop_assert_eq!(v[0xf], (vx as u16 + vf as u16 > 0xff) as u8);
+
+        let v = run(opcode(x, 0xf, 7), vx, vf, Quirks::default());
+        prop_assert_eq!(v[x], vf.wrapping_sub(vx));
+        prop_assert_eq!(v[0xf], (vf >= vx) as u8);
+    }
+
+    #[test]
+    fn logic_resets_vf_only_with_the_quirk(
+        x in register(),
+        y in register(),
+        n in 1u16..4,
+        vx: u8,
+        vy: u8,
+        vf: u8,
+        quirk: bool,
+    ) {
+        prop_assume!(x != y);
+        let mut memory = Memory::new();
+        memory[0x200..0x202].copy_from_slice(&opcode(x, y, n).to_be_bytes());
+        let quirks = Quirks { vf_reset: quirk, ..Quirks::default() };
+        let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), quirks);
+        cpu.registers.v[x] = vx;
+        cpu.registers.v[y] = vy;
+        cpu.registers.v[0xf] = vf;
+        cpu.step().unwrap();
+        let expected = match n {
+            1 => vx | vy,
+            2 => vx & vy,
+            _ => vx ^ vy,
+        };
+        prop_assert_eq!(cpu.registers.v[x], expected);
+        prop_assert_eq!(cpu.registers.v[0xf], if quirk { 0 } else { vf });
+    }
+}
@@ -0,0 +1,94 @@
+//! Recorded playthroughs of whole games, replayed frame by frame with a hash of the display
+//! checked every second against the one in the `.hashes` file next to the movie. A change to the
+//! core that changes how any of them plays shows up as the first second where the display isn't
+//! what it was.
+//!
+//! Run with `CHIP8_BLESS=1` to write the hashes instead, after watching the replay to make sure
+//! it plays as it should.
+use std::fmt::Write;
+use std::sync::Arc;
+
+use chip8::{snapshot, Keypad, Memory, Movie, Quirks, Variant, CPU};
+
+/// How often the display is hashed, in frames.
+const EVERY: usize = 60;
+
+fn replays(name: &str) -> String {
+    format!("{}/tests/replays/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+/// Plays `<name>.movie` on `rom`, checking the display against `<name>.hashes`.
+fn check_replay(name: &str, rom: &str) {
+    let mut memory = Memory::new();
+    memory
+        .load_rom(format!("{}/roms/{}", env!("CARGO_MANIFEST_DIR"), rom))
+        .unwrap();
+    let mut cpu = CPU::new(
+        memory,
+        Arc::new(Keypad::new()),
+        Quirks::preset(Variant::Chip8),
+    );
+    let movie = Movie::load(replays(&format!("{}.movie", name))).unwrap();
+    movie.start_playback(&mut cpu);
+
+    let mut displays = Vec::new();
+    for frame in 0..movie.len() {
+        movie.play_frame(frame, &mut cpu).unwrap();
+        if (frame + 1) % EVERY == 0 {
+            displays.push((frame + 1, cpu.framebuffer().to_text()));
+        }
+    }
+    let hashes: Vec<_> = displays
+        .iter()
+        .map(|(frame, display)| format!("{} {}", frame, chip8::rom_sha1(display.as_bytes())))
+        .collect();
+
+    let path = replays(&format!("{}.hashes", name));
+    if std::env::var_os(snapshot::BLESS_VAR).is_some() {
+        let mut file = String::from("# frame, then the SHA-1 of the display after it\n");
+        for hash in &hashes {
+            writeln!(file, "{}", hash).unwrap();
+        }
+        std::fs::write(&path, file).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!(
+            "couldn't read {}: {} (run with {}=1 to write it)",
+            path,
+            err,
+            snapshot::BLESS_VAR
+        )
+    });
+    let expected: Vec<_> = expected
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(str::trim)
+        .collect();
+    for ((theirs, ours), (frame, display)) in expected.iter().zip(&hashes).zip(&displays) {
+        assert!(
+            theirs == ours,
+            "{} played differently by frame {}; the display is now:\n{}",
+            name,
+            frame,
+            display
+        );
+    }
+    assert_eq!(
+        expected.len(),
+        hashes.len(),
+        "{}.movie isn't the one {} was made from",
+        name,
+        path
+    );
+}
+
+#[test]
+fn pong_two_players() {
+    check_replay("pong", "pong.ch8");
+}
+
+#[test]
+fn keypad_test() {
+    check_replay("keypad", "6-keypad.ch8");
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use chip8::{snapshot, HeadlessRunner, Keypad, Memory, Quirks, Variant, CPU};
+
+fn runner_for(rom: &str, variant: Variant) -> HeadlessRunner {
+    let mut memory = Memory::with_size(variant.memory_size());
+    memory
+        .load_rom(format!("{}/roms/{}", env!("CARGO_MANIFEST_DIR"), rom))
+        .unwrap();
+    let mut cpu = CPU::new(memory, Arc::new(Keypad::new()), Quirks::preset(variant));
+    cpu.set_variant(variant);
+    HeadlessRunner::new(cpu)
+}
+
+fn golden(name: &str) -> String {
+    format!("{}/tests/golden/{}.txt", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+#[test]
+fn keypad_test_menu() {
+    let mut runner = runner_for("6-keypad.ch8", Variant::Chip8);
+    let fb = runner.run_frames(30).unwrap();
+    snapshot::assert_matches(&fb, golden("keypad-menu"));
+}
+
+#[test]
+fn ibm_logo_part_drawn() {
+    // The display wait quirk holds each sprite until the next frame, so a few frames in, only
+    // the start of the logo is there.
+    let mut runner = runner_for("2-ibm-logo.ch8", Variant::Chip8);
+    let fb = runner.run_frames(3).unwrap();
+    snapshot::assert_matches(&fb, golden("ibm-logo-frame-3"));
+}
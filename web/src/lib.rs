@@ -0,0 +1,97 @@
+//! Browser bindings for the emulator core. Build with
+//! `wasm-pack build web --target web` and serve `web/` to try out `index.html`.
+use chip8::{Emulator, Rng, Variant};
+use wasm_bindgen::prelude::*;
+
+/// An emulator with nothing attached. JavaScript drives it a frame at a time from
+/// `requestAnimationFrame`, reads the display straight out of wasm memory and forwards key
+/// events.
+#[wasm_bindgen]
+pub struct WasmEmulator {
+    emulator: Emulator,
+    seed: u32,
+}
+
+#[wasm_bindgen]
+impl WasmEmulator {
+    /// There's no clock to seed the random number generator from in wasm, so pass something
+    /// like `Math.random() * 2 ** 32`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(seed: u32) -> WasmEmulator {
+        let emulator = Emulator::builder()
+            .rng(Rng::new(seed))
+            .build()
+            .expect("no ROM always fits");
+        WasmEmulator { emulator, seed }
+    }
+
+    /// Resets the machine and loads `rom` at 0x200 to run as `variant` (chip8, chip48, schip or
+    /// xochip), with that variant's quirks.
+    pub fn load_rom(&mut self, rom: &[u8], variant: &str) -> Result<(), JsError> {
+        let variant: Variant = variant.parse().map_err(|err: String| JsError::new(&err))?;
+        self.emulator = Emulator::builder()
+            .rom_bytes(rom)
+            .variant(variant)
+            .rng(Rng::new(self.seed))
+            .build()
+            .map_err(|err| JsError::new(&err.to_string()))?;
+        Ok(())
+    }
+
+    /// Runs one 60Hz frame.
+    pub fn step_frame(&mut self) -> Result<(), JsError> {
+        self.emulator
+            .run_frame()
+            .map_err(|err| JsError::new(&err.to_string()))
+    }
+
+    /// Where the display lives in wasm memory: `framebuffer_width() * framebuffer_height()`
+    /// bytes, row-major, non-zero for lit pixels. Only valid until the next call into the
+    /// emulator.
+    pub fn framebuffer_ptr(&self) -> *const u8 {
+        self.emulator.cpu().framebuffer().pixels().as_ptr()
+    }
+
+    pub fn framebuffer_width(&self) -> usize {
+        self.emulator.cpu().display_width()
+    }
+
+    pub fn framebuffer_height(&self) -> usize {
+        self.emulator.cpu().display_height()
+    }
+
+    /// Presses or releases hex keypad key `key`.
+    pub fn key_event(&self, key: u8, pressed: bool) {
+        if pressed {
+            self.emulator.keypad().press(key);
+        } else {
+            self.emulator.keypad().release(key);
+        }
+    }
+
+    /// Whether the sound timer is running, for the page to start or stop a tone.
+    pub fn beeping(&self) -> bool {
+        self.emulator.cpu().beeping()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_a_rom() {
+        // Draw the 0 glyph at the top left.
+        let rom = [0x60, 0x00, 0xf0, 0x29, 0xd0, 0x05, 0x12, 0x06];
+        let mut emulator = WasmEmulator::new(1);
+        emulator.load_rom(&rom, "chip8").ok().unwrap();
+        emulator.step_frame().ok().unwrap();
+
+        let (width, height) = (emulator.framebuffer_width(), emulator.framebuffer_height());
+        assert_eq!((width, height), (64, 32));
+        let display =
+            unsafe { std::slice::from_raw_parts(emulator.framebuffer_ptr(), width * height) };
+        assert_eq!(display[..4], [1, 1, 1, 1]);
+        assert_eq!(display[4], 0);
+    }
+}